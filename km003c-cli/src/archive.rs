@@ -0,0 +1,294 @@
+//! Directories of exported offline recordings described by a `manifest.json`.
+//!
+//! Each manifest entry lists the exported file and its format, the catalog
+//! metadata and source meter of the recording, and CRC-32 checksums of the
+//! samples and the file. Repeated exports skip recordings already archived
+//! unchanged and never overwrite a different recording that reused a device
+//! filename.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use km003c_lib::uom::si::time::{millisecond, second};
+use km003c_lib::{DeviceInfo, HardwareId, LogMetadata, OfflineLog};
+use serde::{Deserialize, Serialize};
+
+/// Name of the manifest inside an archive directory.
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Catalog metadata of a recording, as written to manifests and `--json` output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataRecord {
+    pub filename: String,
+    pub filename_raw: [u8; 16],
+    pub sample_count: u16,
+    pub interval_ms: f64,
+    pub flags: u16,
+    pub recorded_duration_seconds: f64,
+    pub calculated_duration_seconds: f64,
+    pub unknown_0x10: u16,
+    pub final_charge_uah: i32,
+    pub final_energy_uwh: i32,
+    pub data_offset: u32,
+    pub data_address: Option<u32>,
+    /// Hex-encoded.
+    pub reserved_tail: String,
+}
+
+impl From<&LogMetadata> for MetadataRecord {
+    fn from(metadata: &LogMetadata) -> Self {
+        Self {
+            filename: metadata.filename_lossy().into_owned(),
+            filename_raw: metadata.filename_raw,
+            sample_count: metadata.sample_count,
+            interval_ms: metadata.interval.get::<millisecond>(),
+            flags: metadata.flags,
+            recorded_duration_seconds: metadata.recorded_duration.get::<second>(),
+            calculated_duration_seconds: metadata.calculated_duration().get::<second>(),
+            unknown_0x10: metadata.unknown_0x10,
+            final_charge_uah: metadata.final_charge_raw_uah(),
+            final_energy_uwh: metadata.final_energy_raw_uwh(),
+            data_offset: metadata.data_offset,
+            data_address: metadata.data_address().ok(),
+            reserved_tail: hex::encode(metadata.reserved_tail),
+        }
+    }
+}
+
+/// Identity of the meter recordings were read from.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    pub model: String,
+    pub hardware_version: String,
+    pub firmware_version: String,
+    pub serial: String,
+    pub uuid: String,
+    pub hardware_id: Option<String>,
+}
+
+impl DeviceRecord {
+    pub fn new(info: &DeviceInfo, hardware_id: Option<&HardwareId>) -> Self {
+        Self {
+            model: info.model.clone(),
+            hardware_version: info.hw_version.clone(),
+            firmware_version: info.fw_version.clone(),
+            serial: info.serial_id.clone(),
+            uuid: info.uuid.clone(),
+            hardware_id: hardware_id.map(|id| id.to_string()),
+        }
+    }
+}
+
+/// One exported recording in a manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// File name inside the archive directory.
+    pub file: String,
+    /// Extension of the export format.
+    pub format: String,
+    pub catalog_index: usize,
+    pub samples: usize,
+    /// CRC-32 of the raw sample data, as 8 hex digits.
+    pub data_crc32: String,
+    /// CRC-32 of the exported file, as 8 hex digits.
+    pub file_crc32: String,
+    pub device: DeviceRecord,
+    pub metadata: MetadataRecord,
+}
+
+/// Contents of `manifest.json`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub recordings: Vec<ManifestEntry>,
+}
+
+/// An archive directory and the recordings its manifest lists.
+#[derive(Debug)]
+pub struct Archive {
+    dir: PathBuf,
+    device: DeviceRecord,
+    manifest: Manifest,
+}
+
+impl Archive {
+    /// Open `dir` for recordings from the meter described by `device`,
+    /// creating the directory and loading its manifest if one exists.
+    pub fn open(dir: impl Into<PathBuf>, device: DeviceRecord) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(MANIFEST_FILENAME);
+        let manifest = if path.exists() {
+            serde_json::from_reader(File::open(&path)?)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {error}", path.display())))?
+        } else {
+            Manifest::default()
+        };
+        Ok(Self { dir, device, manifest })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.dir.join(MANIFEST_FILENAME)
+    }
+
+    pub fn recordings(&self) -> &[ManifestEntry] {
+        &self.manifest.recordings
+    }
+
+    /// Entry already holding this recording, with this extension, in an intact file.
+    pub fn unchanged(&self, metadata: &LogMetadata, extension: &str) -> Option<&ManifestEntry> {
+        let metadata = MetadataRecord::from(metadata);
+        self.manifest.recordings.iter().find(|entry| {
+            self.is_same_recording(entry, &metadata)
+                && entry.format == extension
+                && file_crc32(&self.dir.join(&entry.file)).is_ok_and(|crc| entry.file_crc32 == format!("{crc:08x}"))
+        })
+    }
+
+    /// File name to export a recording to, never overwriting a different archived recording.
+    pub fn export_filename(&self, metadata: &LogMetadata, extension: &str) -> String {
+        let record = MetadataRecord::from(metadata);
+        let default = default_filename(metadata, extension);
+        let stem = default.strip_suffix(&format!(".{extension}")).unwrap_or(&default);
+        (1..)
+            .map(|copy| match copy {
+                1 => default.clone(),
+                copy => format!("{stem}-{copy}.{extension}"),
+            })
+            .find(
+                |file| match self.manifest.recordings.iter().find(|entry| entry.file == *file) {
+                    Some(entry) => self.is_same_recording(entry, &record),
+                    None => !self.dir.join(file).exists(),
+                },
+            )
+            .expect("an unused file name exists")
+    }
+
+    /// Record `log`, exported to `file` inside the directory, and rewrite the manifest.
+    pub fn insert(&mut self, file: &str, extension: &str, catalog_index: usize, log: &OfflineLog) -> io::Result<()> {
+        let file_crc32 = file_crc32(&self.dir.join(file))?;
+        self.manifest.recordings.retain(|entry| entry.file != file);
+        self.manifest.recordings.push(ManifestEntry {
+            file: file.to_string(),
+            format: extension.to_string(),
+            catalog_index,
+            samples: log.samples.len(),
+            data_crc32: format!("{:08x}", crc32fast::hash(&log.to_bytes())),
+            file_crc32: format!("{file_crc32:08x}"),
+            device: self.device.clone(),
+            metadata: MetadataRecord::from(&log.metadata),
+        });
+        self.save()
+    }
+
+    /// Write the manifest to `manifest.json.partial` and rename it into place.
+    pub fn save(&self) -> io::Result<()> {
+        let partial = self.dir.join(format!("{MANIFEST_FILENAME}.partial"));
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        serde_json::to_writer_pretty(&mut writer, &self.manifest)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(partial, self.manifest_path())
+    }
+
+    /// Whether a manifest entry was exported from this recording on this meter.
+    fn is_same_recording(&self, entry: &ManifestEntry, metadata: &MetadataRecord) -> bool {
+        entry.metadata == *metadata && entry.device.serial == self.device.serial
+    }
+}
+
+/// `<device-filename>.<extension>`, without any directories in the device filename.
+pub fn default_filename(metadata: &LogMetadata, extension: &str) -> String {
+    let device_filename = metadata.filename_lossy();
+    let filename = Path::new(device_filename.as_ref())
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("offline-log");
+    format!("{filename}.{extension}")
+}
+
+fn file_crc32(path: &Path) -> io::Result<u32> {
+    Ok(crc32fast::hash(&std::fs::read(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use km003c_lib::uom::si::electric_charge::microampere_hour;
+    use km003c_lib::uom::si::energy::microwatt_hour;
+    use km003c_lib::uom::si::f64::{ElectricCharge, Energy, Time};
+
+    use super::*;
+
+    fn metadata(filename: &[u8]) -> LogMetadata {
+        let mut filename_raw = [0; 16];
+        filename_raw[..filename.len()].copy_from_slice(filename);
+        LogMetadata {
+            filename_raw,
+            unknown_0x10: 0,
+            sample_count: 0,
+            interval: Time::new::<millisecond>(1_000.0),
+            flags: 0,
+            recorded_duration: Time::new::<second>(0.0),
+            final_charge: ElectricCharge::new::<microampere_hour>(0.0),
+            final_energy: Energy::new::<microwatt_hour>(0.0),
+            data_offset: 0,
+            reserved_tail: [0; 8],
+        }
+    }
+
+    #[test]
+    fn default_filename_preserves_the_device_extension() {
+        assert_eq!(default_filename(&metadata(b"A01.d"), "csv"), "A01.d.csv");
+        assert_eq!(default_filename(&metadata(b"../A01.d"), "json"), "A01.d.json");
+        assert_eq!(default_filename(&metadata(b""), "parquet"), "offline-log.parquet");
+    }
+
+    #[test]
+    fn skips_unchanged_recordings_and_keeps_archived_ones() {
+        let dir = std::env::temp_dir().join(format!("km003c-archive-{}", std::process::id()));
+        let device = DeviceRecord {
+            serial: "007965".to_string(),
+            ..DeviceRecord::default()
+        };
+        let first = metadata(b"A01.d");
+        let mut reused_name = metadata(b"A01.d");
+        reused_name.sample_count = 5;
+
+        let mut archive = Archive::open(&dir, device.clone()).unwrap();
+        let file = archive.export_filename(&first, "csv");
+        assert_eq!(file, "A01.d.csv");
+        std::fs::write(dir.join(&file), "index\n").unwrap();
+        archive
+            .insert(&file, "csv", 0, &OfflineLog::from_bytes(first.clone(), &[]).unwrap())
+            .unwrap();
+        let archive = Archive::open(&dir, device.clone()).unwrap();
+        assert_eq!(archive.recordings().len(), 1);
+        assert_eq!(archive.recordings()[0].device, device);
+
+        assert!(archive.unchanged(&first, "csv").is_some());
+        assert!(archive.unchanged(&first, "json").is_none());
+        assert!(archive.unchanged(&reused_name, "csv").is_none());
+        // The same name on a wiped device is a different recording.
+        assert_eq!(archive.export_filename(&reused_name, "csv"), "A01.d-2.csv");
+
+        // So is an identical catalog entry on another meter.
+        let other_device = DeviceRecord {
+            serial: "008123".to_string(),
+            ..DeviceRecord::default()
+        };
+        let other_meter = Archive::open(&dir, other_device).unwrap();
+        assert!(other_meter.unchanged(&first, "csv").is_none());
+        assert_eq!(other_meter.export_filename(&first, "csv"), "A01.d-2.csv");
+
+        std::fs::write(dir.join("A01.d.csv"), "edited\n").unwrap();
+        assert!(archive.unchanged(&first, "csv").is_none());
+        assert_eq!(archive.export_filename(&first, "csv"), "A01.d.csv");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,14 +1,17 @@
 use clap::Parser;
+use km003c_cli::DeviceArgs;
+use km003c_lib::recording::{RecordingFormat, RecordingMetadata, RecordingRow, RecordingWriter};
 use km003c_lib::uom::si::electric_current::ampere;
 use km003c_lib::uom::si::electric_potential::volt;
 use km003c_lib::uom::si::f64::Frequency;
 use km003c_lib::uom::si::frequency::hertz;
 use km003c_lib::uom::si::power::watt;
 use km003c_lib::{
-    DeviceConfig, GraphSampleRate, KM003C,
+    GraphSampleRate, KM003C, MeasurementAccumulator,
     packet::{Attribute, AttributeSet},
 };
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// AdcQueue streaming example for POWER-Z KM003C
@@ -23,17 +26,16 @@ struct Args {
     #[arg(short, long, default_value = "10")]
     duration: u64,
 
+    /// Record the stream to this file; `.csv` writes CSV, anything else Parquet
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
     /// Verbose logging
     #[arg(short, long)]
     verbose: bool,
 
-    /// Skip USB reset (defaults to true on macOS for compatibility)
-    #[arg(long, default_value_t = cfg!(target_os = "macos"))]
-    no_reset: bool,
-
-    /// Force USB reset even on macOS (overrides --no-reset)
-    #[arg(long)]
-    reset: bool,
+    #[command(flatten)]
+    device: DeviceArgs,
 }
 
 #[derive(Debug, Default)]
@@ -68,6 +70,14 @@ impl SequenceStatistics {
     }
 }
 
+/// Recording format for an output path, chosen by its extension.
+fn recording_format(path: &Path) -> RecordingFormat {
+    match path.extension() {
+        Some(extension) if extension.eq_ignore_ascii_case("csv") => RecordingFormat::Csv,
+        _ => RecordingFormat::Parquet,
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
@@ -90,10 +100,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     };
 
     // AdcQueue requires vendor interface (Full mode)
-    let mut config = DeviceConfig::vendor();
-    if args.no_reset && !args.reset {
-        config = config.skip_reset();
-    }
+    let config = args.device.config()?;
 
     println!("Connecting to POWER-Z KM003C...\n");
     let mut device = KM003C::new(config).await?;
@@ -106,6 +113,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let state = device.state().expect("device initialized");
     println!("{}\n", state);
 
+    let mut recording = match &args.output {
+        Some(path) => Some(RecordingWriter::create(
+            path,
+            recording_format(path),
+            &RecordingMetadata::live(&state.info),
+        )?),
+        None => None,
+    };
+    let mut accumulator = MeasurementAccumulator::default();
+
     println!("Init complete!\n");
 
     // Start graph mode using library API
@@ -201,6 +218,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
             total_samples += 1;
 
+            if let Some(writer) = &mut recording
+                && let Some(measurement) = accumulator.push(*sample, rate)
+            {
+                writer.write_rows(&[RecordingRow::from_measurement(
+                    &measurement,
+                    None,
+                    measurement.sample_index,
+                )])?;
+            }
+
             if (total_samples - 1) % print_interval == 0 {
                 println!(
                     "{:>6} {:>10.3} {:>10.3} {:>10.3} {:>8.3} {:>8.3} {:>8.3} {:>8.3}",
@@ -227,6 +254,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
     device.stop_graph_mode().await?;
     println!("Stopped\n");
 
+    if let Some(writer) = recording {
+        let rows = writer.rows();
+        let path = writer.finish()?;
+        println!("Recorded {} samples to {}\n", rows, path.display());
+    }
+
     // Statistics
     let elapsed = start_time.elapsed().as_secs_f64();
     println!("Statistics:");
@@ -254,6 +287,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn output_format_follows_the_extension() {
+        assert_eq!(recording_format(Path::new("capture.csv")), RecordingFormat::Csv);
+        assert_eq!(recording_format(Path::new("capture.CSV")), RecordingFormat::Csv);
+        assert_eq!(recording_format(Path::new("capture.parquet")), RecordingFormat::Parquet);
+        assert_eq!(recording_format(Path::new("capture")), RecordingFormat::Parquet);
+    }
+
     #[test]
     fn sequence_rate_uses_device_ticks() {
         let mut statistics = SequenceStatistics::default();
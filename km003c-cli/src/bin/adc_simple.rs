@@ -1,4 +1,5 @@
 use clap::Parser;
+use km003c_cli::DeviceArgs;
 use km003c_lib::uom::si::electric_current::ampere;
 use km003c_lib::uom::si::electric_potential::volt;
 use km003c_lib::uom::si::power::watt;
@@ -18,13 +19,8 @@ struct Args {
     #[arg(short, long)]
     verbose: bool,
 
-    /// Skip USB reset (defaults to true on macOS for compatibility)
-    #[arg(long, default_value_t = cfg!(target_os = "macos"))]
-    no_reset: bool,
-
-    /// Force USB reset even on macOS (overrides --no-reset)
-    #[arg(long)]
-    reset: bool,
+    #[command(flatten)]
+    device: DeviceArgs,
 }
 
 #[tokio::main]
@@ -41,15 +37,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt().with_max_level(log_level).init();
 
     // Select configuration based on CLI argument
-    let mut config = match args.interface.as_str() {
+    let config = args.device.config_for(match args.interface.as_str() {
         "vendor" => DeviceConfig::vendor(),
         "hid" => DeviceConfig::hid(),
         _ => unreachable!(), // clap validates this
-    };
-
-    if args.no_reset && !args.reset {
-        config = config.skip_reset();
-    }
+    })?;
 
     println!(
         "Searching for POWER-Z KM003C ({} interface)...\n",
@@ -0,0 +1,108 @@
+use std::error::Error;
+use std::net::SocketAddr;
+
+use clap::Parser;
+use km003c_cli::BRIDGE_KEY_ENV;
+use km003c_lib::device::{INTERFACE_HID, INTERFACE_VENDOR};
+use km003c_lib::{BridgeServer, DeviceConfig, KM003C};
+use tracing::{info, warn};
+
+/// Share a USB-attached KM003C with other machines over TCP
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Address to accept clients on
+    #[arg(short, long, default_value = "127.0.0.1:7003")]
+    listen: SocketAddr,
+
+    /// Pre-shared key clients must present (defaults to $KM003C_BRIDGE_KEY)
+    #[arg(short, long)]
+    key: Option<String>,
+
+    /// USB interface to open: vendor (all features) or hid (ADC/PD polling only)
+    #[arg(short, long, default_value = "vendor", value_parser = ["vendor", "hid"])]
+    interface: String,
+
+    /// Verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Skip USB reset (defaults to true on macOS for compatibility)
+    #[arg(long, default_value_t = cfg!(target_os = "macos"))]
+    no_reset: bool,
+
+    /// Force USB reset even on macOS (overrides --no-reset)
+    #[arg(long)]
+    reset: bool,
+}
+
+/// USB configuration and interface number selected by the arguments.
+fn device_config(args: &Args) -> (DeviceConfig, u8) {
+    let (config, interface) = match args.interface.as_str() {
+        "vendor" => (DeviceConfig::vendor(), INTERFACE_VENDOR),
+        "hid" => (DeviceConfig::hid(), INTERFACE_HID),
+        _ => unreachable!(), // clap validates this
+    };
+    if args.no_reset && !args.reset {
+        (config.skip_reset(), interface)
+    } else {
+        (config, interface)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let log_level = if args.verbose {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::INFO
+    };
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let key = args.key.clone().or_else(|| std::env::var(BRIDGE_KEY_ENV).ok());
+    if key.is_none() && !args.listen.ip().is_loopback() {
+        warn!(
+            "Serving without a key; anyone who can reach {} can use the meter",
+            args.listen
+        );
+    }
+
+    let (config, interface) = device_config(&args);
+    let device = KM003C::new(config).await?;
+    match device.state() {
+        Some(state) => {
+            info!(serial = %state.info.serial_id, "Sharing {} (FW {})", state.model(), state.firmware_version())
+        }
+        None => info!("Sharing the meter in basic mode"),
+    }
+
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    info!("Accepting clients on tcp://{}", listener.local_addr()?);
+    let mut server = BridgeServer::new(device, interface);
+    if let Some(key) = &key {
+        server = server.with_key(key.as_bytes());
+    }
+    tokio::select! {
+        result = server.serve(listener) => result?,
+        _ = tokio::signal::ctrl_c() => info!("Stopping"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hid_interface_is_announced_to_clients() {
+        let args = Args::parse_from(["bridge-server", "--interface", "hid", "--reset"]);
+        let (config, interface) = device_config(&args);
+        assert!(config.is_hid());
+        assert_eq!(interface, INTERFACE_HID);
+
+        let args = Args::parse_from(["bridge-server"]);
+        assert_eq!(device_config(&args).1, INTERFACE_VENDOR);
+    }
+}
@@ -0,0 +1,1026 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::Path as FilePath;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::StatusCode;
+use axum::http::header::{AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE, HOST, ORIGIN};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use clap::Parser;
+use km003c_cli::{DeviceArgs, parse_rate};
+use km003c_lib::error::KMError;
+use km003c_lib::recording::{RecordingFormat, RecordingMetadata, RecordingRow, write_recording_to};
+use km003c_lib::uom::si::electric_current::ampere;
+use km003c_lib::uom::si::electric_potential::volt;
+use km003c_lib::uom::si::time::{millisecond, second};
+use km003c_lib::{
+    DeviceConfig, DeviceInfo, DeviceState, DeviceStream, FormattedPdEvent, GraphSampleRate, KM003C, LogMetadata,
+    MeasurementAccumulator, MeasurementSample, OfflineLog, OfflineLogDownload, PdSessionDecoder, PolicyState,
+    format_event,
+    packet::{Attribute, AttributeSet},
+};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+/// Environment variable holding the API token when `--token` is not given.
+const TOKEN_ENV: &str = "KM003C_LIVE_TOKEN";
+
+/// Polling interval for PD events while no capture is running.
+const PD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Messages buffered for each WebSocket client before it starts skipping.
+const EVENT_BUFFER: usize = 1024;
+
+/// Times an offline download resumes from the last good chunk after a failed read.
+const DOWNLOAD_RETRIES: u32 = 3;
+
+/// Browser dashboard served at `/`.
+const DASHBOARD: &str = include_str!("../../assets/live_dashboard.html");
+
+/// Stream live KM003C data to browsers over WebSocket and HTTP
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Address to serve the dashboard, REST API and WebSocket on
+    #[arg(short, long, default_value = "127.0.0.1:8003")]
+    listen: SocketAddr,
+
+    /// Token required by the API and WebSocket (defaults to $KM003C_LIVE_TOKEN)
+    #[arg(long)]
+    token: Option<String>,
+
+    /// Capture sample rate: 2, 10, 50, or 1000 SPS
+    #[arg(short, long, default_value = "50", value_parser = parse_rate)]
+    rate: GraphSampleRate,
+
+    /// Do not start a capture until one is requested through the API
+    #[arg(long)]
+    idle: bool,
+
+    /// Averaged points per second sent to WebSocket clients
+    #[arg(long, default_value = "20")]
+    points_per_second: u32,
+
+    /// Seconds to wait before reconnecting to a lost or missing device
+    #[arg(long, default_value = "2")]
+    retry_interval: u64,
+
+    /// Verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    #[command(flatten)]
+    device: DeviceArgs,
+}
+
+/// Device state shared between the streaming task and the HTTP handlers.
+#[derive(Debug)]
+struct Live {
+    device: Option<DeviceState>,
+    connected: bool,
+    /// Capture requested through the API, applied by the streaming task.
+    capturing: bool,
+    rate: GraphSampleRate,
+    pd: PolicyState,
+}
+
+impl Live {
+    fn new(rate: GraphSampleRate, capturing: bool) -> Self {
+        Self {
+            device: None,
+            connected: false,
+            capturing,
+            rate,
+            pd: PolicyState::default(),
+        }
+    }
+
+    /// Rate of the capture that should be running, if any.
+    fn requested_capture(&self) -> Option<GraphSampleRate> {
+        self.capturing.then_some(self.rate)
+    }
+
+    fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            connected: self.connected,
+            capturing: self.capturing,
+            rate_sps: self.rate.samples_per_second(),
+            device: self.device.as_ref().map(|state| DeviceSummary {
+                model: state.model().to_string(),
+                hw_version: state.info.hw_version.clone(),
+                fw_version: state.firmware_version().to_string(),
+                serial: state.info.serial_id.clone(),
+                auth_level: state.auth_level,
+            }),
+            pd: PdSummary {
+                attached: self.pd.attached,
+                contract: self.pd.contract.map(|contract| ContractSummary {
+                    position: contract.request.object_position(),
+                    voltage_v: contract.voltage.map(|voltage| voltage.get::<volt>()),
+                    current_a: contract.current.map(|current| current.get::<ampere>()),
+                    epr: contract.epr,
+                }),
+            },
+        }
+    }
+}
+
+/// Device and capture state returned by the API and sent to WebSocket clients.
+#[derive(Debug, Serialize)]
+struct StateSnapshot {
+    connected: bool,
+    capturing: bool,
+    rate_sps: u16,
+    device: Option<DeviceSummary>,
+    pd: PdSummary,
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceSummary {
+    model: String,
+    hw_version: String,
+    fw_version: String,
+    serial: String,
+    auth_level: u8,
+}
+
+#[derive(Debug, Serialize)]
+struct PdSummary {
+    attached: bool,
+    contract: Option<ContractSummary>,
+}
+
+#[derive(Debug, Serialize)]
+struct ContractSummary {
+    position: u8,
+    voltage_v: Option<f64>,
+    current_a: Option<f64>,
+    epr: bool,
+}
+
+/// Messages broadcast to WebSocket clients, tagged with their `type`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event {
+    Device {
+        state: StateSnapshot,
+    },
+    Samples {
+        points: Vec<Point>,
+    },
+    Pd {
+        timestamp_s: f64,
+        category: String,
+        summary: String,
+        details: Vec<String>,
+    },
+    OfflineProgress {
+        index: usize,
+        received_samples: u32,
+        total_samples: u16,
+    },
+}
+
+impl From<FormattedPdEvent> for Event {
+    fn from(event: FormattedPdEvent) -> Self {
+        Self::Pd {
+            timestamp_s: event.timestamp.get::<second>(),
+            category: format!("{:?}", event.category),
+            summary: event.summary,
+            details: event.details,
+        }
+    }
+}
+
+/// Samples averaged over one bucket of device time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Point {
+    elapsed_s: f64,
+    vbus_v: f64,
+    ibus_a: f64,
+    power_w: f64,
+    cc1_v: f64,
+    cc2_v: f64,
+    dp_v: f64,
+    dm_v: f64,
+    charge_ah: f64,
+    energy_wh: f64,
+    samples: u32,
+    missing_samples: u64,
+}
+
+/// Catalog entry of an offline log.
+#[derive(Debug, Serialize)]
+struct CatalogEntry {
+    index: usize,
+    filename: String,
+    sample_count: u16,
+    interval_ms: f64,
+    duration_s: f64,
+    charge_uah: i32,
+    energy_uwh: i32,
+}
+
+impl CatalogEntry {
+    fn new(index: usize, metadata: &LogMetadata) -> Self {
+        Self {
+            index,
+            filename: metadata.filename_lossy().into_owned(),
+            sample_count: metadata.sample_count,
+            interval_ms: metadata.interval.get::<millisecond>(),
+            duration_s: metadata.calculated_duration().get::<second>(),
+            charge_uah: metadata.final_charge_raw_uah(),
+            energy_uwh: metadata.final_energy_raw_uwh(),
+        }
+    }
+}
+
+/// Averages samples into fixed-length buckets of device time.
+#[derive(Debug)]
+struct Decimator {
+    bucket_us: u64,
+    bucket: Option<u64>,
+    count: u32,
+    sums: [f64; 7],
+    last: Option<MeasurementSample>,
+}
+
+impl Decimator {
+    fn new(points_per_second: u32) -> Self {
+        Self {
+            bucket_us: 1_000_000 / u64::from(points_per_second.max(1)),
+            bucket: None,
+            count: 0,
+            sums: [0.0; 7],
+            last: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.bucket = None;
+        self.count = 0;
+        self.sums = [0.0; 7];
+        self.last = None;
+    }
+
+    /// Add a sample and return the previous bucket once `sample` starts a new one.
+    fn push(&mut self, sample: &MeasurementSample) -> Option<Point> {
+        let bucket = sample.elapsed_us / self.bucket_us;
+        let finished = match self.bucket {
+            Some(current) if current != bucket => self.point(),
+            _ => None,
+        };
+        if finished.is_some() {
+            self.count = 0;
+            self.sums = [0.0; 7];
+        }
+        self.bucket = Some(bucket);
+        self.count += 1;
+        let values = [
+            sample.vbus_uv,
+            sample.ibus_ua,
+            sample.power_uw,
+            sample.cc1_uv,
+            sample.cc2_uv,
+            sample.dp_uv,
+            sample.dm_uv,
+        ];
+        for (sum, value) in self.sums.iter_mut().zip(values) {
+            *sum += value as f64 / 1e6;
+        }
+        self.last = Some(*sample);
+        finished
+    }
+
+    fn point(&self) -> Option<Point> {
+        let (bucket, last) = (self.bucket?, self.last?);
+        let mean = |index: usize| self.sums[index] / f64::from(self.count);
+        Some(Point {
+            elapsed_s: (bucket * self.bucket_us) as f64 / 1e6,
+            vbus_v: mean(0),
+            ibus_a: mean(1),
+            power_w: mean(2),
+            cc1_v: mean(3),
+            cc2_v: mean(4),
+            dp_v: mean(5),
+            dm_v: mean(6),
+            charge_ah: last.charge_uah / 1e6,
+            energy_wh: last.energy_uwh / 1e6,
+            samples: self.count,
+            missing_samples: last.cumulative_missing_samples,
+        })
+    }
+}
+
+/// Requests that need exclusive use of the device.
+enum OfflineCommand {
+    Catalog(oneshot::Sender<Result<Vec<LogMetadata>, KMError>>),
+    Download(usize, oneshot::Sender<Result<Option<OfflineLog>, KMError>>),
+}
+
+impl OfflineCommand {
+    async fn run(self, device: &mut KM003C, app: &AppState) {
+        match self {
+            Self::Catalog(reply) => {
+                let _ = reply.send(device.request_log_metadata().await);
+            }
+            Self::Download(index, reply) => {
+                let result = match device.request_log_metadata().await {
+                    Ok(catalog) => match catalog.into_iter().nth(index) {
+                        Some(metadata) => download_log(device, index, metadata, app).await.map(Some),
+                        None => Ok(None),
+                    },
+                    Err(error) => Err(error),
+                };
+                let _ = reply.send(result);
+            }
+        }
+    }
+
+    fn reject(self) {
+        match self {
+            Self::Catalog(reply) => {
+                let _ = reply.send(Err(KMError::DeviceNotFound));
+            }
+            Self::Download(_, reply) => {
+                let _ = reply.send(Err(KMError::DeviceNotFound));
+            }
+        }
+    }
+}
+
+/// Download offline log `index` chunk by chunk, resuming after failed reads
+/// and reporting progress to WebSocket clients.
+async fn download_log(
+    device: &mut KM003C,
+    index: usize,
+    metadata: LogMetadata,
+    app: &AppState,
+) -> Result<OfflineLog, KMError> {
+    let mut download = OfflineLogDownload::new(metadata);
+    let total_samples = download.metadata().sample_count;
+    let mut attempt = 0;
+    loop {
+        let result = device
+            .continue_offline_log_download(&mut download, |progress| {
+                app.send(Event::OfflineProgress {
+                    index,
+                    received_samples: progress.received_samples(),
+                    total_samples,
+                });
+            })
+            .await;
+        match result {
+            Ok(()) => return download.finish(),
+            Err(error) if attempt < DOWNLOAD_RETRIES => {
+                attempt += 1;
+                warn!(
+                    "Offline read failed ({error}); resuming at sample {} (retry {attempt}/{DOWNLOAD_RETRIES})",
+                    download.progress().received_samples()
+                );
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+type ApiError = (StatusCode, String);
+
+#[derive(Clone)]
+struct AppState {
+    live: Arc<Mutex<Live>>,
+    events: broadcast::Sender<String>,
+    commands: mpsc::Sender<OfflineCommand>,
+    token: Option<Arc<str>>,
+}
+
+impl AppState {
+    fn state_message(&self) -> String {
+        let state = self.live.lock().unwrap().snapshot();
+        serde_json::to_string(&Event::Device { state }).expect("events serialize to JSON")
+    }
+
+    fn send(&self, event: Event) {
+        let message = serde_json::to_string(&event).expect("events serialize to JSON");
+        // Fails only while no client is listening.
+        let _ = self.events.send(message);
+    }
+
+    fn publish_state(&self) {
+        let _ = self.events.send(self.state_message());
+    }
+
+    /// Run an offline command on the device task and wait for its result.
+    async fn offline<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<T, KMError>>) -> OfflineCommand,
+    ) -> Result<T, ApiError> {
+        let unavailable = || (StatusCode::SERVICE_UNAVAILABLE, "device task stopped".to_string());
+        let (reply, response) = oneshot::channel();
+        self.commands.send(command(reply)).await.map_err(|_| unavailable())?;
+        response.await.map_err(|_| unavailable())?.map_err(|error| match error {
+            KMError::DeviceNotFound => (StatusCode::SERVICE_UNAVAILABLE, "device not connected".to_string()),
+            error => (StatusCode::BAD_GATEWAY, error.to_string()),
+        })
+    }
+}
+
+/// Keep the device open, reopening it whenever it is lost.
+async fn run_device(
+    config: DeviceConfig,
+    retry: Duration,
+    points_per_second: u32,
+    app: AppState,
+    mut commands: mpsc::Receiver<OfflineCommand>,
+) {
+    loop {
+        if let Err(error) = stream(config, points_per_second, &app, &mut commands).await {
+            warn!("Device unavailable: {error}");
+        }
+        {
+            let mut live = app.live.lock().unwrap();
+            live.connected = false;
+            live.pd = PolicyState::default();
+        }
+        app.publish_state();
+
+        let retry = tokio::time::sleep(retry);
+        tokio::pin!(retry);
+        loop {
+            tokio::select! {
+                _ = &mut retry => break,
+                Some(command) = commands.recv() => command.reject(),
+            }
+        }
+    }
+}
+
+async fn stream(
+    config: DeviceConfig,
+    points_per_second: u32,
+    app: &AppState,
+    commands: &mut mpsc::Receiver<OfflineCommand>,
+) -> Result<(), KMError> {
+    let mut device = DeviceStream::open(config).await?;
+    {
+        let mut live = app.live.lock().unwrap();
+        live.device = Some(device.state().clone());
+        live.connected = true;
+    }
+    app.publish_state();
+
+    let _ = device.device_mut().stop_graph_mode().await;
+    device.device_mut().enable_pd_monitor().await?;
+
+    let mut running = None;
+    let mut accumulator = MeasurementAccumulator::default();
+    let mut decimator = Decimator::new(points_per_second);
+    let mut decoder = PdSessionDecoder::new();
+    loop {
+        let requested = app.live.lock().unwrap().requested_capture();
+        if requested != running {
+            match requested {
+                Some(rate) => {
+                    info!("Capturing at {rate}");
+                    device.start(rate).await?;
+                    accumulator = MeasurementAccumulator::default();
+                    decimator.reset();
+                }
+                None => device.device_mut().stop_graph_mode().await?,
+            }
+            running = requested;
+        }
+
+        tokio::select! {
+            Some(command) = commands.recv() => {
+                // Offline transfers need the device out of graph mode; the
+                // capture restarts on the next iteration.
+                if running.take().is_some() {
+                    device.device_mut().stop_graph_mode().await?;
+                }
+                command.run(device.device_mut(), app).await;
+                continue;
+            }
+            _ = tokio::time::sleep(running.map_or(PD_POLL_INTERVAL, GraphSampleRate::poll_interval)) => {}
+        }
+
+        let mask = match running {
+            Some(_) => AttributeSet::single(Attribute::AdcQueue).with(Attribute::PdPacket),
+            None => AttributeSet::single(Attribute::PdPacket),
+        };
+        match device.request(mask).await? {
+            Ok(packet) => {
+                if let Some(rate) = running
+                    && let Some(queue) = packet.get_adc_queue()
+                {
+                    let points = queue
+                        .samples
+                        .iter()
+                        .filter_map(|sample| accumulator.push(*sample, rate))
+                        .filter_map(|measurement| decimator.push(&measurement))
+                        .collect::<Vec<_>>();
+                    if !points.is_empty() {
+                        app.send(Event::Samples { points });
+                    }
+                }
+                if let Some(stream) = packet.get_pd_events() {
+                    for event in &stream.events {
+                        app.send(format_event(&decoder.decode_event(event)).into());
+                    }
+                    let pd = *decoder.policy_state();
+                    let changed = std::mem::replace(&mut app.live.lock().unwrap().pd, pd) != pd;
+                    if changed {
+                        app.publish_state();
+                    }
+                }
+            }
+            Err(error) => warn!("Request failed: {error}"),
+        }
+    }
+}
+
+async fn device_state(State(app): State<AppState>) -> Json<StateSnapshot> {
+    Json(app.live.lock().unwrap().snapshot())
+}
+
+async fn start_capture(
+    State(app): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<StateSnapshot>, ApiError> {
+    let state = {
+        let mut live = app.live.lock().unwrap();
+        if let Some(rate) = params.get("rate") {
+            live.rate = parse_rate(rate).map_err(|error| (StatusCode::BAD_REQUEST, error))?;
+        }
+        live.capturing = true;
+        live.snapshot()
+    };
+    app.publish_state();
+    Ok(Json(state))
+}
+
+async fn stop_capture(State(app): State<AppState>) -> Json<StateSnapshot> {
+    let state = {
+        let mut live = app.live.lock().unwrap();
+        live.capturing = false;
+        live.snapshot()
+    };
+    app.publish_state();
+    Json(state)
+}
+
+async fn offline_catalog(State(app): State<AppState>) -> Result<Json<Vec<CatalogEntry>>, ApiError> {
+    let catalog = app.offline(OfflineCommand::Catalog).await?;
+    Ok(Json(
+        catalog
+            .iter()
+            .enumerate()
+            .map(|(index, metadata)| CatalogEntry::new(index, metadata))
+            .collect(),
+    ))
+}
+
+/// Offline log in the recording schema shared with `offline-log` and the GUI.
+fn encode_log(log: &OfflineLog, device: &DeviceInfo, format: RecordingFormat) -> Result<Vec<u8>, KMError> {
+    let mut bytes = Vec::new();
+    write_recording_to(
+        &mut bytes,
+        format,
+        &RecordingMetadata::offline(device, &log.metadata),
+        &RecordingRow::from_offline_log(log),
+    )?;
+    Ok(bytes)
+}
+
+/// Attachment name of a downloaded log, without the device's directories.
+fn download_filename(metadata: &LogMetadata, format: RecordingFormat) -> String {
+    let device_filename = metadata.filename_lossy();
+    let filename = FilePath::new(device_filename.as_ref())
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("offline-log")
+        .replace('"', "");
+    format!("{filename}.{}", format.extension())
+}
+
+async fn offline_download(
+    State(app): State<AppState>,
+    Path(index): Path<usize>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, ApiError> {
+    let format = match params.get("format").map(String::as_str) {
+        None | Some("parquet") => RecordingFormat::Parquet,
+        Some("csv") => RecordingFormat::Csv,
+        Some(other) => return Err((StatusCode::BAD_REQUEST, format!("unknown format {other}"))),
+    };
+    let log = app
+        .offline(|reply| OfflineCommand::Download(index, reply))
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, format!("no offline log {index}")))?;
+    let device = app.live.lock().unwrap().device.as_ref().map(|state| state.info.clone());
+    let bytes = encode_log(&log, &device.unwrap_or_default(), format)
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+    let content_type = match format {
+        RecordingFormat::Parquet => "application/vnd.apache.parquet",
+        RecordingFormat::Csv => "text/csv",
+    };
+    let disposition = format!("attachment; filename=\"{}\"", download_filename(&log.metadata, format));
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        bytes,
+    )
+        .into_response())
+}
+
+async fn websocket(ws: WebSocketUpgrade, State(app): State<AppState>) -> Response {
+    ws.on_upgrade(move |socket| stream_to_client(socket, app))
+}
+
+/// Send the device state, then every broadcast message until the client leaves.
+async fn stream_to_client(mut socket: WebSocket, app: AppState) {
+    let mut events = app.events.subscribe();
+    if socket.send(Message::Text(app.state_message().into())).await.is_err() {
+        return;
+    }
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(text) => {
+                    if socket.send(Message::Text(text.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("WebSocket client fell behind, skipped {skipped} messages");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Reject requests that do not carry the server's token, if it has one.
+///
+/// Browsers cannot set headers on WebSocket upgrades or download links, so
+/// the token is accepted as a `token` query parameter as well as a bearer
+/// `Authorization` header.
+async fn require_token(State(app): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(token) = app.token.as_deref() else {
+        return next.run(request).await;
+    };
+    let bearer = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(mut params)| params.remove("token"));
+    let matches = |candidate: Option<&str>| {
+        candidate.is_some_and(|candidate| bool::from(candidate.as_bytes().ct_eq(token.as_bytes())))
+    };
+    if matches(bearer) || matches(query.as_deref()) {
+        next.run(request).await
+    } else {
+        (StatusCode::UNAUTHORIZED, "missing or wrong token").into_response()
+    }
+}
+
+/// Reject requests that browsers send on behalf of other sites.
+///
+/// Browsers attach an `Origin` header to WebSocket upgrades and cross-origin
+/// requests, so pages elsewhere cannot control the meter or read its data
+/// through a visitor's browser. Requests without one, such as from scripts,
+/// pass.
+async fn require_same_origin(request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    if let Some(origin) = headers.get(ORIGIN) {
+        let origin_host = origin
+            .to_str()
+            .ok()
+            .and_then(|origin| origin.split_once("://"))
+            .map(|(_, host)| host);
+        let host = headers.get(HOST).and_then(|host| host.to_str().ok());
+        if origin_host.is_none() || origin_host != host {
+            return (StatusCode::FORBIDDEN, "cross-origin requests are not allowed").into_response();
+        }
+    }
+    next.run(request).await
+}
+
+fn router(app: AppState) -> Router {
+    Router::new()
+        .route("/ws", get(websocket))
+        .route("/api/device", get(device_state))
+        .route("/api/capture/start", post(start_capture))
+        .route("/api/capture/stop", post(stop_capture))
+        .route("/api/offline", get(offline_catalog))
+        .route("/api/offline/{index}", get(offline_download))
+        .route_layer(middleware::from_fn_with_state(app.clone(), require_token))
+        .route_layer(middleware::from_fn(require_same_origin))
+        .route("/", get(|| async { Html(DASHBOARD) }))
+        .with_state(app)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let log_level = if args.verbose {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::INFO
+    };
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let token = args.token.clone().or_else(|| std::env::var(TOKEN_ENV).ok());
+    if token.is_none() && !args.listen.ip().is_loopback() {
+        warn!(
+            "Serving without a token; anyone who can reach {} can control the meter",
+            args.listen
+        );
+    }
+
+    let config = args.device.config()?;
+    let (commands, command_rx) = mpsc::channel(8);
+    let app = AppState {
+        live: Arc::new(Mutex::new(Live::new(args.rate, !args.idle))),
+        events: broadcast::channel(EVENT_BUFFER).0,
+        commands,
+        token: token.map(Arc::from),
+    };
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    info!("Serving the dashboard on http://{}/", listener.local_addr()?);
+    tokio::spawn(run_device(
+        config,
+        Duration::from_secs(args.retry_interval),
+        args.points_per_second,
+        app.clone(),
+        command_rx,
+    ));
+    axum::serve(listener, router(app)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use km003c_lib::uom::si::electric_charge::microampere_hour;
+    use km003c_lib::uom::si::energy::microwatt_hour;
+    use km003c_lib::uom::si::f64::{ElectricCharge, ElectricCurrent, ElectricPotential, Energy, Power, Time};
+    use km003c_lib::uom::si::power::watt;
+    use km003c_lib::{AdcQueueSample, OfflineLogSampleRaw};
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn metadata(filename: &[u8]) -> LogMetadata {
+        let mut filename_raw = [0; 16];
+        filename_raw[..filename.len()].copy_from_slice(filename);
+        LogMetadata {
+            filename_raw,
+            unknown_0x10: 0,
+            sample_count: 2,
+            interval: Time::new::<second>(1.0),
+            flags: 0,
+            recorded_duration: Time::new::<second>(2.0),
+            final_charge: ElectricCharge::new::<microampere_hour>(0.0),
+            final_energy: Energy::new::<microwatt_hour>(0.0),
+            data_offset: 0,
+            reserved_tail: [0; 8],
+        }
+    }
+
+    /// Serve the API with a device task answering offline commands from `catalog`.
+    async fn serve(catalog: Vec<LogMetadata>, token: Option<&str>) -> (SocketAddr, AppState) {
+        let (commands, mut command_rx) = mpsc::channel(8);
+        let app = AppState {
+            live: Arc::new(Mutex::new(Live::new(GraphSampleRate::Sps50, false))),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            commands,
+            token: token.map(Arc::from),
+        };
+        tokio::spawn(async move {
+            while let Some(command) = command_rx.recv().await {
+                match command {
+                    OfflineCommand::Catalog(reply) => {
+                        let _ = reply.send(Ok(catalog.clone()));
+                    }
+                    OfflineCommand::Download(index, reply) => {
+                        let log = catalog.get(index).map(|metadata| OfflineLog {
+                            metadata: metadata.clone(),
+                            samples: [
+                                OfflineLogSampleRaw {
+                                    voltage_uv: 5_000_000,
+                                    current_ua: 1_000_000,
+                                    charge_uah: 0,
+                                    energy_uwh: 0,
+                                },
+                                OfflineLogSampleRaw {
+                                    voltage_uv: 5_100_000,
+                                    current_ua: 900_000,
+                                    charge_uah: 1,
+                                    energy_uwh: 5,
+                                },
+                            ]
+                            .into_iter()
+                            .map(OfflineLogSampleRaw::decode)
+                            .collect(),
+                        });
+                        let _ = reply.send(Ok(log));
+                    }
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = app.clone();
+        tokio::spawn(async move { axum::serve(listener, router(server)).await });
+        (address, app)
+    }
+
+    async fn request(address: SocketAddr, method: &str, path: &str) -> String {
+        request_with(address, method, path, "").await
+    }
+
+    /// Send a request with extra header lines, each ending in CRLF.
+    async fn request_with(address: SocketAddr, method: &str, path: &str, headers: &str) -> String {
+        let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+        let request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n{headers}Connection: close\r\n\r\n");
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    fn body(response: &str) -> Value {
+        serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap()
+    }
+
+    #[test]
+    fn averages_samples_into_points() {
+        let mut accumulator = MeasurementAccumulator::default();
+        let mut decimator = Decimator::new(10);
+        let points = (0..25u16)
+            .filter_map(|sequence| {
+                let sample = AdcQueueSample {
+                    sequence: sequence * 20,
+                    marker: 0,
+                    vbus: ElectricPotential::new::<volt>(if sequence < 5 { 5.0 } else { 9.0 }),
+                    ibus: ElectricCurrent::new::<ampere>(1.0),
+                    power: Power::new::<watt>(5.0),
+                    cc1: ElectricPotential::new::<volt>(1.65),
+                    cc2: ElectricPotential::new::<volt>(0.0),
+                    vdp: ElectricPotential::new::<volt>(0.6),
+                    vdm: ElectricPotential::new::<volt>(0.0),
+                };
+                accumulator.push(sample, GraphSampleRate::Sps50)
+            })
+            .filter_map(|measurement| decimator.push(&measurement))
+            .collect::<Vec<_>>();
+
+        // 50 SPS into 100 ms points: five samples each, the last one pending.
+        assert_eq!(points.len(), 4);
+        assert_eq!(points[0].elapsed_s, 0.0);
+        assert_eq!(points[0].vbus_v, 5.0);
+        assert_eq!(points[1].elapsed_s, 0.1);
+        assert_eq!(points[1].vbus_v, 9.0);
+        assert_eq!(points[1].samples, 5);
+        assert_eq!(points[1].ibus_a, 1.0);
+    }
+
+    #[tokio::test]
+    async fn starts_and_stops_captures() {
+        let (address, app) = serve(Vec::new(), None).await;
+        let mut events = app.events.subscribe();
+
+        let response = request(address, "POST", "/api/capture/start?rate=1000").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert_eq!(body(&response)["rate_sps"], 1000);
+        assert_eq!(body(&response)["capturing"], true);
+        assert_eq!(
+            app.live.lock().unwrap().requested_capture(),
+            Some(GraphSampleRate::Sps1000)
+        );
+        let message: Value = serde_json::from_str(&events.recv().await.unwrap()).unwrap();
+        assert_eq!(message["type"], "device");
+        assert_eq!(message["state"]["capturing"], true);
+
+        let response = request(address, "POST", "/api/capture/start?rate=7").await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+
+        let response = request(address, "POST", "/api/capture/stop").await;
+        assert_eq!(body(&response)["capturing"], false);
+        let response = request(address, "GET", "/api/device").await;
+        assert_eq!(body(&response)["connected"], false);
+        assert_eq!(body(&response)["device"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn lists_and_downloads_offline_logs() {
+        let (address, _) = serve(vec![metadata(b"logs/A01.d")], None).await;
+
+        let response = request(address, "GET", "/api/offline").await;
+        let catalog = body(&response);
+        assert_eq!(catalog[0]["filename"], "logs/A01.d");
+        assert_eq!(catalog[0]["sample_count"], 2);
+
+        let response = request(address, "GET", "/api/offline/0?format=csv").await;
+        assert!(response.contains("content-type: text/csv\r\n"), "{response}");
+        assert!(response.contains("content-disposition: attachment; filename=\"A01.d.csv\"\r\n"));
+        let csv = response.split_once("\r\n\r\n").unwrap().1;
+        assert!(csv.starts_with("elapsed_us,sample_index,"));
+        assert_eq!(csv.lines().count(), 3);
+
+        let response = request(address, "GET", "/api/offline/0").await;
+        assert!(response.contains("filename=\"A01.d.parquet\""));
+        assert!(response.split_once("\r\n\r\n").unwrap().1.starts_with("PAR1"));
+
+        assert!(
+            request(address, "GET", "/api/offline/1")
+                .await
+                .starts_with("HTTP/1.1 404")
+        );
+        assert!(
+            request(address, "GET", "/api/offline/0?format=xlsx")
+                .await
+                .starts_with("HTTP/1.1 400")
+        );
+    }
+
+    #[tokio::test]
+    async fn requires_the_token_when_configured() {
+        let (address, _) = serve(Vec::new(), Some("s3cret")).await;
+
+        assert!(request(address, "GET", "/").await.starts_with("HTTP/1.1 200 OK\r\n"));
+        for (method, path) in [("POST", "/api/capture/start"), ("GET", "/api/offline"), ("GET", "/ws")] {
+            let response = request(address, method, path).await;
+            assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{response}");
+        }
+        let response = request(address, "POST", "/api/capture/start?token=wrong").await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{response}");
+
+        let response = request(address, "POST", "/api/capture/start?rate=10&token=s3cret").await;
+        assert_eq!(body(&response)["rate_sps"], 10);
+
+        let response = request_with(address, "GET", "/api/device", "Authorization: Bearer s3cret\r\n").await;
+        assert_eq!(body(&response)["capturing"], true);
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_from_other_origins() {
+        let (address, _) = serve(Vec::new(), None).await;
+
+        for origin in ["http://evil.example", "http://localhost.evil.example", "null"] {
+            let headers = format!("Origin: {origin}\r\n");
+            for (method, path) in [("POST", "/api/capture/start"), ("GET", "/api/offline"), ("GET", "/ws")] {
+                let response = request_with(address, method, path, &headers).await;
+                assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{response}");
+            }
+        }
+        let response = request_with(address, "POST", "/api/capture/start", "Origin: http://localhost\r\n").await;
+        assert_eq!(body(&response)["capturing"], true);
+    }
+
+    #[tokio::test]
+    async fn streams_state_and_events_over_websocket() {
+        let (address, app) = serve(Vec::new(), None).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{address}/ws"))
+            .await
+            .unwrap();
+        let mut next = async || {
+            let message = socket.next().await.unwrap().unwrap();
+            serde_json::from_str::<Value>(message.to_text().unwrap()).unwrap()
+        };
+
+        let message = next().await;
+        assert_eq!(message["type"], "device");
+        assert_eq!(message["state"]["rate_sps"], 50);
+
+        app.send(Event::OfflineProgress {
+            index: 0,
+            received_samples: 512,
+            total_samples: 2_000,
+        });
+        let message = next().await;
+        assert_eq!(message["type"], "offline_progress");
+        assert_eq!(message["received_samples"], 512);
+    }
+}
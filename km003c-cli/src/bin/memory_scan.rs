@@ -1,6 +1,8 @@
 use clap::Parser;
-use km003c_lib::{DeviceConfig, KM003C, error::KMError};
+use km003c_cli::DeviceArgs;
+use km003c_lib::{KM003C, error::KMError};
 use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::time::Duration;
 
 /// Result of a memory read attempt
@@ -29,13 +31,12 @@ struct Args {
     #[arg(long)]
     full_scan: bool,
 
-    /// Skip USB reset (defaults to true on macOS for compatibility).
-    #[arg(long, default_value_t = cfg!(target_os = "macos"))]
-    no_reset: bool,
-
-    /// Force USB reset even on macOS (overrides --no-reset).
+    /// Write a backup image of every documented region and offline log to this path.
     #[arg(long)]
-    reset: bool,
+    backup: Option<PathBuf>,
+
+    #[command(flatten)]
+    device: DeviceArgs,
 }
 
 fn parse_address(value: &str) -> Result<u32, String> {
@@ -66,14 +67,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("KM003C Memory Scanner");
     println!("=====================\n");
 
-    let config = if args.no_reset && !args.reset {
-        DeviceConfig::vendor().skip_reset()
-    } else {
-        DeviceConfig::vendor()
-    };
-    let mut device = KM003C::new(config).await?;
+    let mut device = KM003C::new(args.device.config()?).await?;
 
-    let state = device.state().expect("device initialized");
+    let state = device
+        .state()
+        .ok_or_else(|| KMError::Protocol("vendor interface required".into()))?;
     println!("{}\n", state);
 
     if let Some(address) = args.address {
@@ -82,6 +80,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    if let Some(path) = args.backup {
+        let image = device.read_backup_image().await?;
+        image.save(&path)?;
+        println!("Wrote backup image to {}:", path.display());
+        for region in &image.regions {
+            let kind = format!("{:?}", region.kind);
+            println!(
+                "  0x{:08X} ({kind:16}): {} bytes, crc32 {:08x}",
+                region.address,
+                region.data.len(),
+                region.checksum()
+            );
+        }
+        println!("  {} offline logs", image.log_metadata()?.len());
+        return Ok(());
+    }
+
     // Track results by address
     let mut results: BTreeMap<u32, ReadResult> = BTreeMap::new();
 
@@ -0,0 +1,476 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::Router;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use clap::Parser;
+use km003c_cli::{DeviceArgs, parse_rate};
+use km003c_lib::error::KMError;
+use km003c_lib::uom::si::electric_current::ampere;
+use km003c_lib::uom::si::electric_potential::volt;
+use km003c_lib::uom::si::thermodynamic_temperature::degree_celsius;
+use km003c_lib::{
+    DeviceConfig, DeviceState, DeviceStream, GraphSampleRate, MeasurementAccumulator, MeasurementSample, PdContract,
+    PolicyTracker,
+    packet::{Attribute, AttributeSet},
+};
+use tracing::{info, warn};
+
+/// Content type of the Prometheus text exposition format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serve live KM003C measurements as Prometheus metrics
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Address to serve `/metrics` on
+    #[arg(short, long, default_value = "127.0.0.1:9435")]
+    listen: SocketAddr,
+
+    /// Sample rate: 2, 10, 50, or 1000 SPS
+    #[arg(short, long, default_value = "50", value_parser = parse_rate)]
+    rate: GraphSampleRate,
+
+    /// Seconds to wait before reconnecting to a lost or missing device
+    #[arg(long, default_value = "2")]
+    retry_interval: u64,
+
+    /// Verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    #[command(flatten)]
+    device: DeviceArgs,
+}
+
+/// Cumulative values of connections that have ended.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Totals {
+    samples: u64,
+    missing_samples: u64,
+    discarded_samples: u64,
+    charge_uah: f64,
+    energy_uwh: f64,
+}
+
+/// State shared between the streaming task and the HTTP server.
+#[derive(Debug, Default)]
+struct Metrics {
+    device: Option<DeviceState>,
+    connected: bool,
+    connections: u64,
+    previous: Totals,
+    latest: Option<MeasurementSample>,
+    temperature_celsius: Option<f64>,
+    pd_attached: bool,
+    contract: Option<PdContract>,
+    errors: BTreeMap<&'static str, u64>,
+}
+
+impl Metrics {
+    fn connected(&mut self, state: &DeviceState) {
+        self.device = Some(state.clone());
+        self.connected = true;
+        self.connections += 1;
+    }
+
+    /// Fold the connection's cumulative values into the totals, since the
+    /// next connection starts a new sample stream.
+    fn disconnected(&mut self) {
+        if let Some(latest) = self.latest.take() {
+            self.previous = self.totals(Some(&latest));
+        }
+        self.connected = false;
+        self.temperature_celsius = None;
+        self.pd_attached = false;
+        self.contract = None;
+    }
+
+    fn record_error(&mut self, error: &KMError) {
+        *self.errors.entry(error_kind(error)).or_default() += 1;
+    }
+
+    fn totals(&self, latest: Option<&MeasurementSample>) -> Totals {
+        let mut totals = self.previous;
+        if let Some(latest) = latest {
+            totals.samples += latest.sample_index + 1;
+            totals.missing_samples += latest.cumulative_missing_samples;
+            totals.discarded_samples += latest.cumulative_discarded_sequence_samples;
+            totals.charge_uah += latest.charge_uah;
+            totals.energy_uwh += latest.energy_uwh;
+        }
+        totals
+    }
+
+    /// Prometheus text exposition of the current state.
+    fn render(&self) -> String {
+        let labels = match &self.device {
+            Some(state) => format!("serial=\"{}\"", escape_label(&state.info.serial_id)),
+            None => String::new(),
+        };
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+            if samples.is_empty() {
+                return;
+            }
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (extra, value) in samples {
+                let labels = [labels.as_str(), extra.as_str()]
+                    .into_iter()
+                    .filter(|labels| !labels.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                if labels.is_empty() {
+                    let _ = writeln!(out, "{name} {value}");
+                } else {
+                    let _ = writeln!(out, "{name}{{{labels}}} {value}");
+                }
+            }
+        };
+        let value = |value: f64| vec![(String::new(), value)];
+        let optional = |value: Option<f64>| {
+            value
+                .map(|value| (String::new(), value))
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        metric(
+            "km003c_up",
+            "gauge",
+            "Whether the device is connected and streaming.",
+            &value(f64::from(u8::from(self.connected))),
+        );
+        if let Some(state) = &self.device {
+            let info = format!(
+                "model=\"{}\",hw_version=\"{}\",fw_version=\"{}\"",
+                escape_label(state.model()),
+                escape_label(&state.info.hw_version),
+                escape_label(state.firmware_version())
+            );
+            metric("km003c_device_info", "gauge", "Device identification.", &[(info, 1.0)]);
+        }
+        metric(
+            "km003c_connections_total",
+            "counter",
+            "Successful connections to the device.",
+            &value(self.connections as f64),
+        );
+
+        let latest = self.latest.as_ref();
+        let volts = |microvolts: fn(&MeasurementSample) -> i64| optional(latest.map(|s| microvolts(s) as f64 / 1e6));
+        metric("km003c_vbus_volts", "gauge", "VBUS voltage.", &volts(|s| s.vbus_uv));
+        metric(
+            "km003c_ibus_amperes",
+            "gauge",
+            "IBUS current; negative when power flows from the male to the female port.",
+            &optional(latest.map(|s| s.ibus_ua as f64 / 1e6)),
+        );
+        metric(
+            "km003c_power_watts",
+            "gauge",
+            "VBUS power.",
+            &optional(latest.map(|s| s.power_uw as f64 / 1e6)),
+        );
+        metric("km003c_cc1_volts", "gauge", "CC1 voltage.", &volts(|s| s.cc1_uv));
+        metric("km003c_cc2_volts", "gauge", "CC2 voltage.", &volts(|s| s.cc2_uv));
+        metric("km003c_dp_volts", "gauge", "D+ voltage.", &volts(|s| s.dp_uv));
+        metric("km003c_dm_volts", "gauge", "D- voltage.", &volts(|s| s.dm_uv));
+        metric(
+            "km003c_temperature_celsius",
+            "gauge",
+            "Meter temperature.",
+            &optional(self.temperature_celsius),
+        );
+
+        let totals = self.totals(latest);
+        metric(
+            "km003c_charge_ampere_hours",
+            "gauge",
+            "Net charge since the exporter started.",
+            &value(totals.charge_uah / 1e6),
+        );
+        metric(
+            "km003c_energy_watt_hours",
+            "gauge",
+            "Net energy since the exporter started.",
+            &value(totals.energy_uwh / 1e6),
+        );
+        metric(
+            "km003c_samples_total",
+            "counter",
+            "AdcQueue samples received.",
+            &value(totals.samples as f64),
+        );
+        metric(
+            "km003c_missing_samples_total",
+            "counter",
+            "AdcQueue samples lost in sequence gaps.",
+            &value(totals.missing_samples as f64),
+        );
+        metric(
+            "km003c_discarded_samples_total",
+            "counter",
+            "Duplicate or out-of-order AdcQueue samples discarded.",
+            &value(totals.discarded_samples as f64),
+        );
+
+        if self.connected {
+            metric(
+                "km003c_pd_attached",
+                "gauge",
+                "Whether a USB PD connection is attached.",
+                &value(f64::from(u8::from(self.pd_attached))),
+            );
+        }
+        let contract = self.contract.as_ref();
+        metric(
+            "km003c_pd_contract_voltage_volts",
+            "gauge",
+            "Voltage of the explicit PD contract.",
+            &optional(
+                contract
+                    .and_then(|contract| contract.voltage)
+                    .map(|voltage| voltage.get::<volt>()),
+            ),
+        );
+        metric(
+            "km003c_pd_contract_current_amperes",
+            "gauge",
+            "Operating current of the explicit PD contract.",
+            &optional(
+                contract
+                    .and_then(|contract| contract.current)
+                    .map(|current| current.get::<ampere>()),
+            ),
+        );
+
+        let errors = self
+            .errors
+            .iter()
+            .map(|(kind, count)| (format!("kind=\"{kind}\""), *count as f64))
+            .collect::<Vec<_>>();
+        metric(
+            "km003c_usb_errors_total",
+            "counter",
+            "Failed device operations by error kind.",
+            &errors,
+        );
+        out
+    }
+}
+
+fn error_kind(error: &KMError) -> &'static str {
+    match error {
+        KMError::DeviceNotFound => "not_found",
+        KMError::Usb(_) => "usb",
+        KMError::Io(_) => "io",
+        KMError::Timeout(_) => "timeout",
+        KMError::Protocol(_) => "protocol",
+        _ => "packet",
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Keep the device streaming, reopening it whenever it is lost.
+async fn run_device(config: DeviceConfig, rate: GraphSampleRate, retry: Duration, metrics: Arc<Mutex<Metrics>>) {
+    loop {
+        if let Err(error) = stream(config, rate, &metrics).await {
+            warn!("Device unavailable: {error}");
+            metrics.lock().unwrap().record_error(&error);
+        }
+        metrics.lock().unwrap().disconnected();
+        tokio::time::sleep(retry).await;
+    }
+}
+
+async fn stream(config: DeviceConfig, rate: GraphSampleRate, metrics: &Mutex<Metrics>) -> Result<(), KMError> {
+    let mut device = DeviceStream::open(config).await?;
+    metrics.lock().unwrap().connected(device.state());
+    device.start(rate).await?;
+
+    let mask = AttributeSet::single(Attribute::AdcQueue)
+        .with(Attribute::Adc)
+        .with(Attribute::PdPacket);
+    let mut accumulator = MeasurementAccumulator::default();
+    let mut policy = PolicyTracker::new();
+    loop {
+        match device.request(mask).await? {
+            Ok(packet) => {
+                let mut metrics = metrics.lock().unwrap();
+                if let Some(queue) = packet.get_adc_queue() {
+                    for sample in &queue.samples {
+                        if let Some(measurement) = accumulator.push(*sample, rate) {
+                            metrics.latest = Some(measurement);
+                        }
+                    }
+                }
+                if let Some(adc) = packet.get_adc() {
+                    metrics.temperature_celsius = Some(adc.temperature.get::<degree_celsius>());
+                }
+                if let Some(stream) = packet.get_pd_events() {
+                    for event in &stream.events {
+                        if let Some(change) = policy.process_event(event).last() {
+                            metrics.pd_attached = change.state.attached;
+                            metrics.contract = change.state.contract;
+                        }
+                    }
+                }
+            }
+            Err(error) => metrics.lock().unwrap().record_error(&error),
+        }
+        tokio::time::sleep(rate.poll_interval()).await;
+    }
+}
+
+async fn serve_metrics(State(metrics): State<Arc<Mutex<Metrics>>>) -> impl IntoResponse {
+    let body = metrics.lock().unwrap().render();
+    ([(CONTENT_TYPE, METRICS_CONTENT_TYPE)], body)
+}
+
+fn router(metrics: Arc<Mutex<Metrics>>) -> Router {
+    Router::new().route("/metrics", get(serve_metrics)).with_state(metrics)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let log_level = if args.verbose {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::INFO
+    };
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let config = args.device.config()?;
+
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    info!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+    tokio::spawn(run_device(
+        config,
+        args.rate,
+        Duration::from_secs(args.retry_interval),
+        metrics.clone(),
+    ));
+    axum::serve(listener, router(metrics)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use km003c_lib::uom::si::f64::{ElectricCurrent, ElectricPotential, Power};
+    use km003c_lib::uom::si::power::watt;
+    use km003c_lib::{AdcQueueSample, DeviceInfo, HardwareId};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn device_state(serial: &str) -> DeviceState {
+        DeviceState {
+            info: DeviceInfo {
+                model: "KM003C".to_string(),
+                hw_version: "2.1".to_string(),
+                fw_version: "1.9.9".to_string(),
+                serial_id: serial.to_string(),
+                ..DeviceInfo::default()
+            },
+            hardware_id: HardwareId::from_bytes([0; 12]),
+            auth_level: 2,
+            adcqueue_enabled: true,
+        }
+    }
+
+    fn stream(metrics: &mut Metrics, sequences: impl IntoIterator<Item = u16>) {
+        let mut accumulator = MeasurementAccumulator::default();
+        for sequence in sequences {
+            let sample = AdcQueueSample {
+                sequence,
+                marker: 0,
+                vbus: ElectricPotential::new::<volt>(20.0),
+                ibus: ElectricCurrent::new::<ampere>(3.0),
+                power: Power::new::<watt>(60.0),
+                cc1: ElectricPotential::new::<volt>(1.65),
+                cc2: ElectricPotential::new::<volt>(0.0),
+                vdp: ElectricPotential::new::<volt>(0.6),
+                vdm: ElectricPotential::new::<volt>(0.0),
+            };
+            if let Some(measurement) = accumulator.push(sample, GraphSampleRate::Sps1000) {
+                metrics.latest = Some(measurement);
+            }
+        }
+    }
+
+    #[test]
+    fn renders_measurements_labelled_by_serial() {
+        let mut metrics = Metrics::default();
+        assert!(metrics.render().contains("\nkm003c_up 0\n"));
+
+        metrics.connected(&device_state("007965"));
+        stream(&mut metrics, [0, 1, 2, 5]);
+        metrics.temperature_celsius = Some(31.5);
+        metrics.record_error(&KMError::Protocol("unexpected response".to_string()));
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE km003c_vbus_volts gauge\nkm003c_vbus_volts{serial=\"007965\"} 20\n"));
+        assert!(text.contains("km003c_ibus_amperes{serial=\"007965\"} 3\n"));
+        assert!(text.contains("km003c_temperature_celsius{serial=\"007965\"} 31.5\n"));
+        assert!(text.contains("km003c_missing_samples_total{serial=\"007965\"} 2\n"));
+        assert!(text.contains("km003c_usb_errors_total{serial=\"007965\",kind=\"protocol\"} 1\n"));
+        assert!(text.contains("km003c_device_info{serial=\"007965\",model=\"KM003C\",hw_version=\"2.1\""));
+        assert!(!text.contains("km003c_pd_contract_voltage_volts"));
+    }
+
+    #[test]
+    fn keeps_cumulative_values_across_reconnects() {
+        let mut metrics = Metrics::default();
+        metrics.connected(&device_state("007965"));
+        stream(&mut metrics, 0..=3_600);
+        metrics.disconnected();
+
+        let text = metrics.render();
+        assert!(text.contains("km003c_up{serial=\"007965\"} 0\n"));
+        assert!(!text.contains("km003c_vbus_volts"));
+        assert!(text.contains("km003c_samples_total{serial=\"007965\"} 3601\n"));
+
+        metrics.connected(&device_state("007965"));
+        stream(&mut metrics, 100..=3_700);
+        let text = metrics.render();
+        assert!(text.contains("km003c_connections_total{serial=\"007965\"} 2\n"));
+        assert!(text.contains("km003c_samples_total{serial=\"007965\"} 7202\n"));
+        // 3 A for two 3.6 s stretches.
+        assert!(text.contains("km003c_charge_ampere_hours{serial=\"007965\"} 0.006\n"));
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_to_a_scraper() {
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        metrics.lock().unwrap().connected(&device_state("a\"b"));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(metrics)).await });
+
+        let mut scraper = tokio::net::TcpStream::connect(address).await.unwrap();
+        scraper
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        scraper.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(METRICS_CONTENT_TYPE));
+        assert!(response.contains("km003c_up{serial=\"a\\\"b\"} 1\n"));
+    }
+}
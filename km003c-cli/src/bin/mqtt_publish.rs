@@ -0,0 +1,236 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Parser;
+use km003c_cli::archive::{Archive, DeviceRecord};
+use km003c_cli::{DeviceArgs, parse_rate};
+use km003c_lib::error::KMError;
+use km003c_lib::recording::{RecordingFormat, RecordingMetadata, RecordingRow, write_recording};
+use km003c_lib::rumqttc::QoS;
+use km003c_lib::{
+    DeviceConfig, DeviceStream, GraphSampleRate, MqttConfig, MqttPublisher,
+    packet::{Attribute, AttributeSet},
+};
+use tracing::{info, warn};
+
+/// Extension of the Parquet files offline logs are archived as.
+const OFFLINE_LOG_EXTENSION: &str = "parquet";
+
+/// Publish live KM003C measurements and PD events to an MQTT broker
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Broker host name or address
+    #[arg(short, long, default_value = "localhost")]
+    broker: String,
+
+    /// Broker port
+    #[arg(short, long, default_value_t = km003c_lib::mqtt::MQTT_DEFAULT_PORT)]
+    port: u16,
+
+    /// Topic prefix; messages go to PREFIX/SERIAL/status, .../measurement and other fixed names
+    #[arg(long, default_value = "km003c")]
+    prefix: String,
+
+    /// MQTT client id (defaults to km003c-PID)
+    #[arg(long)]
+    client_id: Option<String>,
+
+    /// Quality of service: 0, 1, or 2
+    #[arg(long, default_value = "1", value_parser = ["0", "1", "2"])]
+    qos: String,
+
+    /// Milliseconds of samples averaged into each measurement message
+    #[arg(long, default_value = "1000")]
+    interval_ms: u64,
+
+    /// Sample rate: 2, 10, 50, or 1000 SPS
+    #[arg(short, long, default_value = "50", value_parser = parse_rate)]
+    rate: GraphSampleRate,
+
+    /// Download new offline logs into DIR as Parquet on connect and announce them;
+    /// DIR keeps the same manifest as `offline-log export-all`
+    #[arg(long, value_name = "DIR")]
+    offline_dir: Option<PathBuf>,
+
+    /// Seconds to wait before reconnecting to a lost or missing device
+    #[arg(long, default_value = "2")]
+    retry_interval: u64,
+
+    /// Verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    #[command(flatten)]
+    device: DeviceArgs,
+}
+
+fn parse_qos(qos: &str) -> QoS {
+    match qos {
+        "0" => QoS::AtMostOnce,
+        "1" => QoS::AtLeastOnce,
+        "2" => QoS::ExactlyOnce,
+        _ => unreachable!(),
+    }
+}
+
+/// Save offline logs not yet archived in `dir` and announce each one.
+async fn download_offline_logs(
+    device: &mut DeviceStream,
+    dir: &Path,
+    publisher: &mut MqttPublisher,
+) -> Result<(), KMError> {
+    let state = device.state().clone();
+    let mut archive = Archive::open(dir, DeviceRecord::new(&state.info, Some(&state.hardware_id)))?;
+    let catalog = device.device_mut().request_log_metadata().await?;
+    for (index, metadata) in catalog.into_iter().enumerate() {
+        if archive.unchanged(&metadata, OFFLINE_LOG_EXTENSION).is_some() {
+            continue;
+        }
+        let file = archive.export_filename(&metadata, OFFLINE_LOG_EXTENSION);
+        info!("Downloading offline log {}", metadata.filename_lossy());
+        let log = device.device_mut().download_offline_log(metadata).await?;
+        let recording = RecordingMetadata::offline(&state.info, &log.metadata);
+        let path = write_recording(
+            archive.dir().join(&file),
+            RecordingFormat::Parquet,
+            &recording,
+            &RecordingRow::from_offline_log(&log),
+        )?;
+        archive.insert(&file, OFFLINE_LOG_EXTENSION, index, &log)?;
+        info!("Saved offline log to {}", path.display());
+        publisher.publish_offline_log(&log, Some(&path));
+    }
+    Ok(())
+}
+
+/// Stream one device connection until it fails.
+async fn stream(
+    device_config: DeviceConfig,
+    mqtt_config: &MqttConfig,
+    rate: GraphSampleRate,
+    offline_dir: Option<&Path>,
+    publisher: &mut Option<(String, MqttPublisher)>,
+) -> Result<(), KMError> {
+    let mut device = DeviceStream::open(device_config).await?;
+    let state = device.state();
+
+    // Keep the broker session while the same meter reconnects.
+    let publisher = match publisher {
+        Some((serial, publisher)) if *serial == state.info.serial_id => {
+            publisher.encoder_mut().restart_stream();
+            publisher.set_online(true);
+            publisher
+        }
+        _ => {
+            let publisher =
+                publisher.insert((state.info.serial_id.clone(), MqttPublisher::connect(mqtt_config, state)));
+            &mut publisher.1
+        }
+    };
+
+    if let Some(dir) = offline_dir {
+        let _ = device.device_mut().stop_graph_mode().await;
+        download_offline_logs(&mut device, dir, publisher).await?;
+    }
+
+    device.start(rate).await?;
+
+    let mask = AttributeSet::single(Attribute::AdcQueue).with(Attribute::PdPacket);
+    loop {
+        match device.request(mask).await? {
+            Ok(packet) => publisher.publish_packet(&packet, rate),
+            Err(error) => warn!("Request failed: {error}"),
+        }
+        tokio::time::sleep(rate.poll_interval()).await;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let log_level = if args.verbose {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::INFO
+    };
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let device_config = args.device.config()?;
+
+    let mut mqtt_config = MqttConfig::new(&args.broker);
+    mqtt_config.port = args.port;
+    mqtt_config.topic_prefix = args.prefix;
+    mqtt_config.qos = parse_qos(&args.qos);
+    mqtt_config.measurement_interval = Duration::from_millis(args.interval_ms);
+    if let Some(client_id) = args.client_id {
+        mqtt_config.client_id = client_id;
+    }
+    if let Some(dir) = &args.offline_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    info!(
+        "Publishing to mqtt://{}:{}/{}",
+        mqtt_config.host, mqtt_config.port, mqtt_config.topic_prefix
+    );
+
+    let retry = Duration::from_secs(args.retry_interval);
+    let mut publisher = None;
+    let device_loop = async {
+        loop {
+            if let Err(error) = stream(
+                device_config,
+                &mqtt_config,
+                args.rate,
+                args.offline_dir.as_deref(),
+                &mut publisher,
+            )
+            .await
+            {
+                warn!("Device unavailable: {error}");
+            }
+            if let Some((_, publisher)) = &mut publisher {
+                publisher.set_online(false);
+            }
+            tokio::time::sleep(retry).await;
+        }
+    };
+    tokio::select! {
+        _ = device_loop => {}
+        _ = tokio::signal::ctrl_c() => info!("Stopping"),
+    }
+
+    if let Some((_, publisher)) = publisher {
+        if publisher.dropped() > 0 {
+            warn!(
+                "{} messages were dropped while the broker was unreachable",
+                publisher.dropped()
+            );
+        }
+        publisher.disconnect().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_qos_level() {
+        let args = Args::parse_from(["mqtt-publish", "--qos", "2"]);
+        assert_eq!(parse_qos(&args.qos), QoS::ExactlyOnce);
+        assert_eq!(parse_qos("0"), QoS::AtMostOnce);
+        assert!(Args::try_parse_from(["mqtt-publish", "--qos", "3"]).is_err());
+    }
+
+    #[test]
+    fn parses_sample_rates_in_samples_per_second() {
+        assert_eq!(Args::parse_from(["mqtt-publish"]).rate, GraphSampleRate::Sps50);
+        let args = Args::parse_from(["mqtt-publish", "--rate", "1000"]);
+        assert_eq!(args.rate, GraphSampleRate::Sps1000);
+        assert!(Args::try_parse_from(["mqtt-publish", "--rate", "100"]).is_err());
+    }
+}
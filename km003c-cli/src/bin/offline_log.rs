@@ -1,17 +1,17 @@
 use std::error::Error;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 
 use clap::{Parser, Subcommand, ValueEnum};
-use km003c_lib::uom::si::electric_charge::milliampere_hour;
-use km003c_lib::uom::si::electric_current::ampere;
-use km003c_lib::uom::si::electric_potential::volt;
-use km003c_lib::uom::si::energy::milliwatt_hour;
+use km003c_cli::DeviceArgs;
+use km003c_cli::archive::{Archive, DeviceRecord, MetadataRecord, default_filename};
+use km003c_lib::offline::OFFLINE_DOWNLOAD_CHUNK_SIZE;
+use km003c_lib::recording::{RecordingFormat, RecordingMetadata, RecordingRow, partial_path, write_recording};
 use km003c_lib::uom::si::power::watt;
 use km003c_lib::uom::si::time::{millisecond, second};
-use km003c_lib::{DeviceConfig, KM003C, LogMetadata, OfflineLog};
-use serde_json::json;
+use km003c_lib::{BackupImage, DeviceInfo, HardwareId, KM003C, LogMetadata, OfflineLog, OfflineLogDownload};
+use serde::Serialize;
 
 /// Inspect or download the selected offline recording from a POWER-Z KM003C.
 #[derive(Debug, Parser)]
@@ -28,13 +28,12 @@ struct Args {
     #[arg(short, long, global = true)]
     verbose: bool,
 
-    /// Skip USB reset (defaults to true on macOS for compatibility).
-    #[arg(long, default_value_t = cfg!(target_os = "macos"), global = true)]
-    no_reset: bool,
+    #[command(flatten)]
+    device: DeviceArgs,
 
-    /// Force USB reset even on macOS (overrides --no-reset).
+    /// Read recordings from a backup image written by `memory_scan --backup` instead of the device.
     #[arg(long, global = true)]
-    reset: bool,
+    image: Option<PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -55,15 +54,54 @@ enum Command {
         #[arg(short, long, value_enum, default_value_t = OutputFormat::Csv)]
         format: OutputFormat,
 
-        /// Output path. Defaults to `<device-filename>.csv` or `.json`.
+        /// Output path. Defaults to `<device-filename>.csv`, `.parquet` or `.json`.
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        /// Bytes read per memory request.
+        #[arg(long, default_value_t = OFFLINE_DOWNLOAD_CHUNK_SIZE)]
+        chunk_size: u32,
+
+        /// Times to resume from the last good chunk after a failed read.
+        #[arg(long, default_value_t = 3)]
+        retries: u32,
+    },
+    /// Download every stored recording into a directory with a manifest.
+    ///
+    /// Recordings whose metadata and exported file are unchanged since the
+    /// last export are skipped.
+    ExportAll {
+        /// Output directory; created if it does not exist.
+        #[arg(short, long, default_value = ".")]
+        dir: PathBuf,
+
+        /// Output format.
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Csv)]
+        format: OutputFormat,
+
+        /// Only export recordings whose device filename matches this pattern (`*` and `?` wildcards).
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// Export recordings again even when the manifest shows them unchanged.
+        #[arg(long)]
+        force: bool,
+
+        /// Bytes read per memory request.
+        #[arg(long, default_value_t = OFFLINE_DOWNLOAD_CHUNK_SIZE)]
+        chunk_size: u32,
+
+        /// Times to resume from the last good chunk after a failed read.
+        #[arg(long, default_value_t = 3)]
+        retries: u32,
     },
 }
 
+/// Output formats; CSV and Parquet use the recording schema shared with the GUI.
 #[derive(Debug, Clone, Copy, ValueEnum)]
 enum OutputFormat {
     Csv,
+    Parquet,
     Json,
 }
 
@@ -71,9 +109,92 @@ impl OutputFormat {
     const fn extension(self) -> &'static str {
         match self {
             Self::Csv => "csv",
+            Self::Parquet => "parquet",
             Self::Json => "json",
         }
     }
+
+    const fn recording_format(self) -> Option<RecordingFormat> {
+        match self {
+            Self::Csv => Some(RecordingFormat::Csv),
+            Self::Parquet => Some(RecordingFormat::Parquet),
+            Self::Json => None,
+        }
+    }
+}
+
+/// Where recordings are read from.
+enum LogSource {
+    Device(Box<KM003C>),
+    Image(BackupImage),
+}
+
+impl LogSource {
+    async fn log_metadata(&mut self) -> Result<Vec<LogMetadata>, Box<dyn Error>> {
+        match self {
+            Self::Device(device) => Ok(device.request_log_metadata().await?),
+            Self::Image(image) => Ok(image.log_metadata()?),
+        }
+    }
+
+    async fn download(
+        &mut self,
+        index: usize,
+        download: OfflineLogDownload,
+        retries: u32,
+    ) -> Result<OfflineLog, Box<dyn Error>> {
+        match self {
+            Self::Device(device) => download_with_retries(device, download, retries).await,
+            Self::Image(image) => Ok(image.offline_logs()?.swap_remove(index)),
+        }
+    }
+
+    fn device_info(&self) -> (DeviceInfo, Option<HardwareId>) {
+        match self {
+            Self::Device(device) => match device.state() {
+                Some(state) => (state.info.clone(), Some(state.hardware_id.clone())),
+                None => (DeviceInfo::default(), None),
+            },
+            Self::Image(image) => (image.device_info(), image.hardware_id()),
+        }
+    }
+}
+
+async fn download_with_retries(
+    device: &mut KM003C,
+    mut download: OfflineLogDownload,
+    retries: u32,
+) -> Result<OfflineLog, Box<dyn Error>> {
+    let show_progress = std::io::stderr().is_terminal();
+    let total_samples = download.metadata().sample_count;
+    let mut attempt = 0;
+    loop {
+        let result = device
+            .continue_offline_log_download(&mut download, |progress| {
+                if show_progress {
+                    eprint!(
+                        "\rDownloading: {}/{total_samples} samples ({:.0}%)",
+                        progress.received_samples(),
+                        progress.fraction() * 100.0
+                    );
+                }
+            })
+            .await;
+        if show_progress {
+            eprintln!();
+        }
+        match result {
+            Ok(()) => return Ok(download.finish()?),
+            Err(error) if attempt < retries => {
+                attempt += 1;
+                eprintln!(
+                    "Read failed ({error}); resuming at sample {} (retry {attempt}/{retries})",
+                    download.progress().received_samples()
+                );
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
 }
 
 #[tokio::main]
@@ -86,23 +207,28 @@ async fn main() -> Result<(), Box<dyn Error>> {
     };
     tracing_subscriber::fmt().with_max_level(log_level).init();
 
-    let mut config = DeviceConfig::vendor();
-    if args.no_reset && !args.reset {
-        config = config.skip_reset();
-    }
-    let mut device = KM003C::new(config).await?;
+    let mut source = match &args.image {
+        Some(path) => LogSource::Image(BackupImage::load(path)?),
+        None => LogSource::Device(Box::new(KM003C::new(args.device.config()?).await?)),
+    };
 
     match args.command {
         Command::Metadata { json } => {
-            let metadata = device.request_log_metadata().await?;
+            let metadata = source.log_metadata().await?;
             if metadata.is_empty() {
                 println!("No offline logs are stored on the device.");
                 return Ok(());
             }
             print_metadata_list(&metadata, json)?;
         }
-        Command::Download { index, format, output } => {
-            let metadata = device.request_log_metadata().await?;
+        Command::Download {
+            index,
+            format,
+            output,
+            chunk_size,
+            retries,
+        } => {
+            let metadata = source.log_metadata().await?;
             if metadata.is_empty() {
                 println!("No offline logs are stored on the device.");
                 return Ok(());
@@ -114,36 +240,60 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 )
             })?;
             let path = output.unwrap_or_else(|| default_output_path(&metadata, format));
-            let log = device.download_offline_log(metadata).await?;
-            write_log(&path, format, &log)?;
+            let download = OfflineLogDownload::new(metadata).with_chunk_size(chunk_size);
+            let log = source.download(index, download, retries).await?;
+            write_log(&path, format, &log, &source.device_info().0)?;
             println!("Wrote {} samples to {}", log.samples.len(), path.display());
         }
+        Command::ExportAll {
+            dir,
+            format,
+            filter,
+            force,
+            chunk_size,
+            retries,
+        } => {
+            let (device_info, hardware_id) = source.device_info();
+            let mut archive = Archive::open(&dir, DeviceRecord::new(&device_info, hardware_id.as_ref()))?;
+            let catalog = source.log_metadata().await?;
+            let (mut exported, mut skipped) = (0, 0);
+            for (index, metadata) in catalog.into_iter().enumerate() {
+                let filename = metadata.filename_lossy().into_owned();
+                if filter
+                    .as_deref()
+                    .is_some_and(|pattern| !matches_pattern(pattern, &filename))
+                {
+                    continue;
+                }
+                if !force && let Some(entry) = archive.unchanged(&metadata, format.extension()) {
+                    println!("Skipping {filename}: unchanged in {}", entry.file);
+                    skipped += 1;
+                    continue;
+                }
+
+                let file = archive.export_filename(&metadata, format.extension());
+                let path = archive.dir().join(&file);
+                println!("Exporting #{index} {filename} to {}", path.display());
+                let download = OfflineLogDownload::new(metadata).with_chunk_size(chunk_size);
+                let log = source.download(index, download, retries).await?;
+                write_log(&path, format, &log, &device_info)?;
+                archive.insert(&file, format.extension(), index, &log)?;
+                exported += 1;
+            }
+            archive.save()?;
+            println!(
+                "Exported {exported} and skipped {skipped} unchanged recordings; manifest at {}",
+                archive.manifest_path().display()
+            );
+        }
     }
 
     Ok(())
 }
 
-fn metadata_json(metadata: &LogMetadata) -> serde_json::Value {
-    json!({
-        "filename": metadata.filename_lossy(),
-        "filename_raw": metadata.filename_raw,
-        "sample_count": metadata.sample_count,
-        "interval_ms": metadata.interval.get::<millisecond>(),
-        "flags": metadata.flags,
-        "recorded_duration_seconds": metadata.recorded_duration.get::<second>(),
-        "calculated_duration_seconds": metadata.calculated_duration().get::<second>(),
-        "unknown_0x10": metadata.unknown_0x10,
-        "final_charge_uah": metadata.final_charge_raw_uah(),
-        "final_energy_uwh": metadata.final_energy_raw_uwh(),
-        "data_offset": metadata.data_offset,
-        "data_address": metadata.data_address().ok(),
-        "reserved_tail": hex::encode(metadata.reserved_tail),
-    })
-}
-
 fn print_metadata(metadata: &LogMetadata, as_json: bool) -> Result<(), Box<dyn Error>> {
     if as_json {
-        println!("{}", serde_json::to_string_pretty(&metadata_json(metadata))?);
+        println!("{}", serde_json::to_string_pretty(&MetadataRecord::from(metadata))?);
     } else {
         println!("Filename:            {}", metadata.filename_lossy());
         println!("Samples:             {}", metadata.sample_count);
@@ -167,7 +317,7 @@ fn print_metadata(metadata: &LogMetadata, as_json: bool) -> Result<(), Box<dyn E
 
 fn print_metadata_list(metadata: &[LogMetadata], as_json: bool) -> Result<(), Box<dyn Error>> {
     if as_json {
-        let entries = metadata.iter().map(metadata_json).collect::<Vec<_>>();
+        let entries = metadata.iter().map(MetadataRecord::from).collect::<Vec<_>>();
         println!("{}", serde_json::to_string_pretty(&entries)?);
     } else {
         for (index, entry) in metadata.iter().enumerate() {
@@ -182,50 +332,72 @@ fn print_metadata_list(metadata: &[LogMetadata], as_json: bool) -> Result<(), Bo
 }
 
 fn default_output_path(metadata: &LogMetadata, format: OutputFormat) -> PathBuf {
-    let device_filename = metadata.filename_lossy();
-    let filename = Path::new(device_filename.as_ref())
-        .file_name()
-        .and_then(|name| name.to_str())
-        .filter(|name| !name.is_empty())
-        .unwrap_or("offline-log");
-    PathBuf::from(format!("{filename}.{}", format.extension()))
+    PathBuf::from(default_filename(metadata, format.extension()))
+}
+
+/// Match `name` against a pattern where `*` matches any run of characters and `?` one character.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
-fn write_log(path: &Path, format: OutputFormat, log: &OfflineLog) -> Result<(), Box<dyn Error>> {
-    let file = File::create(path)?;
-    let mut writer = BufWriter::new(file);
-    match format {
-        OutputFormat::Csv => write_csv(&mut writer, log)?,
-        OutputFormat::Json => write_json(&mut writer, log)?,
+/// Write `log` to `<path>.partial` and rename it to `path` once complete.
+fn write_log(path: &Path, format: OutputFormat, log: &OfflineLog, device: &DeviceInfo) -> Result<(), Box<dyn Error>> {
+    if let Some(recording_format) = format.recording_format() {
+        let metadata = RecordingMetadata::offline(device, &log.metadata);
+        write_recording(path, recording_format, &metadata, &RecordingRow::from_offline_log(log))?;
+        return Ok(());
     }
+
+    let partial = partial_path(path);
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    write_json(&mut writer, log)?;
     writer.flush()?;
+    drop(writer);
+    std::fs::rename(partial, path)?;
     Ok(())
 }
 
-fn write_csv(mut writer: impl Write, log: &OfflineLog) -> Result<(), std::io::Error> {
-    writeln!(
-        writer,
-        "index,elapsed_seconds,voltage_uv,current_ua,power_w,charge_uah,energy_uwh,voltage_v,current_a,charge_mah,energy_mwh"
-    )?;
-    let interval_seconds = log.metadata.interval.get::<second>();
-    for (index, sample) in log.samples.iter().enumerate() {
-        let raw = sample.raw();
-        writeln!(
-            writer,
-            "{index},{},{},{},{},{},{},{},{},{},{}",
-            index as f64 * interval_seconds,
-            raw.voltage_uv,
-            raw.current_ua,
-            sample.power.get::<watt>(),
-            raw.charge_uah,
-            raw.energy_uwh,
-            sample.voltage.get::<volt>(),
-            sample.current.get::<ampere>(),
-            sample.charge.get::<milliampere_hour>(),
-            sample.energy.get::<milliwatt_hour>(),
-        )?;
-    }
-    Ok(())
+/// One sample of a `--format json` export.
+#[derive(Debug, Serialize)]
+struct JsonSample {
+    index: usize,
+    elapsed_seconds: f64,
+    voltage_uv: i32,
+    current_ua: i32,
+    power_w: f64,
+    charge_uah: i32,
+    energy_uwh: i32,
+}
+
+/// A `--format json` export.
+#[derive(Debug, Serialize)]
+struct JsonLog {
+    metadata: MetadataRecord,
+    samples: Vec<JsonSample>,
 }
 
 fn write_json(mut writer: impl Write, log: &OfflineLog) -> Result<(), Box<dyn Error>> {
@@ -236,24 +408,22 @@ fn write_json(mut writer: impl Write, log: &OfflineLog) -> Result<(), Box<dyn Er
         .enumerate()
         .map(|(index, sample)| {
             let raw = sample.raw();
-            json!({
-                "index": index,
-                "elapsed_seconds": index as f64 * interval_seconds,
-                "voltage_uv": raw.voltage_uv,
-                "current_ua": raw.current_ua,
-                "power_w": sample.power.get::<watt>(),
-                "charge_uah": raw.charge_uah,
-                "energy_uwh": raw.energy_uwh,
-            })
+            JsonSample {
+                index,
+                elapsed_seconds: index as f64 * interval_seconds,
+                voltage_uv: raw.voltage_uv,
+                current_ua: raw.current_ua,
+                power_w: sample.power.get::<watt>(),
+                charge_uah: raw.charge_uah,
+                energy_uwh: raw.energy_uwh,
+            }
         })
-        .collect::<Vec<_>>();
-    serde_json::to_writer_pretty(
-        &mut writer,
-        &json!({
-            "metadata": metadata_json(&log.metadata),
-            "samples": samples,
-        }),
-    )?;
+        .collect();
+    let export = JsonLog {
+        metadata: MetadataRecord::from(&log.metadata),
+        samples,
+    };
+    serde_json::to_writer_pretty(&mut writer, &export)?;
     writeln!(writer)?;
     Ok(())
 }
@@ -309,11 +479,14 @@ mod tests {
         let bytes = hex::decode("81494c0021f0e2ff56ebffffb998ffff").unwrap();
         let log = OfflineLog::from_bytes(metadata, &bytes).unwrap();
 
-        let mut csv = Vec::new();
-        write_csv(&mut csv, &log).unwrap();
-        let csv = String::from_utf8(csv).unwrap();
-        assert!(csv.contains("0,0,4999553,-1904607,"));
-        assert!(csv.contains(",-5290,-26439,"));
+        let path = std::env::temp_dir().join(format!("km003c-offline-log-{}.csv", std::process::id()));
+        write_log(&path, OutputFormat::Csv, &log, &DeviceInfo::default()).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!partial_path(&path).exists());
+        assert!(csv.starts_with("elapsed_us,sample_index,sequence,"));
+        assert!(csv.contains("\n0,0,,,,,,,,,,,4999553,-1904607,"));
+        assert!(csv.contains(",-5290.0,-26439.0,5290.0,26439.0,,,,\n"));
 
         let mut json = Vec::new();
         write_json(&mut json, &log).unwrap();
@@ -323,4 +496,14 @@ mod tests {
         assert_eq!(value["samples"][0]["charge_uah"], -5_290);
         assert_eq!(value["samples"][0]["energy_uwh"], -26_439);
     }
+
+    #[test]
+    fn filename_patterns_support_wildcards() {
+        assert!(matches_pattern("A*.d", "A01.d"));
+        assert!(matches_pattern("A0?.d", "A01.d"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("*1*", "A01.d"));
+        assert!(!matches_pattern("A0?.d", "A010.d"));
+        assert!(!matches_pattern("B*", "A01.d"));
+    }
 }
@@ -2,8 +2,9 @@ use std::error::Error;
 use std::time::Duration;
 
 use clap::Parser;
+use km003c_cli::DeviceArgs;
 use km003c_lib::uom::si::time::second;
-use km003c_lib::{DeviceConfig, KM003C, PdTraceProtocolEvent, PdTraceStateEvent};
+use km003c_lib::{KM003C, PdTraceProtocolEvent, PdTraceStateEvent};
 
 /// Drain and display the KM003C firmware's internal USB PD trace queues.
 #[derive(Debug, Parser)]
@@ -21,13 +22,8 @@ struct Args {
     #[arg(short, long)]
     verbose: bool,
 
-    /// Skip USB reset.
-    #[arg(long, default_value_t = cfg!(target_os = "macos"))]
-    no_reset: bool,
-
-    /// Force USB reset even when --no-reset is the platform default.
-    #[arg(long)]
-    reset: bool,
+    #[command(flatten)]
+    device: DeviceArgs,
 }
 
 #[tokio::main]
@@ -41,11 +37,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         })
         .init();
 
-    let mut config = DeviceConfig::vendor();
-    if args.no_reset && !args.reset {
-        config = config.skip_reset();
-    }
-    let mut device = KM003C::new(config).await?;
+    let mut device = KM003C::new(args.device.config()?).await?;
 
     for poll in 0..args.polls {
         let trace = device.request_pd_trace().await?;
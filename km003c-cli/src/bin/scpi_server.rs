@@ -0,0 +1,818 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::Parser;
+use km003c_cli::DeviceArgs;
+use km003c_lib::error::KMError;
+use km003c_lib::uom::si::electric_current::ampere;
+use km003c_lib::uom::si::electric_potential::volt;
+use km003c_lib::{
+    DeviceConfig, DeviceState, DeviceStream, GraphSampleRate, MeasurementAccumulator, MeasurementSample, PolicyState,
+    PolicyTracker,
+    packet::{Attribute, AttributeSet},
+};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+/// Entries kept in a client's error queue before it reports an overflow.
+const ERROR_QUEUE_SIZE: usize = 16;
+
+/// Longest program message line accepted from a client, in bytes.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// SCPI representation of a value that is not available.
+const NOT_A_NUMBER: &str = "9.91E+37";
+
+/// Serve a KM003C as a SCPI instrument over raw TCP sockets
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Address to accept SCPI clients on
+    #[arg(short, long, default_value = "127.0.0.1:5025")]
+    listen: SocketAddr,
+
+    /// Sample rate after start and for CONFigure:RATE DEFault: 2, 10, 50, or 1000 SPS
+    #[arg(short, long, default_value = "50", value_parser = km003c_cli::parse_rate)]
+    rate: GraphSampleRate,
+
+    /// AdcQueue samples buffered for FETCh:ARRay?; the oldest are dropped first
+    #[arg(long, default_value = "100000")]
+    buffer_size: usize,
+
+    /// Seconds to wait before reconnecting to a lost or missing device
+    #[arg(long, default_value = "2")]
+    retry_interval: u64,
+
+    /// Verbose logging
+    #[arg(short, long)]
+    verbose: bool,
+
+    #[command(flatten)]
+    device: DeviceArgs,
+}
+
+/// State shared between the streaming task and the SCPI clients.
+#[derive(Debug)]
+struct Instrument {
+    device: Option<DeviceState>,
+    connected: bool,
+    default_rate: GraphSampleRate,
+    /// Rate requested by CONFigure:RATE, applied by the streaming task.
+    rate: GraphSampleRate,
+    latest: Option<MeasurementSample>,
+    /// Most recent samples, read by each client through its own cursor.
+    buffer: VecDeque<MeasurementSample>,
+    /// Number of samples recorded before the first one in `buffer`.
+    buffer_start: u64,
+    buffer_size: usize,
+    pd: PolicyState,
+}
+
+impl Instrument {
+    fn new(rate: GraphSampleRate, buffer_size: usize) -> Self {
+        Self {
+            device: None,
+            connected: false,
+            default_rate: rate,
+            rate,
+            latest: None,
+            buffer: VecDeque::new(),
+            buffer_start: 0,
+            buffer_size,
+            pd: PolicyState::default(),
+        }
+    }
+
+    fn connected(&mut self, state: &DeviceState) {
+        self.device = Some(state.clone());
+        self.connected = true;
+    }
+
+    fn disconnected(&mut self) {
+        self.connected = false;
+        self.latest = None;
+        self.pd = PolicyState::default();
+    }
+
+    fn record(&mut self, sample: MeasurementSample) {
+        if self.buffer_size == 0 {
+            self.buffer_start += 1;
+        } else {
+            if self.buffer.len() == self.buffer_size {
+                self.buffer.pop_front();
+                self.buffer_start += 1;
+            }
+            self.buffer.push_back(sample);
+        }
+        self.latest = Some(sample);
+    }
+
+    /// Drop every buffered sample, so no client fetches them.
+    fn clear_buffer(&mut self) {
+        self.buffer_start += self.buffer.len() as u64;
+        self.buffer.clear();
+    }
+
+    /// Number of samples recorded so far.
+    fn buffer_end(&self) -> u64 {
+        self.buffer_start + self.buffer.len() as u64
+    }
+
+    /// Buffered samples a client has not fetched yet, given its cursor.
+    fn unread(&self, cursor: u64) -> impl Iterator<Item = &MeasurementSample> {
+        let skip = cursor.saturating_sub(self.buffer_start) as usize;
+        self.buffer.iter().skip(skip)
+    }
+}
+
+/// Commands of the instrument, matched against the headers in [`COMMANDS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Identify,
+    Reset,
+    ClearStatus,
+    EventStatusEnable,
+    EventStatusEnableQuery,
+    EventStatusRegisterQuery,
+    StatusByteQuery,
+    OperationComplete,
+    OperationCompleteQuery,
+    Wait,
+    SelfTestQuery,
+    ErrorQuery,
+    ErrorCountQuery,
+    VersionQuery,
+    MeasureVoltage,
+    MeasureCurrent,
+    MeasurePower,
+    ConfigureRate,
+    ConfigureRateQuery,
+    FetchArray,
+    FetchArrayPoints,
+    PdAttachedQuery,
+    PdContractQuery,
+    PdContractVoltageQuery,
+    PdContractCurrentQuery,
+}
+
+/// Command headers with their short forms in upper case and optional nodes in brackets.
+const COMMANDS: &[(&str, Command)] = &[
+    ("*IDN?", Command::Identify),
+    ("*RST", Command::Reset),
+    ("*CLS", Command::ClearStatus),
+    ("*ESE", Command::EventStatusEnable),
+    ("*ESE?", Command::EventStatusEnableQuery),
+    ("*ESR?", Command::EventStatusRegisterQuery),
+    ("*STB?", Command::StatusByteQuery),
+    ("*OPC", Command::OperationComplete),
+    ("*OPC?", Command::OperationCompleteQuery),
+    ("*WAI", Command::Wait),
+    ("*TST?", Command::SelfTestQuery),
+    ("SYSTem:ERRor[:NEXT]?", Command::ErrorQuery),
+    ("SYSTem:ERRor:COUNt?", Command::ErrorCountQuery),
+    ("SYSTem:VERSion?", Command::VersionQuery),
+    ("MEASure[:SCALar]:VOLTage[:DC]?", Command::MeasureVoltage),
+    ("MEASure[:SCALar]:CURRent[:DC]?", Command::MeasureCurrent),
+    ("MEASure[:SCALar]:POWer[:DC]?", Command::MeasurePower),
+    ("CONFigure:RATE", Command::ConfigureRate),
+    ("CONFigure:RATE?", Command::ConfigureRateQuery),
+    ("FETCh:ARRay?", Command::FetchArray),
+    ("FETCh:ARRay:POINts?", Command::FetchArrayPoints),
+    ("PD:ATTached?", Command::PdAttachedQuery),
+    ("PD:CONTract?", Command::PdContractQuery),
+    ("PD:CONTract:VOLTage?", Command::PdContractVoltageQuery),
+    ("PD:CONTract:CURRent?", Command::PdContractCurrentQuery),
+];
+
+/// Whether `token` is the long or short form of the node `long`.
+fn node_matches(long: &str, token: &str) -> bool {
+    let short = long.trim_end_matches(|c: char| c.is_ascii_lowercase());
+    token.eq_ignore_ascii_case(long) || token.eq_ignore_ascii_case(short)
+}
+
+fn nodes_match(pattern: &[(&str, bool)], header: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => header.is_empty(),
+        Some((&(node, optional), rest)) => {
+            header
+                .split_first()
+                .is_some_and(|(token, header)| node_matches(node, token) && nodes_match(rest, header))
+                || (optional && nodes_match(rest, header))
+        }
+    }
+}
+
+/// Whether the absolute `header` (e.g. `meas:volt?`) selects `pattern`.
+fn header_matches(pattern: &str, header: &str) -> bool {
+    let (pattern, pattern_query) = pattern.strip_suffix('?').map_or((pattern, false), |p| (p, true));
+    let (header, header_query) = header.strip_suffix('?').map_or((header, false), |h| (h, true));
+    if pattern_query != header_query {
+        return false;
+    }
+    let mut nodes = Vec::new();
+    let mut rest = pattern;
+    while !rest.is_empty() {
+        if let Some(optional) = rest.strip_prefix("[:") {
+            let end = optional.find(']').expect("closed optional node");
+            nodes.push((&optional[..end], true));
+            rest = &optional[end + 1..];
+        } else {
+            let node = rest.strip_prefix(':').unwrap_or(rest);
+            let end = node.find([':', '[']).unwrap_or(node.len());
+            nodes.push((&node[..end], false));
+            rest = &node[end..];
+        }
+    }
+    nodes_match(&nodes, &header.split(':').collect::<Vec<_>>())
+}
+
+fn find_command(header: &str) -> Option<Command> {
+    COMMANDS
+        .iter()
+        .find(|(pattern, _)| header_matches(pattern, header))
+        .map(|&(_, command)| command)
+}
+
+/// A SCPI error: its standard code and message.
+type ScpiError = (i16, &'static str);
+
+const MISSING_PARAMETER: ScpiError = (-109, "Missing parameter");
+const PARAMETER_NOT_ALLOWED: ScpiError = (-108, "Parameter not allowed");
+const DATA_TYPE_ERROR: ScpiError = (-104, "Data type error");
+const UNDEFINED_HEADER: ScpiError = (-113, "Undefined header");
+const DATA_OUT_OF_RANGE: ScpiError = (-222, "Data out of range");
+const DATA_STALE: ScpiError = (-230, "Data corrupt or stale");
+const HARDWARE_MISSING: ScpiError = (-241, "Hardware missing");
+const QUEUE_OVERFLOW: ScpiError = (-350, "Queue overflow");
+
+/// Standard event status register bits.
+const ESR_OPERATION_COMPLETE: u8 = 0x01;
+const ESR_QUERY_ERROR: u8 = 0x04;
+const ESR_DEVICE_ERROR: u8 = 0x08;
+const ESR_EXECUTION_ERROR: u8 = 0x10;
+const ESR_COMMAND_ERROR: u8 = 0x20;
+
+/// Status byte bits.
+const STB_ERROR_AVAILABLE: u8 = 0x04;
+const STB_EVENT_STATUS: u8 = 0x20;
+
+fn parse_rate(value: &str, default: GraphSampleRate) -> Result<GraphSampleRate, ScpiError> {
+    if node_matches("MINimum", value) {
+        return Ok(GraphSampleRate::Sps2);
+    }
+    if node_matches("MAXimum", value) {
+        return Ok(GraphSampleRate::Sps1000);
+    }
+    if node_matches("DEFault", value) {
+        return Ok(default);
+    }
+    match value.parse::<f64>().map_err(|_| DATA_TYPE_ERROR)? {
+        2.0 => Ok(GraphSampleRate::Sps2),
+        10.0 => Ok(GraphSampleRate::Sps10),
+        50.0 => Ok(GraphSampleRate::Sps50),
+        1000.0 => Ok(GraphSampleRate::Sps1000),
+        _ => Err(DATA_OUT_OF_RANGE),
+    }
+}
+
+fn micro(value: i64) -> String {
+    format!("{:.6}", value as f64 / 1e6)
+}
+
+fn optional_number(value: Option<f64>) -> String {
+    value.map_or_else(|| NOT_A_NUMBER.to_string(), |value| format!("{value:.6}"))
+}
+
+/// Error queue, status registers and sample cursor of one client connection.
+#[derive(Debug, Default)]
+struct Session {
+    errors: VecDeque<ScpiError>,
+    event_status: u8,
+    event_status_enable: u8,
+    /// Index of the next sample this client fetches.
+    cursor: u64,
+}
+
+impl Session {
+    fn push_error(&mut self, error: ScpiError) {
+        self.event_status |= match error.0 {
+            -199..=-100 => ESR_COMMAND_ERROR,
+            -299..=-200 => ESR_EXECUTION_ERROR,
+            -399..=-300 => ESR_DEVICE_ERROR,
+            _ => ESR_QUERY_ERROR,
+        };
+        if self.errors.len() == ERROR_QUEUE_SIZE {
+            *self.errors.back_mut().unwrap() = QUEUE_OVERFLOW;
+        } else {
+            self.errors.push_back(error);
+        }
+    }
+
+    fn status_byte(&self) -> u8 {
+        let mut status = 0;
+        if !self.errors.is_empty() {
+            status |= STB_ERROR_AVAILABLE;
+        }
+        if self.event_status & self.event_status_enable != 0 {
+            status |= STB_EVENT_STATUS;
+        }
+        status
+    }
+
+    /// Execute one line of `;`-separated program message units and return the
+    /// responses of its queries, if any.
+    ///
+    /// Units without a leading `:` or `*` continue the header path of the
+    /// previous unit, so `MEAS:VOLT?;CURR?` queries both quantities. Failed
+    /// queries only add to the error queue and give no response.
+    fn execute(&mut self, line: &str, instrument: &Mutex<Instrument>) -> Option<String> {
+        let mut responses = Vec::new();
+        let mut path = String::new();
+        for unit in line.split(';').map(str::trim).filter(|unit| !unit.is_empty()) {
+            let (header, parameters) = unit
+                .split_once(char::is_whitespace)
+                .map_or((unit, None), |(header, parameters)| (header, Some(parameters.trim())));
+            let header = if let Some(absolute) = header.strip_prefix(':') {
+                absolute.to_string()
+            } else if header.starts_with('*') {
+                header.to_string()
+            } else {
+                format!("{path}{header}")
+            };
+            if !header.starts_with('*') {
+                path = header
+                    .rfind(':')
+                    .map_or(String::new(), |end| header[..=end].to_string());
+            }
+            let Some(command) = find_command(&header) else {
+                self.push_error(UNDEFINED_HEADER);
+                continue;
+            };
+            match self.run(command, parameters, instrument) {
+                Ok(Some(response)) => responses.push(response),
+                Ok(None) => {}
+                Err(error) => self.push_error(error),
+            }
+        }
+        (!responses.is_empty()).then(|| responses.join(";"))
+    }
+
+    fn run(
+        &mut self,
+        command: Command,
+        parameters: Option<&str>,
+        instrument: &Mutex<Instrument>,
+    ) -> Result<Option<String>, ScpiError> {
+        let takes_parameter = matches!(
+            command,
+            Command::EventStatusEnable | Command::ConfigureRate | Command::FetchArray
+        );
+        if parameters.is_some() && !takes_parameter {
+            return Err(PARAMETER_NOT_ALLOWED);
+        }
+        let mut instrument = instrument.lock().unwrap();
+        let latest = |instrument: &Instrument| match (instrument.connected, instrument.latest) {
+            (false, _) => Err(HARDWARE_MISSING),
+            (true, None) => Err(DATA_STALE),
+            (true, Some(latest)) => Ok(latest),
+        };
+        let response = match command {
+            Command::Identify => {
+                let state = instrument.device.as_ref().ok_or(HARDWARE_MISSING)?;
+                format!(
+                    "ChargerLAB,{},{},{}",
+                    state.model(),
+                    state.info.serial_id,
+                    state.firmware_version()
+                )
+            }
+            Command::Reset => {
+                self.cursor = instrument.buffer_end();
+                return Ok(None);
+            }
+            Command::ClearStatus => {
+                self.errors.clear();
+                self.event_status = 0;
+                return Ok(None);
+            }
+            Command::EventStatusEnable => {
+                let value = parameters.ok_or(MISSING_PARAMETER)?;
+                let value = value.parse::<f64>().map_err(|_| DATA_TYPE_ERROR)?;
+                if !(0.0..=255.0).contains(&value) {
+                    return Err(DATA_OUT_OF_RANGE);
+                }
+                self.event_status_enable = value.round() as u8;
+                return Ok(None);
+            }
+            Command::EventStatusEnableQuery => self.event_status_enable.to_string(),
+            Command::EventStatusRegisterQuery => std::mem::take(&mut self.event_status).to_string(),
+            Command::StatusByteQuery => self.status_byte().to_string(),
+            Command::OperationComplete => {
+                self.event_status |= ESR_OPERATION_COMPLETE;
+                return Ok(None);
+            }
+            Command::OperationCompleteQuery => "1".to_string(),
+            Command::SelfTestQuery => "0".to_string(),
+            Command::Wait => return Ok(None),
+            Command::ErrorQuery => match self.errors.pop_front() {
+                Some((code, message)) => format!("{code},\"{message}\""),
+                None => "0,\"No error\"".to_string(),
+            },
+            Command::ErrorCountQuery => self.errors.len().to_string(),
+            Command::VersionQuery => "1999.0".to_string(),
+            Command::MeasureVoltage => micro(latest(&instrument)?.vbus_uv),
+            Command::MeasureCurrent => micro(latest(&instrument)?.ibus_ua),
+            Command::MeasurePower => micro(latest(&instrument)?.power_uw),
+            Command::ConfigureRate => {
+                let value = parameters.ok_or(MISSING_PARAMETER)?;
+                instrument.rate = parse_rate(value, instrument.default_rate)?;
+                return Ok(None);
+            }
+            Command::ConfigureRateQuery => instrument.rate.samples_per_second().to_string(),
+            Command::FetchArray => {
+                let count = match parameters {
+                    None => usize::MAX,
+                    Some(value) => {
+                        let value = value.parse::<f64>().map_err(|_| DATA_TYPE_ERROR)?;
+                        if value < 1.0 || value.fract() != 0.0 {
+                            return Err(DATA_OUT_OF_RANGE);
+                        }
+                        value as usize
+                    }
+                };
+                let samples = instrument.unread(self.cursor).take(count).collect::<Vec<_>>();
+                self.cursor = self.cursor.max(instrument.buffer_start) + samples.len() as u64;
+                samples
+                    .into_iter()
+                    .map(|sample| {
+                        format!(
+                            "{},{},{}",
+                            micro(sample.vbus_uv),
+                            micro(sample.ibus_ua),
+                            micro(sample.power_uw)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }
+            Command::FetchArrayPoints => instrument.unread(self.cursor).count().to_string(),
+            Command::PdAttachedQuery => {
+                if !instrument.connected {
+                    return Err(HARDWARE_MISSING);
+                }
+                u8::from(instrument.pd.attached).to_string()
+            }
+            Command::PdContractQuery | Command::PdContractVoltageQuery | Command::PdContractCurrentQuery => {
+                if !instrument.connected {
+                    return Err(HARDWARE_MISSING);
+                }
+                let contract = instrument.pd.contract;
+                let voltage = optional_number(contract.and_then(|c| c.voltage).map(|v| v.get::<volt>()));
+                let current = optional_number(contract.and_then(|c| c.current).map(|c| c.get::<ampere>()));
+                match command {
+                    Command::PdContractVoltageQuery => voltage,
+                    Command::PdContractCurrentQuery => current,
+                    _ => {
+                        let position = contract.map_or(0, |c| c.request.object_position());
+                        format!("{position},{voltage},{current}")
+                    }
+                }
+            }
+        };
+        Ok(Some(response))
+    }
+}
+
+/// Keep the device streaming, reopening it whenever it is lost.
+async fn run_device(config: DeviceConfig, retry: Duration, instrument: Arc<Mutex<Instrument>>) {
+    loop {
+        if let Err(error) = stream(config, &instrument).await {
+            warn!("Device unavailable: {error}");
+        }
+        instrument.lock().unwrap().disconnected();
+        tokio::time::sleep(retry).await;
+    }
+}
+
+async fn stream(config: DeviceConfig, instrument: &Mutex<Instrument>) -> Result<(), KMError> {
+    let mut device = DeviceStream::open(config).await?;
+    let mut rate = {
+        let mut instrument = instrument.lock().unwrap();
+        instrument.connected(device.state());
+        instrument.rate
+    };
+    device.start(rate).await?;
+
+    let mask = AttributeSet::single(Attribute::AdcQueue).with(Attribute::PdPacket);
+    let mut accumulator = MeasurementAccumulator::default();
+    let mut policy = PolicyTracker::new();
+    loop {
+        let requested = instrument.lock().unwrap().rate;
+        if requested != rate {
+            info!("Switching to {requested}");
+            device.start(requested).await?;
+            rate = requested;
+            accumulator = MeasurementAccumulator::default();
+            instrument.lock().unwrap().clear_buffer();
+        }
+        match device.request(mask).await? {
+            Ok(packet) => {
+                let mut instrument = instrument.lock().unwrap();
+                if let Some(queue) = packet.get_adc_queue() {
+                    for sample in &queue.samples {
+                        if let Some(measurement) = accumulator.push(*sample, rate) {
+                            instrument.record(measurement);
+                        }
+                    }
+                }
+                if let Some(stream) = packet.get_pd_events() {
+                    for event in &stream.events {
+                        if let Some(change) = policy.process_event(event).last() {
+                            instrument.pd = change.state;
+                        }
+                    }
+                }
+            }
+            Err(error) => warn!("Request failed: {error}"),
+        }
+        tokio::time::sleep(rate.poll_interval()).await;
+    }
+}
+
+/// Answer newline-terminated program messages until the client disconnects.
+///
+/// Clients sending a line longer than [`MAX_LINE_LENGTH`] are disconnected.
+async fn serve_client(stream: TcpStream, instrument: Arc<Mutex<Instrument>>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut session = Session::default();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let limit = MAX_LINE_LENGTH as u64 + 1;
+        if (&mut reader).take(limit).read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        if line.len() > MAX_LINE_LENGTH && !line.ends_with(b"\n") {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("program message longer than {MAX_LINE_LENGTH} bytes"),
+            ));
+        }
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\n').trim_end_matches('\r');
+        if let Some(response) = session.execute(line, &instrument) {
+            writer.write_all(format!("{response}\n").as_bytes()).await?;
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let log_level = if args.verbose {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::INFO
+    };
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let config = args.device.config()?;
+    let instrument = Arc::new(Mutex::new(Instrument::new(args.rate, args.buffer_size)));
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    info!("Accepting SCPI clients on {}", listener.local_addr()?);
+    tokio::spawn(run_device(
+        config,
+        Duration::from_secs(args.retry_interval),
+        instrument.clone(),
+    ));
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        info!("Client {peer} connected");
+        let instrument = instrument.clone();
+        tokio::spawn(async move {
+            if let Err(error) = serve_client(stream, instrument).await {
+                warn!("Client {peer}: {error}");
+            }
+            info!("Client {peer} disconnected");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use km003c_lib::uom::si::f64::{ElectricCurrent, ElectricPotential, Power, Time};
+    use km003c_lib::uom::si::power::watt;
+    use km003c_lib::uom::si::time::second;
+    use km003c_lib::{AdcQueueSample, DeviceInfo, HardwareId, PdContract, RequestDataObject};
+
+    fn instrument() -> Mutex<Instrument> {
+        let mut instrument = Instrument::new(GraphSampleRate::Sps50, 3);
+        instrument.connected(&DeviceState {
+            info: DeviceInfo {
+                model: "KM003C".to_string(),
+                hw_version: "2.1".to_string(),
+                fw_version: "1.9.9".to_string(),
+                serial_id: "007965".to_string(),
+                ..DeviceInfo::default()
+            },
+            hardware_id: HardwareId::from_bytes([0; 12]),
+            auth_level: 2,
+            adcqueue_enabled: true,
+        });
+        let mut accumulator = MeasurementAccumulator::default();
+        for sequence in 0..4u16 {
+            let sample = AdcQueueSample {
+                sequence: sequence * 20,
+                marker: 0,
+                vbus: ElectricPotential::new::<volt>(20.0 - f64::from(sequence)),
+                ibus: ElectricCurrent::new::<ampere>(3.0),
+                power: Power::new::<watt>(60.0),
+                cc1: ElectricPotential::new::<volt>(1.65),
+                cc2: ElectricPotential::new::<volt>(0.0),
+                vdp: ElectricPotential::new::<volt>(0.6),
+                vdm: ElectricPotential::new::<volt>(0.0),
+            };
+            if let Some(measurement) = accumulator.push(sample, GraphSampleRate::Sps50) {
+                instrument.record(measurement);
+            }
+        }
+        Mutex::new(instrument)
+    }
+
+    #[test]
+    fn answers_queries_in_short_and_long_form() {
+        let instrument = instrument();
+        let mut session = Session::default();
+
+        assert_eq!(
+            session.execute("*IDN?", &instrument).as_deref(),
+            Some("ChargerLAB,KM003C,007965,1.9.9")
+        );
+        assert_eq!(
+            session
+                .execute("meas:volt?;CURR?;:MEASure:SCALar:POWer:DC?", &instrument)
+                .as_deref(),
+            Some("17.000000;3.000000;60.000000")
+        );
+        assert_eq!(session.execute("CONF:RATE 1E3", &instrument), None);
+        assert_eq!(session.execute("conf:rate?", &instrument).as_deref(), Some("1000"));
+        // The rate is shared by all clients, so *RST leaves it alone.
+        assert_eq!(
+            session.execute("*RST;:CONFigure:RATE?", &instrument).as_deref(),
+            Some("1000")
+        );
+        assert_eq!(session.execute("CONF:RATE DEF", &instrument), None);
+        assert_eq!(session.execute("CONF:RATE?", &instrument).as_deref(), Some("50"));
+        assert_eq!(
+            session.execute("SYST:ERR?", &instrument).as_deref(),
+            Some("0,\"No error\"")
+        );
+    }
+
+    #[test]
+    fn fetches_buffered_samples_per_client() {
+        let instrument = instrument();
+        let mut session = Session::default();
+        let mut other = Session::default();
+
+        // The buffer holds the last three of four samples.
+        assert_eq!(session.execute("FETC:ARR:POIN?", &instrument).as_deref(), Some("3"));
+        assert_eq!(
+            session.execute("FETCh:ARRay? 2", &instrument).as_deref(),
+            Some("19.000000,3.000000,60.000000,18.000000,3.000000,60.000000")
+        );
+        assert_eq!(
+            session.execute("FETC:ARR?;ARR:POIN?", &instrument).as_deref(),
+            Some("17.000000,3.000000,60.000000;0")
+        );
+        assert_eq!(session.execute("FETC:ARR?", &instrument).as_deref(), Some(""));
+
+        // Another client still reads every buffered sample.
+        assert_eq!(other.execute("FETC:ARR:POIN?", &instrument).as_deref(), Some("3"));
+        assert_eq!(
+            other.execute("FETC:ARR? 1", &instrument).as_deref(),
+            Some("19.000000,3.000000,60.000000")
+        );
+
+        // Samples pushed out of the ring are skipped, and *RST only empties this client's view.
+        let latest = instrument.lock().unwrap().latest.unwrap();
+        instrument.lock().unwrap().record(latest);
+        instrument.lock().unwrap().record(latest);
+        assert_eq!(other.execute("FETC:ARR:POIN?", &instrument).as_deref(), Some("3"));
+        assert_eq!(session.execute("FETC:ARR:POIN?", &instrument).as_deref(), Some("2"));
+        assert_eq!(other.execute("*RST;:FETC:ARR:POIN?", &instrument).as_deref(), Some("0"));
+        assert_eq!(session.execute("FETC:ARR:POIN?", &instrument).as_deref(), Some("2"));
+
+        instrument.lock().unwrap().clear_buffer();
+        assert_eq!(session.execute("FETC:ARR:POIN?", &instrument).as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn reports_pd_contracts() {
+        let instrument = instrument();
+        let mut session = Session::default();
+        assert_eq!(
+            session.execute("PD:ATT?;CONT?", &instrument).as_deref(),
+            Some("0;0,9.91E+37,9.91E+37")
+        );
+
+        let mut pd = PolicyState {
+            attached: true,
+            ..PolicyState::default()
+        };
+        pd.contract = Some(PdContract {
+            requested_at: Time::new::<second>(1.0),
+            accepted_at: Time::new::<second>(1.0),
+            ready_at: None,
+            request: RequestDataObject(0x2000_012C),
+            pdo: None,
+            epr: false,
+            voltage: Some(ElectricPotential::new::<volt>(9.0)),
+            current: Some(ElectricCurrent::new::<ampere>(3.0)),
+        });
+        instrument.lock().unwrap().pd = pd;
+        assert_eq!(
+            session
+                .execute("PD:ATTached?;CONTract?;CONT:VOLT?", &instrument)
+                .as_deref(),
+            Some("1;2,9.000000,3.000000;9.000000")
+        );
+
+        instrument.lock().unwrap().disconnected();
+        assert_eq!(session.execute("PD:CONT:CURR?;:MEAS:VOLT?", &instrument), None);
+        assert_eq!(
+            session.execute("SYST:ERR?;ERR?", &instrument).as_deref(),
+            Some("-241,\"Hardware missing\";-241,\"Hardware missing\"")
+        );
+    }
+
+    #[test]
+    fn queues_errors_and_sets_status_bits() {
+        let instrument = instrument();
+        let mut session = Session::default();
+
+        assert_eq!(
+            session.execute("*ESE 48;FOO?;CONF:RATE 7;:CONF:RATE", &instrument),
+            None
+        );
+        assert_eq!(session.execute("SYST:ERR:COUN?", &instrument).as_deref(), Some("3"));
+        assert_eq!(
+            session.execute("*STB?;*ESR?;*ESR?", &instrument).as_deref(),
+            Some("36;48;0")
+        );
+        assert_eq!(
+            session.execute("SYST:ERR?;ERR?;ERR?;ERR?", &instrument).as_deref(),
+            Some("-113,\"Undefined header\";-222,\"Data out of range\";-109,\"Missing parameter\";0,\"No error\"")
+        );
+
+        for _ in 0..20 {
+            session.execute("*IDN? 1", &instrument);
+        }
+        assert_eq!(session.errors.len(), ERROR_QUEUE_SIZE);
+        assert_eq!(session.errors.back(), Some(&QUEUE_OVERFLOW));
+        session.execute("*CLS", &instrument);
+        assert_eq!(session.status_byte(), 0);
+    }
+
+    #[tokio::test]
+    async fn serves_clients_over_tcp() {
+        let instrument = Arc::new(instrument());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve_client(stream, instrument).await
+        });
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(b"*CLS\r\n*IDN?\r\n").await.unwrap();
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(
+            lines.next_line().await.unwrap().as_deref(),
+            Some("ChargerLAB,KM003C,007965,1.9.9")
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnects_clients_sending_overlong_lines() {
+        let instrument = Arc::new(instrument());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve_client(stream, instrument).await
+        });
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(&[b' '; MAX_LINE_LENGTH + 1]).await.unwrap();
+        let error = server.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
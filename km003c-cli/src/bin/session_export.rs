@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use km003c_lib::uom::si::electric_current::milliampere;
+use km003c_lib::uom::si::electric_potential::millivolt;
+use km003c_lib::uom::si::f64::{ElectricCurrent, ElectricPotential};
+use km003c_lib::{SessionReader, SigrokExport, VcdExport, VcdOptions};
+
+/// Convert a KM003C session file for other analysis tools.
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Session file written by `SessionWriter`.
+    input: PathBuf,
+
+    /// Output format.
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Sigrok)]
+    format: ExportFormat,
+
+    /// Output path. Defaults to the input path with the format's extension.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// VCD: include VBUS, rounded to this many millivolts.
+    #[arg(long, value_name = "MV")]
+    vbus_step_mv: Option<f64>,
+
+    /// VCD: include IBUS, rounded to this many milliamperes.
+    #[arg(long, value_name = "MA")]
+    ibus_step_ma: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    /// sigrok session archive for PulseView and sigrok-cli.
+    Sigrok,
+    /// Value Change Dump of PD and Type-C state for GTKWave.
+    Vcd,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Sigrok => "sr",
+            Self::Vcd => "vcd",
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let output = args.output.unwrap_or_else(|| default_output(&args.input, args.format));
+
+    let mut reader = SessionReader::open(&args.input)?;
+    if reader.torn_bytes() > 0 {
+        eprintln!(
+            "Ignoring {} bytes of an incomplete chunk at the end of {}",
+            reader.torn_bytes(),
+            args.input.display()
+        );
+    }
+    let entries = reader.entries()?;
+
+    match args.format {
+        ExportFormat::Sigrok => {
+            let export = SigrokExport::from_session(&entries)?;
+            export.save(&output)?;
+            println!(
+                "Exported {} samples at {} ({} filled gap samples) to {}",
+                export.len(),
+                export.sample_rate(),
+                export.gap_samples(),
+                output.display()
+            );
+        }
+        ExportFormat::Vcd => {
+            let options = VcdOptions {
+                vbus_step: args.vbus_step_mv.map(ElectricPotential::new::<millivolt>),
+                ibus_step: args.ibus_step_ma.map(ElectricCurrent::new::<milliampere>),
+            };
+            let export = VcdExport::from_session(&entries, options);
+            export.save(&output)?;
+            println!(
+                "Exported {} value changes over {:.3} s to {}",
+                export.value_changes(),
+                export.end_time_us() as f64 / 1_000_000.0,
+                output.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn default_output(input: &Path, format: ExportFormat) -> PathBuf {
+    input.with_extension(format.extension())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_defaults_to_the_input_with_the_format_extension() {
+        assert_eq!(
+            default_output(Path::new("captures/bench.km003c"), ExportFormat::Sigrok),
+            PathBuf::from("captures/bench.sr")
+        );
+        assert_eq!(
+            default_output(Path::new("bench"), ExportFormat::Vcd),
+            PathBuf::from("bench.vcd")
+        );
+    }
+}
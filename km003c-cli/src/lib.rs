@@ -0,0 +1,95 @@
+//! Command-line options and helpers shared by the km003c-cli tools.
+
+pub mod archive;
+
+use km003c_lib::error::KMError;
+use km003c_lib::{DeviceConfig, GraphSampleRate};
+
+/// Environment variable holding the pre-shared key of a bridge server.
+pub const BRIDGE_KEY_ENV: &str = "KM003C_BRIDGE_KEY";
+
+/// Options selecting how the meter is opened.
+///
+/// Global, so tools with subcommands also accept them after the subcommand.
+#[derive(clap::Args, Debug, Clone)]
+pub struct DeviceArgs {
+    /// Skip USB reset (defaults to true on macOS for compatibility)
+    #[arg(long, default_value_t = cfg!(target_os = "macos"), global = true)]
+    pub no_reset: bool,
+
+    /// Force USB reset even on macOS (overrides --no-reset)
+    #[arg(long, global = true)]
+    pub reset: bool,
+
+    /// Use a meter shared by bridge-server at tcp://HOST[:PORT] instead of USB
+    #[arg(long, value_name = "URL", global = true)]
+    pub remote: Option<String>,
+
+    /// Pre-shared key of the bridge server given with --remote
+    #[arg(long, value_name = "KEY", env = BRIDGE_KEY_ENV, hide_env_values = true, global = true)]
+    pub bridge_key: Option<String>,
+}
+
+impl DeviceArgs {
+    /// Vendor-interface configuration for these options.
+    pub fn config(&self) -> Result<DeviceConfig, KMError> {
+        self.config_for(DeviceConfig::vendor())
+    }
+
+    /// `local` with these options applied, or the bridge server given with --remote.
+    pub fn config_for(&self, local: DeviceConfig) -> Result<DeviceConfig, KMError> {
+        if let Some(url) = &self.remote {
+            let config = DeviceConfig::remote(url)?;
+            return Ok(match &self.bridge_key {
+                Some(key) => config.bridge_key(key.as_bytes()),
+                None => config,
+            });
+        }
+        Ok(if self.no_reset && !self.reset {
+            local.skip_reset()
+        } else {
+            local
+        })
+    }
+}
+
+/// Parse a graph sample rate given in samples per second: 2, 10, 50 or 1000.
+pub fn parse_rate(rate: &str) -> Result<GraphSampleRate, String> {
+    rate.parse()
+        .ok()
+        .and_then(GraphSampleRate::from_samples_per_second)
+        .ok_or_else(|| format!("unsupported sample rate {rate:?}; expected 2, 10, 50, or 1000"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_parse_from_samples_per_second() {
+        assert_eq!(parse_rate("2"), Ok(GraphSampleRate::Sps2));
+        assert_eq!(parse_rate("1000"), Ok(GraphSampleRate::Sps1000));
+        assert!(parse_rate("100").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        device: DeviceArgs,
+    }
+
+    #[test]
+    fn bridge_options_open_a_remote_meter() {
+        use clap::Parser;
+
+        let cli = Cli::parse_from(["tool", "--remote", "tcp://127.0.0.1:7003", "--bridge-key", "rack-7"]);
+        assert_eq!(cli.device.bridge_key.as_deref(), Some("rack-7"));
+        assert!(cli.device.config().unwrap().is_remote());
+        assert!(cli.device.config_for(DeviceConfig::hid()).unwrap().is_remote());
+
+        let cli = Cli::parse_from(["tool", "--no-reset"]);
+        assert!(cli.device.config_for(DeviceConfig::hid()).unwrap().is_hid());
+        assert!(!cli.device.config().unwrap().is_remote());
+    }
+}
@@ -8,28 +8,33 @@ mod recording;
 
 use eframe::egui;
 use egui_plot::{Line, Plot, PlotPoints};
+use km003c_lib::recording::{RecordingFormat, RecordingMetadata};
 use km003c_lib::uom::si::electric_charge::milliampere_hour;
 use km003c_lib::uom::si::electric_potential::volt;
 use km003c_lib::uom::si::energy::milliwatt_hour;
 use km003c_lib::uom::si::time::{millisecond, second};
 use km003c_lib::{
-    AdcQueueSample, DeviceConfig, DeviceState, GraphSampleRate, KM003C, LogMetadata, OfflineLog, PdTrace,
+    AdcQueueSample, DeviceConfig, DeviceState, GraphSampleRate, KM003C, LogMetadata, MeasurementAccumulator,
+    MeasurementSample, OfflineDownloadProgress, OfflineLog, OfflineLogDownload, PdEventCategory, PdTrace,
     packet::{Attribute, AttributeSet},
     pd::{PdEvent, PdEventData, PdStatus},
 };
-use measurement::{MeasurementAccumulator, MeasurementSample, PlotMetric};
+use measurement::PlotMetric;
 use offline_export::{OfflineExportEvent, OfflineExportTask};
 use offline_view::OfflineRecordingView;
 use pd_connection::PdConnectionTracker;
-use pd_decoder::{DecodedPdEntry, PdCategory, PdDecoder};
+use pd_decoder::{DecodedPdEntry, PdDecoder};
 use pd_trace_view::{PdTraceCategory, PdTraceEntry, decode_trace};
-use recording::{Recorder, RecordingEvent, RecordingFormat, RecordingMetadata, RecordingSummary};
+use recording::{Recorder, RecordingEvent, RecordingSummary};
 use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
 
+/// Environment variable holding the pre-shared key of the bridge server.
+const BRIDGE_KEY_ENV: &str = "KM003C_BRIDGE_KEY";
+
 /// Message from USB task to UI
 #[derive(Debug, Clone)]
 enum UsbMessage {
@@ -47,8 +52,12 @@ enum UsbMessage {
     PdTrace(PdTrace),
     /// Device offline-recording catalog
     OfflineCatalog(Vec<LogMetadata>),
+    /// Bytes received so far by the running offline download
+    OfflineDownloadProgress(OfflineDownloadProgress),
     /// Complete selected offline recording
     OfflineLogDownloaded(OfflineLog),
+    /// Offline download failed; the partial download can be resumed
+    OfflineDownloadInterrupted(OfflineLogDownload, String),
     /// Offline catalog or download operation failed
     OfflineOperationFailed(String),
     /// Streaming started at given rate
@@ -72,8 +81,8 @@ enum UsbCommand {
     SetPdTraceEnabled(bool),
     /// Fetch the catalog of recordings stored by the device
     RequestOfflineCatalog,
-    /// Download one catalog entry from device memory
-    DownloadOfflineLog(LogMetadata),
+    /// Start or resume downloading one catalog entry from device memory
+    DownloadOfflineLog(OfflineLogDownload),
     /// Stop streaming and disconnect
     Disconnect,
 }
@@ -249,6 +258,10 @@ struct PowerMonitorApp {
     offline_device_metadata: Option<RecordingMetadata>,
     /// Whether a device catalog or download operation is running
     offline_busy: bool,
+    /// Progress of the running offline download
+    offline_progress: Option<OfflineDownloadProgress>,
+    /// Interrupted offline download kept for resuming, across reconnects
+    offline_partial: Option<OfflineLogDownload>,
     /// Offline browser and export status
     offline_status: String,
     /// Background export of a downloaded offline recording
@@ -311,6 +324,8 @@ impl PowerMonitorApp {
             offline_view: None,
             offline_device_metadata: None,
             offline_busy: false,
+            offline_progress: None,
+            offline_partial: None,
             offline_status: "Catalog not loaded".to_string(),
             offline_export: None,
             plot_source: PlotSource::Live,
@@ -431,15 +446,19 @@ impl PowerMonitorApp {
                     self.offline_selected = (!catalog.is_empty()).then_some(0);
                     self.offline_catalog = catalog;
                 }
+                UsbMessage::OfflineDownloadProgress(progress) => {
+                    self.offline_progress = Some(progress);
+                }
                 UsbMessage::OfflineLogDownloaded(log) => {
+                    self.offline_progress = None;
+                    self.offline_partial = None;
                     let samples = log.samples.len();
                     let filename = log.metadata.filename_lossy().into_owned();
+                    self.offline_device_metadata = self
+                        .device_state
+                        .as_ref()
+                        .map(|state| RecordingMetadata::offline(&state.info, &log.metadata));
                     self.offline_view = Some(Arc::new(OfflineRecordingView::new(log)));
-                    self.offline_device_metadata = self.device_state.as_ref().map(|state| RecordingMetadata {
-                        model: state.info.model.clone(),
-                        firmware: state.info.fw_version.clone(),
-                        serial: state.info.serial_id.clone(),
-                    });
                     self.offline_busy = false;
                     self.offline_status = format!("Downloaded {samples} samples from {filename}");
                     self.plot_source = PlotSource::Offline;
@@ -450,6 +469,17 @@ impl PowerMonitorApp {
                         }
                     }
                 }
+                UsbMessage::OfflineDownloadInterrupted(download, error) => {
+                    self.offline_busy = false;
+                    self.offline_progress = None;
+                    let received = download.progress().received_samples();
+                    self.offline_status = if received > 0 {
+                        format!("{error}; {received} samples kept for resuming")
+                    } else {
+                        error
+                    };
+                    self.offline_partial = (received > 0).then_some(download);
+                }
                 UsbMessage::OfflineOperationFailed(error) => {
                     self.offline_busy = false;
                     self.offline_status = error;
@@ -468,6 +498,7 @@ impl PowerMonitorApp {
                     self.pd_status = None;
                     self.pd_connection = PdConnectionTracker::default();
                     self.offline_busy = false;
+                    self.offline_progress = None;
                     self.stop_recording();
                 }
             }
@@ -502,11 +533,7 @@ impl PowerMonitorApp {
             return;
         }
 
-        let metadata = RecordingMetadata {
-            model: state.info.model.clone(),
-            firmware: state.info.fw_version.clone(),
-            serial: state.info.serial_id.clone(),
-        };
+        let metadata = RecordingMetadata::live(&state.info);
         let Some(path) = self.select_recording_path("km003c-live", "Save KM003C live recording") else {
             return;
         };
@@ -534,11 +561,7 @@ impl PowerMonitorApp {
             self.recording_status = "The plot buffer is empty".to_string();
             return;
         };
-        let metadata = RecordingMetadata {
-            model: state.info.model.clone(),
-            firmware: state.info.fw_version.clone(),
-            serial: state.info.serial_id.clone(),
-        };
+        let metadata = RecordingMetadata::live(&state.info);
         let Some(path) = self.select_recording_path("km003c-buffer", "Export KM003C plot buffer") else {
             return;
         };
@@ -643,14 +666,23 @@ impl PowerMonitorApp {
             self.offline_status = "Select an offline recording first".to_string();
             return;
         };
+        let download = match self.offline_partial.take() {
+            Some(partial) if *partial.metadata() == metadata => partial,
+            _ => OfflineLogDownload::new(metadata),
+        };
         self.offline_busy = true;
+        self.offline_progress = Some(download.progress());
         self.offline_status = format!(
             "Downloading {} samples from {}...",
-            metadata.sample_count,
-            metadata.filename_lossy()
+            download.metadata().sample_count,
+            download.metadata().filename_lossy()
         );
-        if self.cmd_sender.send(UsbCommand::DownloadOfflineLog(metadata)).is_err() {
+        if let Err(error) = self.cmd_sender.send(UsbCommand::DownloadOfflineLog(download)) {
+            if let UsbCommand::DownloadOfflineLog(download) = error.0 {
+                self.offline_partial = Some(download);
+            }
             self.offline_busy = false;
+            self.offline_progress = None;
             self.offline_status = "USB task is not available".to_string();
         }
     }
@@ -1036,16 +1068,11 @@ impl eframe::App for PowerMonitorApp {
             }
 
             if let Some(recorder) = &self.recorder {
-                ui.label(format!("Samples: {}", recorder.rows));
-                ui.label(format!("Missing: {}", recorder.missing_samples));
-                ui.label(format!("Discarded: {}", recorder.discarded_sequence_samples));
-                let completeness = if recorder.elapsed_us == 0 {
-                    100.0
-                } else {
-                    (1.0 - recorder.interpolated_duration_us as f64 / recorder.elapsed_us as f64).max(0.0)
-                        * 100.0
-                };
-                ui.label(format!("Completeness: {completeness:.6}%"));
+                let progress = &recorder.summary;
+                ui.label(format!("Samples: {}", progress.rows));
+                ui.label(format!("Missing: {}", progress.missing_samples));
+                ui.label(format!("Discarded: {}", progress.discarded_sequence_samples));
+                ui.label(format!("Completeness: {:.6}%", progress.completeness_percent()));
             } else if let Some(summary) = &self.last_recording {
                 ui.label(format!("Last capture: {} samples", summary.rows));
                 ui.label(format!("Discarded: {}", summary.discarded_sequence_samples));
@@ -1071,10 +1098,18 @@ impl eframe::App for PowerMonitorApp {
                 {
                     self.request_offline_catalog();
                 }
-                if self.offline_busy {
+                if self.offline_busy && self.offline_progress.is_none() {
                     ui.spinner();
                 }
             });
+            if let Some(progress) = self.offline_progress {
+                let text = format!(
+                    "{} samples ({:.0}%)",
+                    progress.received_samples(),
+                    progress.fraction() * 100.0
+                );
+                ui.add(egui::ProgressBar::new(progress.fraction() as f32).text(text));
+            }
 
             if !self.offline_catalog.is_empty() {
                 let selected_text = self
@@ -1124,6 +1159,11 @@ impl eframe::App for PowerMonitorApp {
                         });
                 }
 
+                let resumable = self.offline_partial.as_ref().is_some_and(|partial| {
+                    self.offline_selected
+                        .and_then(|index| self.offline_catalog.get(index))
+                        .is_some_and(|metadata| metadata == partial.metadata())
+                });
                 if ui
                     .add_enabled(
                         self.device_state.is_some()
@@ -1131,7 +1171,11 @@ impl eframe::App for PowerMonitorApp {
                             && !self.offline_busy
                             && self.recorder.is_none()
                             && self.offline_export.is_none(),
-                        egui::Button::new("Download and View"),
+                        egui::Button::new(if resumable {
+                            "Resume Download"
+                        } else {
+                            "Download and View"
+                        }),
                     )
                     .clicked()
                 {
@@ -1233,13 +1277,13 @@ impl eframe::App for PowerMonitorApp {
                                 match timeline_entry {
                                     PdTimelineEntry::Protocol(entry) => {
                                         let color = match entry.category {
-                                            PdCategory::Connect => egui::Color32::GREEN,
-                                            PdCategory::Disconnect => egui::Color32::RED,
-                                            PdCategory::SourceCaps => egui::Color32::from_rgb(100, 149, 237),
-                                            PdCategory::Request => egui::Color32::YELLOW,
-                                            PdCategory::Control => egui::Color32::GRAY,
-                                            PdCategory::Extended => egui::Color32::from_rgb(255, 165, 0),
-                                            PdCategory::Error => egui::Color32::from_rgb(255, 80, 80),
+                                            PdEventCategory::Connect => egui::Color32::GREEN,
+                                            PdEventCategory::Disconnect => egui::Color32::RED,
+                                            PdEventCategory::SourceCapabilities => egui::Color32::from_rgb(100, 149, 237),
+                                            PdEventCategory::Request => egui::Color32::YELLOW,
+                                            PdEventCategory::Control => egui::Color32::GRAY,
+                                            PdEventCategory::Extended => egui::Color32::from_rgb(255, 165, 0),
+                                            PdEventCategory::Error => egui::Color32::from_rgb(255, 80, 80),
                                         };
 
                                         ui.colored_label(
@@ -1342,7 +1386,12 @@ impl eframe::App for PowerMonitorApp {
     }
 }
 
-async fn usb_streaming_task(tx: mpsc::UnboundedSender<UsbMessage>, mut cmd_rx: mpsc::UnboundedReceiver<UsbCommand>) {
+/// `remote` selects a meter shared by a bridge server instead of local USB.
+async fn usb_streaming_task(
+    tx: mpsc::UnboundedSender<UsbMessage>,
+    mut cmd_rx: mpsc::UnboundedReceiver<UsbCommand>,
+    remote: Option<DeviceConfig>,
+) {
     info!("USB task started, waiting for Connect command");
 
     // Main loop - wait for commands
@@ -1359,7 +1408,7 @@ async fn usb_streaming_task(tx: mpsc::UnboundedSender<UsbMessage>, mut cmd_rx: m
         match cmd {
             UsbCommand::Connect(initial_rate, usb_reset) => {
                 info!("Connect command received, rate={:?}, reset={}", initial_rate, usb_reset);
-                run_streaming_session(&tx, &mut cmd_rx, initial_rate, usb_reset).await;
+                run_streaming_session(&tx, &mut cmd_rx, initial_rate, usb_reset, remote).await;
             }
             UsbCommand::SetSampleRate(_)
             | UsbCommand::SetPdTraceEnabled(_)
@@ -1378,12 +1427,13 @@ async fn run_streaming_session(
     cmd_rx: &mut mpsc::UnboundedReceiver<UsbCommand>,
     initial_rate: GraphSampleRate,
     usb_reset: bool,
+    remote: Option<DeviceConfig>,
 ) {
     // Connect to device with vendor interface (Full mode for AdcQueue)
-    let config = if usb_reset {
-        DeviceConfig::vendor()
-    } else {
-        DeviceConfig::vendor().skip_reset()
+    let config = match remote {
+        Some(config) => config,
+        None if usb_reset => DeviceConfig::vendor(),
+        None => DeviceConfig::vendor().skip_reset(),
     };
     let mut device = match KM003C::new(config).await {
         Ok(dev) => dev,
@@ -1477,10 +1527,11 @@ async fn run_streaming_session(
                     break;
                 }
             }
-            Ok(UsbCommand::DownloadOfflineLog(metadata)) => {
+            Ok(UsbCommand::DownloadOfflineLog(mut download)) => {
                 info!(
-                    filename = %metadata.filename_lossy(),
-                    samples = metadata.sample_count,
+                    filename = %download.metadata().filename_lossy(),
+                    samples = download.metadata().sample_count,
+                    received = download.progress().received_samples(),
                     "Downloading offline recording"
                 );
                 if let Err(error) = device.stop_graph_mode().await {
@@ -1490,16 +1541,24 @@ async fn run_streaming_session(
                     continue;
                 }
                 let _ = tx.send(UsbMessage::StreamingStopped);
-                match device.download_offline_log(metadata).await {
-                    Ok(log) => {
-                        let _ = tx.send(UsbMessage::OfflineLogDownloaded(log));
-                    }
-                    Err(error) => {
-                        let _ = tx.send(UsbMessage::OfflineOperationFailed(format!(
-                            "Failed to download offline recording: {error}"
-                        )));
-                    }
-                }
+                let result = device
+                    .continue_offline_log_download(&mut download, |progress| {
+                        let _ = tx.send(UsbMessage::OfflineDownloadProgress(progress));
+                    })
+                    .await;
+                let message = match result {
+                    Ok(()) => match download.finish() {
+                        Ok(log) => UsbMessage::OfflineLogDownloaded(log),
+                        Err(error) => UsbMessage::OfflineOperationFailed(format!(
+                            "Downloaded offline recording failed validation: {error}"
+                        )),
+                    },
+                    Err(error) => UsbMessage::OfflineDownloadInterrupted(
+                        download,
+                        format!("Failed to download offline recording: {error}"),
+                    ),
+                };
+                let _ = tx.send(message);
                 if let Err(error) = start_streaming(&mut device, current_rate, tx).await {
                     let _ = tx.send(UsbMessage::Error(format!(
                         "Failed to resume streaming after offline download: {error}"
@@ -1604,12 +1663,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
     info!("Starting POWER-Z KM003C GUI application");
 
+    // An optional tcp://host:port argument opens a meter shared by bridge-server.
+    let remote = std::env::args()
+        .nth(1)
+        .map(|url| {
+            let config = DeviceConfig::remote(&url)?;
+            Ok::<_, km003c_lib::error::KMError>(match std::env::var(BRIDGE_KEY_ENV) {
+                Ok(key) => config.bridge_key(key.as_bytes()),
+                Err(_) => config,
+            })
+        })
+        .transpose()?;
+
     // Create channels for communication
     let (usb_tx, usb_rx) = mpsc::unbounded_channel();
     let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
 
     // Spawn USB streaming task
-    tokio::spawn(usb_streaming_task(usb_tx, cmd_rx));
+    tokio::spawn(usb_streaming_task(usb_tx, cmd_rx, remote));
 
     // Auto-connect on startup
     let _ = cmd_tx.send(UsbCommand::Connect(GraphSampleRate::Sps50, !cfg!(target_os = "macos")));
@@ -1632,7 +1703,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 mod tests {
     use super::*;
     use crate::offline_view::captured_test_view;
-    use polars::prelude::{CsvReader, ParquetReader, SerReader};
+    use km003c_lib::polars::prelude::{CsvReader, ParquetReader, SerReader};
 
     #[test]
     fn firmware_trace_is_only_requested_when_enabled() {
@@ -1651,7 +1722,7 @@ mod tests {
     fn pd_timeline_filters_and_orders_both_sources() {
         let protocol_log = VecDeque::from([DecodedPdEntry {
             timestamp_seconds: 12.25,
-            category: PdCategory::Control,
+            category: PdEventCategory::Control,
             summary: "wire".to_string(),
             details: Vec::new(),
         }]);
@@ -1690,6 +1761,52 @@ mod tests {
         assert!(!app.offline_busy);
     }
 
+    #[test]
+    fn interrupted_offline_download_resumes_where_it_stopped() {
+        let (usb_tx, usb_rx) = mpsc::unbounded_channel();
+        let (cmd_tx, mut cmd_rx) = mpsc::unbounded_channel();
+        let mut app = PowerMonitorApp::new(usb_rx, cmd_tx);
+        let fixture = captured_test_view();
+        let metadata = fixture.log.metadata.clone();
+        app.device_state = Some(Arc::new(DeviceState {
+            info: Default::default(),
+            hardware_id: km003c_lib::HardwareId::from_bytes([0; 12]),
+            auth_level: 1,
+            adcqueue_enabled: true,
+        }));
+        app.offline_catalog = vec![metadata.clone()];
+        app.offline_selected = Some(0);
+
+        let mut partial = OfflineLogDownload::new(metadata.clone()).with_chunk_size(32);
+        partial.push_chunk(&fixture.log.to_bytes()[..32]).unwrap();
+        usb_tx
+            .send(UsbMessage::OfflineDownloadInterrupted(partial, "timeout".to_string()))
+            .unwrap();
+        app.process_messages();
+        assert!(!app.offline_busy);
+        assert_eq!(app.offline_status, "timeout; 2 samples kept for resuming");
+
+        app.download_selected_offline_log();
+        let Ok(UsbCommand::DownloadOfflineLog(resumed)) = cmd_rx.try_recv() else {
+            panic!("expected a download command");
+        };
+        assert_eq!(resumed.progress().received_samples(), 2);
+        assert_eq!(app.offline_progress, Some(resumed.progress()));
+        assert!(app.offline_partial.is_none());
+
+        // Another recording starts from the beginning.
+        app.offline_busy = false;
+        app.offline_partial = Some(resumed);
+        let mut other = metadata;
+        other.filename_raw[2] = b'2';
+        app.offline_catalog = vec![other];
+        app.download_selected_offline_log();
+        let Ok(UsbCommand::DownloadOfflineLog(fresh)) = cmd_rx.try_recv() else {
+            panic!("expected a download command");
+        };
+        assert_eq!(fresh.progress().received_bytes, 0);
+    }
+
     #[test]
     fn empty_offline_catalog_clears_selection() {
         let (usb_tx, usb_rx) = mpsc::unbounded_channel();
@@ -1711,7 +1828,7 @@ mod tests {
         tokio::runtime::Runtime::new().unwrap().block_on(async {
             let (usb_tx, mut usb_rx) = mpsc::unbounded_channel();
             let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
-            let task = tokio::spawn(usb_streaming_task(usb_tx, cmd_rx));
+            let task = tokio::spawn(usb_streaming_task(usb_tx, cmd_rx, None));
             cmd_tx.send(UsbCommand::Connect(GraphSampleRate::Sps50, false)).unwrap();
 
             let startup_deadline = tokio::time::Instant::now() + Duration::from_secs(10);
@@ -1759,7 +1876,11 @@ mod tests {
 
             let mut downloaded_log = None;
             if let Some(metadata) = catalog.first().cloned() {
-                cmd_tx.send(UsbCommand::DownloadOfflineLog(metadata.clone())).unwrap();
+                cmd_tx
+                    .send(UsbCommand::DownloadOfflineLog(OfflineLogDownload::new(
+                        metadata.clone(),
+                    )))
+                    .unwrap();
                 let download_deadline = tokio::time::Instant::now() + Duration::from_secs(20);
                 let mut stopped = false;
                 let mut downloaded = false;
@@ -1779,7 +1900,10 @@ mod tests {
                             downloaded = true;
                         }
                         UsbMessage::StreamingStarted(GraphSampleRate::Sps50) => resumed = true,
-                        UsbMessage::OfflineOperationFailed(error) => panic!("offline download failed: {error}"),
+                        UsbMessage::OfflineOperationFailed(error)
+                        | UsbMessage::OfflineDownloadInterrupted(_, error) => {
+                            panic!("offline download failed: {error}")
+                        }
                         _ => {}
                     }
                 }
@@ -1793,11 +1917,7 @@ mod tests {
 
             if let Some(log) = downloaded_log {
                 let device_state = device_state.expect("connected device state was not retained");
-                let recording_metadata = RecordingMetadata {
-                    model: device_state.info.model.clone(),
-                    firmware: device_state.info.fw_version.clone(),
-                    serial: device_state.info.serial_id.clone(),
-                };
+                let recording_metadata = RecordingMetadata::offline(&device_state.info, &log.metadata);
                 let expected_rows = log.samples.len();
                 let expected_charge_uah = log
                     .metadata
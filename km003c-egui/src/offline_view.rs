@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
-use km003c_lib::uom::si::power::microwatt;
-use km003c_lib::{OfflineLog, OfflineLogSample};
+use km003c_lib::OfflineLog;
+use km003c_lib::recording::RecordingRow;
 
 use crate::measurement::PlotMetric;
 
@@ -47,49 +47,24 @@ pub(crate) struct OfflineRecordingView {
 
 impl OfflineRecordingView {
     pub(crate) fn new(log: OfflineLog) -> Self {
-        let log = Arc::new(log);
-        let interval_us = (log.metadata.interval.get::<km003c_lib::uom::si::time::microsecond>()).round() as u64;
-        let mut previous_charge_uah = 0_i32;
-        let mut previous_energy_uwh = 0_i32;
-        let mut charge_throughput_uah = 0_u64;
-        let mut energy_throughput_uwh = 0_u64;
-        let samples = log
-            .samples
-            .iter()
-            .enumerate()
-            .map(|(index, sample)| {
-                let raw = sample.raw();
-                charge_throughput_uah =
-                    charge_throughput_uah.saturating_add(u64::from(raw.charge_uah.abs_diff(previous_charge_uah)));
-                energy_throughput_uwh =
-                    energy_throughput_uwh.saturating_add(u64::from(raw.energy_uwh.abs_diff(previous_energy_uwh)));
-                previous_charge_uah = raw.charge_uah;
-                previous_energy_uwh = raw.energy_uwh;
-                decode_sample(sample, index, interval_us, charge_throughput_uah, energy_throughput_uwh)
+        let samples = RecordingRow::from_offline_log(&log)
+            .into_iter()
+            .map(|row| OfflineViewSample {
+                elapsed_us: row.elapsed_us,
+                sample_index: row.sample_index,
+                vbus_uv: row.vbus_uv,
+                ibus_ua: row.ibus_ua,
+                power_uw: row.power_uw,
+                charge_uah: row.charge_uah,
+                energy_uwh: row.energy_uwh,
+                charge_throughput_uah: row.charge_throughput_uah,
+                energy_throughput_uwh: row.energy_throughput_uwh,
             })
             .collect();
-        Self { log, samples }
-    }
-}
-
-fn decode_sample(
-    sample: &OfflineLogSample,
-    index: usize,
-    interval_us: u64,
-    charge_throughput_uah: u64,
-    energy_throughput_uwh: u64,
-) -> OfflineViewSample {
-    let raw = sample.raw();
-    OfflineViewSample {
-        elapsed_us: (index as u64).saturating_mul(interval_us),
-        sample_index: index as u64,
-        vbus_uv: i64::from(raw.voltage_uv),
-        ibus_ua: i64::from(raw.current_ua),
-        power_uw: sample.power.get::<microwatt>().round() as i64,
-        charge_uah: f64::from(raw.charge_uah),
-        energy_uwh: f64::from(raw.energy_uwh),
-        charge_throughput_uah: charge_throughput_uah as f64,
-        energy_throughput_uwh: energy_throughput_uwh as f64,
+        Self {
+            log: Arc::new(log),
+            samples,
+        }
     }
 }
 
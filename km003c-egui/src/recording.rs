@@ -1,151 +1,13 @@
 use std::error::Error;
-use std::fs::File;
-use std::io::BufWriter;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
 use std::thread::{self, JoinHandle};
 
-use polars::df;
-use polars::prelude::{CsvWriter, DataFrame, KeyValueMetadata, ParquetWriter, SerWriter};
+use km003c_lib::MeasurementSample;
+use km003c_lib::recording::{RecordingFormat, RecordingMetadata, RecordingRow, RecordingWriter, partial_path};
 
-use crate::measurement::MeasurementSample;
-pub(crate) const RECORDING_SCHEMA_VERSION: &str = "1";
-const ROW_GROUP_SIZE: usize = 8_192;
 const CHANNEL_CAPACITY: usize = 32;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) enum RecordingFormat {
-    Parquet,
-    Csv,
-}
-
-impl RecordingFormat {
-    pub(crate) const ALL: [Self; 2] = [Self::Parquet, Self::Csv];
-
-    pub(crate) const fn label(self) -> &'static str {
-        match self {
-            Self::Parquet => "Parquet",
-            Self::Csv => "CSV",
-        }
-    }
-
-    pub(crate) const fn extension(self) -> &'static str {
-        match self {
-            Self::Parquet => "parquet",
-            Self::Csv => "csv",
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub(crate) struct RecordingMetadata {
-    pub(crate) model: String,
-    pub(crate) firmware: String,
-    pub(crate) serial: String,
-}
-
-#[derive(Debug, Clone, Copy)]
-struct RecordingOrigin {
-    elapsed_us: u64,
-    charge_uah: f64,
-    energy_uwh: f64,
-    charge_throughput_uah: f64,
-    energy_throughput_uwh: f64,
-    cumulative_missing_samples: u64,
-    cumulative_interpolated_duration_us: u64,
-    cumulative_discarded_sequence_samples: u64,
-}
-
-impl From<Option<MeasurementSample>> for RecordingOrigin {
-    fn from(sample: Option<MeasurementSample>) -> Self {
-        sample.map_or(
-            Self {
-                elapsed_us: 0,
-                charge_uah: 0.0,
-                energy_uwh: 0.0,
-                charge_throughput_uah: 0.0,
-                energy_throughput_uwh: 0.0,
-                cumulative_missing_samples: 0,
-                cumulative_interpolated_duration_us: 0,
-                cumulative_discarded_sequence_samples: 0,
-            },
-            |sample| Self {
-                elapsed_us: sample.elapsed_us,
-                charge_uah: sample.charge_uah,
-                energy_uwh: sample.energy_uwh,
-                charge_throughput_uah: sample.charge_throughput_uah,
-                energy_throughput_uwh: sample.energy_throughput_uwh,
-                cumulative_missing_samples: sample.cumulative_missing_samples,
-                cumulative_interpolated_duration_us: sample.cumulative_interpolated_duration_us,
-                cumulative_discarded_sequence_samples: sample.cumulative_discarded_sequence_samples,
-            },
-        )
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-struct RecordingRow {
-    elapsed_us: u64,
-    sample_index: u64,
-    sequence: u32,
-    marker: u32,
-    sample_rate_hz: u32,
-    missing_samples: u32,
-    gap_duration_us: u64,
-    interpolated: bool,
-    cumulative_missing_samples: u64,
-    cumulative_interpolated_duration_us: u64,
-    discarded_sequence_samples: u32,
-    cumulative_discarded_sequence_samples: u64,
-    vbus_uv: i64,
-    ibus_ua: i64,
-    power_uw: i64,
-    charge_uah: f64,
-    energy_uwh: f64,
-    charge_throughput_uah: f64,
-    energy_throughput_uwh: f64,
-    cc1_uv: i64,
-    cc2_uv: i64,
-    dp_uv: i64,
-    dm_uv: i64,
-}
-
-impl RecordingRow {
-    fn from_sample(sample: MeasurementSample, origin: RecordingOrigin, sample_index: u64) -> Self {
-        Self {
-            elapsed_us: sample.elapsed_us.saturating_sub(origin.elapsed_us),
-            sample_index,
-            sequence: u32::from(sample.sequence),
-            marker: u32::from(sample.marker),
-            sample_rate_hz: u32::from(sample.sample_rate_hz),
-            missing_samples: u32::from(sample.missing_samples),
-            gap_duration_us: sample.gap_duration_us,
-            interpolated: sample.interpolated,
-            cumulative_missing_samples: sample
-                .cumulative_missing_samples
-                .saturating_sub(origin.cumulative_missing_samples),
-            cumulative_interpolated_duration_us: sample
-                .cumulative_interpolated_duration_us
-                .saturating_sub(origin.cumulative_interpolated_duration_us),
-            discarded_sequence_samples: sample.discarded_sequence_samples,
-            cumulative_discarded_sequence_samples: sample
-                .cumulative_discarded_sequence_samples
-                .saturating_sub(origin.cumulative_discarded_sequence_samples),
-            vbus_uv: sample.vbus_uv,
-            ibus_ua: sample.ibus_ua,
-            power_uw: sample.power_uw,
-            charge_uah: sample.charge_uah - origin.charge_uah,
-            energy_uwh: sample.energy_uwh - origin.energy_uwh,
-            charge_throughput_uah: sample.charge_throughput_uah - origin.charge_throughput_uah,
-            energy_throughput_uwh: sample.energy_throughput_uwh - origin.energy_throughput_uwh,
-            cc1_uv: sample.cc1_uv,
-            cc2_uv: sample.cc2_uv,
-            dp_uv: sample.dp_uv,
-            dm_uv: sample.dm_uv,
-        }
-    }
-}
-
 enum WriterCommand {
     Rows(Vec<RecordingRow>),
     Finish,
@@ -169,6 +31,25 @@ pub(crate) struct RecordingSummary {
 }
 
 impl RecordingSummary {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            rows: 0,
+            elapsed_us: 0,
+            missing_samples: 0,
+            interpolated_duration_us: 0,
+            discarded_sequence_samples: 0,
+        }
+    }
+
+    fn update(&mut self, last: &RecordingRow) {
+        self.rows = last.sample_index + 1;
+        self.elapsed_us = last.elapsed_us;
+        self.missing_samples = last.cumulative_missing_samples.unwrap_or_default();
+        self.interpolated_duration_us = last.cumulative_interpolated_duration_us.unwrap_or_default();
+        self.discarded_sequence_samples = last.cumulative_discarded_sequence_samples.unwrap_or_default();
+    }
+
     pub(crate) fn completeness_percent(&self) -> f64 {
         if self.elapsed_us == 0 {
             100.0
@@ -178,20 +59,17 @@ impl RecordingSummary {
     }
 }
 
+/// Streams live measurements to a `RecordingWriter` on a background thread.
 pub(crate) struct Recorder {
     command_tx: SyncSender<WriterCommand>,
     event_rx: Receiver<RecordingEvent>,
     handle: Option<JoinHandle<()>>,
-    origin: RecordingOrigin,
+    origin: Option<MeasurementSample>,
     next_sample_index: u64,
     finishing: bool,
     interrupted: Option<String>,
     pub(crate) path: PathBuf,
-    pub(crate) rows: u64,
-    pub(crate) elapsed_us: u64,
-    pub(crate) missing_samples: u64,
-    pub(crate) interpolated_duration_us: u64,
-    pub(crate) discarded_sequence_samples: u64,
+    pub(crate) summary: RecordingSummary,
 }
 
 impl Recorder {
@@ -206,21 +84,14 @@ impl Recorder {
             return Err(format!("output directory does not exist: {}", parent.display()));
         }
 
-        let partial_path = partial_path(&path);
         let (command_tx, command_rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
         let (event_tx, event_rx) = mpsc::channel();
         let final_path = path.clone();
         let handle = thread::Builder::new()
             .name("km003c-recorder".to_string())
             .spawn(move || {
-                let result = run_writer(&partial_path, format, metadata, command_rx).and_then(|summary| {
-                    replace_file(&partial_path, &final_path)?;
-                    Ok(RecordingSummary {
-                        path: final_path,
-                        ..summary
-                    })
-                });
-                let event = match result {
+                let partial_path = partial_path(&final_path);
+                let event = match run_writer(final_path, format, &metadata, command_rx) {
                     Ok(summary) => RecordingEvent::Finished(summary),
                     Err(error) => RecordingEvent::Failed(format!(
                         "{error}; incomplete recording remains at {}",
@@ -235,16 +106,12 @@ impl Recorder {
             command_tx,
             event_rx,
             handle: Some(handle),
-            origin: origin.into(),
+            origin,
             next_sample_index: 0,
             finishing: false,
             interrupted: None,
+            summary: RecordingSummary::new(path.clone()),
             path,
-            rows: 0,
-            elapsed_us: 0,
-            missing_samples: 0,
-            interpolated_duration_us: 0,
-            discarded_sequence_samples: 0,
         })
     }
 
@@ -256,21 +123,18 @@ impl Recorder {
         let first_sample_index = self.next_sample_index;
         let rows = samples
             .iter()
-            .copied()
             .enumerate()
-            .map(|(offset, sample)| RecordingRow::from_sample(sample, self.origin, first_sample_index + offset as u64))
+            .map(|(offset, sample)| {
+                RecordingRow::from_measurement(sample, self.origin.as_ref(), first_sample_index + offset as u64)
+            })
             .collect::<Vec<_>>();
+        let last = rows.last().copied();
 
         match self.command_tx.try_send(WriterCommand::Rows(rows)) {
             Ok(()) => {
                 self.next_sample_index += samples.len() as u64;
-                if let Some(last) = samples.last() {
-                    let last = RecordingRow::from_sample(*last, self.origin, self.next_sample_index - 1);
-                    self.rows = self.next_sample_index;
-                    self.elapsed_us = last.elapsed_us;
-                    self.missing_samples = last.cumulative_missing_samples;
-                    self.interpolated_duration_us = last.cumulative_interpolated_duration_us;
-                    self.discarded_sequence_samples = last.cumulative_discarded_sequence_samples;
+                if let Some(last) = last {
+                    self.summary.update(&last);
                 }
                 Ok(())
             }
@@ -332,167 +196,38 @@ impl Drop for Recorder {
 }
 
 fn run_writer(
-    partial_path: &Path,
+    path: PathBuf,
     format: RecordingFormat,
-    metadata: RecordingMetadata,
+    metadata: &RecordingMetadata,
     command_rx: Receiver<WriterCommand>,
 ) -> Result<RecordingSummary, Box<dyn Error + Send + Sync>> {
-    let file = File::create(partial_path)?;
-    match format {
-        RecordingFormat::Parquet => write_parquet(file, metadata, command_rx, partial_path),
-        RecordingFormat::Csv => write_csv(file, command_rx, partial_path),
-    }
-}
-
-fn write_parquet(
-    file: File,
-    metadata: RecordingMetadata,
-    command_rx: Receiver<WriterCommand>,
-    partial_path: &Path,
-) -> Result<RecordingSummary, Box<dyn Error + Send + Sync>> {
-    let metadata = KeyValueMetadata::from_static(vec![
-        (
-            "km003c.schema_version".to_string(),
-            RECORDING_SCHEMA_VERSION.to_string(),
-        ),
-        ("km003c.source".to_string(), "live".to_string()),
-        ("km003c.accumulator_source".to_string(), "host_trapezoidal".to_string()),
-        ("km003c.model".to_string(), metadata.model),
-        ("km003c.firmware".to_string(), metadata.firmware),
-        ("km003c.serial".to_string(), metadata.serial),
-    ]);
-    let empty = rows_to_dataframe(&[])?;
-    let mut writer = ParquetWriter::new(BufWriter::new(file))
-        .with_key_value_metadata(Some(metadata))
-        .with_row_group_size(Some(ROW_GROUP_SIZE))
-        .batched(empty.schema())?;
-    let summary = consume_rows(
-        command_rx,
-        |rows| {
-            let dataframe = rows_to_dataframe(rows)?;
-            writer.write_batch(&dataframe)?;
-            Ok(())
-        },
-        partial_path,
-    )?;
-    writer.finish()?;
-    Ok(summary)
-}
-
-fn write_csv(
-    file: File,
-    command_rx: Receiver<WriterCommand>,
-    partial_path: &Path,
-) -> Result<RecordingSummary, Box<dyn Error + Send + Sync>> {
-    let empty = rows_to_dataframe(&[])?;
-    let mut writer = CsvWriter::new(BufWriter::new(file)).batched(empty.schema())?;
-    let summary = consume_rows(
-        command_rx,
-        |rows| {
-            let dataframe = rows_to_dataframe(rows)?;
-            writer.write_batch(&dataframe)?;
-            Ok(())
-        },
-        partial_path,
-    )?;
-    writer.finish()?;
-    Ok(summary)
-}
-
-fn consume_rows<F>(
-    command_rx: Receiver<WriterCommand>,
-    mut write: F,
-    partial_path: &Path,
-) -> Result<RecordingSummary, Box<dyn Error + Send + Sync>>
-where
-    F: FnMut(&[RecordingRow]) -> Result<(), Box<dyn Error + Send + Sync>>,
-{
-    let mut buffered = Vec::with_capacity(ROW_GROUP_SIZE);
-    let mut summary = RecordingSummary {
-        path: partial_path.to_path_buf(),
-        rows: 0,
-        elapsed_us: 0,
-        missing_samples: 0,
-        interpolated_duration_us: 0,
-        discarded_sequence_samples: 0,
-    };
-
+    let mut writer = RecordingWriter::create(path, format, metadata)?;
+    let mut summary = RecordingSummary::new(writer.partial_path().to_path_buf());
     loop {
         match command_rx.recv()? {
-            WriterCommand::Rows(mut rows) => {
+            WriterCommand::Rows(rows) => {
                 if let Some(last) = rows.last() {
-                    summary.rows = last.sample_index + 1;
-                    summary.elapsed_us = last.elapsed_us;
-                    summary.missing_samples = last.cumulative_missing_samples;
-                    summary.interpolated_duration_us = last.cumulative_interpolated_duration_us;
-                    summary.discarded_sequence_samples = last.cumulative_discarded_sequence_samples;
-                }
-                buffered.append(&mut rows);
-                if buffered.len() >= ROW_GROUP_SIZE {
-                    write(&buffered)?;
-                    buffered.clear();
+                    summary.update(last);
                 }
+                writer.write_rows(&rows)?;
             }
             WriterCommand::Finish => {
-                if !buffered.is_empty() {
-                    write(&buffered)?;
-                }
+                summary.path = writer.finish()?;
                 return Ok(summary);
             }
         }
     }
 }
 
-fn rows_to_dataframe(rows: &[RecordingRow]) -> Result<DataFrame, polars::error::PolarsError> {
-    df!(
-        "elapsed_us" => rows.iter().map(|row| row.elapsed_us).collect::<Vec<_>>(),
-        "sample_index" => rows.iter().map(|row| row.sample_index).collect::<Vec<_>>(),
-        "sequence" => rows.iter().map(|row| row.sequence).collect::<Vec<_>>(),
-        "marker" => rows.iter().map(|row| row.marker).collect::<Vec<_>>(),
-        "sample_rate_hz" => rows.iter().map(|row| row.sample_rate_hz).collect::<Vec<_>>(),
-        "missing_samples" => rows.iter().map(|row| row.missing_samples).collect::<Vec<_>>(),
-        "gap_duration_us" => rows.iter().map(|row| row.gap_duration_us).collect::<Vec<_>>(),
-        "interpolated" => rows.iter().map(|row| row.interpolated).collect::<Vec<_>>(),
-        "cumulative_missing_samples" => rows.iter().map(|row| row.cumulative_missing_samples).collect::<Vec<_>>(),
-        "cumulative_interpolated_duration_us" => rows.iter().map(|row| row.cumulative_interpolated_duration_us).collect::<Vec<_>>(),
-        "discarded_sequence_samples" => rows.iter().map(|row| row.discarded_sequence_samples).collect::<Vec<_>>(),
-        "cumulative_discarded_sequence_samples" => rows.iter().map(|row| row.cumulative_discarded_sequence_samples).collect::<Vec<_>>(),
-        "vbus_uv" => rows.iter().map(|row| row.vbus_uv).collect::<Vec<_>>(),
-        "ibus_ua" => rows.iter().map(|row| row.ibus_ua).collect::<Vec<_>>(),
-        "power_uw" => rows.iter().map(|row| row.power_uw).collect::<Vec<_>>(),
-        "charge_uah" => rows.iter().map(|row| row.charge_uah).collect::<Vec<_>>(),
-        "energy_uwh" => rows.iter().map(|row| row.energy_uwh).collect::<Vec<_>>(),
-        "charge_throughput_uah" => rows.iter().map(|row| row.charge_throughput_uah).collect::<Vec<_>>(),
-        "energy_throughput_uwh" => rows.iter().map(|row| row.energy_throughput_uwh).collect::<Vec<_>>(),
-        "cc1_uv" => rows.iter().map(|row| row.cc1_uv).collect::<Vec<_>>(),
-        "cc2_uv" => rows.iter().map(|row| row.cc2_uv).collect::<Vec<_>>(),
-        "dp_uv" => rows.iter().map(|row| row.dp_uv).collect::<Vec<_>>(),
-        "dm_uv" => rows.iter().map(|row| row.dm_uv).collect::<Vec<_>>(),
-    )
-}
-
-fn partial_path(path: &Path) -> PathBuf {
-    let mut name = path.as_os_str().to_os_string();
-    name.push(".partial");
-    PathBuf::from(name)
-}
-
-fn replace_file(partial: &Path, final_path: &Path) -> std::io::Result<()> {
-    if final_path.exists() {
-        std::fs::remove_file(final_path)?;
-    }
-    std::fs::rename(partial, final_path)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::measurement::MeasurementAccumulator;
+    use km003c_lib::polars::prelude::{ChunkAgg, CsvReader, ParquetReader, SerReader};
     use km003c_lib::{
-        DeviceConfig, GraphSampleRate, KM003C,
+        DeviceConfig, DeviceInfo, GraphSampleRate, KM003C, MeasurementAccumulator,
         packet::{Attribute, AttributeSet},
     };
-    use polars::prelude::{ChunkAgg, CsvReader, ParquetReader, SerReader};
+    use std::fs::File;
     use std::sync::atomic::{AtomicU64, Ordering};
     use std::time::{Duration, Instant};
 
@@ -526,18 +261,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn recording_rows_are_relative_to_the_start() {
-        let origin = RecordingOrigin::from(Some(sample(1_000_000, 2, 40_000)));
-        let row = RecordingRow::from_sample(sample(2_000_000, 3, 60_000), origin, 0);
-
-        assert_eq!(row.elapsed_us, 1_000_000);
-        assert_eq!(row.sample_index, 0);
-        assert_eq!(row.cumulative_missing_samples, 1);
-        assert_eq!(row.cumulative_interpolated_duration_us, 20_000);
-        assert_eq!(row.charge_uah, 0.0);
-    }
-
     #[test]
     fn completeness_reports_interpolated_time_fraction() {
         let summary = RecordingSummary {
@@ -552,46 +275,44 @@ mod tests {
     }
 
     #[test]
-    fn dataframe_uses_the_stable_recording_schema() {
-        let row = RecordingRow::from_sample(sample(1_000, 0, 0), RecordingOrigin::from(None), 0);
-        let dataframe = rows_to_dataframe(&[row]).unwrap();
-
-        assert_eq!(dataframe.height(), 1);
-        assert_eq!(dataframe.width(), 23);
-        assert_eq!(
-            dataframe.column("vbus_uv").unwrap().i64().unwrap().get(0),
-            Some(5_000_000)
-        );
-    }
+    fn recorder_writes_rows_relative_to_the_origin() {
+        for format in RecordingFormat::ALL {
+            let path = test_path(format.extension());
+            let metadata = RecordingMetadata::live(&DeviceInfo::default());
+            let origin = sample(1_000_000, 2, 40_000);
+            let mut recorder = Recorder::start(path.clone(), format, metadata, Some(origin)).unwrap();
+            recorder
+                .push(&[sample(1_020_000, 2, 40_000), sample(1_060_000, 3, 60_000)])
+                .unwrap();
+            assert_eq!(recorder.summary.rows, 2);
+            assert_eq!(recorder.summary.missing_samples, 1);
+            recorder.request_finish().unwrap();
 
-    #[test]
-    fn parquet_writer_produces_a_readable_recording() {
-        let path = test_path("parquet");
-        let summary = write_test_recording(&path, RecordingFormat::Parquet);
-        let dataframe = ParquetReader::new(File::open(&path).unwrap()).finish().unwrap();
-
-        assert_eq!(summary.rows, 2);
-        assert_eq!(dataframe.shape(), (2, 23));
-        assert_eq!(
-            dataframe.column("elapsed_us").unwrap().u64().unwrap().get(1),
-            Some(20_000)
-        );
-        std::fs::remove_file(path).unwrap();
-    }
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let summary = loop {
+                match recorder.poll_event() {
+                    Some(RecordingEvent::Finished(summary)) => break summary,
+                    Some(event) => panic!("recording failed: {event:?}"),
+                    None if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+                    None => panic!("recording did not finish before the deadline"),
+                }
+            };
+            let dataframe = match format {
+                RecordingFormat::Parquet => ParquetReader::new(File::open(&path).unwrap()).finish().unwrap(),
+                RecordingFormat::Csv => CsvReader::new(File::open(&path).unwrap()).finish().unwrap(),
+            };
+            std::fs::remove_file(&path).unwrap();
 
-    #[test]
-    fn csv_writer_produces_a_readable_recording() {
-        let path = test_path("csv");
-        let summary = write_test_recording(&path, RecordingFormat::Csv);
-        let dataframe = CsvReader::new(File::open(&path).unwrap()).finish().unwrap();
-
-        assert_eq!(summary.rows, 2);
-        assert_eq!(dataframe.shape(), (2, 23));
-        assert_eq!(
-            dataframe.column("vbus_uv").unwrap().i64().unwrap().get(0),
-            Some(5_000_000)
-        );
-        std::fs::remove_file(path).unwrap();
+            assert_eq!(summary.path, path);
+            assert_eq!(summary.rows, 2);
+            assert_eq!(summary.elapsed_us, 60_000);
+            assert_eq!(summary.interpolated_duration_us, 20_000);
+            assert_eq!(dataframe.shape(), (2, 23));
+            assert_eq!(
+                dataframe.column("vbus_uv").unwrap().i64().unwrap().get(0),
+                Some(5_000_000)
+            );
+        }
     }
 
     #[test]
@@ -600,11 +321,7 @@ mod tests {
         tokio::runtime::Runtime::new().unwrap().block_on(async {
             let mut device = KM003C::new(DeviceConfig::vendor()).await.unwrap();
             let state = device.state().unwrap();
-            let metadata = RecordingMetadata {
-                model: state.info.model.clone(),
-                firmware: state.info.fw_version.clone(),
-                serial: state.info.serial_id.clone(),
-            };
+            let metadata = RecordingMetadata::live(&state.info);
             let path = test_path("hardware.parquet");
             let mut recorder = Recorder::start(path.clone(), RecordingFormat::Parquet, metadata, None).unwrap();
             let rate = GraphSampleRate::Sps1000;
@@ -729,28 +446,6 @@ mod tests {
         });
     }
 
-    fn write_test_recording(path: &Path, format: RecordingFormat) -> RecordingSummary {
-        let (command_tx, command_rx) = mpsc::sync_channel(2);
-        let rows = vec![
-            RecordingRow::from_sample(sample(0, 0, 0), RecordingOrigin::from(None), 0),
-            RecordingRow::from_sample(sample(20_000, 1, 20_000), RecordingOrigin::from(None), 1),
-        ];
-        command_tx.send(WriterCommand::Rows(rows)).unwrap();
-        command_tx.send(WriterCommand::Finish).unwrap();
-
-        run_writer(
-            path,
-            format,
-            RecordingMetadata {
-                model: "KM003C".to_string(),
-                firmware: "1.9.9".to_string(),
-                serial: "test".to_string(),
-            },
-            command_rx,
-        )
-        .unwrap()
-    }
-
     fn test_path(extension: &str) -> PathBuf {
         let unique = NEXT_TEST_FILE.fetch_add(1, Ordering::Relaxed);
         std::env::temp_dir().join(format!(
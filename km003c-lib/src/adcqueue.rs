@@ -1,5 +1,6 @@
 use num_enum::TryFromPrimitive;
 use std::fmt;
+use std::time::Duration;
 use uom::si::electric_current::microampere;
 use uom::si::electric_potential::{microvolt, millivolt};
 use uom::si::f64::{ElectricCurrent, ElectricPotential, Frequency, Power, Time};
@@ -35,7 +36,8 @@ pub enum GraphSampleRate {
 impl GraphSampleRate {
     const SEQUENCE_TICKS_PER_SECOND: u16 = 1000;
 
-    const fn sample_count_per_second(self) -> u16 {
+    /// Samples the device produces per second at this rate.
+    pub const fn samples_per_second(self) -> u16 {
         match self {
             Self::Sps2 => 2,
             Self::Sps10 => 10,
@@ -46,12 +48,12 @@ impl GraphSampleRate {
 
     /// Expected increment of the device's 1000 Hz sequence counter.
     pub const fn sequence_step(self) -> u16 {
-        Self::SEQUENCE_TICKS_PER_SECOND / self.sample_count_per_second()
+        Self::SEQUENCE_TICKS_PER_SECOND / self.samples_per_second()
     }
 
     /// Sampling frequency represented as a typed physical quantity.
     pub fn frequency(self) -> Frequency {
-        Frequency::new::<hertz>(f64::from(self.sample_count_per_second()))
+        Frequency::new::<hertz>(f64::from(self.samples_per_second()))
     }
 
     /// Frequency of the wrapping sequence counter used by graph samples.
@@ -70,6 +72,27 @@ impl GraphSampleRate {
         }
     }
 
+    /// Rate producing `samples` samples per second, if the device supports it.
+    pub const fn from_samples_per_second(samples: u16) -> Option<Self> {
+        match samples {
+            2 => Some(Self::Sps2),
+            10 => Some(Self::Sps10),
+            50 => Some(Self::Sps50),
+            1000 => Some(Self::Sps1000),
+            _ => None,
+        }
+    }
+
+    /// Interval between AdcQueue polls that keeps up with the device buffer.
+    pub const fn poll_interval(self) -> Duration {
+        Duration::from_millis(match self {
+            Self::Sps2 => 200,
+            Self::Sps10 => 50,
+            Self::Sps50 => 20,
+            Self::Sps1000 => 5,
+        })
+    }
+
     /// Infer the configured rate from a contiguous sequence-counter step.
     pub const fn from_sequence_step(step: u16) -> Option<Self> {
         match step {
@@ -79,6 +79,7 @@ pub const STREAMING_AUTH_CREDENTIAL_SIZE: usize = 12;
 /// - Bytes 8-9: Device ID (little-endian u16)
 /// - Bytes 10-11: Padding (typically 0xFF 0xFF)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct HardwareId {
     pub bytes: [u8; HARDWARE_ID_SIZE],
 }
@@ -121,6 +122,7 @@ impl std::fmt::Display for HardwareId {
 /// Firmware V1.9.9 accepts the device HardwareID for auth level 1 and the
 /// beginning of its selected calibration record for auth level 2.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct AuthCredential {
     bytes: [u8; STREAMING_AUTH_CREDENTIAL_SIZE],
 }
@@ -215,6 +217,11 @@ impl DeviceInfo {
     }
 }
 
+/// Whether a calibration record is erased flash and must not be used as a credential.
+pub(crate) fn calibration_record_is_erased(record: &[u8]) -> bool {
+    record.starts_with(&[0xff; 4])
+}
+
 /// Extract null-terminated string from byte slice
 fn extract_string(data: &[u8], start: usize, end: usize) -> String {
     if start >= data.len() || end > data.len() || start >= end {
@@ -228,6 +235,7 @@ fn extract_string(data: &[u8], start: usize, end: usize) -> String {
 
 /// Result of StreamingAuth command
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct StreamingAuthResult {
     /// Whether authentication was successful (AdcQueue access granted)
     pub success: bool,
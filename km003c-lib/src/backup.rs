@@ -0,0 +1,378 @@
+//! Full memory backup images and offline extraction of device records.
+//!
+//! A backup image captures every memory region the KM003C is known to expose,
+//! together with the offline log catalog, so device information, calibration
+//! and recordings can be recovered later without the meter attached.
+//!
+//! Image layout (all integers little-endian):
+//!
+//! | Offset | Size         | Content                                            |
+//! |--------|--------------|----------------------------------------------------|
+//! | 0x00   | 8            | Magic `KM003CBK`                                   |
+//! | 0x08   | 2            | Format version (`1`)                               |
+//! | 0x0A   | 2            | Region count `n`                                   |
+//! | 0x0C   | 4            | CRC-32 of the manifest                             |
+//! | 0x10   | 20 × `n`     | Manifest: kind, reserved, address, offset, length, CRC-32 |
+//! | …      | …            | Region data at the manifest offsets                |
+
+use std::path::Path;
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+use zerocopy::byteorder::little_endian::{U16, U32};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
+
+use crate::auth::{
+    AuthCredential, CALIBRATION_ADDRESS, DEVICE_INFO_ADDRESS, DeviceInfo, FIRMWARE_INFO_ADDRESS, HARDWARE_ID_ADDRESS,
+    HARDWARE_ID_SIZE, HardwareId, INFO_BLOCK_SIZE, PREFERRED_CALIBRATION_ADDRESS, STREAMING_AUTH_CREDENTIAL_SIZE,
+    calibration_record_is_erased,
+};
+use crate::error::KMError;
+use crate::offline::{LOG_METADATA_SIZE, LogMetadata, OFFLINE_LOG_ADDRESS, OfflineLog};
+
+/// Magic bytes at the start of every backup image.
+pub const BACKUP_IMAGE_MAGIC: &[u8; 8] = b"KM003CBK";
+/// Backup image format version written by this library.
+pub const BACKUP_IMAGE_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct BackupHeaderWire {
+    magic: [u8; 8],
+    version: U16,
+    region_count: U16,
+    manifest_crc: U32,
+}
+
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct BackupManifestEntryWire {
+    kind: U16,
+    reserved: U16,
+    address: U32,
+    offset: U32,
+    length: U32,
+    crc: U32,
+}
+
+const HEADER_SIZE: usize = size_of::<BackupHeaderWire>();
+const MANIFEST_ENTRY_SIZE: usize = size_of::<BackupManifestEntryWire>();
+
+/// Content of one backup region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u16)]
+pub enum BackupRegionKind {
+    /// DeviceInfo1 block at `0x420`.
+    DeviceInfo = 1,
+    /// FirmwareInfo block at `0x4420`.
+    FirmwareInfo = 2,
+    /// Calibration record at `0x03000C00`.
+    Calibration = 3,
+    /// Calibration record at `0x03000D80`, preferred by firmware V1.9.9.
+    PreferredCalibration = 4,
+    /// HardwareID at `0x40010450`.
+    HardwareId = 5,
+    /// Concatenated `LogMetadata` entries; not backed by a memory address.
+    LogCatalog = 6,
+    /// Offline log sample data starting at `OFFLINE_LOG_ADDRESS`.
+    OfflineLogArea = 7,
+    #[num_enum(catch_all)]
+    Unknown(u16),
+}
+
+impl BackupRegionKind {
+    /// Fixed-size memory regions read for every backup, in read order.
+    pub const FIXED: [Self; 5] = [
+        Self::DeviceInfo,
+        Self::FirmwareInfo,
+        Self::Calibration,
+        Self::PreferredCalibration,
+        Self::HardwareId,
+    ];
+
+    /// Device address and size of a fixed-size memory region.
+    pub const fn fixed_region(self) -> Option<(u32, u32)> {
+        match self {
+            Self::DeviceInfo => Some((DEVICE_INFO_ADDRESS, INFO_BLOCK_SIZE as u32)),
+            Self::FirmwareInfo => Some((FIRMWARE_INFO_ADDRESS, INFO_BLOCK_SIZE as u32)),
+            Self::Calibration => Some((CALIBRATION_ADDRESS, INFO_BLOCK_SIZE as u32)),
+            Self::PreferredCalibration => Some((PREFERRED_CALIBRATION_ADDRESS, INFO_BLOCK_SIZE as u32)),
+            Self::HardwareId => Some((HARDWARE_ID_ADDRESS, HARDWARE_ID_SIZE as u32)),
+            Self::LogCatalog | Self::OfflineLogArea | Self::Unknown(_) => None,
+        }
+    }
+}
+
+/// One region stored in a backup image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct BackupRegion {
+    pub kind: BackupRegionKind,
+    /// Device address the data was read from, or 0 for the log catalog.
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl BackupRegion {
+    pub fn new(kind: BackupRegionKind, address: u32, data: Vec<u8>) -> Self {
+        Self { kind, address, data }
+    }
+
+    /// CRC-32 of the region data, as stored in the manifest.
+    pub fn checksum(&self) -> u32 {
+        crc32fast::hash(&self.data)
+    }
+}
+
+/// A snapshot of the readable device memory and offline recordings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct BackupImage {
+    pub regions: Vec<BackupRegion>,
+}
+
+impl BackupImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a region, replacing any previous region of the same kind.
+    pub fn insert(&mut self, region: BackupRegion) {
+        match self.regions.iter_mut().find(|existing| existing.kind == region.kind) {
+            Some(existing) => *existing = region,
+            None => self.regions.push(region),
+        }
+    }
+
+    pub fn region(&self, kind: BackupRegionKind) -> Option<&BackupRegion> {
+        self.regions.iter().find(|region| region.kind == kind)
+    }
+
+    /// Store the offline log catalog and the sample data covering every entry.
+    ///
+    /// `area` holds device memory from `OFFLINE_LOG_ADDRESS` onwards and must
+    /// reach the end of the last log.
+    pub fn set_offline_logs(&mut self, metadata: &[LogMetadata], area: Vec<u8>) -> Result<(), KMError> {
+        let required = offline_log_area_size(metadata)?;
+        if (area.len() as u64) < u64::from(required) {
+            return Err(KMError::InvalidPacket(format!(
+                "Offline log area is {} bytes but the catalog needs {required}",
+                area.len()
+            )));
+        }
+        let catalog = metadata.iter().flat_map(LogMetadata::to_bytes).collect();
+        self.insert(BackupRegion::new(BackupRegionKind::LogCatalog, 0, catalog));
+        self.insert(BackupRegion::new(
+            BackupRegionKind::OfflineLogArea,
+            OFFLINE_LOG_ADDRESS,
+            area,
+        ));
+        Ok(())
+    }
+
+    /// Device, firmware and calibration information parsed like a live device.
+    pub fn device_info(&self) -> DeviceInfo {
+        let mut info = DeviceInfo::default();
+        if let Some(region) = self.region(BackupRegionKind::DeviceInfo) {
+            info.parse_device_info(&region.data);
+        }
+        if let Some(region) = self.region(BackupRegionKind::FirmwareInfo) {
+            info.parse_firmware_info(&region.data);
+        }
+        if let Some(region) = self.region(BackupRegionKind::Calibration) {
+            info.parse_calibration(&region.data);
+        }
+        info
+    }
+
+    pub fn hardware_id(&self) -> Option<HardwareId> {
+        let data = &self.region(BackupRegionKind::HardwareId)?.data;
+        Some(HardwareId::from_bytes(data.get(..HARDWARE_ID_SIZE)?.try_into().ok()?))
+    }
+
+    /// Calibration credential selected the same way as
+    /// [`KM003C::authenticate_calibration`](crate::KM003C::authenticate_calibration).
+    pub fn calibration_credential(&self) -> Option<AuthCredential> {
+        let credential = |kind| -> Option<[u8; STREAMING_AUTH_CREDENTIAL_SIZE]> {
+            let data = &self.region(kind)?.data;
+            data.get(..STREAMING_AUTH_CREDENTIAL_SIZE)?.try_into().ok()
+        };
+        let bytes = match credential(BackupRegionKind::PreferredCalibration) {
+            Some(preferred) if !calibration_record_is_erased(&preferred) => preferred,
+            _ => credential(BackupRegionKind::Calibration)?,
+        };
+        Some(AuthCredential::from_bytes(bytes))
+    }
+
+    /// Offline log catalog; empty when the device had no recordings.
+    pub fn log_metadata(&self) -> Result<Vec<LogMetadata>, KMError> {
+        let Some(region) = self.region(BackupRegionKind::LogCatalog) else {
+            return Ok(Vec::new());
+        };
+        if region.data.len() % LOG_METADATA_SIZE != 0 {
+            return Err(KMError::InvalidPacket(format!(
+                "Log catalog length {} is not a multiple of {LOG_METADATA_SIZE}",
+                region.data.len()
+            )));
+        }
+        region
+            .data
+            .chunks_exact(LOG_METADATA_SIZE)
+            .map(LogMetadata::from_bytes)
+            .collect()
+    }
+
+    /// Decode every offline log listed in the catalog.
+    pub fn offline_logs(&self) -> Result<Vec<OfflineLog>, KMError> {
+        let metadata = self.log_metadata()?;
+        if metadata.is_empty() {
+            return Ok(Vec::new());
+        }
+        let area = self
+            .region(BackupRegionKind::OfflineLogArea)
+            .ok_or_else(|| KMError::InvalidPacket("Backup has a log catalog but no offline log data".to_string()))?;
+        metadata
+            .into_iter()
+            .map(|metadata| {
+                let start = metadata.data_offset as usize;
+                let data = start
+                    .checked_add(metadata.data_size() as usize)
+                    .and_then(|end| area.data.get(start..end))
+                    .ok_or_else(|| {
+                        KMError::InvalidPacket(format!(
+                            "Offline log {} lies outside the backed-up log area",
+                            metadata.filename_lossy()
+                        ))
+                    })?;
+                OfflineLog::from_bytes(metadata, data)
+            })
+            .collect()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KMError> {
+        let region_count = u16::try_from(self.regions.len()).map_err(|_| {
+            KMError::InvalidPacket(format!("Backup has {} regions, at most 65535 fit", self.regions.len()))
+        })?;
+        let mut offset = HEADER_SIZE + self.regions.len() * MANIFEST_ENTRY_SIZE;
+        let mut manifest = Vec::with_capacity(self.regions.len() * MANIFEST_ENTRY_SIZE);
+        for region in &self.regions {
+            let too_large = || {
+                KMError::InvalidPacket(format!(
+                    "Backup region {:?} at offset {offset} does not fit in a 4 GiB image",
+                    region.kind
+                ))
+            };
+            let entry = BackupManifestEntryWire {
+                kind: U16::new(region.kind.into()),
+                reserved: U16::new(0),
+                address: U32::new(region.address),
+                offset: U32::new(u32::try_from(offset).map_err(|_| too_large())?),
+                length: U32::new(u32::try_from(region.data.len()).map_err(|_| too_large())?),
+                crc: U32::new(region.checksum()),
+            };
+            manifest.extend_from_slice(entry.as_bytes());
+            offset += region.data.len();
+        }
+
+        let header = BackupHeaderWire {
+            magic: *BACKUP_IMAGE_MAGIC,
+            version: U16::new(BACKUP_IMAGE_VERSION),
+            region_count: U16::new(region_count),
+            manifest_crc: U32::new(crc32fast::hash(&manifest)),
+        };
+        let mut bytes = Vec::with_capacity(offset);
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&manifest);
+        for region in &self.regions {
+            bytes.extend_from_slice(&region.data);
+        }
+        Ok(bytes)
+    }
+
+    /// Parse an image, verifying the manifest and every region checksum.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KMError> {
+        let (header, rest) = BackupHeaderWire::ref_from_prefix(bytes).map_err(|_| KMError::ParseError {
+            offset: 0,
+            message: format!("Backup image is shorter than its {HEADER_SIZE}-byte header"),
+        })?;
+        if header.magic != *BACKUP_IMAGE_MAGIC {
+            return Err(KMError::ParseError {
+                offset: 0,
+                message: "Not a KM003C backup image".to_string(),
+            });
+        }
+        if header.version.get() != BACKUP_IMAGE_VERSION {
+            return Err(KMError::ParseError {
+                offset: 8,
+                message: format!("Unsupported backup image version {}", header.version.get()),
+            });
+        }
+
+        let count = usize::from(header.region_count.get());
+        let manifest = rest
+            .get(..count * MANIFEST_ENTRY_SIZE)
+            .ok_or_else(|| KMError::ParseError {
+                offset: HEADER_SIZE,
+                message: format!("Backup manifest of {count} regions is truncated"),
+            })?;
+        if crc32fast::hash(manifest) != header.manifest_crc.get() {
+            return Err(KMError::ParseError {
+                offset: HEADER_SIZE,
+                message: "Backup manifest checksum mismatch".to_string(),
+            });
+        }
+
+        let regions = manifest
+            .chunks_exact(MANIFEST_ENTRY_SIZE)
+            .enumerate()
+            .map(|(index, entry)| {
+                let entry_offset = HEADER_SIZE + index * MANIFEST_ENTRY_SIZE;
+                let entry = BackupManifestEntryWire::ref_from_bytes(entry).map_err(|_| KMError::ParseError {
+                    offset: entry_offset,
+                    message: "Failed to parse backup manifest entry".to_string(),
+                })?;
+                let kind = BackupRegionKind::from(entry.kind.get());
+                let start = entry.offset.get() as usize;
+                let data = start
+                    .checked_add(entry.length.get() as usize)
+                    .and_then(|end| bytes.get(start..end))
+                    .ok_or_else(|| KMError::ParseError {
+                        offset: entry_offset,
+                        message: format!("{kind:?} region extends past the end of the image"),
+                    })?;
+                if crc32fast::hash(data) != entry.crc.get() {
+                    return Err(KMError::ParseError {
+                        offset: start,
+                        message: format!("{kind:?} region checksum mismatch"),
+                    });
+                }
+                Ok(BackupRegion::new(kind, entry.address.get(), data.to_vec()))
+            })
+            .collect::<Result<Vec<_>, KMError>>()?;
+        Ok(Self { regions })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), KMError> {
+        Ok(std::fs::write(path, self.to_bytes()?)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KMError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Bytes from `OFFLINE_LOG_ADDRESS` needed to cover every log in a catalog.
+pub fn offline_log_area_size(metadata: &[LogMetadata]) -> Result<u32, KMError> {
+    metadata.iter().try_fold(0, |size: u32, metadata| {
+        metadata
+            .data_offset
+            .checked_add(metadata.data_size())
+            .map(|end| size.max(end))
+            .ok_or_else(|| {
+                KMError::Protocol(format!(
+                    "Offline log data offset 0x{:08X} overflows the log area",
+                    metadata.data_offset
+                ))
+            })
+    })
+}
@@ -0,0 +1,467 @@
+//! TCP bridge sharing one meter with remote clients.
+//!
+//! [`BridgeServer`] owns the connection to a meter, normally a [`KM003C`] on
+//! USB, and forwards protocol transfers from any number of TCP clients.
+//! Clients open the meter with [`DeviceConfig::remote`] and then use
+//! [`KM003C`] exactly as they would locally.
+//!
+//! # Wire format
+//!
+//! Every message is a frame: a little-endian `u32` length, a kind byte and
+//! the payload, where the length counts the kind byte and the payload.
+//!
+//! 1. The server sends `Hello`: magic `KM3B`, the protocol version and a
+//!    16-byte random challenge.
+//! 2. The client answers `Auth`: HMAC-SHA256 of the challenge keyed with the
+//!    SHA-256 digest of the pre-shared key, or an empty payload without a key.
+//! 3. The server answers `Ready` with the USB interface it opened (0 for the
+//!    vendor interface, 3 for HID), or `Error` and closes the connection.
+//!
+//! Afterwards both sides exchange `Transfer` frames holding one protocol
+//! transfer each, exactly as read from or written to the USB endpoint. The
+//! server reports meter errors while handling a request as an `Error` frame.
+//!
+//! # Sharing the meter
+//!
+//! Requests from different clients are handled one at a time. The server sends
+//! each request under a fresh transaction ID, forwards the response carrying
+//! that ID with the client's own ID restored, followed by the encrypted data
+//! of a successful MemoryRead, and discards any other transfer. Requests the
+//! meter does not answer hold it for [`EXCHANGE_TIMEOUT`], so clients wait up
+//! to [`BRIDGE_TIMEOUT`] by default to cover the time queued behind others.
+//! Graph mode, the PD monitor and the authentication level remain shared by
+//! all clients.
+//!
+//! The key only authenticates clients; transfers are not encrypted, so use a
+//! VPN or SSH tunnel across untrusted networks.
+//!
+//! [`DeviceConfig::remote`]: crate::device::DeviceConfig::remote
+
+use std::future::Future;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, KeyInit, Mac};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::time::{Instant, timeout};
+use tracing::{debug, info, warn};
+
+use crate::auth::parse_memory_read_payload;
+use crate::device::{KM003C, memory_response_size, parse_framed_response};
+use crate::error::KMError;
+use crate::packet::PacketType;
+
+pub const BRIDGE_DEFAULT_PORT: u16 = 7003;
+
+/// Time the meter has to answer one forwarded request.
+pub const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default time a client waits for a response, see [`DeviceConfig::bridge_timeout`].
+///
+/// [`DeviceConfig::bridge_timeout`]: crate::device::DeviceConfig::bridge_timeout
+pub const BRIDGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAGIC: &[u8; 4] = b"KM3B";
+const VERSION: u8 = 1;
+const CHALLENGE_SIZE: usize = 16;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest accepted frame; a whole offline log area fits comfortably.
+const MAX_FRAME_SIZE: usize = 16 << 20;
+/// Largest accepted Hello, Auth, Ready or handshake Error frame, so an
+/// unauthenticated peer cannot make the other end allocate a full frame.
+const HANDSHAKE_FRAME_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+enum FrameKind {
+    Hello = 1,
+    Auth = 2,
+    Ready = 3,
+    Transfer = 4,
+    Error = 5,
+}
+
+/// Address, key digest and response timeout of a bridge server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BridgeTarget {
+    pub(crate) address: SocketAddr,
+    pub(crate) timeout: Duration,
+    key: Option<[u8; 32]>,
+}
+
+impl BridgeTarget {
+    /// Resolve a `tcp://host[:port]` URL.
+    pub(crate) fn parse(url: &str) -> Result<Self, KMError> {
+        let authority = url
+            .strip_prefix("tcp://")
+            .ok_or_else(|| KMError::Protocol(format!("Bridge URL {url:?} must start with tcp://")))?
+            .trim_end_matches('/');
+        let mut addresses = match authority.to_socket_addrs() {
+            Ok(addresses) => addresses,
+            // No port given.
+            Err(_) => format!("{authority}:{BRIDGE_DEFAULT_PORT}").to_socket_addrs()?,
+        };
+        let address = addresses
+            .next()
+            .ok_or_else(|| KMError::Protocol(format!("Bridge host in {url:?} did not resolve")))?;
+        Ok(Self {
+            address,
+            timeout: BRIDGE_TIMEOUT,
+            key: None,
+        })
+    }
+
+    pub(crate) fn with_key(self, key: &[u8]) -> Self {
+        Self {
+            key: Some(key_digest(key)),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+}
+
+fn key_digest(key: &[u8]) -> [u8; 32] {
+    Sha256::digest(key).into()
+}
+
+fn sign(key: &[u8; 32], challenge: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(challenge);
+    mac
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, kind: FrameKind, payload: &[u8]) -> Result<(), KMError> {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32 + 1).to_le_bytes());
+    frame.push(kind.into());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+fn frame_length(header: [u8; 4], max_size: usize) -> Result<usize, KMError> {
+    let length = u32::from_le_bytes(header) as usize;
+    if length == 0 || length > max_size {
+        return Err(KMError::Protocol(format!(
+            "Bridge frame has an invalid length of {length} bytes"
+        )));
+    }
+    Ok(length)
+}
+
+/// Split a frame body into its kind and payload.
+fn split_frame(mut frame: Vec<u8>) -> Result<(FrameKind, Vec<u8>), KMError> {
+    let payload = frame.split_off(1);
+    let kind = FrameKind::try_from(frame[0])
+        .map_err(|_| KMError::Protocol(format!("Unknown bridge frame kind {}", frame[0])))?;
+    Ok((kind, payload))
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, max_size: usize) -> Result<(FrameKind, Vec<u8>), KMError> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header).await?;
+    let mut frame = vec![0; frame_length(header, max_size)?];
+    reader.read_exact(&mut frame).await?;
+    split_frame(frame)
+}
+
+/// Send one protocol transfer to the bridge server.
+pub(crate) async fn write_transfer(writer: &mut OwnedWriteHalf, data: &[u8]) -> Result<(), KMError> {
+    write_frame(writer, FrameKind::Transfer, data).await
+}
+
+/// Receiving half of a client connection.
+///
+/// Bytes are buffered until a whole frame has arrived, so a read cancelled by
+/// a timeout leaves the stream in sync and the next read resumes the frame.
+pub(crate) struct BridgeReader {
+    reader: OwnedReadHalf,
+    buffer: Vec<u8>,
+}
+
+impl BridgeReader {
+    fn new(reader: OwnedReadHalf) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Receive one protocol transfer forwarded by the bridge server.
+    ///
+    /// Cancellation safe.
+    pub(crate) async fn read_transfer(&mut self) -> Result<Vec<u8>, KMError> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return match split_frame(frame)? {
+                    (FrameKind::Transfer, data) => Ok(data),
+                    (FrameKind::Error, message) => Err(KMError::Protocol(format!(
+                        "Bridge: {}",
+                        String::from_utf8_lossy(&message)
+                    ))),
+                    (kind, _) => Err(KMError::Protocol(format!("Unexpected bridge frame {kind:?}"))),
+                };
+            }
+            // `read_buf` either appends what it read or nothing when cancelled.
+            self.buffer.reserve(4096);
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
+    /// Remove the first frame body from the buffer once it is complete.
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, KMError> {
+        let Some(header) = self.buffer.first_chunk::<4>() else {
+            return Ok(None);
+        };
+        let end = 4 + frame_length(*header, MAX_FRAME_SIZE)?;
+        if self.buffer.len() < end {
+            return Ok(None);
+        }
+        let frame = self.buffer[4..end].to_vec();
+        self.buffer.drain(..end);
+        Ok(Some(frame))
+    }
+}
+
+/// Connect and authenticate to a bridge server.
+///
+/// Returns the stream halves and the USB interface the server opened.
+pub(crate) async fn connect(target: BridgeTarget) -> Result<(BridgeReader, OwnedWriteHalf, u8), KMError> {
+    let stream = timeout(HANDSHAKE_TIMEOUT, TcpStream::connect(target.address)).await??;
+    stream.set_nodelay(true)?;
+    let (mut reader, mut writer) = stream.into_split();
+
+    let hello = match timeout(HANDSHAKE_TIMEOUT, read_frame(&mut reader, HANDSHAKE_FRAME_SIZE)).await?? {
+        (FrameKind::Hello, hello) => hello,
+        (kind, _) => return Err(KMError::Protocol(format!("Expected bridge Hello, got {kind:?}"))),
+    };
+    if hello.len() != MAGIC.len() + 1 + CHALLENGE_SIZE || !hello.starts_with(MAGIC) {
+        return Err(KMError::Protocol(format!("{} is not a KM003C bridge", target.address)));
+    }
+    if hello[4] != VERSION {
+        return Err(KMError::Protocol(format!(
+            "Bridge protocol version {} is not supported, expected {VERSION}",
+            hello[4]
+        )));
+    }
+    let challenge = &hello[5..];
+    let response = match &target.key {
+        Some(key) => sign(key, challenge).finalize().into_bytes().to_vec(),
+        None => Vec::new(),
+    };
+    write_frame(&mut writer, FrameKind::Auth, &response).await?;
+
+    match timeout(HANDSHAKE_TIMEOUT, read_frame(&mut reader, HANDSHAKE_FRAME_SIZE)).await?? {
+        (FrameKind::Ready, ready) if ready.len() == 1 => Ok((BridgeReader::new(reader), writer, ready[0])),
+        (FrameKind::Error, message) => Err(KMError::Protocol(format!(
+            "Bridge refused the connection: {}",
+            String::from_utf8_lossy(&message)
+        ))),
+        (kind, _) => Err(KMError::Protocol(format!("Expected bridge Ready, got {kind:?}"))),
+    }
+}
+
+/// Connection to the meter that a [`BridgeServer`] forwards transfers to.
+pub trait BridgeDevice: Send + 'static {
+    /// Allocate a transaction ID for the next request.
+    fn next_tid(&mut self) -> u8;
+
+    /// Write one protocol transfer.
+    fn send_raw(&mut self, data: &[u8]) -> impl Future<Output = Result<(), KMError>> + Send;
+
+    /// Read one protocol transfer.
+    fn receive_raw(&mut self) -> impl Future<Output = Result<Vec<u8>, KMError>> + Send;
+}
+
+impl BridgeDevice for KM003C {
+    fn next_tid(&mut self) -> u8 {
+        KM003C::next_tid(self)
+    }
+
+    fn send_raw(&mut self, data: &[u8]) -> impl Future<Output = Result<(), KMError>> + Send {
+        KM003C::send_raw(self, data)
+    }
+
+    fn receive_raw(&mut self) -> impl Future<Output = Result<Vec<u8>, KMError>> + Send {
+        KM003C::receive_raw(self)
+    }
+}
+
+/// Serves one meter to TCP clients.
+///
+/// # Examples
+///
+/// ```no_run
+/// use km003c_lib::{BridgeServer, DeviceConfig, KM003C};
+/// use km003c_lib::device::INTERFACE_VENDOR;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let device = KM003C::new(DeviceConfig::vendor()).await?;
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:7003").await?;
+/// BridgeServer::new(device, INTERFACE_VENDOR)
+///     .with_key(b"rack-7")
+///     .serve(listener)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct BridgeServer<D> {
+    device: Mutex<D>,
+    interface: u8,
+    key: Option<[u8; 32]>,
+}
+
+impl<D: BridgeDevice> BridgeServer<D> {
+    /// Share `device`, which was opened on USB `interface`.
+    pub fn new(device: D, interface: u8) -> Self {
+        Self {
+            device: Mutex::new(device),
+            interface,
+            key: None,
+        }
+    }
+
+    /// Require clients to prove knowledge of a pre-shared key.
+    pub fn with_key(mut self, key: &[u8]) -> Self {
+        self.key = Some(key_digest(key));
+        self
+    }
+
+    /// Accept clients until accepting fails.
+    pub async fn serve(self, listener: TcpListener) -> Result<(), KMError> {
+        let server = Arc::new(self);
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let server = server.clone();
+            tokio::spawn(async move {
+                info!(%peer, "Client connected");
+                match server.serve_client(stream).await {
+                    Ok(()) => info!(%peer, "Client disconnected"),
+                    Err(error) => warn!(%peer, "Client dropped: {error}"),
+                }
+            });
+        }
+    }
+
+    async fn serve_client(&self, stream: TcpStream) -> Result<(), KMError> {
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+
+        let challenge: [u8; CHALLENGE_SIZE] = rand::random();
+        let mut hello = MAGIC.to_vec();
+        hello.push(VERSION);
+        hello.extend_from_slice(&challenge);
+        write_frame(&mut writer, FrameKind::Hello, &hello).await?;
+        let response = match timeout(HANDSHAKE_TIMEOUT, read_frame(&mut reader, HANDSHAKE_FRAME_SIZE)).await?? {
+            (FrameKind::Auth, response) => response,
+            (kind, _) => return Err(KMError::Protocol(format!("Expected Auth, got {kind:?}"))),
+        };
+        if let Some(key) = &self.key
+            && sign(key, &challenge).verify_slice(&response).is_err()
+        {
+            write_frame(&mut writer, FrameKind::Error, b"invalid key").await?;
+            return Err(KMError::Protocol("Client failed key authentication".to_string()));
+        }
+        write_frame(&mut writer, FrameKind::Ready, &[self.interface]).await?;
+
+        loop {
+            let request = match read_frame(&mut reader, MAX_FRAME_SIZE).await {
+                Ok((FrameKind::Transfer, request)) => request,
+                Ok((kind, _)) => return Err(KMError::Protocol(format!("Expected Transfer, got {kind:?}"))),
+                Err(KMError::Io(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(error) => return Err(error),
+            };
+            // Release the meter before writing, so a slow client does not stall the others.
+            let responses = exchange(&mut *self.device.lock().await, request).await;
+            for (kind, payload) in responses {
+                write_frame(&mut writer, kind, &payload).await?;
+            }
+        }
+    }
+}
+
+/// Forward one client request and collect the frames to send back.
+///
+/// Meter errors become Error frames for the client.
+async fn exchange<D: BridgeDevice>(device: &mut D, mut request: Vec<u8>) -> Vec<(FrameKind, Vec<u8>)> {
+    let error = |message: &[u8]| vec![(FrameKind::Error, message.to_vec())];
+    if request.len() < 4 {
+        return error(b"request is shorter than a packet header");
+    }
+    let client_id = request[1];
+    let device_id = device.next_tid();
+    request[1] = device_id;
+    let request_type = PacketType::from(request[0] & 0x7F);
+    let memory_read_size = match request_type {
+        PacketType::MemoryRead => parse_memory_read_payload(&request[4..]).map(|(_, size)| size),
+        _ => None,
+    };
+
+    if let Err(send_error) = device.send_raw(&request).await {
+        return error(send_error.to_string().as_bytes());
+    }
+
+    let mut responses = Vec::new();
+    let mut deadline = Instant::now() + EXCHANGE_TIMEOUT;
+    let mut expected_data = None;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut transfer = match timeout(remaining, device.receive_raw()).await {
+            Ok(Ok(transfer)) => transfer,
+            Ok(Err(KMError::Timeout(_))) | Err(_) => {
+                debug!(id = device_id, "No response from the meter");
+                return responses;
+            }
+            Ok(Err(receive_error)) => {
+                responses.push((FrameKind::Error, receive_error.to_string().into_bytes()));
+                return responses;
+            }
+        };
+
+        // Encrypted MemoryRead data follows the confirmation without a header.
+        if let Some(remaining_data) = &mut expected_data {
+            *remaining_data = usize::saturating_sub(*remaining_data, transfer.len());
+            responses.push((FrameKind::Transfer, transfer));
+            if *remaining_data == 0 {
+                return responses;
+            }
+            deadline = Instant::now() + EXCHANGE_TIMEOUT;
+            continue;
+        }
+
+        let Some(packet) = parse_framed_response(&transfer) else {
+            debug!(len = transfer.len(), "Discarding an unframed transfer");
+            continue;
+        };
+        let response_type = packet.packet_type();
+        // StreamingAuth responses always carry ID 0.
+        let matched = packet.id() == device_id
+            || (request_type == PacketType::StreamingAuth && response_type == PacketType::StreamingAuth);
+        if !matched {
+            debug!(id = packet.id(), "Discarding a stale {response_type:?} response");
+            continue;
+        }
+        if packet.id() == device_id {
+            transfer[1] = client_id;
+        }
+        responses.push((FrameKind::Transfer, transfer));
+
+        match memory_read_size {
+            Some(size) if response_type == PacketType::MemoryRead && size > 0 => {
+                expected_data = Some(memory_response_size(size));
+            }
+            _ => return responses,
+        }
+    }
+}
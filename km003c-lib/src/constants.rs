@@ -36,5 +36,8 @@ pub const PD_CONNECTION_DISCONNECT_LEGACY: u8 = 0x12;
 /// Mask for extracting wire length from PD event size_flag
 pub const PD_EVENT_SIZE_MASK: u8 = 0x3F;
 
+/// High bits set in the size_flag of captured PD message events
+pub const PD_EVENT_MESSAGE_FLAG: u8 = 0x80;
+
 /// Offset to subtract from masked size to get wire length
 pub const PD_EVENT_SIZE_OFFSET: u8 = 5;
@@ -46,11 +46,16 @@ use crate::adc::AdcDataSimple;
 use crate::adcqueue::GraphSampleRate;
 use crate::auth::{
     AuthCredential, CALIBRATION_ADDRESS, DeviceInfo, HardwareId, PREFERRED_CALIBRATION_ADDRESS,
-    STREAMING_AUTH_CREDENTIAL_SIZE, StreamingAuthResult,
+    STREAMING_AUTH_CREDENTIAL_SIZE, StreamingAuthResult, calibration_record_is_erased,
 };
+use crate::backup::{BackupImage, BackupRegion, BackupRegionKind, offline_log_area_size};
+use crate::bridge::{self, BridgeReader, BridgeTarget};
 use crate::error::KMError;
 use crate::message::Packet;
-use crate::offline::{LogMetadata, LogMetadataResponse, OfflineLog};
+use crate::offline::{
+    LogMetadata, LogMetadataResponse, OFFLINE_DOWNLOAD_CHUNK_SIZE, OFFLINE_LOG_ADDRESS, OfflineDownloadProgress,
+    OfflineLog, OfflineLogDownload,
+};
 use crate::packet::{Attribute, AttributeSet, PacketType, RawPacket};
 use crate::pd::{PdEventStream, PdStatus};
 use crate::settings::Settings;
@@ -62,13 +67,10 @@ use std::collections::VecDeque;
 use std::fmt;
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::time::timeout;
 use tracing::{debug, info, trace, warn};
 
-fn calibration_record_is_erased(record: &[u8]) -> bool {
-    record.starts_with(&[0xff; 4])
-}
-
 /// Device state populated by initialization
 ///
 /// Contains all information gathered during device init:
@@ -157,7 +159,7 @@ const INTERRUPT_TRANSFER_SIZE: usize = 64;
 const MAX_PENDING_RESPONSES: usize = 256;
 const AES_BLOCK_SIZE: usize = 16;
 
-fn parse_framed_response(bytes: &[u8]) -> Option<RawPacket> {
+pub(crate) fn parse_framed_response(bytes: &[u8]) -> Option<RawPacket> {
     RawPacket::try_from(Bytes::copy_from_slice(bytes)).ok()
 }
 
@@ -186,7 +188,7 @@ fn memory_confirmation_matches(bytes: &[u8], id: u8) -> bool {
     })
 }
 
-fn memory_response_size(requested_size: u32) -> usize {
+pub(crate) fn memory_response_size(requested_size: u32) -> usize {
     (requested_size as usize).div_ceil(AES_BLOCK_SIZE) * AES_BLOCK_SIZE
 }
 
@@ -309,6 +311,9 @@ fn ensure_adcqueue_available(mode: &ConnectionMode) -> Result<(), KMError> {
 /// - **Vendor** (Interface 0): Full mode - all features including AdcQueue
 /// - **HID** (Interface 3): Basic mode - ADC/PD polling only
 ///
+/// A meter shared by a [`crate::bridge::BridgeServer`] is opened with
+/// [`DeviceConfig::remote`] instead; the server's interface decides the mode.
+///
 /// # Examples
 ///
 /// ```no_run
@@ -323,6 +328,9 @@ fn ensure_adcqueue_available(mode: &ConnectionMode) -> Result<(), KMError> {
 ///
 /// // With options
 /// let device = KM003C::new(DeviceConfig::vendor().skip_reset()).await?;
+///
+/// // Meter attached to another machine
+/// let device = KM003C::new(DeviceConfig::remote("tcp://lab-rack:7003")?.bridge_key(b"rack-7")).await?;
 /// # Ok(())
 /// # }
 /// ```
@@ -338,6 +346,10 @@ pub struct DeviceConfig {
     transfer_type: TransferType,
     /// Skip initial USB reset
     skip_reset: bool,
+    /// Bridge server to connect to instead of local USB
+    remote: Option<BridgeTarget>,
+    /// Which of several attached meters to open, in enumeration order
+    device_index: usize,
 }
 
 impl DeviceConfig {
@@ -361,6 +373,8 @@ impl DeviceConfig {
             endpoint_in: ENDPOINT_IN_VENDOR,
             transfer_type: TransferType::Bulk,
             skip_reset: false,
+            remote: None,
+            device_index: 0,
         }
     }
 
@@ -386,9 +400,51 @@ impl DeviceConfig {
             endpoint_in: ENDPOINT_IN_HID,
             transfer_type: TransferType::Interrupt,
             skip_reset: false,
+            remote: None,
+            device_index: 0,
         }
     }
 
+    /// Meter shared over TCP by a bridge server, from a `tcp://host[:port]` URL
+    ///
+    /// The host name is resolved immediately. Servers started with a key
+    /// also need [`Self::bridge_key`]. The interface the server opened
+    /// determines the connection mode, and USB options such as
+    /// [`Self::skip_reset`] have no effect.
+    pub fn remote(url: &str) -> Result<Self, KMError> {
+        Ok(Self {
+            remote: Some(BridgeTarget::parse(url)?),
+            ..Self::vendor()
+        })
+    }
+
+    /// Pre-shared key of the bridge server
+    ///
+    /// Has no effect on USB configurations.
+    pub fn bridge_key(mut self, key: &[u8]) -> Self {
+        self.remote = self.remote.map(|remote| remote.with_key(key));
+        self
+    }
+
+    /// How long to wait for the bridge server to forward a response
+    ///
+    /// Defaults to [`bridge::BRIDGE_TIMEOUT`], which leaves room for the
+    /// server's [`bridge::EXCHANGE_TIMEOUT`] and requests of other clients
+    /// queued before this one. Has no effect on USB configurations.
+    pub fn bridge_timeout(mut self, timeout: Duration) -> Self {
+        self.remote = self.remote.map(|remote| remote.with_timeout(timeout));
+        self
+    }
+
+    /// Open the meter at `index` in [`list_devices`] order instead of the first one
+    ///
+    /// Used when several meters are attached to the same host. Has no effect
+    /// on remote configurations.
+    pub fn device_index(mut self, index: usize) -> Self {
+        self.device_index = index;
+        self
+    }
+
     /// Skip USB reset during connection
     ///
     /// Some systems (particularly MacOS) may have issues with USB reset.
@@ -407,28 +463,73 @@ impl DeviceConfig {
     pub fn is_hid(&self) -> bool {
         self.interface == INTERFACE_HID
     }
+
+    /// Check if this config connects through a bridge server
+    pub fn is_remote(&self) -> bool {
+        self.remote.is_some()
+    }
+}
+
+/// A KM003C attached to this host, as reported by [`list_devices`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "python", pyo3::pyclass(get_all, skip_from_py_object))]
+pub struct AttachedDevice {
+    /// Position accepted by [`DeviceConfig::device_index`]
+    pub index: usize,
+    /// USB bus the meter is attached to
+    pub bus_id: String,
+    /// USB device address on that bus
+    pub address: u8,
+}
+
+/// List the KM003C meters attached to this host
+///
+/// The order matches [`DeviceConfig::device_index`]. Listing does not open
+/// or reset any device.
+pub async fn list_devices() -> Result<Vec<AttachedDevice>, KMError> {
+    Ok(find_devices()
+        .await?
+        .enumerate()
+        .map(|(index, info)| AttachedDevice {
+            index,
+            bus_id: info.bus_id().to_string(),
+            address: info.device_address(),
+        })
+        .collect())
+}
+
+async fn find_devices() -> Result<impl Iterator<Item = nusb::DeviceInfo>, KMError> {
+    Ok(nusb::list_devices()
+        .await?
+        .filter(|d| d.vendor_id() == VID && d.product_id() == PID))
 }
 
-/// Endpoint reader wrapper to handle both Bulk and Interrupt types
+/// Endpoint reader wrapper to handle Bulk, Interrupt and bridge transfers
 enum EndpointReaderType {
     Bulk(EndpointRead<Bulk>),
     Interrupt(EndpointRead<Interrupt>),
+    Bridge(BridgeReader),
 }
 
-/// Endpoint writer wrapper to handle both Bulk and Interrupt types
+/// Endpoint writer wrapper to handle Bulk, Interrupt and bridge transfers
 enum EndpointWriterType {
     Bulk(EndpointWrite<Bulk>),
     Interrupt(EndpointWrite<Interrupt>),
+    Bridge(OwnedWriteHalf),
 }
 
 pub struct KM003C {
-    /// Kept alive for RAII - dropping this releases the USB interface claim
+    /// Kept alive for RAII - dropping this releases the USB interface claim.
+    /// `None` when connected through a bridge server.
     #[allow(dead_code)]
-    interface: Interface,
+    interface: Option<Interface>,
     transaction_id: u8,
     reader: EndpointReaderType,
     writer: EndpointWriterType,
     pending_responses: VecDeque<Vec<u8>>,
+    /// How long to wait for a response: [`DEFAULT_TIMEOUT`] on USB, the
+    /// configured bridge timeout on remote connections.
+    response_timeout: Duration,
     /// Rate currently selected with StartGraph, used to decode rate-dependent fields.
     graph_sample_rate: Option<GraphSampleRate>,
     /// Connection mode: Basic (HID) or Full (Vendor with device state)
@@ -463,9 +564,12 @@ impl KM003C {
     /// # }
     /// ```
     pub async fn new(config: DeviceConfig) -> Result<Self, KMError> {
-        let mut device = Self::connect(config).await?;
+        let (mut device, interface) = match config.remote {
+            Some(target) => Self::connect_remote(target).await?,
+            None => (Self::connect(config).await?, config.interface),
+        };
 
-        if config.is_vendor() {
+        if interface == INTERFACE_VENDOR {
             // Full mode: run init sequence
             device.run_init().await?;
         }
@@ -477,9 +581,9 @@ impl KM003C {
     /// Internal: Connect to USB device without initialization
     async fn connect(config: DeviceConfig) -> Result<Self, KMError> {
         info!("Searching for POWER-Z KM003C...");
-        let device_info = nusb::list_devices()
+        let device_info = find_devices()
             .await?
-            .find(|d| d.vendor_id() == VID && d.product_id() == PID)
+            .nth(config.device_index)
             .ok_or(KMError::DeviceNotFound)?;
 
         info!(
@@ -498,9 +602,11 @@ impl KM003C {
             // (validated through protocol research - 100ms is insufficient for AdcQueue)
             tokio::time::sleep(Duration::from_millis(1500)).await;
             // Re-enumerate and reopen after reset (old handle may be invalid).
-            let device_info = nusb::list_devices()
+            // The address may change and other meters may shift the index, so
+            // look the meter up again by the port it is plugged into.
+            let device_info = find_devices()
                 .await?
-                .find(|d| d.vendor_id() == VID && d.product_id() == PID)
+                .find(|info| info.bus_id() == device_info.bus_id() && info.port_chain() == device_info.port_chain())
                 .ok_or(KMError::DeviceNotFound)?;
             device = device_info.open().await?;
         } else {
@@ -550,11 +656,12 @@ impl KM003C {
         };
 
         let km003c = Self {
-            interface,
+            interface: Some(interface),
             transaction_id: 0,
             reader,
             writer,
             pending_responses: VecDeque::new(),
+            response_timeout: DEFAULT_TIMEOUT,
             graph_sample_rate: None,
             mode: ConnectionMode::Basic,
         };
@@ -563,6 +670,28 @@ impl KM003C {
         Ok(km003c)
     }
 
+    /// Internal: Connect to a bridge server without initialization
+    ///
+    /// Returns the device and the USB interface the server opened.
+    async fn connect_remote(target: BridgeTarget) -> Result<(Self, u8), KMError> {
+        info!("Connecting to bridge at {}...", target.address);
+        let (reader, writer, interface) = bridge::connect(target).await?;
+
+        let km003c = Self {
+            interface: None,
+            transaction_id: 0,
+            reader: EndpointReaderType::Bridge(reader),
+            writer: EndpointWriterType::Bridge(writer),
+            pending_responses: VecDeque::new(),
+            response_timeout: target.timeout,
+            graph_sample_rate: None,
+            mode: ConnectionMode::Basic,
+        };
+
+        info!("Bridge connection established (interface {})", interface);
+        Ok((km003c, interface))
+    }
+
     /// Get the next transaction ID (internal use)
     fn next_transaction_id(&mut self) -> u8 {
         let id = self.transaction_id;
@@ -638,17 +767,7 @@ impl KM003C {
         let message = message_bytes.to_vec();
         trace!("TX [{} bytes]: {:02x?}", message.len(), message);
 
-        // Use the persistent writer
-        match &mut self.writer {
-            EndpointWriterType::Bulk(writer) => {
-                timeout(DEFAULT_TIMEOUT, writer.write_all(&message)).await??;
-                timeout(DEFAULT_TIMEOUT, writer.flush_end_async()).await??;
-            }
-            EndpointWriterType::Interrupt(writer) => {
-                timeout(DEFAULT_TIMEOUT, writer.write_all(&message)).await??;
-                timeout(DEFAULT_TIMEOUT, writer.flush_end_async()).await??;
-            }
-        }
+        self.write_transfer(&message).await?;
 
         debug!("Sent successfully");
         Ok(())
@@ -657,6 +776,11 @@ impl KM003C {
     /// Send raw bytes to the device (for protocol research/testing)
     pub async fn send_raw(&mut self, data: &[u8]) -> Result<(), KMError> {
         trace!("TX [{} bytes]: {:02x?}", data.len(), data);
+        self.write_transfer(data).await
+    }
+
+    /// Write one complete transfer through the persistent writer.
+    async fn write_transfer(&mut self, data: &[u8]) -> Result<(), KMError> {
         match &mut self.writer {
             EndpointWriterType::Bulk(writer) => {
                 timeout(DEFAULT_TIMEOUT, writer.write_all(data)).await??;
@@ -666,11 +790,14 @@ impl KM003C {
                 timeout(DEFAULT_TIMEOUT, writer.write_all(data)).await??;
                 timeout(DEFAULT_TIMEOUT, writer.flush_end_async()).await??;
             }
+            EndpointWriterType::Bridge(writer) => {
+                timeout(DEFAULT_TIMEOUT, bridge::write_transfer(writer, data)).await??;
+            }
         }
         Ok(())
     }
 
-    /// Read one complete response directly from the USB endpoint.
+    /// Read one complete response directly from the USB endpoint or bridge.
     async fn read_raw_from_usb(&mut self) -> Result<Vec<u8>, KMError> {
         let mut buffer = Vec::new();
         let bytes_read = match &mut self.reader {
@@ -691,6 +818,10 @@ impl KM003C {
                 buffer.truncate(bytes_read);
                 bytes_read
             }
+            EndpointReaderType::Bridge(reader) => {
+                buffer = timeout(self.response_timeout, reader.read_transfer()).await??;
+                buffer.len()
+            }
         };
         trace!("RX [{} bytes]: {:02x?}", bytes_read, buffer);
         Ok(buffer)
@@ -715,7 +846,7 @@ impl KM003C {
                 .expect("pending response index is valid"));
         }
 
-        let deadline = tokio::time::Instant::now() + DEFAULT_TIMEOUT;
+        let deadline = tokio::time::Instant::now() + self.response_timeout;
         loop {
             let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
             if remaining.is_zero() {
@@ -871,10 +1002,56 @@ impl KM003C {
 
     /// Download the offline samples described by previously requested metadata.
     pub async fn download_offline_log(&mut self, metadata: LogMetadata) -> Result<OfflineLog, KMError> {
-        let data = self
-            .read_memory_block(metadata.data_address()?, metadata.data_size())
-            .await?;
-        OfflineLog::from_bytes(metadata, &data)
+        let mut download = OfflineLogDownload::new(metadata);
+        self.continue_offline_log_download(&mut download, |_| {}).await?;
+        download.finish()
+    }
+
+    /// Start or resume a chunked offline log download.
+    ///
+    /// Reads the remaining chunks one memory request at a time and reports
+    /// progress after each verified chunk. On error, `download` keeps every
+    /// chunk received so far; call this again, on this or a reconnected
+    /// device, to continue from there, then `OfflineLogDownload::finish`.
+    pub async fn continue_offline_log_download(
+        &mut self,
+        download: &mut OfflineLogDownload,
+        mut on_progress: impl FnMut(OfflineDownloadProgress),
+    ) -> Result<(), KMError> {
+        while let Some((address, size)) = download.next_chunk()? {
+            let data = self.read_memory_block(address, size).await?;
+            on_progress(download.push_chunk(&data)?);
+        }
+        Ok(())
+    }
+
+    /// Read every known memory region and offline log into a backup image.
+    ///
+    /// Captures the device, firmware and calibration blocks, the HardwareID,
+    /// the offline log catalog and the log area covering every catalog entry.
+    /// Any failed read aborts the backup rather than producing a partial image.
+    pub async fn read_backup_image(&mut self) -> Result<BackupImage, KMError> {
+        let mut image = BackupImage::new();
+        for kind in BackupRegionKind::FIXED {
+            if let Some((address, size)) = kind.fixed_region() {
+                let data = self.read_memory_block(address, size).await?;
+                image.insert(BackupRegion::new(kind, address, data));
+            }
+        }
+
+        let metadata = self.request_log_metadata().await?;
+        let area_size = offline_log_area_size(&metadata)?;
+        let mut area = Vec::with_capacity(area_size as usize);
+        for chunk_offset in (0..area_size).step_by(OFFLINE_DOWNLOAD_CHUNK_SIZE as usize) {
+            let size = OFFLINE_DOWNLOAD_CHUNK_SIZE.min(area_size - chunk_offset);
+            let address = OFFLINE_LOG_ADDRESS
+                .checked_add(chunk_offset)
+                .ok_or_else(|| KMError::Protocol("Offline log chunk address overflows".to_string()))?;
+            let data = self.read_memory_block(address, size).await?;
+            area.extend_from_slice(&data);
+        }
+        image.set_offline_logs(&metadata, area)?;
+        Ok(image)
     }
 
     /// Request PD data (returns full packet as it can contain PdStatus OR PdEventStream)
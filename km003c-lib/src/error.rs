@@ -34,6 +34,18 @@ pub enum KMError {
 
     #[error("Serialization is not supported for {packet}")]
     UnsupportedSerialization { packet: &'static str },
+
+    #[cfg(feature = "recording")]
+    #[error("Recording error: {0}")]
+    Recording(#[from] polars::error::PolarsError),
+
+    #[cfg(feature = "serde")]
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[cfg(feature = "mqtt")]
+    #[error("MQTT error: {0}")]
+    Mqtt(#[from] rumqttc::ClientError),
 }
 
 impl From<TryFromSliceError> for KMError {
@@ -1,17 +1,46 @@
 pub mod adc;
 pub mod adcqueue;
 pub mod auth;
+pub mod backup;
+pub mod bridge;
 pub mod constants;
 pub mod device;
 pub mod error;
+pub mod measurement;
 pub mod message;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod offline;
 pub mod packet;
 pub mod pd;
+pub mod pd_alt_mode;
+pub mod pd_cable;
+pub mod pd_correlation;
 #[cfg(feature = "usbpd")]
 pub mod pd_decode;
+pub mod pd_encode;
+pub mod pd_epr;
+pub mod pd_extended;
+pub mod pd_fingerprint;
+#[cfg(feature = "usbpd")]
+pub mod pd_format;
+pub mod pd_pdo;
+pub mod pd_policy;
+pub mod pd_pps;
+pub mod pd_protocol;
 pub mod pd_trace;
+pub mod pd_vdm;
+pub mod pd_wire;
+#[cfg(feature = "recording")]
+pub mod recording;
+#[cfg(feature = "session")]
+pub mod session;
 pub mod settings;
+#[cfg(feature = "sigrok")]
+pub mod sigrok;
+pub mod streaming;
+#[cfg(feature = "session")]
+pub mod vcd;
 
 #[cfg(feature = "python")]
 pub mod python;
@@ -24,17 +53,60 @@ pub use adcqueue::{
     AdcQueueData, AdcQueueRawData, AdcQueueSample, AdcQueueSampleRaw, GraphSampleRate, sequence_elapsed,
 };
 pub use auth::{AuthCredential, DeviceInfo, HardwareId, StreamingAuthResult};
-pub use device::{ConnectionMode, DeviceConfig, DeviceState, KM003C, TransferType};
+pub use backup::{BackupImage, BackupRegion, BackupRegionKind};
+pub use bridge::{BridgeDevice, BridgeServer};
+pub use device::{AttachedDevice, ConnectionMode, DeviceConfig, DeviceState, KM003C, TransferType, list_devices};
+pub use measurement::{MeasurementAccumulator, MeasurementSample};
 pub use message::{Packet, PayloadData};
-pub use offline::{LogMetadata, LogMetadataResponse, OfflineLog, OfflineLogSample, OfflineLogSampleRaw};
+#[cfg(feature = "mqtt")]
+pub use mqtt::{MqttConfig, MqttEncoder, MqttMessage, MqttPublisher};
+pub use offline::{
+    LogMetadata, LogMetadataResponse, OfflineDownloadProgress, OfflineLog, OfflineLogDownload, OfflineLogSample,
+    OfflineLogSampleRaw,
+};
 pub use packet::{Attribute, AttributeSet, LogicalPacket, RawPacket};
 pub use pd::{PdEvent, PdEventData, PdEventStream, PdStatus};
+pub use pd_alt_mode::{AltModeSummary, AltModeTracker, DisplayPortState};
+pub use pd_cable::{CableDiscovery, CableDiscoveryResult, CableIdentity, CableIdentityTracker, CableKind};
+pub use pd_correlation::{
+    CorrelatedProtocolEvent, CorrelatedStateEvent, PdClockAlignment, PdTraceCorrelation, PdTraceCorrelator,
+    TraceCorrelationIssue,
+};
 #[cfg(feature = "usbpd")]
 pub use pd_decode::{
     DecodedPdEvent, DecodedPdMessage, PdChunkState, PdChunkStatus, PdDecodeError, PdDecodeFailure, PdSessionDecoder,
 };
+pub use pd_encode::{PdCaptureBuilder, PdMessageBody, PdMessageSender};
+pub use pd_epr::{EprContract, EprEvent, EprModeState, EprTracker, EprViolation};
+pub use pd_extended::{ExtendedChunkProgress, ExtendedMessage, ExtendedMessageAssembler, ExtendedPayload};
+#[cfg(feature = "usbpd")]
+pub use pd_fingerprint::FingerprintCollector;
+pub use pd_fingerprint::{
+    ChargerFingerprint, ChargerIdentity, FingerprintDatabase, FingerprintEntry, FingerprintMatch, FingerprintTiming,
+};
+#[cfg(feature = "usbpd")]
+pub use pd_format::{FormattedPdEvent, PdEventCategory, PdField, format_event};
+pub use pd_pdo::{RequestDataObject, SourcePdo};
+pub use pd_policy::{PdContract, PolicyChange, PolicyEvent, PolicyState, PolicyTracker};
+pub use pd_pps::{PpsTimelineEntry, PpsTracker, ProgrammableRequest, ProgrammableSupplyKind};
+pub use pd_protocol::{PdTransmission, ProtocolAnalyzer, ProtocolIssue, ProtocolIssueEvent, ProtocolStatistics};
 pub use pd_trace::{PdProtocolTraceEventKind, PdTrace, PdTraceProtocolEvent, PdTraceStateEvent, PdTypeCState};
+pub use pd_vdm::{ModeVdo, VdmContent, VdmHeader, VendorDefinedMessage};
+pub use pd_wire::{PdMessageHeader, PdMessageType, PdSopType, PdWireMessage};
+#[cfg(feature = "recording")]
+pub use polars;
+#[cfg(feature = "recording")]
+pub use recording::{RecordingFormat, RecordingMetadata, RecordingRow, RecordingWriter};
+#[cfg(feature = "mqtt")]
+pub use rumqttc;
+#[cfg(feature = "session")]
+pub use session::{SessionEntry, SessionReader, SessionRecord, SessionRecordKind, SessionWriter};
 pub use settings::Settings;
+#[cfg(feature = "sigrok")]
+pub use sigrok::SigrokExport;
+pub use streaming::DeviceStream;
 pub use uom;
 #[cfg(feature = "usbpd")]
 pub use usbpd;
+#[cfg(feature = "session")]
+pub use vcd::{VcdExport, VcdOptions};
@@ -19,6 +19,7 @@ const PD_MONITOR_DISABLED_PARAMETER: u16 = 0;
 
 /// Represents parsed payload data from logical packets
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "python", derive(pyo3::IntoPyObject))]
 pub enum PayloadData {
     Adc(AdcDataSimple),
@@ -33,6 +34,7 @@ pub enum PayloadData {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum Packet {
     /// Data response with parsed payload data
     DataResponse { payloads: Vec<PayloadData> },
@@ -374,9 +376,14 @@ impl Packet {
                                 payload: queue.to_bytes(),
                             });
                         }
-                        PayloadData::PdEvents(_) => {
-                            return Err(KMError::UnsupportedSerialization {
-                                packet: "PdEventStream",
+                        PayloadData::PdEvents(events) => {
+                            let payload = events.to_bytes()?;
+                            logical_packets.push(LogicalPacket {
+                                attribute: Attribute::PdPacket,
+                                next: false,
+                                chunk: 0,
+                                size: payload.len() as u16,
+                                payload,
                             });
                         }
                         PayloadData::PdTrace(trace) => {
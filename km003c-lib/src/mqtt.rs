@@ -0,0 +1,489 @@
+//! MQTT publishing of live measurements, PD activity and offline downloads.
+//!
+//! All topics of a meter live under `{prefix}/{serial}`. Only the prefix is
+//! configurable; the topic names below are fixed:
+//!
+//! | Topic | Retained | Payload |
+//! |---|---|---|
+//! | `status` | yes | `online`, or `offline` once the meter is lost (also the last will) |
+//! | `device` | yes | device identification as JSON |
+//! | `measurement` | yes | AdcQueue measurements averaged over an interval |
+//! | `pd/attach` | yes | attach and detach events from the PD stream |
+//! | `pd/contract` | yes | the explicit contract, `null` once it ends |
+//! | `offline/complete` | no | a finished offline log download |
+//!
+//! [`MqttEncoder`] turns AdcQueue samples and PD events into messages with
+//! [`MeasurementAccumulator`] and [`PolicyTracker`], independently of any
+//! broker. [`MqttPublisher`] sends them through a background connection that
+//! reconnects on its own and republishes the last retained state of every
+//! topic after a reconnect, so subscribers never see stale values for long.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, Incoming, LastWill, MqttOptions, Outgoing, QoS};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uom::si::electric_charge::ampere_hour;
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::energy::watt_hour;
+use uom::si::time::second;
+
+use crate::adcqueue::{AdcQueueData, GraphSampleRate};
+use crate::device::DeviceState;
+use crate::error::KMError;
+use crate::measurement::{MeasurementAccumulator, MeasurementSample};
+use crate::message::Packet;
+use crate::offline::OfflineLog;
+use crate::pd::PdEventStream;
+use crate::pd_policy::{PdContract, PolicyChange, PolicyTracker};
+
+pub const MQTT_DEFAULT_PORT: u16 = 1883;
+
+/// Delay before reconnecting after the broker connection fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Requests buffered for the connection task; further measurements are dropped.
+const REQUEST_CAPACITY: usize = 256;
+
+/// Broker connection and topic settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    /// Topic levels before the device serial; the topic names after it are fixed.
+    pub topic_prefix: String,
+    pub qos: QoS,
+    /// Averaging window of `measurement` messages, in device time.
+    pub measurement_interval: Duration,
+    pub keep_alive: Duration,
+}
+
+impl MqttConfig {
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            port: MQTT_DEFAULT_PORT,
+            client_id: format!("km003c-{}", std::process::id()),
+            topic_prefix: "km003c".to_string(),
+            qos: QoS::AtLeastOnce,
+            measurement_interval: Duration::from_secs(1),
+            keep_alive: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A message ready to be published.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+/// Sums of the measurements in the current averaging window.
+#[derive(Debug, Clone, Copy)]
+struct MeasurementWindow {
+    start_us: u64,
+    samples: u32,
+    /// VBUS, IBUS, power, CC1, CC2, D+ and D- in micro-units.
+    sums: [f64; 7],
+}
+
+/// Converts device data into MQTT messages for one meter.
+#[derive(Debug)]
+pub struct MqttEncoder {
+    base_topic: String,
+    interval_us: u64,
+    rate: Option<GraphSampleRate>,
+    accumulator: MeasurementAccumulator,
+    window: Option<MeasurementWindow>,
+    policy: PolicyTracker,
+}
+
+impl MqttEncoder {
+    pub fn new(topic_prefix: &str, serial: &str, measurement_interval: Duration) -> Self {
+        Self {
+            base_topic: format!("{}/{}", topic_prefix.trim_end_matches('/'), topic_level(serial)),
+            interval_us: measurement_interval.as_micros() as u64,
+            rate: None,
+            accumulator: MeasurementAccumulator::default(),
+            window: None,
+            policy: PolicyTracker::new(),
+        }
+    }
+
+    /// Full topic name of `suffix`, e.g. `km003c/007965/measurement`.
+    pub fn topic(&self, suffix: &str) -> String {
+        format!("{}/{suffix}", self.base_topic)
+    }
+
+    pub fn status(&self, online: bool) -> MqttMessage {
+        self.message("status", if online { "online" } else { "offline" }.into(), true)
+    }
+
+    pub fn device(&self, state: &DeviceState) -> MqttMessage {
+        self.json("device", &DevicePayload::from(state), true)
+    }
+
+    /// Restart the measurement stream, e.g. after reconnecting to the device.
+    pub fn restart_stream(&mut self) {
+        self.rate = None;
+        self.accumulator = MeasurementAccumulator::default();
+        self.window = None;
+    }
+
+    /// Measurement messages completed by a batch of AdcQueue samples.
+    pub fn adc_queue(&mut self, data: &AdcQueueData, rate: GraphSampleRate) -> Vec<MqttMessage> {
+        if self.rate.replace(rate).is_some_and(|previous| previous != rate) {
+            self.accumulator = MeasurementAccumulator::default();
+            self.window = None;
+        }
+        let mut messages = Vec::new();
+        for sample in &data.samples {
+            if let Some(measurement) = self.accumulator.push(*sample, rate) {
+                messages.extend(self.measurement(&measurement));
+            }
+        }
+        messages
+    }
+
+    /// Attach and contract messages for the PD events of a stream.
+    pub fn pd_events(&mut self, stream: &PdEventStream) -> Vec<MqttMessage> {
+        let mut messages = Vec::new();
+        for event in &stream.events {
+            for policy_event in self.policy.process_event(event).to_vec() {
+                let timestamp_s = policy_event.timestamp.get::<second>();
+                match policy_event.change {
+                    PolicyChange::Attached | PolicyChange::Detached => {
+                        let attached = matches!(policy_event.change, PolicyChange::Attached);
+                        let payload = AttachPayload { attached, timestamp_s };
+                        messages.push(self.json("pd/attach", &payload, true));
+                    }
+                    PolicyChange::ContractAccepted(_)
+                    | PolicyChange::PowerReady(_)
+                    | PolicyChange::ContractEnded { .. } => {
+                        let payload = policy_event.state.contract.as_ref().map(ContractPayload::from);
+                        messages.push(self.json("pd/contract", &payload, true));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        messages
+    }
+
+    /// Messages for the AdcQueue samples and PD events of a response packet.
+    pub fn packet(&mut self, packet: &Packet, rate: GraphSampleRate) -> Vec<MqttMessage> {
+        let mut messages = Vec::new();
+        if let Some(data) = packet.get_adc_queue() {
+            messages.extend(self.adc_queue(data, rate));
+        }
+        if let Some(stream) = packet.get_pd_events() {
+            messages.extend(self.pd_events(stream));
+        }
+        messages
+    }
+
+    /// Completion of an offline log download, with the file it was saved to.
+    pub fn offline_log(&self, log: &OfflineLog, saved_to: Option<&Path>) -> MqttMessage {
+        let metadata = &log.metadata;
+        let payload = OfflineLogPayload {
+            filename: metadata.filename_lossy().into_owned(),
+            samples: log.samples.len(),
+            interval_s: metadata.interval.get::<second>(),
+            duration_s: metadata.recorded_duration.get::<second>(),
+            charge_ah: metadata.final_charge.get::<ampere_hour>(),
+            energy_wh: metadata.final_energy.get::<watt_hour>(),
+            path: saved_to.map(|path| path.display().to_string()),
+        };
+        self.json("offline/complete", &payload, false)
+    }
+
+    fn measurement(&mut self, measurement: &MeasurementSample) -> Option<MqttMessage> {
+        let window = self.window.get_or_insert(MeasurementWindow {
+            start_us: measurement.elapsed_us,
+            samples: 0,
+            sums: [0.0; 7],
+        });
+        let values = [
+            measurement.vbus_uv,
+            measurement.ibus_ua,
+            measurement.power_uw,
+            measurement.cc1_uv,
+            measurement.cc2_uv,
+            measurement.dp_uv,
+            measurement.dm_uv,
+        ];
+        for (sum, value) in window.sums.iter_mut().zip(values) {
+            *sum += value as f64;
+        }
+        window.samples += 1;
+        if measurement.elapsed_us - window.start_us < self.interval_us {
+            return None;
+        }
+
+        let window = self.window.take()?;
+        let [vbus, ibus, power, cc1, cc2, dp, dm] = window.sums.map(|sum| sum / f64::from(window.samples) / 1e6);
+        let payload = MeasurementPayload {
+            elapsed_s: measurement.elapsed_seconds(),
+            samples: window.samples,
+            sample_rate_hz: measurement.sample_rate_hz,
+            vbus_v: vbus,
+            ibus_a: ibus,
+            power_w: power,
+            cc1_v: cc1,
+            cc2_v: cc2,
+            dp_v: dp,
+            dm_v: dm,
+            charge_ah: measurement.charge_uah / 1e6,
+            energy_wh: measurement.energy_uwh / 1e6,
+            missing_samples: measurement.cumulative_missing_samples,
+        };
+        Some(self.json("measurement", &payload, true))
+    }
+
+    fn json(&self, suffix: &str, payload: &impl Serialize, retain: bool) -> MqttMessage {
+        let payload = serde_json::to_vec(payload).expect("MQTT payloads serialize to JSON");
+        self.message(suffix, payload, retain)
+    }
+
+    fn message(&self, suffix: &str, payload: Vec<u8>, retain: bool) -> MqttMessage {
+        MqttMessage {
+            topic: self.topic(suffix),
+            payload,
+            retain,
+        }
+    }
+}
+
+/// Publishes a meter's messages to a broker, reconnecting transparently.
+///
+/// Must be created inside a Tokio runtime, which runs the connection task.
+#[derive(Debug)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    qos: QoS,
+    encoder: MqttEncoder,
+    retained: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    dropped: u64,
+    connection: JoinHandle<()>,
+}
+
+impl MqttPublisher {
+    /// Start connecting to the broker and publish the device identification.
+    pub fn connect(config: &MqttConfig, state: &DeviceState) -> Self {
+        let encoder = MqttEncoder::new(&config.topic_prefix, &state.info.serial_id, config.measurement_interval);
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(config.keep_alive);
+        let offline = encoder.status(false);
+        options.set_last_will(LastWill::new(offline.topic, offline.payload, config.qos, true));
+        let (client, mut eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+
+        let retained = Arc::new(Mutex::new(BTreeMap::<String, Vec<u8>>::new()));
+        let connection = tokio::spawn({
+            let client = client.clone();
+            let retained = retained.clone();
+            let qos = config.qos;
+            async move {
+                let mut reconnect = false;
+                loop {
+                    match eventloop.poll().await {
+                        Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                            info!("Connected to MQTT broker");
+                            // Messages queued before the first connection go out on their own. After a
+                            // reconnect the will may have announced the meter offline; restore the last state.
+                            if std::mem::replace(&mut reconnect, true) {
+                                for (topic, payload) in retained.lock().unwrap().iter() {
+                                    let _ = client.try_publish(topic, qos, true, payload.clone());
+                                }
+                            }
+                        }
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                        Ok(_) => {}
+                        Err(error) => {
+                            warn!("MQTT connection failed: {error}; reconnecting");
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        let mut publisher = Self {
+            client,
+            qos: config.qos,
+            encoder,
+            retained,
+            dropped: 0,
+            connection,
+        };
+        let device = publisher.encoder.device(state);
+        publisher.publish(device);
+        publisher.set_online(true);
+        publisher
+    }
+
+    pub fn encoder(&self) -> &MqttEncoder {
+        &self.encoder
+    }
+
+    pub fn encoder_mut(&mut self) -> &mut MqttEncoder {
+        &mut self.encoder
+    }
+
+    /// Messages dropped because the broker was unreachable for too long.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Queue a message; retained messages are also kept for republishing.
+    pub fn publish(&mut self, message: MqttMessage) {
+        if message.retain {
+            self.retained
+                .lock()
+                .unwrap()
+                .insert(message.topic.clone(), message.payload.clone());
+        }
+        if self
+            .client
+            .try_publish(message.topic, self.qos, message.retain, message.payload)
+            .is_err()
+        {
+            self.dropped += 1;
+        }
+    }
+
+    /// Announce whether the meter is streaming.
+    pub fn set_online(&mut self, online: bool) {
+        let status = self.encoder.status(online);
+        self.publish(status);
+    }
+
+    /// Publish the measurements and PD changes of a response packet.
+    pub fn publish_packet(&mut self, packet: &Packet, rate: GraphSampleRate) {
+        for message in self.encoder.packet(packet, rate) {
+            self.publish(message);
+        }
+    }
+
+    pub fn publish_offline_log(&mut self, log: &OfflineLog, saved_to: Option<&Path>) {
+        let message = self.encoder.offline_log(log, saved_to);
+        self.publish(message);
+    }
+
+    /// Announce the meter offline and close the connection.
+    pub async fn disconnect(mut self) -> Result<(), KMError> {
+        self.set_online(false);
+        self.client.disconnect().await?;
+        if tokio::time::timeout(Duration::from_secs(5), &mut self.connection)
+            .await
+            .is_err()
+        {
+            self.connection.abort();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MqttPublisher {
+    fn drop(&mut self) {
+        self.connection.abort();
+    }
+}
+
+/// Payload of the `device` topic.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DevicePayload {
+    pub model: String,
+    pub hardware_version: String,
+    pub firmware_version: String,
+    pub serial: String,
+    pub uuid: String,
+    pub hardware_id: String,
+}
+
+impl From<&DeviceState> for DevicePayload {
+    fn from(state: &DeviceState) -> Self {
+        let info = &state.info;
+        Self {
+            model: info.model.clone(),
+            hardware_version: info.hw_version.clone(),
+            firmware_version: info.fw_version.clone(),
+            serial: info.serial_id.clone(),
+            uuid: info.uuid.clone(),
+            hardware_id: state.hardware_id.to_string(),
+        }
+    }
+}
+
+/// Payload of the `measurement` topic: averages over one interval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeasurementPayload {
+    pub elapsed_s: f64,
+    pub samples: u32,
+    pub sample_rate_hz: u16,
+    pub vbus_v: f64,
+    pub ibus_a: f64,
+    pub power_w: f64,
+    pub cc1_v: f64,
+    pub cc2_v: f64,
+    pub dp_v: f64,
+    pub dm_v: f64,
+    pub charge_ah: f64,
+    pub energy_wh: f64,
+    /// Samples missing since the stream started.
+    pub missing_samples: u64,
+}
+
+/// Payload of the `pd/attach` topic.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttachPayload {
+    pub attached: bool,
+    pub timestamp_s: f64,
+}
+
+/// Payload of the `pd/contract` topic, published as `null` once the contract ends.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractPayload {
+    pub voltage_v: Option<f64>,
+    pub current_a: Option<f64>,
+    pub object_position: u8,
+    pub epr: bool,
+    pub accepted_at_s: f64,
+}
+
+impl From<&PdContract> for ContractPayload {
+    fn from(contract: &PdContract) -> Self {
+        Self {
+            voltage_v: contract.voltage.map(|voltage| voltage.get::<volt>()),
+            current_a: contract.current.map(|current| current.get::<ampere>()),
+            object_position: contract.request.object_position(),
+            epr: contract.epr,
+            accepted_at_s: contract.accepted_at.get::<second>(),
+        }
+    }
+}
+
+/// Payload of the `offline/complete` topic.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OfflineLogPayload {
+    pub filename: String,
+    pub samples: usize,
+    pub interval_s: f64,
+    pub duration_s: f64,
+    pub charge_ah: f64,
+    pub energy_wh: f64,
+    /// File the log was saved to, if any.
+    pub path: Option<String>,
+}
+
+/// A serial usable as one topic level.
+fn topic_level(serial: &str) -> String {
+    let level = serial.trim().replace(['/', '+', '#'], "_");
+    if level.is_empty() { "unknown".to_string() } else { level }
+}
@@ -20,6 +20,8 @@ pub const OFFLINE_LOG_ADDRESS: u32 = 0x9810_0000;
 pub const LOG_METADATA_SIZE: usize = 48;
 /// Size of one offline log sample.
 pub const OFFLINE_LOG_SAMPLE_SIZE: usize = 16;
+/// Default number of bytes read per memory request by `OfflineLogDownload`.
+pub const OFFLINE_DOWNLOAD_CHUNK_SIZE: u32 = 4096;
 
 #[derive(Debug, Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
@@ -227,6 +229,7 @@ impl From<OfflineLogSampleRaw> for OfflineLogSampleWire {
 /// One decoded offline log sample with typed physical quantities.
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "python", pyo3::pyclass(skip_from_py_object))]
 pub struct OfflineLogSample {
     raw: OfflineLogSampleRaw,
     pub voltage: ElectricPotential,
@@ -245,6 +248,7 @@ impl OfflineLogSample {
 /// A complete offline log downloaded from the device.
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "python", pyo3::pyclass(skip_from_py_object))]
 pub struct OfflineLog {
     pub metadata: LogMetadata,
     pub samples: Vec<OfflineLogSample>,
@@ -293,6 +297,244 @@ impl OfflineLog {
     }
 }
 
+#[cfg(feature = "python")]
+#[pyo3::pymethods]
+impl LogMetadata {
+    #[getter(filename)]
+    fn py_filename(&self) -> String {
+        self.filename_lossy().into_owned()
+    }
+
+    #[getter(sample_count)]
+    fn py_sample_count(&self) -> u16 {
+        self.sample_count
+    }
+
+    #[getter]
+    fn interval_s(&self) -> f64 {
+        self.interval.get::<second>()
+    }
+
+    #[getter]
+    fn recorded_duration_s(&self) -> f64 {
+        self.recorded_duration.get::<second>()
+    }
+
+    #[getter]
+    fn final_charge_ah(&self) -> f64 {
+        self.final_charge.get::<uom::si::electric_charge::ampere_hour>()
+    }
+
+    #[getter]
+    fn final_energy_wh(&self) -> f64 {
+        self.final_energy.get::<uom::si::energy::watt_hour>()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "LogMetadata(filename={:?}, {} samples, interval={}s)",
+            self.filename_lossy(),
+            self.sample_count,
+            self.interval.get::<second>()
+        )
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyo3::pymethods]
+impl OfflineLogSample {
+    #[getter]
+    fn voltage_v(&self) -> f64 {
+        self.voltage.get::<uom::si::electric_potential::volt>()
+    }
+
+    #[getter]
+    fn current_a(&self) -> f64 {
+        self.current.get::<uom::si::electric_current::ampere>()
+    }
+
+    #[getter]
+    fn power_w(&self) -> f64 {
+        self.power.get::<uom::si::power::watt>()
+    }
+
+    #[getter]
+    fn charge_ah(&self) -> f64 {
+        self.charge.get::<uom::si::electric_charge::ampere_hour>()
+    }
+
+    #[getter]
+    fn energy_wh(&self) -> f64 {
+        self.energy.get::<uom::si::energy::watt_hour>()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "OfflineLogSample(voltage={:.3}V, current={:.3}A)",
+            self.voltage_v(),
+            self.current_a()
+        )
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyo3::pymethods]
+impl OfflineLog {
+    #[getter]
+    fn metadata(&self) -> LogMetadata {
+        self.metadata.clone()
+    }
+
+    #[getter]
+    fn samples(&self) -> Vec<OfflineLogSample> {
+        self.samples.clone()
+    }
+
+    fn __len__(&self) -> usize {
+        self.samples.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "OfflineLog(filename={:?}, {} samples)",
+            self.metadata.filename_lossy(),
+            self.samples.len()
+        )
+    }
+}
+
+/// Progress of a chunked offline log download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OfflineDownloadProgress {
+    pub received_bytes: u32,
+    pub total_bytes: u32,
+}
+
+impl OfflineDownloadProgress {
+    pub const fn received_samples(&self) -> u32 {
+        self.received_bytes / OFFLINE_LOG_SAMPLE_SIZE as u32
+    }
+
+    pub const fn is_complete(&self) -> bool {
+        self.received_bytes >= self.total_bytes
+    }
+
+    /// Completed fraction in `0.0..=1.0`.
+    pub fn fraction(&self) -> f64 {
+        if self.total_bytes == 0 {
+            1.0
+        } else {
+            f64::from(self.received_bytes) / f64::from(self.total_bytes)
+        }
+    }
+}
+
+/// Resumable state of a chunked offline log download.
+///
+/// Chunks are verified before they are appended, so after a failed read,
+/// timeout or reconnect the download continues from the last good chunk.
+/// `finish` validates the complete log against the metadata's final charge
+/// and energy accumulators.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineLogDownload {
+    metadata: LogMetadata,
+    chunk_size: u32,
+    data: Vec<u8>,
+}
+
+impl OfflineLogDownload {
+    pub fn new(metadata: LogMetadata) -> Self {
+        Self {
+            metadata,
+            chunk_size: OFFLINE_DOWNLOAD_CHUNK_SIZE,
+            data: Vec::new(),
+        }
+    }
+
+    /// Read `size` bytes per request, rounded down to whole samples.
+    pub fn with_chunk_size(mut self, size: u32) -> Self {
+        let sample_size = OFFLINE_LOG_SAMPLE_SIZE as u32;
+        self.chunk_size = (size / sample_size).max(1) * sample_size;
+        self
+    }
+
+    pub fn metadata(&self) -> &LogMetadata {
+        &self.metadata
+    }
+
+    pub const fn chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+
+    pub fn progress(&self) -> OfflineDownloadProgress {
+        OfflineDownloadProgress {
+            received_bytes: self.data.len() as u32,
+            total_bytes: self.metadata.data_size(),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.progress().is_complete()
+    }
+
+    /// Device address and size of the next chunk to read, if any remain.
+    pub fn next_chunk(&self) -> Result<Option<(u32, u32)>, KMError> {
+        let progress = self.progress();
+        if progress.is_complete() {
+            return Ok(None);
+        }
+        let address = self
+            .metadata
+            .data_address()?
+            .checked_add(progress.received_bytes)
+            .ok_or_else(|| KMError::Protocol("Offline log chunk address overflows".to_string()))?;
+        let size = self.chunk_size.min(progress.total_bytes - progress.received_bytes);
+        Ok(Some((address, size)))
+    }
+
+    /// Verify and append the data read for `next_chunk`.
+    ///
+    /// A chunk of the wrong length or containing erased-flash samples is
+    /// rejected and leaves the download unchanged.
+    pub fn push_chunk(&mut self, bytes: &[u8]) -> Result<OfflineDownloadProgress, KMError> {
+        let Some((address, size)) = self.next_chunk()? else {
+            return Err(KMError::InvalidPacket(
+                "Offline log download is already complete".to_string(),
+            ));
+        };
+        if bytes.len() != size as usize {
+            return Err(KMError::InvalidPacket(format!(
+                "Offline log chunk at 0x{address:08X} has {} bytes, expected {size}",
+                bytes.len()
+            )));
+        }
+        if let Some(index) = bytes
+            .chunks_exact(OFFLINE_LOG_SAMPLE_SIZE)
+            .position(|sample| sample.iter().all(|byte| *byte == 0xff))
+        {
+            return Err(KMError::InvalidPacket(format!(
+                "Offline log chunk at 0x{address:08X} contains erased flash at sample {}",
+                self.progress().received_samples() as usize + index
+            )));
+        }
+        self.data.extend_from_slice(bytes);
+        Ok(self.progress())
+    }
+
+    /// Decode the complete log, validating its final accumulators.
+    pub fn finish(self) -> Result<OfflineLog, KMError> {
+        let progress = self.progress();
+        if !progress.is_complete() {
+            return Err(KMError::InvalidPacket(format!(
+                "Offline log download is incomplete: {} of {} bytes received",
+                progress.received_bytes, progress.total_bytes
+            )));
+        }
+        OfflineLog::from_bytes(self.metadata, &self.data)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use uom::si::electric_charge::milliampere_hour;
@@ -393,4 +635,49 @@ mod tests {
         let packet = Packet::try_from(RawPacket::try_from(Bytes::from(empty)).unwrap()).unwrap();
         assert_eq!(packet.get_log_metadata(), Some(&LogMetadataResponse::Empty));
     }
+
+    #[test]
+    fn chunked_download_resumes_from_the_last_good_chunk() {
+        let samples = [
+            "81494c0021f0e2ff56ebffffb998ffff",
+            "bcaa89006e25f2ff2dd5f8fff7fdd6ff",
+            "cf2a8900947dfeffa1a2f3ffe04da8ff",
+        ]
+        .map(|sample| hex::decode(sample).unwrap());
+        let mut metadata = LogMetadata::from_bytes(&hex::decode(CAPTURED_METADATA).unwrap()).unwrap();
+        metadata.sample_count = 3;
+        metadata.data_offset = 0x40;
+        let mut download = OfflineLogDownload::new(metadata).with_chunk_size(40);
+
+        assert_eq!(download.chunk_size(), 32);
+        assert_eq!(download.next_chunk().unwrap(), Some((OFFLINE_LOG_ADDRESS + 0x40, 32)));
+        assert!(download.push_chunk(&samples[0]).is_err());
+        let erased = [samples[0].clone(), vec![0xff; OFFLINE_LOG_SAMPLE_SIZE]].concat();
+        let error = download.push_chunk(&erased).unwrap_err();
+        assert!(error.to_string().contains("erased flash at sample 1"));
+        assert_eq!(download.progress().received_bytes, 0);
+
+        let progress = download.push_chunk(&samples[..2].concat()).unwrap();
+        assert_eq!(progress.received_samples(), 2);
+        assert_eq!(progress.fraction(), 2.0 / 3.0);
+        assert!(download.clone().finish().is_err());
+
+        // A reconnect resumes with the same state and reads only the remainder.
+        assert_eq!(download.next_chunk().unwrap(), Some((OFFLINE_LOG_ADDRESS + 0x60, 16)));
+        let mut wrong_log = download.clone();
+        wrong_log.push_chunk(&samples[1]).unwrap();
+        assert!(
+            wrong_log
+                .finish()
+                .unwrap_err()
+                .to_string()
+                .contains("final accumulators")
+        );
+
+        assert!(download.push_chunk(&samples[2]).unwrap().is_complete());
+        assert_eq!(download.next_chunk().unwrap(), None);
+        assert!(download.push_chunk(&samples[2]).is_err());
+        let log = download.finish().unwrap();
+        assert_eq!(log.to_bytes(), samples.concat());
+    }
 }
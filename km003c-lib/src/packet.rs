@@ -53,6 +53,27 @@ pub struct ExtendedHeader {
     pub size: B10,
 }
 
+/// Serialize bitfield packet headers as their four wire bytes.
+#[cfg(feature = "serde")]
+macro_rules! serde_header_bytes {
+    ($($header:ty),*) => {$(
+        impl serde::Serialize for $header {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serde::Serialize::serialize(&self.into_bytes(), serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $header {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <[u8; 4] as serde::Deserialize>::deserialize(deserializer).map(Self::from_bytes)
+            }
+        }
+    )*};
+}
+
+#[cfg(feature = "serde")]
+serde_header_bytes!(CtrlHeader, DataHeader);
+
 /// KM003C protocol packet types.
 ///
 /// Values < 0x40 are control packet types, >= 0x40 are data packet types.
@@ -118,6 +139,7 @@ impl<'py> pyo3::IntoPyObject<'py> for PacketType {
 ///
 /// These values specify the type of data or command being sent.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 #[repr(u16)]
 pub enum Attribute {
     None = 0,
@@ -155,6 +177,7 @@ impl<'py> pyo3::IntoPyObject<'py> for Attribute {
 /// Set of attributes for use in request masks.
 /// Can represent single or multiple attributes combined with bitwise OR.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "python", derive(pyo3::IntoPyObject))]
 pub struct AttributeSet {
     mask: u16,
@@ -267,6 +290,7 @@ impl FromIterator<Attribute> for AttributeSet {
 /// PutData packets can contain multiple chained logical packets,
 /// each with its own extended header and payload.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(
     feature = "python",
     pyo3::pyclass(get_all, skip_from_py_object, name = "LogicalPacket")
@@ -280,6 +304,7 @@ pub struct LogicalPacket {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum RawPacket {
     Ctrl {
         header: CtrlHeader,
@@ -104,6 +104,7 @@ impl PdStatus {
 
 /// Event data types that can appear in PD stream
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "python", derive(pyo3::IntoPyObject))]
 pub enum PdEventData {
     #[cfg_attr(feature = "python", pyo3(transparent))]
@@ -118,14 +119,55 @@ pub enum PdEventData {
 
 /// Timestamped PD event
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "python", pyo3::pyclass(skip_from_py_object, name = "PdEvent"))]
 pub struct PdEvent {
     pub timestamp: Time,
     pub data: PdEventData,
 }
 
+impl PdEvent {
+    /// Serialize the event in the KM003C event framing.
+    ///
+    /// Connection events and messages without wire data use the 6-byte
+    /// connection record with its 24-bit timestamp.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KMError> {
+        let timestamp = self.timestamp.get::<millisecond>() as u32;
+        let (code, wire_data) = match &self.data {
+            PdEventData::Connect(()) => (PD_CONNECTION_CONNECT, &[][..]),
+            PdEventData::Disconnect(()) => (PD_CONNECTION_DISCONNECT, &[][..]),
+            PdEventData::PdMessage { sop, wire_data } => (*sop, wire_data.as_slice()),
+        };
+
+        if wire_data.is_empty() {
+            if timestamp > 0x00FF_FFFF {
+                return Err(KMError::InvalidPacket(format!(
+                    "PD connection event timestamp {timestamp} ms does not fit in 24 bits"
+                )));
+            }
+            let [ts0, ts1, ts2, _] = timestamp.to_le_bytes();
+            return Ok(vec![PD_EVENT_TYPE_CONNECTION, ts0, ts1, ts2, 0, code]);
+        }
+
+        let encoded_size = usize::from(PD_EVENT_SIZE_OFFSET) + wire_data.len();
+        if encoded_size > usize::from(PD_EVENT_SIZE_MASK) {
+            return Err(KMError::InvalidPacket(format!(
+                "PD wire message of {} bytes is too long for a PD event",
+                wire_data.len()
+            )));
+        }
+        let mut bytes = Vec::with_capacity(PD_EVENT_HEADER_SIZE + wire_data.len());
+        bytes.push(PD_EVENT_MESSAGE_FLAG | encoded_size as u8);
+        bytes.extend_from_slice(&timestamp.to_le_bytes());
+        bytes.push(code);
+        bytes.extend_from_slice(wire_data);
+        Ok(bytes)
+    }
+}
+
 /// Complete PD event stream with preamble and events
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "python", pyo3::pyclass(skip_from_py_object, name = "PdEventStream"))]
 pub struct PdEventStream {
     /// Measurements captured immediately before the event records.
@@ -231,6 +273,15 @@ impl PdEventStream {
         Ok(Self { preamble, events })
     }
 
+    /// Serialize the preamble and events in the framing parsed by [`Self::from_bytes`].
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KMError> {
+        let mut bytes = PdStatusRaw::from(self.preamble).as_bytes().to_vec();
+        for event in &self.events {
+            bytes.extend_from_slice(&event.to_bytes()?);
+        }
+        Ok(bytes)
+    }
+
     /// Helper: get all PD messages, ignoring connection events
     pub fn pd_messages(&self) -> impl Iterator<Item = (&Time, u8, &Vec<u8>)> {
         self.events.iter().filter_map(|e| match &e.data {
@@ -0,0 +1,400 @@
+//! Alternate-mode and USB4 entry tracking for a USB PD session.
+//!
+//! After an explicit contract the DFP discovers the partner's SVIDs and modes,
+//! enters an alternate mode and, for DisplayPort, exchanges Status Update and
+//! Configure commands before the sink asserts HPD. [`AltModeTracker`] follows
+//! those exchanges in a KM003C event stream and keeps an [`AltModeSummary`]
+//! of what was discovered, what was entered and where negotiation stopped.
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+use uom::si::electric_current::ampere;
+use uom::si::f64::{ElectricCurrent, Time};
+
+use crate::pd::{PdEvent, PdEventData};
+use crate::pd_vdm::{
+    DISPLAYPORT_SVID, DP_CONFIGURE, DP_STATUS_UPDATE, DiscoverIdentity, DisplayPortConfigureVdo,
+    DisplayPortPinAssignment, DisplayPortStatusVdo, ModeVdo, UsbHighestSpeed, VdmCommand, VdmCommandType,
+    VendorDefinedMessage, bit, bits,
+};
+use crate::pd_wire::{PdControlMessageType, PdDataMessageType, PdMessageType, PdSopType, PdWireMessage};
+
+/// USB mode requested by an Enter_USB message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum EnterUsbMode {
+    Usb20 = 0,
+    Usb32 = 1,
+    Usb4 = 2,
+    #[num_enum(catch_all)]
+    Reserved(u8),
+}
+
+/// Cable type reported in an Enter_USB message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum EnterUsbCableType {
+    #[num_enum(default)]
+    Passive = 0,
+    ActiveRetimer = 1,
+    ActiveRedriver = 2,
+    OpticallyIsolated = 3,
+}
+
+/// Enter_USB Data Object, sent by the DFP to enter USB4 or USB 3.2 operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct EnterUsbDataObject(pub u32);
+
+impl EnterUsbDataObject {
+    pub fn usb_mode(&self) -> EnterUsbMode {
+        EnterUsbMode::from_primitive(bits(self.0, 28, 3) as u8)
+    }
+
+    pub fn usb4_drd(&self) -> bool {
+        bit(self.0, 26)
+    }
+
+    pub fn usb3_drd(&self) -> bool {
+        bit(self.0, 25)
+    }
+
+    /// Highest signalling speed of the cable, encoded as in the cable VDO.
+    pub fn cable_speed(&self) -> UsbHighestSpeed {
+        UsbHighestSpeed::from_primitive(bits(self.0, 21, 3) as u8)
+    }
+
+    pub fn cable_type(&self) -> EnterUsbCableType {
+        EnterUsbCableType::from_primitive(bits(self.0, 19, 2) as u8)
+    }
+
+    /// Current the cable carries, or `None` when VBUS is not supported or reserved.
+    pub fn cable_current(&self) -> Option<ElectricCurrent> {
+        match bits(self.0, 17, 2) {
+            2 => Some(ElectricCurrent::new::<ampere>(3.0)),
+            3 => Some(ElectricCurrent::new::<ampere>(5.0)),
+            _ => None,
+        }
+    }
+
+    pub fn pcie_supported(&self) -> bool {
+        bit(self.0, 16)
+    }
+
+    pub fn dp_supported(&self) -> bool {
+        bit(self.0, 15)
+    }
+
+    pub fn tbt_supported(&self) -> bool {
+        bit(self.0, 14)
+    }
+
+    pub fn host_present(&self) -> bool {
+        bit(self.0, 13)
+    }
+}
+
+/// Mode VDOs discovered for one SVID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DiscoveredModes {
+    pub svid: u16,
+    pub modes: Vec<ModeVdo>,
+}
+
+/// An alternate mode whose Enter Mode request was acknowledged.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ActiveAltMode {
+    pub sop: PdSopType,
+    pub svid: u16,
+    /// One-based index into the modes discovered for the SVID.
+    pub object_position: u8,
+    /// Discovered mode VDO at the object position, if Discover Modes was captured.
+    pub mode: Option<ModeVdo>,
+    pub entered_at: Time,
+}
+
+/// A NAK or BUSY response to Enter Mode or DisplayPort Configure.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct AltModeFailure {
+    pub timestamp: Time,
+    pub sop: PdSopType,
+    pub svid: u16,
+    pub object_position: u8,
+    pub command: VdmCommand,
+    pub response: VdmCommandType,
+}
+
+/// DisplayPort alternate-mode state on SOP.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DisplayPortState {
+    /// Last acknowledged DisplayPort Configure VDO.
+    pub configuration: Option<DisplayPortConfigureVdo>,
+    pub configured_at: Option<Time>,
+    /// Last status reported by the UFP_D, from a Status Update ACK or Attention.
+    pub status: Option<DisplayPortStatusVdo>,
+    pub status_at: Option<Time>,
+    /// Number of IRQ_HPD pulses signalled through Attention.
+    pub irq_hpd_count: u32,
+}
+
+impl DisplayPortState {
+    /// Hot Plug Detect level from the last UFP_D status.
+    pub fn hpd(&self) -> bool {
+        self.status.is_some_and(|status| status.hpd_state())
+    }
+
+    /// Pin assignment of the active configuration.
+    pub fn pin_assignment(&self) -> Option<DisplayPortPinAssignment> {
+        self.configuration
+            .and_then(|configuration| configuration.pin_assignment())
+    }
+}
+
+/// Enter_USB request and the response to it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct EnterUsbState {
+    pub timestamp: Time,
+    pub request: EnterUsbDataObject,
+    /// Accept, Reject, Wait or Not_Supported; `None` while unanswered.
+    pub response: Option<PdControlMessageType>,
+}
+
+impl EnterUsbState {
+    pub fn accepted(&self) -> bool {
+        self.response == Some(PdControlMessageType::Accept)
+    }
+}
+
+/// Alternate-mode state of one connection.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct AltModeSummary {
+    pub partner_identity: Option<DiscoverIdentity>,
+    pub partner_svids: Vec<u16>,
+    pub cable_svids: Vec<u16>,
+    pub partner_modes: Vec<DiscoveredModes>,
+    pub cable_modes: Vec<DiscoveredModes>,
+    pub active_modes: Vec<ActiveAltMode>,
+    pub failures: Vec<AltModeFailure>,
+    /// Present once DisplayPort mode is entered or DisplayPort traffic is seen on SOP.
+    pub displayport: Option<DisplayPortState>,
+    pub enter_usb: Option<EnterUsbState>,
+}
+
+impl AltModeSummary {
+    /// Modes the port partner reported for an SVID.
+    pub fn partner_modes_for(&self, svid: u16) -> Option<&[ModeVdo]> {
+        find_modes(&self.partner_modes, svid)
+    }
+
+    /// Modes the cable reported for an SVID.
+    pub fn cable_modes_for(&self, svid: u16) -> Option<&[ModeVdo]> {
+        find_modes(&self.cable_modes, svid)
+    }
+
+    /// Whether an alternate mode for the SVID is currently entered with the port partner.
+    pub fn is_active(&self, svid: u16) -> bool {
+        self.active_modes
+            .iter()
+            .any(|mode| mode.sop == PdSopType::Sop && mode.svid == svid)
+    }
+}
+
+fn find_modes(modes: &[DiscoveredModes], svid: u16) -> Option<&[ModeVdo]> {
+    modes
+        .iter()
+        .find(|discovered| discovered.svid == svid)
+        .map(|discovered| discovered.modes.as_slice())
+}
+
+/// Follows SVID and mode discovery, mode entry and DisplayPort configuration
+/// in a KM003C event stream.
+///
+/// Connection changes clear the summary.
+#[derive(Debug, Clone, Default)]
+pub struct AltModeTracker {
+    summary: AltModeSummary,
+    pending_dp_configure: Option<DisplayPortConfigureVdo>,
+    awaiting_enter_usb_response: bool,
+}
+
+impl AltModeTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget all discovered and entered modes.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn summary(&self) -> &AltModeSummary {
+        &self.summary
+    }
+
+    /// Process one KM003C PD event.
+    pub fn process_event(&mut self, event: &PdEvent) {
+        let (sop, wire_data) = match &event.data {
+            PdEventData::Connect(()) | PdEventData::Disconnect(()) => {
+                self.reset();
+                return;
+            }
+            PdEventData::PdMessage { sop, wire_data } => (PdSopType::from(*sop), wire_data),
+        };
+        if !matches!(sop, PdSopType::Sop | PdSopType::SopPrime | PdSopType::SopDoublePrime) {
+            return;
+        }
+        let Ok(message) = PdWireMessage::from_bytes(wire_data) else {
+            return;
+        };
+
+        match message.message_type() {
+            PdMessageType::Control(PdControlMessageType::GoodCrc) => {}
+            PdMessageType::Data(PdDataMessageType::EnterUsb) if sop == PdSopType::Sop => {
+                if let Some(request) = message.data_objects().next() {
+                    self.summary.enter_usb = Some(EnterUsbState {
+                        timestamp: event.timestamp,
+                        request: EnterUsbDataObject(request),
+                        response: None,
+                    });
+                    self.awaiting_enter_usb_response = true;
+                }
+            }
+            PdMessageType::Control(control) if sop == PdSopType::Sop && self.awaiting_enter_usb_response => {
+                self.awaiting_enter_usb_response = false;
+                if let Some(enter_usb) = &mut self.summary.enter_usb {
+                    enter_usb.response = Some(control);
+                }
+            }
+            PdMessageType::Data(PdDataMessageType::VendorDefined) => {
+                if let Some(vdm) = VendorDefinedMessage::from_wire(&message) {
+                    self.process_vdm(event.timestamp, sop, &vdm);
+                }
+            }
+            _ if sop == PdSopType::Sop => self.awaiting_enter_usb_response = false,
+            _ => {}
+        }
+    }
+
+    fn process_vdm(&mut self, timestamp: Time, sop: PdSopType, vdm: &VendorDefinedMessage) {
+        if !vdm.header.is_structured() {
+            return;
+        }
+
+        let svid = vdm.header.svid();
+        let object_position = vdm.header.object_position();
+        let command = vdm.header.command();
+        let command_type = vdm.header.command_type();
+        let partner = sop == PdSopType::Sop;
+
+        match (command, command_type) {
+            (VdmCommand::DiscoverIdentity, VdmCommandType::Ack) if partner => {
+                self.summary.partner_identity = vdm.discover_identity();
+            }
+            (VdmCommand::DiscoverSvids, VdmCommandType::Ack) => {
+                let svids = if partner {
+                    &mut self.summary.partner_svids
+                } else {
+                    &mut self.summary.cable_svids
+                };
+                // More than twelve SVIDs are returned over repeated requests.
+                for discovered in vdm.discover_svids().unwrap_or_default() {
+                    if !svids.contains(&discovered) {
+                        svids.push(discovered);
+                    }
+                }
+            }
+            (VdmCommand::DiscoverModes, VdmCommandType::Ack) => {
+                let modes = if partner {
+                    &mut self.summary.partner_modes
+                } else {
+                    &mut self.summary.cable_modes
+                };
+                modes.retain(|discovered| discovered.svid != svid);
+                modes.push(DiscoveredModes {
+                    svid,
+                    modes: vdm.discover_modes(sop).unwrap_or_default(),
+                });
+            }
+            (VdmCommand::EnterMode, VdmCommandType::Ack) => {
+                let discovered = if partner {
+                    self.summary.partner_modes_for(svid)
+                } else {
+                    self.summary.cable_modes_for(svid)
+                };
+                let mode = discovered
+                    .and_then(|modes| modes.get(usize::from(object_position).checked_sub(1)?))
+                    .copied();
+                self.summary
+                    .active_modes
+                    .retain(|active| active.sop != sop || active.svid != svid);
+                self.summary.active_modes.push(ActiveAltMode {
+                    sop,
+                    svid,
+                    object_position,
+                    mode,
+                    entered_at: timestamp,
+                });
+                if partner && svid == DISPLAYPORT_SVID {
+                    self.summary.displayport = Some(DisplayPortState::default());
+                }
+            }
+            (VdmCommand::ExitMode, VdmCommandType::Ack) => {
+                self.summary
+                    .active_modes
+                    .retain(|active| active.sop != sop || active.svid != svid);
+                if partner && svid == DISPLAYPORT_SVID {
+                    self.summary.displayport = None;
+                }
+            }
+            (VdmCommand::EnterMode, VdmCommandType::Nak | VdmCommandType::Busy) => {
+                self.record_failure(timestamp, sop, vdm);
+            }
+            _ if !partner || svid != DISPLAYPORT_SVID => {}
+            // The Status Update request carries the DFP_D's own status; only
+            // the ACK and Attention describe the sink.
+            (VdmCommand::Attention, _) | (VdmCommand::SvidSpecific(DP_STATUS_UPDATE), VdmCommandType::Ack) => {
+                if let Some(&status) = vdm.objects.first() {
+                    let status = DisplayPortStatusVdo(status);
+                    let displayport = self.summary.displayport.get_or_insert_with(Default::default);
+                    displayport.status = Some(status);
+                    displayport.status_at = Some(timestamp);
+                    if command == VdmCommand::Attention && status.irq_hpd() {
+                        displayport.irq_hpd_count += 1;
+                    }
+                }
+            }
+            (VdmCommand::SvidSpecific(DP_CONFIGURE), VdmCommandType::Request) => {
+                self.pending_dp_configure = vdm.objects.first().copied().map(DisplayPortConfigureVdo);
+            }
+            (VdmCommand::SvidSpecific(DP_CONFIGURE), VdmCommandType::Ack) => {
+                let configuration = self.pending_dp_configure.take();
+                let displayport = self.summary.displayport.get_or_insert_with(Default::default);
+                displayport.configuration = configuration;
+                displayport.configured_at = Some(timestamp);
+            }
+            (VdmCommand::SvidSpecific(DP_CONFIGURE), VdmCommandType::Nak | VdmCommandType::Busy) => {
+                self.pending_dp_configure = None;
+                self.record_failure(timestamp, sop, vdm);
+            }
+            _ => {}
+        }
+    }
+
+    fn record_failure(&mut self, timestamp: Time, sop: PdSopType, vdm: &VendorDefinedMessage) {
+        self.summary.failures.push(AltModeFailure {
+            timestamp,
+            sop,
+            svid: vdm.header.svid(),
+            object_position: vdm.header.object_position(),
+            command: vdm.header.command(),
+            response: vdm.header.command_type(),
+        });
+    }
+}
@@ -0,0 +1,223 @@
+//! E-marked cable identification from SOP'/SOP'' Discover Identity traffic.
+//!
+//! A source that wants to offer more than 3 A, or to enter EPR, first asks the
+//! cable's e-marker who it is with a Discover Identity request on SOP'. The
+//! [`CableIdentityTracker`] follows those exchanges in a KM003C event stream
+//! and decodes each acknowledged response into a [`CableIdentity`].
+
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+
+use crate::pd::{PdEvent, PdEventData};
+use crate::pd_vdm::{
+    ActiveCableVdo2, CableLatency, CablePlugProductType, CablePlugType, CableTermination, CableVdo, DiscoverIdentity,
+    PD_SID, UsbHighestSpeed, VdmCommand, VdmCommandType, VendorDefinedMessage,
+};
+use crate::pd_wire::{PdSopType, PdWireMessage};
+
+/// Whether an e-marker describes a passive or an active cable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum CableKind {
+    Passive,
+    Active,
+}
+
+/// Properties reported only by active cables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ActiveCableDetails {
+    pub sbu_supported: bool,
+    pub sbu_active: bool,
+    pub vbus_through_cable: bool,
+    pub sop_double_prime_controller: bool,
+    /// Physical and USB signalling details, absent when the cable sent only VDO1.
+    pub vdo2: Option<ActiveCableVdo2>,
+}
+
+/// Identity of an e-marked cable decoded from a Discover Identity ACK.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CableIdentity {
+    /// Plug that answered: SOP' for the near end, SOP'' for the far end of an active cable.
+    pub sop: PdSopType,
+    pub kind: CableKind,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bcd_device: u16,
+    /// USB-IF assigned XID, zero for uncertified cables.
+    pub xid: u32,
+    pub hardware_version: u8,
+    pub firmware_version: u8,
+    pub vdo_version: u8,
+    pub plug_type: CablePlugType,
+    pub epr_mode_capable: bool,
+    pub latency: CableLatency,
+    pub termination: CableTermination,
+    pub max_vbus_voltage: ElectricPotential,
+    /// 3 or 5 A; `None` when the e-marker reports a reserved value.
+    pub max_current: Option<ElectricCurrent>,
+    pub usb_highest_speed: UsbHighestSpeed,
+    pub active: Option<ActiveCableDetails>,
+    /// Raw Passive Cable VDO or Active Cable VDO1.
+    pub cable_vdo: CableVdo,
+}
+
+impl CableIdentity {
+    /// Decode a cable identity, returning `None` if the responder is not a cable.
+    pub fn from_discover_identity(sop: PdSopType, identity: &DiscoverIdentity) -> Option<Self> {
+        let kind = match identity.id_header.cable_plug_product_type() {
+            CablePlugProductType::PassiveCable => CableKind::Passive,
+            CablePlugProductType::ActiveCable => CableKind::Active,
+            _ => return None,
+        };
+        let cable_vdo = CableVdo(*identity.product_type_vdos.first()?);
+
+        let active = (kind == CableKind::Active).then(|| ActiveCableDetails {
+            sbu_supported: cable_vdo.sbu_supported(),
+            sbu_active: cable_vdo.sbu_active(),
+            vbus_through_cable: cable_vdo.vbus_through_cable(),
+            sop_double_prime_controller: cable_vdo.sop_double_prime_controller(),
+            vdo2: identity.product_type_vdos.get(1).copied().map(ActiveCableVdo2),
+        });
+
+        Some(Self {
+            sop,
+            kind,
+            vendor_id: identity.id_header.vendor_id(),
+            product_id: identity.product.map_or(0, |product| product.product_id()),
+            bcd_device: identity.product.map_or(0, |product| product.bcd_device()),
+            xid: identity.cert_stat.map_or(0, |cert_stat| cert_stat.xid()),
+            hardware_version: cable_vdo.hardware_version(),
+            firmware_version: cable_vdo.firmware_version(),
+            vdo_version: cable_vdo.vdo_version(),
+            plug_type: cable_vdo.plug_type(),
+            epr_mode_capable: cable_vdo.epr_mode_capable(),
+            latency: cable_vdo.latency(),
+            termination: cable_vdo.termination(),
+            max_vbus_voltage: cable_vdo.max_vbus_voltage(),
+            max_current: cable_vdo.vbus_current(),
+            usb_highest_speed: cable_vdo.usb_highest_speed(),
+            active,
+            cable_vdo,
+        })
+    }
+}
+
+/// Response observed for a cable Discover Identity request.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum CableDiscoveryResult {
+    Identified(CableIdentity),
+    /// An ACK from a responder that is not a passive or active cable, such as a
+    /// VCONN-powered device.
+    NotCable(DiscoverIdentity),
+    Nak,
+    Busy,
+}
+
+/// One completed cable Discover Identity exchange.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CableDiscovery {
+    /// Time of the response.
+    pub timestamp: Time,
+    /// Time of the matching request, if it was captured.
+    pub request_timestamp: Option<Time>,
+    pub sop: PdSopType,
+    pub result: CableDiscoveryResult,
+}
+
+/// Follows SOP'/SOP'' Discover Identity exchanges in a KM003C event stream.
+///
+/// Connection changes clear the identified cables, because a different cable
+/// may be plugged in before the next attach.
+#[derive(Debug, Clone, Default)]
+pub struct CableIdentityTracker {
+    pending_sop_prime: Option<Time>,
+    pending_sop_double_prime: Option<Time>,
+    sop_prime: Option<CableIdentity>,
+    sop_double_prime: Option<CableIdentity>,
+}
+
+impl CableIdentityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget all identified cables and outstanding requests.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Identity reported by the near-end plug on SOP'.
+    pub fn cable(&self) -> Option<&CableIdentity> {
+        self.sop_prime.as_ref()
+    }
+
+    /// Identity reported by the plug on the given SOP'/SOP'' channel.
+    pub fn identity(&self, sop: PdSopType) -> Option<&CableIdentity> {
+        match sop {
+            PdSopType::SopPrime => self.sop_prime.as_ref(),
+            PdSopType::SopDoublePrime => self.sop_double_prime.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Process one KM003C PD event, returning a discovery when a response completes one.
+    pub fn process_event(&mut self, event: &PdEvent) -> Option<CableDiscovery> {
+        let (sop, wire_data) = match &event.data {
+            PdEventData::Connect(()) | PdEventData::Disconnect(()) => {
+                self.reset();
+                return None;
+            }
+            PdEventData::PdMessage { sop, wire_data } => (PdSopType::from(*sop), wire_data),
+        };
+        if !sop.is_cable_plug() {
+            return None;
+        }
+
+        let message = PdWireMessage::from_bytes(wire_data).ok()?;
+        let vdm = VendorDefinedMessage::from_wire(&message)?;
+        if vdm.header.svid() != PD_SID
+            || !vdm.header.is_structured()
+            || vdm.header.command() != VdmCommand::DiscoverIdentity
+        {
+            return None;
+        }
+
+        let pending = match sop {
+            PdSopType::SopPrime => &mut self.pending_sop_prime,
+            _ => &mut self.pending_sop_double_prime,
+        };
+        let result = match vdm.header.command_type() {
+            VdmCommandType::Request => {
+                *pending = Some(event.timestamp);
+                return None;
+            }
+            VdmCommandType::Nak => CableDiscoveryResult::Nak,
+            VdmCommandType::Busy => CableDiscoveryResult::Busy,
+            VdmCommandType::Ack => {
+                let identity = vdm.discover_identity()?;
+                match CableIdentity::from_discover_identity(sop, &identity) {
+                    Some(cable) => CableDiscoveryResult::Identified(cable),
+                    None => CableDiscoveryResult::NotCable(identity),
+                }
+            }
+        };
+        let request_timestamp = pending.take();
+
+        if let CableDiscoveryResult::Identified(cable) = &result {
+            match sop {
+                PdSopType::SopPrime => self.sop_prime = Some(cable.clone()),
+                _ => self.sop_double_prime = Some(cable.clone()),
+            }
+        }
+
+        Some(CableDiscovery {
+            timestamp: event.timestamp,
+            request_timestamp,
+            sop,
+            result,
+        })
+    }
+}
@@ -0,0 +1,367 @@
+//! Correlation of firmware PD traces with captured wire traffic.
+//!
+//! The KM003C reports its own Type-C and protocol-engine transitions in
+//! [`PdTrace`] queues, timestamped with whole seconds of device uptime, while
+//! the PD event stream carries millisecond timestamps of the messages on the
+//! wire. [`PdTraceCorrelator`] estimates the offset between the two clocks,
+//! attributes each trace event to the wire event that caused it, and reports
+//! trace events without traffic and traffic the firmware did not trace.
+
+use uom::si::f64::Time;
+use uom::si::time::second;
+
+use crate::pd::{PdEvent, PdEventData};
+use crate::pd_trace::{PdProtocolTraceEventKind, PdTrace, PdTraceProtocolEvent, PdTraceStateEvent};
+use crate::pd_wire::{PdControlMessageType, PdMessageType, PdWireMessage};
+
+/// Resolution of firmware trace timestamps, in seconds.
+pub const TRACE_RESOLUTION_S: f64 = 1.0;
+
+/// Estimated offset from the PD event clock to the trace uptime clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PdClockAlignment {
+    /// Added to a PD event timestamp to obtain device uptime.
+    pub offset: Time,
+    /// Smallest offset consistent with the most trace events.
+    pub min_offset: Time,
+    /// Largest offset consistent with the most trace events, exclusive.
+    pub max_offset: Time,
+    /// Trace and wire event pairs consistent with the offset.
+    pub matches: usize,
+}
+
+/// A protocol-engine trace event with the wire event that caused it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CorrelatedProtocolEvent {
+    pub event: PdTraceProtocolEvent,
+    /// Index into [`PdTraceCorrelator::events`].
+    pub wire_event: Option<usize>,
+}
+
+/// A Type-C state trace event with the connection event it corresponds to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CorrelatedStateEvent {
+    pub event: PdTraceStateEvent,
+    /// Index into [`PdTraceCorrelator::events`]; always `None` for
+    /// intermediate states such as AttachWait.
+    pub wire_event: Option<usize>,
+}
+
+/// A mismatch between the firmware trace and the wire traffic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum TraceCorrelationIssue {
+    /// A protocol-engine transition with no message that could have caused
+    /// it, such as a timer expiry.
+    ProtocolEventWithoutTraffic(PdTraceProtocolEvent),
+    /// An attach or detach state without a captured connection change.
+    StateChangeWithoutConnection(PdTraceStateEvent),
+    /// A message captured while tracing that the firmware did not process.
+    MessageWithoutTrace { wire_event: usize, timestamp: Time },
+    /// A connection change captured while tracing without an attach or
+    /// detach state.
+    ConnectionWithoutStateChange { wire_event: usize, timestamp: Time },
+}
+
+/// Firmware trace events aligned with the wire traffic.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PdTraceCorrelation {
+    /// `None` when no trace event could be paired with traffic; the clocks
+    /// are then assumed to agree.
+    pub alignment: Option<PdClockAlignment>,
+    pub protocol_events: Vec<CorrelatedProtocolEvent>,
+    pub state_events: Vec<CorrelatedStateEvent>,
+    /// Mismatches ordered by device uptime.
+    pub issues: Vec<TraceCorrelationIssue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WireKind {
+    Connect,
+    Disconnect,
+    GoodCrc,
+    Message,
+    /// A chunk of an extended message, other than a chunk request.
+    Chunk,
+}
+
+impl WireKind {
+    fn of(event: &PdEvent) -> Self {
+        let wire_data = match &event.data {
+            PdEventData::Connect(()) => return Self::Connect,
+            PdEventData::Disconnect(()) => return Self::Disconnect,
+            PdEventData::PdMessage { wire_data, .. } => wire_data,
+        };
+        let Ok(message) = PdWireMessage::from_bytes(wire_data) else {
+            return Self::Message;
+        };
+        match (message.message_type(), message.extended_header) {
+            (PdMessageType::Control(PdControlMessageType::GoodCrc), _) => Self::GoodCrc,
+            (_, Some(header)) if header.chunked() && !header.request_chunk() => Self::Chunk,
+            _ => Self::Message,
+        }
+    }
+
+    fn is_message(self) -> bool {
+        matches!(self, Self::Message | Self::Chunk)
+    }
+}
+
+/// Collects PD events and firmware traces of one session and correlates them.
+///
+/// Protocol-engine events are attributed in queue order: each
+/// ReceivedMessage marker to the next message captured within its second,
+/// a Disabled reset to a detach in the same or the previous second, and every
+/// other transition to the message of the preceding marker if that arrived
+/// within the trace resolution. GoodCRC messages are not traced by the
+/// firmware and are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct PdTraceCorrelator {
+    events: Vec<PdEvent>,
+    kinds: Vec<WireKind>,
+    state_events: Vec<PdTraceStateEvent>,
+    protocol_events: Vec<PdTraceProtocolEvent>,
+}
+
+impl PdTraceCorrelator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget all events and traces.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Captured PD events, in the order they were processed.
+    pub fn events(&self) -> &[PdEvent] {
+        &self.events
+    }
+
+    /// Record one captured PD event.
+    pub fn process_event(&mut self, event: &PdEvent) {
+        self.kinds.push(WireKind::of(event));
+        self.events.push(event.clone());
+    }
+
+    /// Record the events of one drained trace.
+    pub fn process_trace(&mut self, trace: &PdTrace) {
+        self.state_events.extend_from_slice(&trace.state_events);
+        self.protocol_events.extend_from_slice(&trace.protocol_events);
+    }
+
+    /// Estimate the clock offset from ReceivedMessage markers and attach or
+    /// detach states paired with the traffic in the same second.
+    ///
+    /// The estimate is the middle of the offset range consistent with the
+    /// most trace events.
+    pub fn clock_alignment(&self) -> Option<PdClockAlignment> {
+        let mut edges = Vec::new();
+        let mut add_event = |uptime: Time, candidates: Vec<usize>| {
+            let uptime = uptime.get::<second>();
+            let mut ranges = candidates
+                .into_iter()
+                .map(|index| uptime - self.events[index].timestamp.get::<second>())
+                .map(|start| (start, start + TRACE_RESOLUTION_S))
+                .collect::<Vec<_>>();
+            ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+            // Count each trace event once, however many messages share its second.
+            let mut merged: Vec<(f64, f64)> = Vec::new();
+            for (start, end) in ranges {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            for (start, end) in merged {
+                edges.push((start, 1));
+                edges.push((end, -1));
+            }
+        };
+        for event in &self.protocol_events {
+            if event.kind == PdProtocolTraceEventKind::ReceivedMessage {
+                add_event(event.timestamp, self.indices(WireKind::is_message).collect());
+            }
+        }
+        for event in &self.state_events {
+            if let Some(kind) = connection_kind(event) {
+                add_event(event.timestamp, self.indices(|wire| wire == kind).collect());
+            }
+        }
+
+        // Ranges are half-open, so one ending where another starts does not overlap it.
+        edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let mut best: Option<(f64, f64, usize)> = None;
+        let mut count = 0;
+        for (position, &(offset, change)) in edges.iter().enumerate() {
+            count += change;
+            let end = edges.get(position + 1).map_or(offset, |&(end, _)| end);
+            let matches = count as usize;
+            if end <= offset {
+                continue;
+            }
+            match &mut best {
+                Some(best) if matches == best.2 && offset == best.1 => best.1 = end,
+                Some(best) if matches <= best.2 => {}
+                _ => best = Some((offset, end, matches)),
+            }
+        }
+
+        best.map(|(min, max, matches)| PdClockAlignment {
+            offset: Time::new::<second>((min + max) / 2.0),
+            min_offset: Time::new::<second>(min),
+            max_offset: Time::new::<second>(max),
+            matches,
+        })
+    }
+
+    /// Attribute the recorded trace events to the recorded traffic.
+    pub fn correlate(&self) -> PdTraceCorrelation {
+        let alignment = self.clock_alignment();
+        let offset = alignment.map_or(0.0, |alignment| alignment.offset.get::<second>());
+        let uptime = |index: usize| self.events[index].timestamp.get::<second>() + offset;
+
+        let mut protocol_events = self.protocol_events.clone();
+        protocol_events.sort_by(|a, b| a.timestamp.get::<second>().total_cmp(&b.timestamp.get::<second>()));
+        let mut state_events = self.state_events.clone();
+        state_events.sort_by(|a, b| a.timestamp.get::<second>().total_cmp(&b.timestamp.get::<second>()));
+
+        let mut traced = vec![false; self.events.len()];
+        let mut issues = Vec::new();
+
+        let messages = self.indices(WireKind::is_message).collect::<Vec<_>>();
+        let connects = self.indices(|kind| kind == WireKind::Connect).collect::<Vec<_>>();
+        let disconnects = self.indices(|kind| kind == WireKind::Disconnect).collect::<Vec<_>>();
+        let mut next_message = 0;
+        let mut cause = None;
+        let protocol_events = protocol_events
+            .into_iter()
+            .map(|event| {
+                let start = event.timestamp.get::<second>();
+                let (previous, until) = (start - TRACE_RESOLUTION_S, start + TRACE_RESOLUTION_S);
+                let wire_event = match event.kind {
+                    PdProtocolTraceEventKind::ReceivedMessage => {
+                        cause = next_in_window(&messages, &mut next_message, start, until, uptime);
+                        cause
+                    }
+                    PdProtocolTraceEventKind::Disabled => disconnects
+                        .iter()
+                        .copied()
+                        .find(|&index| (previous..until).contains(&uptime(index))),
+                    PdProtocolTraceEventKind::ExtendedChunkRequest => cause
+                        .filter(|&index| self.kinds[index] == WireKind::Chunk)
+                        .filter(|&index| uptime(index) >= previous),
+                    _ => cause.filter(|&index| uptime(index) >= previous),
+                };
+                match wire_event {
+                    Some(index) => traced[index] = true,
+                    None => issues.push((start, TraceCorrelationIssue::ProtocolEventWithoutTraffic(event))),
+                }
+                CorrelatedProtocolEvent { event, wire_event }
+            })
+            .collect();
+
+        let (mut next_connect, mut next_disconnect) = (0, 0);
+        let state_events = state_events
+            .into_iter()
+            .map(|event| {
+                let start = event.timestamp.get::<second>();
+                // Attach and detach debouncing may straddle a second boundary.
+                let (from, until) = (start - TRACE_RESOLUTION_S, start + 2.0 * TRACE_RESOLUTION_S);
+                let wire_event = match connection_kind(&event) {
+                    Some(WireKind::Connect) => next_in_window(&connects, &mut next_connect, from, until, uptime),
+                    Some(_) => next_in_window(&disconnects, &mut next_disconnect, from, until, uptime),
+                    None => {
+                        return CorrelatedStateEvent {
+                            event,
+                            wire_event: None,
+                        };
+                    }
+                };
+                match wire_event {
+                    Some(index) => traced[index] = true,
+                    None => issues.push((start, TraceCorrelationIssue::StateChangeWithoutConnection(event))),
+                }
+                CorrelatedStateEvent { event, wire_event }
+            })
+            .collect();
+
+        // Only traffic captured while the firmware was tracing is expected in the trace.
+        let trace_times = self
+            .state_events
+            .iter()
+            .map(|event| event.timestamp)
+            .chain(self.protocol_events.iter().map(|event| event.timestamp))
+            .map(|timestamp| timestamp.get::<second>());
+        let first = trace_times.clone().fold(f64::INFINITY, f64::min);
+        let last = trace_times.fold(f64::NEG_INFINITY, f64::max) + TRACE_RESOLUTION_S;
+        for (index, kind) in self.kinds.iter().enumerate() {
+            let time = uptime(index);
+            if traced[index] || time < first || time >= last {
+                continue;
+            }
+            let wire_event = index;
+            let timestamp = self.events[index].timestamp;
+            let issue = match kind {
+                WireKind::Connect | WireKind::Disconnect => {
+                    TraceCorrelationIssue::ConnectionWithoutStateChange { wire_event, timestamp }
+                }
+                WireKind::Message | WireKind::Chunk => {
+                    TraceCorrelationIssue::MessageWithoutTrace { wire_event, timestamp }
+                }
+                WireKind::GoodCrc => continue,
+            };
+            issues.push((time, issue));
+        }
+        issues.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        PdTraceCorrelation {
+            alignment,
+            protocol_events,
+            state_events,
+            issues: issues.into_iter().map(|(_, issue)| issue).collect(),
+        }
+    }
+
+    fn indices<'a>(&'a self, filter: impl Fn(WireKind) -> bool + 'a) -> impl Iterator<Item = usize> + 'a {
+        self.kinds
+            .iter()
+            .enumerate()
+            .filter(move |&(_, &kind)| filter(kind))
+            .map(|(index, _)| index)
+    }
+}
+
+/// Wire event an attach or detach state corresponds to.
+fn connection_kind(event: &PdTraceStateEvent) -> Option<WireKind> {
+    if event.state.is_attached() {
+        Some(WireKind::Connect)
+    } else if event.state.is_unattached() {
+        Some(WireKind::Disconnect)
+    } else {
+        None
+    }
+}
+
+/// Take the next candidate with an uptime in `from..until`, skipping earlier candidates.
+fn next_in_window(
+    candidates: &[usize],
+    next: &mut usize,
+    from: f64,
+    until: f64,
+    uptime: impl Fn(usize) -> f64,
+) -> Option<usize> {
+    while candidates.get(*next).is_some_and(|&index| uptime(index) < from) {
+        *next += 1;
+    }
+    let index = *candidates.get(*next)?;
+    if uptime(index) >= until {
+        return None;
+    }
+    *next += 1;
+    Some(index)
+}
@@ -2,42 +2,82 @@
 //!
 //! The KM003C-specific framing parser in [`crate::pd`] extracts standard USB PD
 //! wire messages. This module optionally decodes those messages through the
-//! `usbpd` crate while retaining the source capabilities and extended-message
-//! assembly state needed across events.
+//! `usbpd` crate while retaining the source capabilities, extended-message
+//! assembly and alternate-mode state needed across events.
+//!
+//! With the `serde` feature, decoded messages serialize as their wire bytes,
+//! the session state their decoding depended on and the crate's own typed
+//! decodings. The `usbpd` types are not serializable themselves, so
+//! deserialization decodes the wire bytes again.
 
+use num_enum::FromPrimitive;
 use thiserror::Error;
 use uom::si::f64::Time;
 use usbpd::protocol_layer::message::data::Data;
 use usbpd::protocol_layer::message::data::source_capabilities::SourceCapabilities;
-use usbpd::protocol_layer::message::extended::chunked::{ChunkResult, ChunkedMessageAssembler};
 use usbpd::protocol_layer::message::header::{ExtendedMessageType, Header, MessageType};
 use usbpd::protocol_layer::message::{Message, ParseError, Payload};
 
 use crate::pd::{PdEvent, PdEventData};
+use crate::pd_alt_mode::{AltModeSummary, AltModeTracker};
+use crate::pd_extended::{ExtendedChunkProgress, ExtendedMessage, ExtendedMessageAssembler};
+use crate::pd_pdo::SourcePdo;
+use crate::pd_policy::{PolicyEvent, PolicyState, PolicyTracker};
+use crate::pd_vdm::VendorDefinedMessage;
+use crate::pd_wire::{
+    PdDataMessageType, PdExtendedMessageType, PdMessageHeader, PdMessageType, PdSopType, PdWireMessage,
+};
 
 /// A semantically decoded USB PD message with its KM003C capture metadata.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(into = "DecodedPdMessageRepr", try_from = "DecodedPdMessageRepr")
+)]
 pub struct DecodedPdMessage {
     pub timestamp: Time,
     pub sop: u8,
     pub message: Message,
+    /// Captured wire bytes; the last chunk of a reassembled extended message.
+    pub wire_data: Vec<u8>,
+    /// Structured or unstructured VDM carried by a Vendor_Defined message.
+    pub vdm: Option<VendorDefinedMessage>,
+    /// Extended message with its typed data block, reassembled if it was chunked.
+    pub extended: Option<ExtendedMessage>,
+    /// Source capabilities a Request or EPR_Request was interpreted against.
+    pub request_capabilities: Option<Vec<SourcePdo>>,
 }
 
 /// Progress reported while handling a chunked USB PD extended message.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum PdChunkState {
-    Request { chunk_number: u8 },
-    Pending { received_chunk: u8, next_chunk: u8 },
-    Requested { chunk_number: u8 },
-    Unsupported { chunk_number: u8, data_size: u16 },
+    Request {
+        chunk_number: u8,
+    },
+    Pending {
+        received_chunk: u8,
+        next_chunk: u8,
+    },
+    /// Out-of-order chunk; the partially assembled message was dropped.
+    Discarded {
+        chunk_number: u8,
+        expected_chunk: Option<u8>,
+    },
+    /// Retransmitted chunk, ignored.
+    Duplicate {
+        chunk_number: u8,
+    },
 }
 
 /// A chunked-message state change with its KM003C capture metadata.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct PdChunkStatus {
     pub timestamp: Time,
     pub sop: u8,
-    pub message_type: ExtendedMessageType,
+    pub message_type: PdExtendedMessageType,
     pub state: PdChunkState,
 }
 
@@ -48,19 +88,31 @@ pub enum PdDecodeError {
     EmptyMessage,
     #[error(transparent)]
     Parse(#[from] ParseError),
+    #[error("USB PD extended message chunk is shorter than its header announces")]
+    TruncatedChunk,
+    #[error("Request capabilities do not decode as Source_Capabilities")]
+    InvalidRequestCapabilities,
 }
 
 /// A semantic decoding failure with the original wire bytes.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(into = "PdDecodeFailureRepr", try_from = "PdDecodeFailureRepr")
+)]
 pub struct PdDecodeFailure {
     pub timestamp: Time,
     pub sop: u8,
     pub error: PdDecodeError,
     pub wire_data: Vec<u8>,
+    /// Source capabilities a Request or EPR_Request was parsed against.
+    pub request_capabilities: Option<Vec<SourcePdo>>,
 }
 
 /// Result of decoding one KM003C PD event.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum DecodedPdEvent {
     Connect { timestamp: Time },
     Disconnect { timestamp: Time },
@@ -73,19 +125,25 @@ pub enum DecodedPdEvent {
 ///
 /// The decoder remembers SPR source capabilities so that subsequent Request
 /// messages can be interpreted using the selected PDO type. It also assembles
-/// chunked EPR Source Capabilities messages.
+/// chunked extended messages of every type, tracks alternate-mode
+/// negotiation and reconstructs the policy-engine state.
 #[derive(Debug, Clone, Default)]
 pub struct PdSessionDecoder {
     source_capabilities: Option<SourceCapabilities>,
-    epr_assembler: ChunkedMessageAssembler,
+    /// Raw PDOs of `source_capabilities`, recorded with decoded Requests.
+    source_pdos: Option<Vec<SourcePdo>>,
+    extended_assembler: ExtendedMessageAssembler,
+    alt_modes: AltModeTracker,
+    /// Keeps its log across connections; not cleared by [`Self::reset`].
+    policy: PolicyTracker,
 }
 
 #[derive(Debug, Clone, Copy)]
 struct ChunkDescriptor {
     number: u8,
-    data_size: u16,
     request: bool,
     message_type: ExtendedMessageType,
+    wire_type: PdExtendedMessageType,
 }
 
 impl PdSessionDecoder {
@@ -96,7 +154,9 @@ impl PdSessionDecoder {
     /// Clear all connection-specific decoding state.
     pub fn reset(&mut self) {
         self.source_capabilities = None;
-        self.epr_assembler.reset();
+        self.source_pdos = None;
+        self.extended_assembler.reset();
+        self.alt_modes.reset();
     }
 
     /// Most recently observed SPR source capabilities.
@@ -104,8 +164,25 @@ impl PdSessionDecoder {
         self.source_capabilities.as_ref()
     }
 
+    /// Discovered and entered alternate modes of the current connection.
+    pub fn alt_modes(&self) -> &AltModeSummary {
+        self.alt_modes.summary()
+    }
+
+    /// Contract, roles and revision of the current connection.
+    pub fn policy_state(&self) -> &PolicyState {
+        self.policy.state()
+    }
+
+    /// Policy-engine state changes of every decoded event.
+    pub fn policy_events(&self) -> &[PolicyEvent] {
+        self.policy.events()
+    }
+
     /// Decode one KM003C PD event.
     pub fn decode_event(&mut self, event: &PdEvent) -> DecodedPdEvent {
+        self.alt_modes.process_event(event);
+        self.policy.process_event(event);
         match &event.data {
             PdEventData::Connect(()) => {
                 self.reset();
@@ -130,30 +207,49 @@ impl PdSessionDecoder {
 
         match parse_message_with_state(wire_data, self.source_capabilities.as_ref()) {
             Ok(message) => {
+                let wire = PdWireMessage::from_bytes(wire_data).ok();
                 if let Some(Payload::Data(Data::SourceCapabilities(capabilities))) = &message.payload {
                     self.source_capabilities = Some(capabilities.clone());
+                    self.source_pdos = wire
+                        .as_ref()
+                        .map(|wire| wire.data_objects().map(SourcePdo::from_raw).collect());
                 }
 
+                let request_capabilities = self.request_capabilities(wire_data);
+                let vdm = wire.as_ref().and_then(VendorDefinedMessage::from_wire);
+                let progress =
+                    wire.and_then(|wire| self.extended_assembler.process_message(PdSopType::from(sop), &wire));
+                let extended = match progress {
+                    Some(ExtendedChunkProgress::Complete(extended)) => Some(extended),
+                    _ => None,
+                };
+
                 DecodedPdEvent::Message(DecodedPdMessage {
                     timestamp,
                     sop,
                     message,
+                    wire_data: wire_data.to_vec(),
+                    vdm,
+                    extended,
+                    request_capabilities,
                 })
             }
             Err(ParseError::ChunkedExtendedMessage {
                 chunk_number,
-                data_size,
                 request_chunk,
                 message_type,
+                ..
             }) => self.decode_chunk(
                 timestamp,
                 sop,
                 wire_data,
                 ChunkDescriptor {
                     number: chunk_number,
-                    data_size,
                     request: request_chunk,
                     message_type,
+                    wire_type: PdExtendedMessageType::from_primitive(
+                        PdMessageHeader::from_bytes([wire_data[0], wire_data[1]]).message_type(),
+                    ),
                 },
             ),
             Err(error) => self.failure(timestamp, sop, error.into(), wire_data),
@@ -161,65 +257,63 @@ impl PdSessionDecoder {
     }
 
     fn decode_chunk(&mut self, timestamp: Time, sop: u8, wire_data: &[u8], chunk: ChunkDescriptor) -> DecodedPdEvent {
-        if chunk.request {
-            return DecodedPdEvent::Chunk(PdChunkStatus {
+        let status = |state| {
+            DecodedPdEvent::Chunk(PdChunkStatus {
                 timestamp,
                 sop,
-                message_type: chunk.message_type,
-                state: PdChunkState::Request {
-                    chunk_number: chunk.number,
-                },
-            });
-        }
-
-        if chunk.message_type != ExtendedMessageType::EprSourceCapabilities {
-            return DecodedPdEvent::Chunk(PdChunkStatus {
-                timestamp,
-                sop,
-                message_type: chunk.message_type,
-                state: PdChunkState::Unsupported {
-                    chunk_number: chunk.number,
-                    data_size: chunk.data_size,
-                },
+                message_type: chunk.wire_type,
+                state,
+            })
+        };
+        if chunk.request {
+            return status(PdChunkState::Request {
+                chunk_number: chunk.number,
             });
         }
 
-        let (header, extended_header, chunk_data) = match Message::parse_extended_chunk(wire_data) {
+        let (header, _, _) = match Message::parse_extended_chunk(wire_data) {
             Ok(parts) => parts,
             Err(error) => return self.failure(timestamp, sop, error.into(), wire_data),
         };
+        let Ok(wire) = PdWireMessage::from_bytes(wire_data) else {
+            return self.failure(timestamp, sop, PdDecodeError::TruncatedChunk, wire_data);
+        };
 
-        match self.epr_assembler.process_chunk(header, extended_header, chunk_data) {
-            Ok(ChunkResult::Complete(data)) => {
-                let payload = Message::parse_extended_payload(chunk.message_type, &data);
-                DecodedPdEvent::Message(DecodedPdMessage {
-                    timestamp,
-                    sop,
-                    message: Message {
-                        header,
-                        payload: Some(Payload::Extended(payload)),
-                    },
-                })
-            }
-            Ok(ChunkResult::NeedMoreChunks(next_chunk)) => DecodedPdEvent::Chunk(PdChunkStatus {
+        match self.extended_assembler.process_message(PdSopType::from(sop), &wire) {
+            Some(ExtendedChunkProgress::Complete(extended)) => DecodedPdEvent::Message(DecodedPdMessage {
                 timestamp,
                 sop,
-                message_type: chunk.message_type,
-                state: PdChunkState::Pending {
-                    received_chunk: chunk.number,
-                    next_chunk,
-                },
+                message: reassembled_message(header, chunk.message_type, &extended.data),
+                wire_data: wire_data.to_vec(),
+                vdm: None,
+                extended: Some(extended),
+                request_capabilities: None,
             }),
-            Ok(ChunkResult::ChunkRequested(chunk_number)) => DecodedPdEvent::Chunk(PdChunkStatus {
-                timestamp,
-                sop,
-                message_type: chunk.message_type,
-                state: PdChunkState::Requested { chunk_number },
+            Some(ExtendedChunkProgress::Pending {
+                received_chunk,
+                next_chunk,
+            }) => status(PdChunkState::Pending {
+                received_chunk,
+                next_chunk,
             }),
-            Err(error) => {
-                self.epr_assembler.reset();
-                self.failure(timestamp, sop, error.into(), wire_data)
-            }
+            Some(ExtendedChunkProgress::Request { chunk_number }) => status(PdChunkState::Request { chunk_number }),
+            Some(ExtendedChunkProgress::Discarded {
+                chunk_number,
+                expected_chunk,
+            }) => status(PdChunkState::Discarded {
+                chunk_number,
+                expected_chunk,
+            }),
+            Some(ExtendedChunkProgress::Duplicate { chunk_number }) => status(PdChunkState::Duplicate { chunk_number }),
+            None => self.failure(timestamp, sop, PdDecodeError::TruncatedChunk, wire_data),
+        }
+    }
+
+    /// Source PDOs that a Request or EPR_Request in `wire_data` is interpreted against.
+    fn request_capabilities(&self, wire_data: &[u8]) -> Option<Vec<SourcePdo>> {
+        match PdMessageHeader::from_bytes(*wire_data.first_chunk::<2>()?).kind() {
+            PdMessageType::Data(PdDataMessageType::Request | PdDataMessageType::EprRequest) => self.source_pdos.clone(),
+            _ => None,
         }
     }
 
@@ -229,10 +323,19 @@ impl PdSessionDecoder {
             sop,
             error,
             wire_data: wire_data.to_vec(),
+            request_capabilities: self.request_capabilities(wire_data),
         })
     }
 }
 
+/// Message of a reassembled extended message, given the header of its last chunk.
+fn reassembled_message(header: Header, message_type: ExtendedMessageType, data: &[u8]) -> Message {
+    Message {
+        header,
+        payload: Some(Payload::Extended(Message::parse_extended_payload(message_type, data))),
+    }
+}
+
 fn parse_message_with_state(
     wire_data: &[u8],
     source_capabilities: Option<&SourceCapabilities>,
@@ -254,3 +357,173 @@ fn parse_message_with_state(
         _ => Message::from_bytes(wire_data),
     }
 }
+
+/// Decode the Source_Capabilities a Request was interpreted against.
+#[cfg(feature = "serde")]
+fn source_capabilities_from_pdos(pdos: &[SourcePdo]) -> Result<SourceCapabilities, PdDecodeError> {
+    // Source_Capabilities header, Revision 3.x, sent by the source.
+    let header = 0x0001 | (2 << 6) | (1 << 8) | ((pdos.len() as u16) << 12);
+    let mut wire_data = header.to_le_bytes().to_vec();
+    for pdo in pdos {
+        wire_data.extend_from_slice(&pdo.raw().to_le_bytes());
+    }
+    match parse_message_with_state(&wire_data, None)?.payload {
+        Some(Payload::Data(Data::SourceCapabilities(capabilities))) => Ok(capabilities),
+        _ => Err(PdDecodeError::InvalidRequestCapabilities),
+    }
+}
+
+/// Serialized form of [`DecodedPdMessage`].
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize, serde::Serialize)]
+struct DecodedPdMessageRepr {
+    timestamp: Time,
+    sop: u8,
+    wire_data: Vec<u8>,
+    /// Message type from the header, for reading and diffing. Ignored when
+    /// loading.
+    #[serde(default)]
+    message_type: Option<PdMessageType>,
+    vdm: Option<VendorDefinedMessage>,
+    extended: Option<ExtendedMessage>,
+    request_capabilities: Option<Vec<SourcePdo>>,
+}
+
+#[cfg(feature = "serde")]
+impl From<DecodedPdMessage> for DecodedPdMessageRepr {
+    fn from(decoded: DecodedPdMessage) -> Self {
+        Self {
+            timestamp: decoded.timestamp,
+            sop: decoded.sop,
+            wire_data: decoded.wire_data,
+            message_type: decoded
+                .wire_data
+                .first_chunk::<2>()
+                .map(|&header| PdMessageHeader::from_bytes(header).kind()),
+            vdm: decoded.vdm,
+            extended: decoded.extended,
+            request_capabilities: decoded.request_capabilities,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<DecodedPdMessageRepr> for DecodedPdMessage {
+    type Error = PdDecodeError;
+
+    fn try_from(repr: DecodedPdMessageRepr) -> Result<Self, Self::Error> {
+        let capabilities = repr
+            .request_capabilities
+            .as_deref()
+            .map(source_capabilities_from_pdos)
+            .transpose()?;
+        let message = match parse_message_with_state(&repr.wire_data, capabilities.as_ref()) {
+            Ok(message) => message,
+            Err(ParseError::ChunkedExtendedMessage { message_type, .. }) => {
+                let extended = repr.extended.as_ref().ok_or(PdDecodeError::TruncatedChunk)?;
+                let (header, _, _) = Message::parse_extended_chunk(&repr.wire_data)?;
+                reassembled_message(header, message_type, &extended.data)
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        Ok(Self {
+            timestamp: repr.timestamp,
+            sop: repr.sop,
+            message,
+            wire_data: repr.wire_data,
+            vdm: repr.vdm,
+            extended: repr.extended,
+            request_capabilities: repr.request_capabilities,
+        })
+    }
+}
+
+/// Variant of a [`PdDecodeError`]; the details of a `usbpd` parse error are
+/// recovered from the wire bytes.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+enum PdDecodeErrorKind {
+    EmptyMessage,
+    Parse,
+    TruncatedChunk,
+    InvalidRequestCapabilities,
+}
+
+#[cfg(feature = "serde")]
+impl From<&PdDecodeError> for PdDecodeErrorKind {
+    fn from(error: &PdDecodeError) -> Self {
+        match error {
+            PdDecodeError::EmptyMessage => Self::EmptyMessage,
+            PdDecodeError::Parse(_) => Self::Parse,
+            PdDecodeError::TruncatedChunk => Self::TruncatedChunk,
+            PdDecodeError::InvalidRequestCapabilities => Self::InvalidRequestCapabilities,
+        }
+    }
+}
+
+/// Serialized form of [`PdDecodeFailure`]; the error is recovered from the
+/// wire bytes when loading and must be of the recorded kind.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize, serde::Serialize)]
+struct PdDecodeFailureRepr {
+    timestamp: Time,
+    sop: u8,
+    error: PdDecodeErrorKind,
+    wire_data: Vec<u8>,
+    #[serde(default)]
+    request_capabilities: Option<Vec<SourcePdo>>,
+}
+
+#[cfg(feature = "serde")]
+impl From<PdDecodeFailure> for PdDecodeFailureRepr {
+    fn from(failure: PdDecodeFailure) -> Self {
+        Self {
+            timestamp: failure.timestamp,
+            sop: failure.sop,
+            error: PdDecodeErrorKind::from(&failure.error),
+            wire_data: failure.wire_data,
+            request_capabilities: failure.request_capabilities,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<PdDecodeFailureRepr> for PdDecodeFailure {
+    type Error = String;
+
+    fn try_from(repr: PdDecodeFailureRepr) -> Result<Self, Self::Error> {
+        let capabilities = repr
+            .request_capabilities
+            .as_deref()
+            .map(source_capabilities_from_pdos)
+            .transpose()
+            .map_err(|error| error.to_string())?;
+        let error = if repr.wire_data.is_empty() {
+            Some(PdDecodeError::EmptyMessage)
+        } else {
+            match parse_message_with_state(&repr.wire_data, capabilities.as_ref()) {
+                Ok(_) => None,
+                Err(ParseError::ChunkedExtendedMessage { .. }) => Some(
+                    Message::parse_extended_chunk(&repr.wire_data)
+                        .map_or_else(PdDecodeError::from, |_| PdDecodeError::TruncatedChunk),
+                ),
+                Err(error) => Some(error.into()),
+            }
+        };
+
+        match error {
+            Some(error) if PdDecodeErrorKind::from(&error) == repr.error => Ok(Self {
+                timestamp: repr.timestamp,
+                sop: repr.sop,
+                error,
+                wire_data: repr.wire_data,
+                request_capabilities: repr.request_capabilities,
+            }),
+            _ => Err(format!(
+                "USB PD decode failure {:?} is not reproduced by its wire bytes",
+                repr.error
+            )),
+        }
+    }
+}
@@ -0,0 +1,422 @@
+//! USB PD message encoding.
+//!
+//! The inverse of [`crate::pd_wire`]: builds wire messages with a valid
+//! message header, data objects and, for extended messages, the extended
+//! header and chunking. [`PdCaptureBuilder`] wraps encoded messages in the
+//! KM003C event framing, so analyzers and `pd_decode` can be exercised with
+//! synthetic captures of exchanges that are hard to record on real hardware.
+
+use std::collections::HashMap;
+
+use uom::si::f64::Time;
+
+use crate::error::KMError;
+use crate::pd::{PdEvent, PdEventData, PdEventStream, PdStatus};
+use crate::pd_epr::{EprModeDataObject, ExtendedControlType};
+use crate::pd_extended::MAX_EXTENDED_CHUNK_LEN;
+use crate::pd_pdo::{RequestDataObject, SourcePdo};
+use crate::pd_policy::{PdDataRole, PdPowerRole, PdSpecRevision};
+use crate::pd_vdm::VendorDefinedMessage;
+use crate::pd_wire::{
+    PD_DATA_OBJECT_SIZE, PD_EXTENDED_HEADER_SIZE, PD_HEADER_SIZE, PdControlMessageType, PdDataMessageType,
+    PdExtendedHeader, PdExtendedMessageType, PdMessageHeader, PdSopType,
+};
+
+/// Largest number of data objects in one message.
+pub const MAX_DATA_OBJECTS: usize = 7;
+
+/// Largest data block of an extended message (MaxExtendedMsgLen).
+pub const MAX_EXTENDED_MESSAGE_LEN: usize = 260;
+
+/// Header fields describing the sender of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PdMessageSender {
+    pub revision: PdSpecRevision,
+    /// Power role sent on SOP.
+    pub power_role: PdPowerRole,
+    /// Data role sent on SOP.
+    pub data_role: PdDataRole,
+    /// Cable Plug flag sent on SOP'/SOP'' instead of the port roles.
+    pub cable_plug: bool,
+}
+
+impl PdMessageSender {
+    /// A Revision 3.x source acting as DFP, as after attach.
+    pub fn source() -> Self {
+        Self {
+            revision: PdSpecRevision::Revision3,
+            power_role: PdPowerRole::Source,
+            data_role: PdDataRole::Dfp,
+            cable_plug: false,
+        }
+    }
+
+    /// A Revision 3.x sink acting as UFP, as after attach.
+    pub fn sink() -> Self {
+        Self {
+            power_role: PdPowerRole::Sink,
+            data_role: PdDataRole::Ufp,
+            ..Self::source()
+        }
+    }
+
+    /// A Revision 3.x cable plug answering on SOP'/SOP''.
+    pub fn cable() -> Self {
+        Self {
+            cable_plug: true,
+            ..Self::sink()
+        }
+    }
+
+    pub fn with_revision(self, revision: PdSpecRevision) -> Self {
+        Self { revision, ..self }
+    }
+
+    /// The other end of the link on `sop`, which acknowledges this sender's messages.
+    pub fn partner(&self, sop: PdSopType) -> Self {
+        if sop.is_cable_plug() {
+            return Self {
+                cable_plug: !self.cable_plug,
+                ..*self
+            };
+        }
+        Self {
+            power_role: match self.power_role {
+                PdPowerRole::Source => PdPowerRole::Sink,
+                PdPowerRole::Sink => PdPowerRole::Source,
+            },
+            data_role: match self.data_role {
+                PdDataRole::Dfp => PdDataRole::Ufp,
+                PdDataRole::Ufp => PdDataRole::Dfp,
+            },
+            ..*self
+        }
+    }
+
+    /// Value of the header's power role bit, which holds the Cable Plug flag on SOP'/SOP''.
+    fn power_role_bit(&self, sop: PdSopType) -> bool {
+        if sop.is_cable_plug() {
+            self.cable_plug
+        } else {
+            self.power_role == PdPowerRole::Source
+        }
+    }
+
+    fn header(&self, sop: PdSopType, message_id: u8, message_type: u8, num_data_objects: usize) -> PdMessageHeader {
+        // The data role bit is reserved on SOP'/SOP''.
+        let data_role_bit = !sop.is_cable_plug() && self.data_role == PdDataRole::Dfp;
+        PdMessageHeader::new()
+            .with_message_type(message_type & 0x1f)
+            .with_port_data_role(data_role_bit)
+            .with_spec_revision(u8::from(self.revision) & 0x03)
+            .with_port_power_role(self.power_role_bit(sop))
+            .with_message_id(message_id & 0x07)
+            .with_num_data_objects(num_data_objects as u8)
+    }
+}
+
+/// Content of a USB PD message to encode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PdMessageBody {
+    Control(PdControlMessageType),
+    SourceCapabilities(Vec<SourcePdo>),
+    Request(RequestDataObject),
+    /// EPR_Request: the RDO followed by a copy of the requested PDO.
+    EprRequest {
+        request: RequestDataObject,
+        pdo: SourcePdo,
+    },
+    EprMode(EprModeDataObject),
+    VendorDefined(VendorDefinedMessage),
+    /// Any other data message, from its raw data objects.
+    Data {
+        message_type: PdDataMessageType,
+        objects: Vec<u32>,
+    },
+    /// An extended message, from its unchunked data block.
+    Extended {
+        message_type: PdExtendedMessageType,
+        data: Vec<u8>,
+    },
+}
+
+impl PdMessageBody {
+    /// EPR_Source_Capabilities with the given PDOs, SPR ones first.
+    pub fn epr_source_capabilities(pdos: &[SourcePdo]) -> Self {
+        Self::Extended {
+            message_type: PdExtendedMessageType::EprSourceCapabilities,
+            data: pdos.iter().flat_map(|pdo| pdo.raw().to_le_bytes()).collect(),
+        }
+    }
+
+    /// Extended_Control, such as EPR_KeepAlive.
+    pub fn extended_control(control: ExtendedControlType, data: u8) -> Self {
+        Self::Extended {
+            message_type: PdExtendedMessageType::ExtendedControl,
+            data: vec![control.into(), data],
+        }
+    }
+
+    /// Encode the message as sent on `sop`, one wire message per transmission.
+    ///
+    /// Extended messages are always sent chunked; chunk `n` carries MessageID
+    /// `message_id + n`, as if each chunk request was answered immediately.
+    /// Every other message encodes to a single wire message.
+    pub fn encode(&self, sop: PdSopType, sender: &PdMessageSender, message_id: u8) -> Result<Vec<Vec<u8>>, KMError> {
+        let (message_type, objects) = match self {
+            Self::Control(message_type) => {
+                let header = sender.header(sop, message_id, (*message_type).into(), 0);
+                return Ok(vec![header.into_bytes().to_vec()]);
+            }
+            Self::Extended { message_type, data } => {
+                return encode_extended(sop, sender, message_id, *message_type, data);
+            }
+            Self::SourceCapabilities(pdos) => (
+                PdDataMessageType::SourceCapabilities,
+                pdos.iter().map(SourcePdo::raw).collect(),
+            ),
+            Self::Request(request) => (PdDataMessageType::Request, vec![request.0]),
+            Self::EprRequest { request, pdo } => (PdDataMessageType::EprRequest, vec![request.0, pdo.raw()]),
+            Self::EprMode(mode) => (PdDataMessageType::EprMode, vec![mode.0]),
+            Self::VendorDefined(vdm) => (
+                PdDataMessageType::VendorDefined,
+                std::iter::once(vdm.header.0)
+                    .chain(vdm.objects.iter().copied())
+                    .collect(),
+            ),
+            Self::Data { message_type, objects } => (*message_type, objects.clone()),
+        };
+
+        if objects.is_empty() || objects.len() > MAX_DATA_OBJECTS {
+            return Err(KMError::InvalidPacket(format!(
+                "{message_type:?} needs 1 to {MAX_DATA_OBJECTS} data objects, got {}",
+                objects.len()
+            )));
+        }
+        let header = sender.header(sop, message_id, message_type.into(), objects.len());
+        let mut wire_data = header.into_bytes().to_vec();
+        for object in objects {
+            wire_data.extend_from_slice(&object.to_le_bytes());
+        }
+        Ok(vec![wire_data])
+    }
+}
+
+fn extended_header(
+    sop: PdSopType,
+    sender: &PdMessageSender,
+    message_id: u8,
+    message_type: PdExtendedMessageType,
+    payload_len: usize,
+) -> PdMessageHeader {
+    // Extended header and chunk data, padded to whole data objects.
+    let num_data_objects = (PD_EXTENDED_HEADER_SIZE + payload_len).div_ceil(PD_DATA_OBJECT_SIZE);
+    sender
+        .header(sop, message_id, message_type.into(), num_data_objects)
+        .with_extended(true)
+}
+
+fn encode_extended(
+    sop: PdSopType,
+    sender: &PdMessageSender,
+    message_id: u8,
+    message_type: PdExtendedMessageType,
+    data: &[u8],
+) -> Result<Vec<Vec<u8>>, KMError> {
+    if data.len() > MAX_EXTENDED_MESSAGE_LEN {
+        return Err(KMError::InvalidPacket(format!(
+            "{message_type:?} data block of {} bytes exceeds {MAX_EXTENDED_MESSAGE_LEN} bytes",
+            data.len()
+        )));
+    }
+
+    // An empty data block still takes one chunk.
+    let chunks = data.chunks(MAX_EXTENDED_CHUNK_LEN).map(<[u8]>::to_vec);
+    let chunks = if data.is_empty() {
+        vec![Vec::new()]
+    } else {
+        chunks.collect()
+    };
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(chunk_number, chunk)| {
+            let header = extended_header(
+                sop,
+                sender,
+                message_id.wrapping_add(chunk_number as u8),
+                message_type,
+                chunk.len(),
+            );
+            let extended = PdExtendedHeader::new()
+                .with_data_size(data.len() as u16)
+                .with_chunk_number(chunk_number as u8)
+                .with_chunked(true);
+            let mut wire_data = header.into_bytes().to_vec();
+            wire_data.extend_from_slice(&extended.into_bytes());
+            wire_data.extend_from_slice(&chunk);
+            wire_data.resize(
+                PD_HEADER_SIZE + usize::from(header.num_data_objects()) * PD_DATA_OBJECT_SIZE,
+                0,
+            );
+            wire_data
+        })
+        .collect())
+}
+
+/// Encode the chunk request a receiver sends for chunk `chunk_number` of an extended message.
+pub fn encode_chunk_request(
+    sop: PdSopType,
+    sender: &PdMessageSender,
+    message_id: u8,
+    message_type: PdExtendedMessageType,
+    chunk_number: u8,
+) -> Vec<u8> {
+    let header = extended_header(sop, sender, message_id, message_type, 2);
+    let extended = PdExtendedHeader::new()
+        .with_request_chunk(true)
+        .with_chunk_number(chunk_number & 0x0f)
+        .with_chunked(true);
+    let mut wire_data = header.into_bytes().to_vec();
+    wire_data.extend_from_slice(&extended.into_bytes());
+    // The extended header is padded to a whole data object.
+    wire_data.extend_from_slice(&[0, 0]);
+    wire_data
+}
+
+/// Builds synthetic KM003C PD event streams.
+///
+/// Every message is acknowledged with a GoodCRC from its partner, and chunked
+/// extended messages include the receiver's chunk requests, as on a healthy
+/// link. MessageID counters are kept per SOP* channel and header power role
+/// bit; Soft_Reset restarts the counters of its channel.
+#[derive(Debug, Clone)]
+pub struct PdCaptureBuilder {
+    preamble: PdStatus,
+    events: Vec<PdEvent>,
+    message_ids: HashMap<(PdSopType, bool), u8>,
+}
+
+impl PdCaptureBuilder {
+    pub fn new(preamble: PdStatus) -> Self {
+        Self {
+            preamble,
+            events: Vec::new(),
+            message_ids: HashMap::new(),
+        }
+    }
+
+    pub fn connect(&mut self, timestamp: Time) -> &mut Self {
+        self.events.push(PdEvent {
+            timestamp,
+            data: PdEventData::Connect(()),
+        });
+        self
+    }
+
+    /// Record a detach; counters restart as after a Hard Reset.
+    pub fn disconnect(&mut self, timestamp: Time) -> &mut Self {
+        self.hard_reset();
+        self.events.push(PdEvent {
+            timestamp,
+            data: PdEventData::Disconnect(()),
+        });
+        self
+    }
+
+    /// Restart every MessageID counter. Hard Reset signaling itself is not captured.
+    pub fn hard_reset(&mut self) -> &mut Self {
+        self.message_ids.clear();
+        self
+    }
+
+    /// Exchange the SOP counters of source and sink after a power role swap,
+    /// so each port continues its own counter.
+    pub fn swap_power_roles(&mut self) -> &mut Self {
+        let source = self.message_ids.remove(&(PdSopType::Sop, true));
+        let sink = self.message_ids.remove(&(PdSopType::Sop, false));
+        if let Some(id) = source {
+            self.message_ids.insert((PdSopType::Sop, false), id);
+        }
+        if let Some(id) = sink {
+            self.message_ids.insert((PdSopType::Sop, true), id);
+        }
+        self
+    }
+
+    /// Record a message with its acknowledgements, all at `timestamp`.
+    pub fn message(
+        &mut self,
+        timestamp: Time,
+        sop: PdSopType,
+        sender: PdMessageSender,
+        body: &PdMessageBody,
+    ) -> Result<&mut Self, KMError> {
+        if *body == PdMessageBody::Control(PdControlMessageType::SoftReset) {
+            self.message_ids.retain(|&(channel, _), _| channel != sop);
+        }
+        let receiver = sender.partner(sop);
+        let chunks = body.encode(sop, &sender, self.message_id(sop, &sender))?;
+
+        let message_type = match body {
+            PdMessageBody::Extended { message_type, .. } => Some(*message_type),
+            _ => None,
+        };
+        for (chunk_number, wire_data) in chunks.into_iter().enumerate() {
+            if let (Some(message_type), 1..) = (message_type, chunk_number) {
+                let message_id = self.next_message_id(sop, &receiver);
+                let request = encode_chunk_request(sop, &receiver, message_id, message_type, chunk_number as u8);
+                self.transmit(timestamp, sop, &receiver, request);
+            }
+            self.next_message_id(sop, &sender);
+            self.transmit(timestamp, sop, &sender, wire_data);
+        }
+        Ok(self)
+    }
+
+    /// Record one wire message from `sender` and the partner's GoodCRC.
+    fn transmit(&mut self, timestamp: Time, sop: PdSopType, sender: &PdMessageSender, wire_data: Vec<u8>) {
+        let message_id = PdMessageHeader::from_bytes([wire_data[0], wire_data[1]]).message_id();
+        let good_crc = PdMessageBody::Control(PdControlMessageType::GoodCrc)
+            .encode(sop, &sender.partner(sop), message_id)
+            .expect("control messages always encode")
+            .remove(0);
+        for wire_data in [wire_data, good_crc] {
+            self.events.push(PdEvent {
+                timestamp,
+                data: PdEventData::PdMessage {
+                    sop: sop.into(),
+                    wire_data,
+                },
+            });
+        }
+    }
+
+    fn message_id(&self, sop: PdSopType, sender: &PdMessageSender) -> u8 {
+        self.message_ids
+            .get(&(sop, sender.power_role_bit(sop)))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Return the sender's current MessageID and advance its counter.
+    fn next_message_id(&mut self, sop: PdSopType, sender: &PdMessageSender) -> u8 {
+        let counter = self.message_ids.entry((sop, sender.power_role_bit(sop))).or_insert(0);
+        let message_id = *counter;
+        *counter = (*counter + 1) % 8;
+        message_id
+    }
+
+    pub fn events(&self) -> &[PdEvent] {
+        &self.events
+    }
+
+    pub fn build(&self) -> PdEventStream {
+        PdEventStream {
+            preamble: self.preamble,
+            events: self.events.clone(),
+        }
+    }
+}
@@ -0,0 +1,456 @@
+//! EPR mode entry and keep-alive tracking.
+//!
+//! Extended Power Range contracts above 20 V are only legal after the sink
+//! has entered EPR mode: EPR_Mode Enter, Enter Acknowledged, a cable
+//! Discover Identity by the source, then Enter Succeeded. While in EPR mode
+//! the sink must request power with EPR_Request and keep the mode alive with
+//! EPR_KeepAlive. [`EprTracker`] follows that state machine in a KM003C
+//! capture and records the evidence, including violations and whether VBUS
+//! actually reached the negotiated EPR voltage.
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use uom::si::time::second;
+
+use crate::pd::{PdEvent, PdEventData, PdStatus};
+use crate::pd_cable::{CableIdentity, CableIdentityTracker};
+use crate::pd_pdo::{RequestDataObject, SourcePdo};
+use crate::pd_vdm::bits;
+use crate::pd_wire::{
+    PdControlMessageType, PdDataMessageType, PdExtendedMessageType, PdMessageType, PdSopType, PdWireMessage,
+};
+
+/// Longest time a source waits for a message from an EPR sink (tSourceEPRKeepAlive).
+pub const EPR_KEEP_ALIVE_TIMEOUT_S: f64 = 1.0;
+
+/// Highest voltage of a Standard Power Range contract.
+pub const SPR_MAX_VOLTAGE_V: f64 = 20.0;
+
+/// Relative VBUS tolerance used to decide whether a contract voltage was reached.
+pub const CONTRACT_VOLTAGE_TOLERANCE: f64 = 0.05;
+
+/// Action of an EPR_Mode message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum EprModeAction {
+    Enter = 1,
+    EnterAcknowledged = 2,
+    EnterSucceeded = 3,
+    EnterFailed = 4,
+    Exit = 5,
+    #[num_enum(catch_all)]
+    Reserved(u8),
+}
+
+/// Reason carried by an EPR_Mode Enter Failed message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum EprEnterFailure {
+    Unknown = 0,
+    CableNotEprCapable = 1,
+    SourceFailedToBecomeVconnSource = 2,
+    RequestNotEprCapable = 3,
+    SourceUnableNow = 4,
+    PdoNotEprCapable = 5,
+    #[num_enum(catch_all)]
+    Reserved(u8),
+}
+
+/// EPR Mode Data Object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct EprModeDataObject(pub u32);
+
+impl EprModeDataObject {
+    pub fn action(&self) -> EprModeAction {
+        EprModeAction::from_primitive(bits(self.0, 24, 8) as u8)
+    }
+
+    /// Sink operational PDP in watts for Enter, the failure reason for Enter Failed.
+    pub fn data(&self) -> u8 {
+        bits(self.0, 16, 8) as u8
+    }
+
+    pub fn failure_reason(&self) -> Option<EprEnterFailure> {
+        (self.action() == EprModeAction::EnterFailed).then(|| EprEnterFailure::from_primitive(self.data()))
+    }
+}
+
+/// Extended_Control message types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum ExtendedControlType {
+    EprGetSourceCap = 1,
+    EprGetSinkCap = 2,
+    EprKeepAlive = 3,
+    EprKeepAliveAck = 4,
+    #[num_enum(catch_all)]
+    Reserved(u8),
+}
+
+/// EPR mode state of the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum EprModeState {
+    #[default]
+    Spr,
+    EnterRequested,
+    EnterAcknowledged,
+    Epr,
+}
+
+/// A contract requested with EPR_Request and accepted by the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct EprContract {
+    pub requested_at: Time,
+    pub accepted_at: Time,
+    /// Time of the PS_RDY that completed the transition.
+    pub ready_at: Option<Time>,
+    pub request: RequestDataObject,
+    /// Copy of the requested PDO carried by the EPR_Request.
+    pub pdo: SourcePdo,
+    pub voltage: ElectricPotential,
+    pub current: ElectricCurrent,
+}
+
+impl EprContract {
+    /// Whether the contract is above the Standard Power Range.
+    pub fn is_epr_voltage(&self) -> bool {
+        self.voltage > ElectricPotential::new::<volt>(SPR_MAX_VOLTAGE_V)
+    }
+}
+
+/// Protocol violation observed while following EPR mode.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum EprViolation {
+    /// No message from the sink within the EPR keep-alive timeout.
+    KeepAliveTimeout {
+        last_sink_message: Time,
+        elapsed: Time,
+    },
+    EprRequestOutsideEprMode,
+    /// A plain Request while in EPR mode, where EPR_Request is required.
+    SprRequestInEprMode,
+    KeepAliveOutsideEprMode,
+    /// EPR_Mode Enter while the source's first PDO does not advertise EPR capability.
+    SourceNotEprCapable,
+    /// Enter Succeeded without a captured Discover Identity showing an
+    /// EPR-capable 50 V / 5 A cable.
+    UnverifiedCable {
+        cable: Option<CableIdentity>,
+    },
+}
+
+/// One entry of the EPR session log.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum EprEvent {
+    Mode {
+        timestamp: Time,
+        action: EprModeAction,
+        /// Sink PDP for Enter, failure reason for Enter Failed.
+        data: u8,
+    },
+    KeepAlive {
+        timestamp: Time,
+        /// Time since the previous EPR_KeepAlive in the same EPR session.
+        interval: Option<Time>,
+    },
+    KeepAliveAck {
+        timestamp: Time,
+        /// Time since the EPR_KeepAlive being acknowledged.
+        latency: Option<Time>,
+    },
+    Contract(EprContract),
+    Violation {
+        timestamp: Time,
+        violation: EprViolation,
+    },
+}
+
+/// Follows EPR mode entry, keep-alive and EPR contracts in a KM003C capture.
+///
+/// Connection changes return the state to SPR but keep the event log.
+#[derive(Debug, Clone)]
+pub struct EprTracker {
+    keep_alive_timeout: Time,
+    state: EprModeState,
+    cables: CableIdentityTracker,
+    first_source_pdo: Option<SourcePdo>,
+    pending_request: Option<(Time, RequestDataObject, SourcePdo)>,
+    contract: Option<EprContract>,
+    last_sink_message: Option<Time>,
+    last_keep_alive: Option<Time>,
+    unacknowledged_keep_alive: Option<Time>,
+    keep_alive_reported: bool,
+    measured_vbus: Option<ElectricPotential>,
+    events: Vec<EprEvent>,
+}
+
+impl Default for EprTracker {
+    fn default() -> Self {
+        Self::with_keep_alive_timeout(Time::new::<second>(EPR_KEEP_ALIVE_TIMEOUT_S))
+    }
+}
+
+impl EprTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a tracker that reports keep-alive timeouts after a custom interval.
+    pub fn with_keep_alive_timeout(keep_alive_timeout: Time) -> Self {
+        Self {
+            keep_alive_timeout,
+            state: EprModeState::Spr,
+            cables: CableIdentityTracker::new(),
+            first_source_pdo: None,
+            pending_request: None,
+            contract: None,
+            last_sink_message: None,
+            last_keep_alive: None,
+            unacknowledged_keep_alive: None,
+            keep_alive_reported: false,
+            measured_vbus: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Clear the event log and all connection state.
+    pub fn reset(&mut self) {
+        *self = Self::with_keep_alive_timeout(self.keep_alive_timeout);
+    }
+
+    pub fn events(&self) -> &[EprEvent] {
+        &self.events
+    }
+
+    pub fn state(&self) -> EprModeState {
+        self.state
+    }
+
+    /// Current EPR contract, cleared when EPR mode is exited.
+    pub fn contract(&self) -> Option<&EprContract> {
+        self.contract.as_ref()
+    }
+
+    /// Most recent VBUS measurement passed to [`Self::process_status`].
+    pub fn measured_vbus(&self) -> Option<ElectricPotential> {
+        self.measured_vbus
+    }
+
+    /// Whether VBUS is within tolerance of a ready contract above 20 V.
+    pub fn is_running_at_epr_voltage(&self) -> bool {
+        let (Some(contract), Some(vbus)) = (&self.contract, self.measured_vbus) else {
+            return false;
+        };
+        self.state == EprModeState::Epr
+            && contract.ready_at.is_some()
+            && contract.is_epr_voltage()
+            && ((vbus - contract.voltage).abs() / contract.voltage).value <= CONTRACT_VOLTAGE_TOLERANCE
+    }
+
+    /// Process one KM003C PD event, returning the log entries it added.
+    pub fn process_event(&mut self, event: &PdEvent) -> &[EprEvent] {
+        let start = self.events.len();
+        self.cables.process_event(event);
+        self.check_keep_alive(event.timestamp);
+
+        match &event.data {
+            PdEventData::Connect(()) | PdEventData::Disconnect(()) => self.end_connection(),
+            PdEventData::PdMessage { sop, wire_data } if PdSopType::from(*sop) == PdSopType::Sop => {
+                if let Ok(message) = PdWireMessage::from_bytes(wire_data) {
+                    self.process_message(event.timestamp, &message);
+                }
+            }
+            PdEventData::PdMessage { .. } => {}
+        }
+
+        &self.events[start..]
+    }
+
+    /// Process a KM003C PD status measurement, returning the log entries it added.
+    pub fn process_status(&mut self, status: &PdStatus) -> &[EprEvent] {
+        let start = self.events.len();
+        self.check_keep_alive(status.timestamp);
+        self.measured_vbus = Some(status.vbus);
+        &self.events[start..]
+    }
+
+    fn end_connection(&mut self) {
+        let keep_alive_timeout = self.keep_alive_timeout;
+        let events = std::mem::take(&mut self.events);
+        *self = Self::with_keep_alive_timeout(keep_alive_timeout);
+        self.events = events;
+    }
+
+    fn violation(&mut self, timestamp: Time, violation: EprViolation) {
+        self.events.push(EprEvent::Violation { timestamp, violation });
+    }
+
+    fn process_message(&mut self, timestamp: Time, message: &PdWireMessage) {
+        let message_type = message.message_type();
+        let from_sink = !message.header.port_power_role();
+        if from_sink && message_type != PdMessageType::Control(PdControlMessageType::GoodCrc) {
+            self.last_sink_message = Some(timestamp);
+            self.keep_alive_reported = false;
+        }
+
+        match message_type {
+            PdMessageType::Data(PdDataMessageType::SourceCapabilities) => {
+                self.first_source_pdo = message.data_objects().next().map(SourcePdo::from_raw);
+            }
+            PdMessageType::Data(PdDataMessageType::EprMode) => {
+                if let Some(mode) = message.data_objects().next().map(EprModeDataObject) {
+                    self.process_mode(timestamp, mode);
+                }
+            }
+            PdMessageType::Data(PdDataMessageType::Request) => {
+                self.pending_request = None;
+                if self.state == EprModeState::Epr {
+                    self.violation(timestamp, EprViolation::SprRequestInEprMode);
+                }
+            }
+            PdMessageType::Data(PdDataMessageType::EprRequest) => {
+                if self.state != EprModeState::Epr {
+                    self.violation(timestamp, EprViolation::EprRequestOutsideEprMode);
+                }
+                let mut objects = message.data_objects();
+                self.pending_request = objects
+                    .next()
+                    .zip(objects.next())
+                    .map(|(rdo, pdo)| (timestamp, RequestDataObject(rdo), SourcePdo::from_raw(pdo)));
+            }
+            PdMessageType::Control(PdControlMessageType::Accept) => {
+                if let Some((requested_at, request, pdo)) = self.pending_request.take() {
+                    let contract = contract(requested_at, timestamp, request, pdo);
+                    self.contract = Some(contract);
+                    self.events.push(EprEvent::Contract(contract));
+                }
+            }
+            PdMessageType::Control(PdControlMessageType::Reject | PdControlMessageType::Wait) => {
+                self.pending_request = None;
+            }
+            PdMessageType::Control(PdControlMessageType::PsRdy) => {
+                if let Some(contract) = &mut self.contract
+                    && contract.ready_at.is_none()
+                {
+                    contract.ready_at = Some(timestamp);
+                }
+            }
+            PdMessageType::Extended(PdExtendedMessageType::ExtendedControl) => {
+                if let Some(&control) = message.payload.first() {
+                    self.process_extended_control(timestamp, ExtendedControlType::from_primitive(control));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn process_mode(&mut self, timestamp: Time, mode: EprModeDataObject) {
+        let action = mode.action();
+        self.events.push(EprEvent::Mode {
+            timestamp,
+            action,
+            data: mode.data(),
+        });
+
+        match action {
+            EprModeAction::Enter => {
+                self.state = EprModeState::EnterRequested;
+                if let Some(SourcePdo::FixedSupply(pdo)) = self.first_source_pdo
+                    && !pdo.epr_mode_capable()
+                {
+                    self.violation(timestamp, EprViolation::SourceNotEprCapable);
+                }
+            }
+            EprModeAction::EnterAcknowledged => self.state = EprModeState::EnterAcknowledged,
+            EprModeAction::EnterSucceeded => {
+                self.state = EprModeState::Epr;
+                self.last_sink_message = Some(timestamp);
+                self.last_keep_alive = None;
+                self.keep_alive_reported = false;
+                let cable = self.cables.cable().cloned();
+                if !cable.as_ref().is_some_and(is_epr_cable) {
+                    self.violation(timestamp, EprViolation::UnverifiedCable { cable });
+                }
+            }
+            EprModeAction::EnterFailed | EprModeAction::Exit => {
+                self.state = EprModeState::Spr;
+                self.contract = None;
+                self.unacknowledged_keep_alive = None;
+            }
+            EprModeAction::Reserved(_) => {}
+        }
+    }
+
+    fn process_extended_control(&mut self, timestamp: Time, control: ExtendedControlType) {
+        match control {
+            ExtendedControlType::EprKeepAlive => {
+                if self.state != EprModeState::Epr {
+                    self.violation(timestamp, EprViolation::KeepAliveOutsideEprMode);
+                }
+                let interval = self.last_keep_alive.map(|last| timestamp - last);
+                self.last_keep_alive = Some(timestamp);
+                self.unacknowledged_keep_alive = Some(timestamp);
+                self.events.push(EprEvent::KeepAlive { timestamp, interval });
+            }
+            ExtendedControlType::EprKeepAliveAck => {
+                let latency = self.unacknowledged_keep_alive.take().map(|sent| timestamp - sent);
+                self.events.push(EprEvent::KeepAliveAck { timestamp, latency });
+            }
+            _ => {}
+        }
+    }
+
+    fn check_keep_alive(&mut self, now: Time) {
+        if self.state != EprModeState::Epr || self.keep_alive_reported {
+            return;
+        }
+        let Some(last_sink_message) = self.last_sink_message else {
+            return;
+        };
+
+        let elapsed = now - last_sink_message;
+        if elapsed > self.keep_alive_timeout {
+            self.keep_alive_reported = true;
+            self.violation(
+                now,
+                EprViolation::KeepAliveTimeout {
+                    last_sink_message,
+                    elapsed,
+                },
+            );
+        }
+    }
+}
+
+fn contract(requested_at: Time, accepted_at: Time, request: RequestDataObject, pdo: SourcePdo) -> EprContract {
+    let (voltage, current) = request.operating_point(&pdo);
+
+    EprContract {
+        requested_at,
+        accepted_at,
+        ready_at: None,
+        request,
+        pdo,
+        voltage,
+        current,
+    }
+}
+
+/// Whether an e-marker allows EPR: EPR mode capable, rated 50 V and 5 A.
+fn is_epr_cable(cable: &CableIdentity) -> bool {
+    cable.epr_mode_capable
+        && cable.max_vbus_voltage >= ElectricPotential::new::<volt>(50.0)
+        && cable
+            .max_current
+            .is_some_and(|current| current >= ElectricCurrent::new::<ampere>(5.0))
+}
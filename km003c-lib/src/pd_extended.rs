@@ -0,0 +1,545 @@
+//! Extended USB PD message reassembly and data blocks.
+//!
+//! Extended messages longer than one chunk travel as a sequence of chunks,
+//! each one requested by the receiver, and chunked transfers on SOP and
+//! SOP' can interleave. [`ExtendedMessageAssembler`] rebuilds the data block
+//! of every extended message type from raw wire messages and decodes it into
+//! an [`ExtendedPayload`].
+
+use std::collections::HashMap;
+
+use uom::si::f64::Power;
+use uom::si::power::watt;
+
+use crate::pd::{PdEvent, PdEventData};
+use crate::pd_epr::ExtendedControlType;
+use crate::pd_pdo::SourcePdo;
+use crate::pd_pps::PpsStatus;
+use crate::pd_wire::{
+    PdControlMessageType, PdDataMessageType, PdExtendedMessageType, PdMessageType, PdSopType, PdWireMessage,
+};
+
+/// Largest number of data bytes carried by one chunk (MaxExtendedMsgChunkLen).
+pub const MAX_EXTENDED_CHUNK_LEN: usize = 26;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn watts(value: u8) -> Power {
+    Power::new::<watt>(f64::from(value))
+}
+
+fn whole_watts(power: Power) -> u8 {
+    power.get::<watt>().round() as u8
+}
+
+/// Two-letter ISO 3166 country code, sent with its first character in the upper byte.
+fn country_code(data: &[u8], offset: usize) -> String {
+    [data[offset + 1], data[offset]]
+        .iter()
+        .map(|&byte| char::from(byte))
+        .collect()
+}
+
+/// Source Capabilities Extended Data Block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct SourceCapabilitiesExtended {
+    pub vid: u16,
+    pub pid: u16,
+    pub xid: u32,
+    pub firmware_version: u8,
+    pub hardware_version: u8,
+    pub voltage_regulation: u8,
+    /// Holdup time in milliseconds.
+    pub holdup_time: u8,
+    pub compliance: u8,
+    pub touch_current: u8,
+    /// Peak Current1..3 overload descriptors.
+    pub peak_current: [u16; 3],
+    pub touch_temperature: u8,
+    pub source_inputs: u8,
+    pub batteries: u8,
+    pub spr_source_pdp: Power,
+    /// Absent in data blocks of sources predating USB PD Revision 3.1.
+    pub epr_source_pdp: Option<Power>,
+}
+
+impl SourceCapabilitiesExtended {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 24 {
+            return None;
+        }
+        Some(Self {
+            vid: u16_at(data, 0),
+            pid: u16_at(data, 2),
+            xid: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            firmware_version: data[8],
+            hardware_version: data[9],
+            voltage_regulation: data[10],
+            holdup_time: data[11],
+            compliance: data[12],
+            touch_current: data[13],
+            peak_current: [u16_at(data, 14), u16_at(data, 16), u16_at(data, 18)],
+            touch_temperature: data[20],
+            source_inputs: data[21],
+            batteries: data[22],
+            spr_source_pdp: watts(data[23]),
+            epr_source_pdp: data.get(24).map(|&pdp| watts(pdp)),
+        })
+    }
+
+    /// Encode the data block, with the EPR Source PDP only when it is present.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(25);
+        data.extend_from_slice(&self.vid.to_le_bytes());
+        data.extend_from_slice(&self.pid.to_le_bytes());
+        data.extend_from_slice(&self.xid.to_le_bytes());
+        data.extend_from_slice(&[
+            self.firmware_version,
+            self.hardware_version,
+            self.voltage_regulation,
+            self.holdup_time,
+            self.compliance,
+            self.touch_current,
+        ]);
+        for peak_current in self.peak_current {
+            data.extend_from_slice(&peak_current.to_le_bytes());
+        }
+        data.extend_from_slice(&[
+            self.touch_temperature,
+            self.source_inputs,
+            self.batteries,
+            whole_watts(self.spr_source_pdp),
+        ]);
+        data.extend(self.epr_source_pdp.map(whole_watts));
+        data
+    }
+}
+
+/// Sink Capabilities Extended Data Block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct SinkCapabilitiesExtended {
+    pub vid: u16,
+    pub pid: u16,
+    pub xid: u32,
+    pub firmware_version: u8,
+    pub hardware_version: u8,
+    pub version: u8,
+    pub load_step: u8,
+    pub load_characteristics: u16,
+    pub compliance: u8,
+    pub touch_temperature: u8,
+    pub battery_info: u8,
+    pub sink_modes: u8,
+    pub sink_minimum_pdp: Power,
+    pub sink_operational_pdp: Power,
+    pub sink_maximum_pdp: Power,
+    pub epr_sink_minimum_pdp: Power,
+    pub epr_sink_operational_pdp: Power,
+    pub epr_sink_maximum_pdp: Power,
+}
+
+impl SinkCapabilitiesExtended {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 24 {
+            return None;
+        }
+        Some(Self {
+            vid: u16_at(data, 0),
+            pid: u16_at(data, 2),
+            xid: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            firmware_version: data[8],
+            hardware_version: data[9],
+            version: data[10],
+            load_step: data[11],
+            load_characteristics: u16_at(data, 12),
+            compliance: data[14],
+            touch_temperature: data[15],
+            battery_info: data[16],
+            sink_modes: data[17],
+            sink_minimum_pdp: watts(data[18]),
+            sink_operational_pdp: watts(data[19]),
+            sink_maximum_pdp: watts(data[20]),
+            epr_sink_minimum_pdp: watts(data[21]),
+            epr_sink_operational_pdp: watts(data[22]),
+            epr_sink_maximum_pdp: watts(data[23]),
+        })
+    }
+}
+
+/// Status Data Block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct StatusDataBlock {
+    /// Internal temperature in °C, or `None` when not supported.
+    pub internal_temperature: Option<u8>,
+    pub present_input: u8,
+    pub present_battery_input: u8,
+    pub event_flags: u8,
+    pub temperature_status: u8,
+    pub power_status: u8,
+    /// Absent in data blocks predating USB PD Revision 3.1.
+    pub power_state_change: Option<u8>,
+}
+
+impl StatusDataBlock {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 6 {
+            return None;
+        }
+        Some(Self {
+            internal_temperature: (data[0] != 0).then_some(data[0]),
+            present_input: data[1],
+            present_battery_input: data[2],
+            event_flags: data[3],
+            temperature_status: data[4],
+            power_status: data[5],
+            power_state_change: data.get(6).copied(),
+        })
+    }
+}
+
+/// Battery Capability Data Block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct BatteryCapabilities {
+    pub vid: u16,
+    pub pid: u16,
+    /// Design capacity in 0.1 Wh units, or `None` when unknown.
+    pub design_capacity: Option<u16>,
+    /// Last full charge capacity in 0.1 Wh units, or `None` when unknown.
+    pub last_full_charge_capacity: Option<u16>,
+    /// Set when the requested battery reference does not exist.
+    pub invalid_battery_reference: bool,
+}
+
+impl BatteryCapabilities {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 9 {
+            return None;
+        }
+        let capacity = |offset| Some(u16_at(data, offset)).filter(|&capacity| capacity != 0xffff);
+        Some(Self {
+            vid: u16_at(data, 0),
+            pid: u16_at(data, 2),
+            design_capacity: capacity(4),
+            last_full_charge_capacity: capacity(6),
+            invalid_battery_reference: data[8] & 0x01 != 0,
+        })
+    }
+}
+
+/// Manufacturer Info Data Block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ManufacturerInfo {
+    pub vid: u16,
+    pub pid: u16,
+    /// Manufacturer string, without trailing NUL padding.
+    pub manufacturer: String,
+}
+
+impl ManufacturerInfo {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        let text = &data[4..];
+        let end = text.iter().position(|&byte| byte == 0).unwrap_or(text.len());
+        Some(Self {
+            vid: u16_at(data, 0),
+            pid: u16_at(data, 2),
+            manufacturer: String::from_utf8_lossy(&text[..end]).into_owned(),
+        })
+    }
+
+    /// Encode the data block without NUL padding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(4 + self.manufacturer.len());
+        data.extend_from_slice(&self.vid.to_le_bytes());
+        data.extend_from_slice(&self.pid.to_le_bytes());
+        data.extend_from_slice(self.manufacturer.as_bytes());
+        data
+    }
+}
+
+/// Country Info Data Block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CountryInfo {
+    pub country_code: String,
+    pub data: Vec<u8>,
+}
+
+impl CountryInfo {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        Some(Self {
+            country_code: country_code(data, 0),
+            data: data[4..].to_vec(),
+        })
+    }
+}
+
+/// Decoded data block of an extended message.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ExtendedPayload {
+    SourceCapabilitiesExtended(SourceCapabilitiesExtended),
+    SinkCapabilitiesExtended(SinkCapabilitiesExtended),
+    Status(StatusDataBlock),
+    /// Get_Battery_Cap or Get_Battery_Status for a battery reference.
+    GetBattery {
+        battery_reference: u8,
+    },
+    BatteryCapabilities(BatteryCapabilities),
+    GetManufacturerInfo {
+        target: u8,
+        reference: u8,
+    },
+    ManufacturerInfo(ManufacturerInfo),
+    PpsStatus(PpsStatus),
+    CountryInfo(CountryInfo),
+    CountryCodes(Vec<String>),
+    ExtendedControl {
+        control: ExtendedControlType,
+        data: u8,
+    },
+    EprSourceCapabilities(Vec<SourcePdo>),
+    /// Raw sink PDOs of EPR_Sink_Capabilities.
+    EprSinkCapabilities(Vec<u32>),
+    /// Security, firmware update and vendor-defined messages, unknown types,
+    /// and data blocks too short for their type.
+    Other(Vec<u8>),
+}
+
+impl ExtendedPayload {
+    /// Decode the complete data block of an extended message.
+    pub fn decode(message_type: PdExtendedMessageType, data: &[u8]) -> Self {
+        use PdExtendedMessageType as Type;
+
+        let objects = || {
+            data.chunks_exact(4)
+                .map(|object| u32::from_le_bytes([object[0], object[1], object[2], object[3]]))
+        };
+        let decoded = match message_type {
+            Type::SourceCapabilitiesExtended => {
+                SourceCapabilitiesExtended::from_bytes(data).map(Self::SourceCapabilitiesExtended)
+            }
+            Type::SinkCapabilitiesExtended => {
+                SinkCapabilitiesExtended::from_bytes(data).map(Self::SinkCapabilitiesExtended)
+            }
+            Type::Status => StatusDataBlock::from_bytes(data).map(Self::Status),
+            Type::GetBatteryCap | Type::GetBatteryStatus => data
+                .first()
+                .map(|&battery_reference| Self::GetBattery { battery_reference }),
+            Type::BatteryCapabilities => BatteryCapabilities::from_bytes(data).map(Self::BatteryCapabilities),
+            Type::GetManufacturerInfo => (data.len() >= 2).then(|| Self::GetManufacturerInfo {
+                target: data[0],
+                reference: data[1],
+            }),
+            Type::ManufacturerInfo => ManufacturerInfo::from_bytes(data).map(Self::ManufacturerInfo),
+            Type::PpsStatus => PpsStatus::from_bytes(data).map(Self::PpsStatus),
+            Type::CountryInfo => CountryInfo::from_bytes(data).map(Self::CountryInfo),
+            Type::CountryCodes => data.first().map(|&count| {
+                let codes = data.get(2..).unwrap_or_default();
+                Self::CountryCodes(
+                    (0..usize::from(count))
+                        .map(|index| index * 2)
+                        .take_while(|&offset| offset + 2 <= codes.len())
+                        .map(|offset| country_code(codes, offset))
+                        .collect(),
+                )
+            }),
+            Type::ExtendedControl => (data.len() >= 2).then(|| Self::ExtendedControl {
+                control: ExtendedControlType::from(data[0]),
+                data: data[1],
+            }),
+            Type::EprSourceCapabilities => Some(Self::EprSourceCapabilities(
+                objects().map(SourcePdo::from_raw).collect(),
+            )),
+            Type::EprSinkCapabilities => Some(Self::EprSinkCapabilities(objects().collect())),
+            _ => None,
+        };
+        decoded.unwrap_or_else(|| Self::Other(data.to_vec()))
+    }
+}
+
+/// A complete extended message, reassembled if it was chunked.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ExtendedMessage {
+    pub sop: PdSopType,
+    pub message_type: PdExtendedMessageType,
+    /// Data block trimmed to the announced data size.
+    pub data: Vec<u8>,
+    pub payload: ExtendedPayload,
+}
+
+/// Result of feeding one extended wire message to [`ExtendedMessageAssembler`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ExtendedChunkProgress {
+    /// An unchunked message, or the last chunk of a chunked one.
+    Complete(ExtendedMessage),
+    Pending {
+        received_chunk: u8,
+        next_chunk: u8,
+    },
+    /// The receiver asked the sender for the next chunk.
+    Request {
+        chunk_number: u8,
+    },
+    /// A chunk that does not continue a transfer in progress; any partial
+    /// data of that transfer is dropped.
+    Discarded {
+        chunk_number: u8,
+        expected_chunk: Option<u8>,
+    },
+    /// A retransmission of the last chunk accepted for a transfer still in
+    /// progress, with the same MessageID; the transfer is unaffected.
+    Duplicate {
+        chunk_number: u8,
+    },
+}
+
+/// Sender of a chunked transfer: SOP* channel, power role or cable plug flag,
+/// and message type.
+type TransferKey = (PdSopType, bool, PdExtendedMessageType);
+
+#[derive(Debug, Clone)]
+struct PartialMessage {
+    data_size: usize,
+    data: Vec<u8>,
+    next_chunk: u8,
+    /// MessageID and chunk number of the last chunk accepted.
+    last_chunk: (u8, u8),
+}
+
+/// Reassembles chunked extended messages of every type.
+///
+/// Transfers are tracked per SOP* channel, sender and message type, so chunks
+/// of messages on SOP and SOP' may interleave. A Soft_Reset drops the
+/// transfers of its channel, and a Source_Capabilities with MessageID 0 on SOP,
+/// as sent after a Hard Reset, drops all of them.
+#[derive(Debug, Clone, Default)]
+pub struct ExtendedMessageAssembler {
+    transfers: HashMap<TransferKey, PartialMessage>,
+}
+
+impl ExtendedMessageAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop all partially received messages.
+    pub fn reset(&mut self) {
+        self.transfers.clear();
+    }
+
+    /// Process one KM003C PD event; connection changes drop partial messages.
+    pub fn process_event(&mut self, event: &PdEvent) -> Option<ExtendedChunkProgress> {
+        match &event.data {
+            PdEventData::Connect(()) | PdEventData::Disconnect(()) => {
+                self.reset();
+                None
+            }
+            PdEventData::PdMessage { sop, wire_data } => {
+                let message = PdWireMessage::from_bytes(wire_data).ok()?;
+                self.process_message(PdSopType::from(*sop), &message)
+            }
+        }
+    }
+
+    /// Process one wire message, returning `None` unless it is an extended message.
+    pub fn process_message(&mut self, sop: PdSopType, message: &PdWireMessage) -> Option<ExtendedChunkProgress> {
+        let message_type = match message.message_type() {
+            PdMessageType::Extended(message_type) => message_type,
+            PdMessageType::Control(PdControlMessageType::SoftReset) => {
+                self.transfers.retain(|(channel, _, _), _| *channel != sop);
+                return None;
+            }
+            PdMessageType::Data(PdDataMessageType::SourceCapabilities)
+                if sop == PdSopType::Sop && message.header.message_id() == 0 =>
+            {
+                self.reset();
+                return None;
+            }
+            _ => return None,
+        };
+        let extended_header = message.extended_header?;
+        let chunk_number = extended_header.chunk_number();
+
+        if !extended_header.chunked() {
+            return Some(ExtendedChunkProgress::Complete(ExtendedMessage {
+                sop,
+                message_type,
+                data: message.payload.clone(),
+                payload: ExtendedPayload::decode(message_type, &message.payload),
+            }));
+        }
+        if extended_header.request_chunk() {
+            return Some(ExtendedChunkProgress::Request { chunk_number });
+        }
+
+        let key = (sop, message.header.port_power_role(), message_type);
+        let chunk_id = (message.header.message_id(), chunk_number);
+        if self
+            .transfers
+            .get(&key)
+            .is_some_and(|transfer| transfer.last_chunk == chunk_id)
+        {
+            return Some(ExtendedChunkProgress::Duplicate { chunk_number });
+        }
+        if chunk_number == 0 {
+            self.transfers.insert(
+                key,
+                PartialMessage {
+                    data_size: usize::from(extended_header.data_size()),
+                    data: Vec::new(),
+                    next_chunk: 0,
+                    last_chunk: chunk_id,
+                },
+            );
+        }
+        let Some(transfer) = self.transfers.get_mut(&key) else {
+            return Some(ExtendedChunkProgress::Discarded {
+                chunk_number,
+                expected_chunk: None,
+            });
+        };
+
+        let expected_len = (transfer.data_size - transfer.data.len()).min(MAX_EXTENDED_CHUNK_LEN);
+        if chunk_number != transfer.next_chunk || message.payload.len() < expected_len {
+            let expected_chunk = transfer.next_chunk;
+            self.transfers.remove(&key);
+            return Some(ExtendedChunkProgress::Discarded {
+                chunk_number,
+                expected_chunk: Some(expected_chunk),
+            });
+        }
+
+        transfer.data.extend_from_slice(&message.payload[..expected_len]);
+        transfer.last_chunk = chunk_id;
+        if transfer.data.len() < transfer.data_size {
+            transfer.next_chunk += 1;
+            return Some(ExtendedChunkProgress::Pending {
+                received_chunk: chunk_number,
+                next_chunk: transfer.next_chunk,
+            });
+        }
+
+        let data = self.transfers.remove(&key)?.data;
+        Some(ExtendedChunkProgress::Complete(ExtendedMessage {
+            sop,
+            message_type,
+            payload: ExtendedPayload::decode(message_type, &data),
+            data,
+        }))
+    }
+}
@@ -0,0 +1,476 @@
+//! Charger fingerprints derived from USB PD traffic.
+//!
+//! Chargers of the same model advertise the same capabilities, report the same
+//! Source_Capabilities_Extended, Manufacturer_Info and Discover Identity
+//! values, and tend to answer with similar timing. A [`ChargerFingerprint`]
+//! collects those properties from one capture, and a [`FingerprintDatabase`]
+//! scores new captures against previously identified chargers and, with the
+//! `serde` feature, stores them as JSON.
+
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+use uom::si::f64::Time;
+use uom::si::time::second;
+
+#[cfg(feature = "serde")]
+use crate::error::KMError;
+#[cfg(feature = "usbpd")]
+use crate::pd_decode::{DecodedPdEvent, DecodedPdMessage};
+#[cfg(feature = "usbpd")]
+use crate::pd_extended::ExtendedPayload;
+use crate::pd_extended::{ManufacturerInfo, SourceCapabilitiesExtended};
+use crate::pd_pdo::SourcePdo;
+use crate::pd_vdm::DiscoverIdentity;
+#[cfg(feature = "usbpd")]
+use crate::pd_wire::{PdControlMessageType, PdDataMessageType, PdMessageType, PdSopType, PdWireMessage};
+
+/// Unconstrained Power flag of the vSafe5V Fixed Supply PDO; it follows the
+/// charger's mains connection rather than its model.
+const UNCONSTRAINED_POWER: u32 = 1 << 27;
+
+const SOURCE_PDO_WEIGHT: f64 = 4.0;
+const EPR_PDO_WEIGHT: f64 = 2.0;
+const VENDOR_PRODUCT_WEIGHT: f64 = 2.0;
+const MANUFACTURER_WEIGHT: f64 = 1.0;
+const CAPABILITIES_EXTENDED_WEIGHT: f64 = 1.0;
+const TIMING_WEIGHT: f64 = 1.0;
+
+/// Product identity reported by the charger in a Discover Identity ACK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ChargerIdentity {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bcd_device: u16,
+    /// USB-IF assigned XID, zero for uncertified products.
+    pub xid: u32,
+}
+
+impl ChargerIdentity {
+    pub fn from_discover_identity(identity: &DiscoverIdentity) -> Self {
+        Self {
+            vendor_id: identity.id_header.vendor_id(),
+            product_id: identity.product.map_or(0, |product| product.product_id()),
+            bcd_device: identity.product.map_or(0, |product| product.bcd_device()),
+            xid: identity.cert_stat.map_or(0, |cert_stat| cert_stat.xid()),
+        }
+    }
+}
+
+/// Response times observed while the charger negotiated its first contract.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct FingerprintTiming {
+    /// Attach to the first Source_Capabilities.
+    pub capabilities_delay: Option<Time>,
+    /// Between the first two Source_Capabilities sent before a Request,
+    /// excluding protocol-layer retries.
+    pub capabilities_interval: Option<Time>,
+    /// Accept to PS_RDY of the first contract.
+    pub transition_time: Option<Time>,
+}
+
+impl FingerprintTiming {
+    fn values(&self) -> [Option<Time>; 3] {
+        [
+            self.capabilities_delay,
+            self.capabilities_interval,
+            self.transition_time,
+        ]
+    }
+
+    /// Mean relative agreement of the times measured in both, if any.
+    fn similarity(&self, other: &Self) -> Option<f64> {
+        let scores = self
+            .values()
+            .into_iter()
+            .zip(other.values())
+            .filter_map(|(a, b)| {
+                let (a, b) = (a?.get::<second>(), b?.get::<second>());
+                let longest = a.max(b);
+                Some(if longest > 0.0 {
+                    1.0 - (a - b).abs() / longest
+                } else {
+                    1.0
+                })
+            })
+            .collect::<Vec<_>>();
+        (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64)
+    }
+}
+
+/// Properties identifying a charger model, collected from one capture.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ChargerFingerprint {
+    /// SPR Source_Capabilities of the first contract negotiation.
+    pub source_pdos: Vec<SourcePdo>,
+    /// EPR_Source_Capabilities, empty if the charger never sent them.
+    pub epr_source_pdos: Vec<SourcePdo>,
+    pub capabilities_extended: Option<SourceCapabilitiesExtended>,
+    pub manufacturer_info: Option<ManufacturerInfo>,
+    pub identity: Option<ChargerIdentity>,
+    pub timing: FingerprintTiming,
+}
+
+impl ChargerFingerprint {
+    /// Whether no identifying property was observed.
+    pub fn is_empty(&self) -> bool {
+        self.source_pdos.is_empty()
+            && self.epr_source_pdos.is_empty()
+            && self.capabilities_extended.is_none()
+            && self.manufacturer_info.is_none()
+            && self.identity.is_none()
+    }
+
+    /// USB VID and PID, from Discover Identity, Source_Capabilities_Extended
+    /// or Manufacturer_Info, in that order.
+    pub fn vendor_product(&self) -> Option<(u16, u16)> {
+        self.identity
+            .map(|identity| (identity.vendor_id, identity.product_id))
+            .or_else(|| self.capabilities_extended.map(|extended| (extended.vid, extended.pid)))
+            .or_else(|| self.manufacturer_info.as_ref().map(|info| (info.vid, info.pid)))
+    }
+
+    /// Stable 64-bit signature of the advertised capabilities and identity.
+    ///
+    /// Chargers with equal signatures advertise the same PDOs and report the
+    /// same VID, PID and manufacturer. Timing is not part of the signature;
+    /// use [`Self::similarity`] to compare captures of the same model.
+    pub fn signature(&self) -> String {
+        let mut hash = Fnv1a::default();
+        for pdos in [&self.source_pdos, &self.epr_source_pdos] {
+            hash.write(&(pdos.len() as u32).to_le_bytes());
+            for pdo in pdos {
+                hash.write(&capability_key(pdo).to_le_bytes());
+            }
+        }
+        let (vid, pid) = self.vendor_product().unwrap_or_default();
+        hash.write(&vid.to_le_bytes());
+        hash.write(&pid.to_le_bytes());
+        if let Some(info) = &self.manufacturer_info {
+            hash.write(info.manufacturer.as_bytes());
+        }
+        format!("{:016x}", hash.0)
+    }
+
+    /// Similarity to another fingerprint, from 0.0 to 1.0.
+    ///
+    /// Properties are weighted by how specific they are to a model, the
+    /// advertised PDOs most, and only properties present in both fingerprints
+    /// count. Fingerprints without a common property score 0.0.
+    pub fn similarity(&self, other: &Self) -> f64 {
+        let mut components = Vec::new();
+        if !self.source_pdos.is_empty() && !other.source_pdos.is_empty() {
+            components.push((SOURCE_PDO_WEIGHT, pdo_similarity(&self.source_pdos, &other.source_pdos)));
+        }
+        if !self.epr_source_pdos.is_empty() && !other.epr_source_pdos.is_empty() {
+            components.push((
+                EPR_PDO_WEIGHT,
+                pdo_similarity(&self.epr_source_pdos, &other.epr_source_pdos),
+            ));
+        }
+        if let (Some(a), Some(b)) = (self.vendor_product(), other.vendor_product()) {
+            components.push((VENDOR_PRODUCT_WEIGHT, if a == b { 1.0 } else { 0.0 }));
+        }
+        if let (Some(a), Some(b)) = (&self.manufacturer_info, &other.manufacturer_info) {
+            let same = a.manufacturer.trim() == b.manufacturer.trim();
+            components.push((MANUFACTURER_WEIGHT, if same { 1.0 } else { 0.0 }));
+        }
+        if let (Some(a), Some(b)) = (&self.capabilities_extended, &other.capabilities_extended) {
+            components.push((CAPABILITIES_EXTENDED_WEIGHT, capabilities_extended_similarity(a, b)));
+        }
+        if let Some(timing) = self.timing.similarity(&other.timing) {
+            components.push((TIMING_WEIGHT, timing));
+        }
+
+        let weight = components.iter().map(|(weight, _)| weight).sum::<f64>();
+        if weight == 0.0 {
+            return 0.0;
+        }
+        components.iter().map(|(weight, score)| weight * score).sum::<f64>() / weight
+    }
+}
+
+/// PDO value compared between fingerprints.
+fn capability_key(pdo: &SourcePdo) -> u32 {
+    match pdo {
+        SourcePdo::FixedSupply(_) => pdo.raw() & !UNCONSTRAINED_POWER,
+        _ => pdo.raw(),
+    }
+}
+
+/// Fraction of object positions advertising the same PDO.
+fn pdo_similarity(a: &[SourcePdo], b: &[SourcePdo]) -> f64 {
+    let same = a
+        .iter()
+        .zip(b)
+        .filter(|(a, b)| capability_key(a) == capability_key(b))
+        .count();
+    same as f64 / a.len().max(b.len()) as f64
+}
+
+/// Fraction of equal fields other than the VID and PID.
+fn capabilities_extended_similarity(a: &SourceCapabilitiesExtended, b: &SourceCapabilitiesExtended) -> f64 {
+    let fields = [
+        a.xid == b.xid,
+        a.firmware_version == b.firmware_version,
+        a.hardware_version == b.hardware_version,
+        a.voltage_regulation == b.voltage_regulation,
+        a.holdup_time == b.holdup_time,
+        a.compliance == b.compliance,
+        a.touch_current == b.touch_current,
+        a.peak_current == b.peak_current,
+        a.touch_temperature == b.touch_temperature,
+        a.source_inputs == b.source_inputs,
+        a.batteries == b.batteries,
+        a.spr_source_pdp == b.spr_source_pdp,
+        a.epr_source_pdp == b.epr_source_pdp,
+    ];
+    fields.iter().filter(|&&same| same).count() as f64 / fields.len() as f64
+}
+
+/// 64-bit FNV-1a, stable across platforms and releases.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// Collects a [`ChargerFingerprint`] from the events of a [`crate::PdSessionDecoder`].
+///
+/// Only SOP traffic sent by the source is attributed to the charger. The first
+/// Source_Capabilities and EPR_Source_Capabilities are kept; later data blocks
+/// replace earlier ones.
+#[cfg(feature = "usbpd")]
+#[derive(Debug, Clone, Default)]
+pub struct FingerprintCollector {
+    fingerprint: ChargerFingerprint,
+    connected_at: Option<Time>,
+    /// Time and MessageID of the last Source_Capabilities before a Request.
+    last_capabilities: Option<(Time, u8)>,
+    capabilities_acknowledged: bool,
+    requested: bool,
+    accepted_at: Option<Time>,
+}
+
+#[cfg(feature = "usbpd")]
+impl FingerprintCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget the collected fingerprint.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn fingerprint(&self) -> &ChargerFingerprint {
+        &self.fingerprint
+    }
+
+    pub fn into_fingerprint(self) -> ChargerFingerprint {
+        self.fingerprint
+    }
+
+    /// Process one decoded event.
+    pub fn process_event(&mut self, event: &DecodedPdEvent) {
+        match event {
+            DecodedPdEvent::Connect { timestamp } => {
+                self.restart_negotiation();
+                self.connected_at = Some(*timestamp);
+            }
+            DecodedPdEvent::Disconnect { .. } => {
+                self.restart_negotiation();
+                self.connected_at = None;
+            }
+            DecodedPdEvent::Message(message) => self.process_message(message),
+            DecodedPdEvent::Chunk(_) | DecodedPdEvent::Error(_) => {}
+        }
+    }
+
+    fn restart_negotiation(&mut self) {
+        self.last_capabilities = None;
+        self.capabilities_acknowledged = false;
+        self.requested = false;
+        self.accepted_at = None;
+    }
+
+    fn process_message(&mut self, message: &DecodedPdMessage) {
+        if PdSopType::from(message.sop) != PdSopType::Sop {
+            return;
+        }
+        let Ok(wire) = PdWireMessage::from_bytes(&message.wire_data) else {
+            return;
+        };
+        let timestamp = message.timestamp;
+        let from_source = wire.header.port_power_role();
+        let timing = &mut self.fingerprint.timing;
+
+        match wire.message_type() {
+            PdMessageType::Control(PdControlMessageType::GoodCrc)
+                if !from_source && self.last_capabilities.is_some() =>
+            {
+                self.capabilities_acknowledged = true;
+            }
+            PdMessageType::Data(PdDataMessageType::SourceCapabilities) if from_source => {
+                if self.fingerprint.source_pdos.is_empty() {
+                    self.fingerprint.source_pdos = wire.data_objects().map(SourcePdo::from_raw).collect();
+                    timing.capabilities_delay = self.connected_at.map(|connected| timestamp - connected);
+                }
+                if self.requested {
+                    return;
+                }
+                let message_id = wire.header.message_id();
+                if let Some((previous, previous_id)) = self.last_capabilities {
+                    // Unacknowledged and resent with the same MessageID: a protocol-layer retry.
+                    if previous_id == message_id && !self.capabilities_acknowledged {
+                        return;
+                    }
+                    if timing.capabilities_interval.is_none() {
+                        timing.capabilities_interval = Some(timestamp - previous);
+                    }
+                }
+                self.last_capabilities = Some((timestamp, message_id));
+                self.capabilities_acknowledged = false;
+            }
+            PdMessageType::Data(PdDataMessageType::Request) if !from_source => self.requested = true,
+            PdMessageType::Control(PdControlMessageType::Accept)
+                if from_source && self.requested && timing.transition_time.is_none() =>
+            {
+                self.accepted_at = Some(timestamp);
+            }
+            PdMessageType::Control(PdControlMessageType::PsRdy) if from_source => {
+                if let Some(accepted) = self.accepted_at.take() {
+                    timing.transition_time = Some(timestamp - accepted);
+                }
+            }
+            _ => {}
+        }
+
+        if !from_source {
+            return;
+        }
+        if let Some(identity) = message.vdm.as_ref().and_then(|vdm| vdm.discover_identity()) {
+            self.fingerprint.identity = Some(ChargerIdentity::from_discover_identity(&identity));
+        }
+        match message.extended.as_ref().map(|extended| &extended.payload) {
+            Some(ExtendedPayload::SourceCapabilitiesExtended(extended)) => {
+                self.fingerprint.capabilities_extended = Some(*extended);
+            }
+            Some(ExtendedPayload::ManufacturerInfo(info)) => {
+                self.fingerprint.manufacturer_info = Some(info.clone());
+            }
+            Some(ExtendedPayload::EprSourceCapabilities(pdos)) if self.fingerprint.epr_source_pdos.is_empty() => {
+                self.fingerprint.epr_source_pdos = pdos.clone();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A named fingerprint of a known charger.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct FingerprintEntry {
+    pub name: String,
+    pub fingerprint: ChargerFingerprint,
+}
+
+/// A database entry scored against a capture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FingerprintMatch<'a> {
+    pub entry: &'a FingerprintEntry,
+    /// Similarity from 0.0 to 1.0.
+    pub score: f64,
+}
+
+/// Fingerprints of known chargers.
+///
+/// With the `serde` feature the database is stored as a JSON file listing
+/// each charger's name and fingerprint.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct FingerprintDatabase {
+    entries: Vec<FingerprintEntry>,
+}
+
+impl FingerprintDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &[FingerprintEntry] {
+        &self.entries
+    }
+
+    /// Add a charger, replacing any entry with the same name.
+    pub fn insert(&mut self, name: impl Into<String>, fingerprint: ChargerFingerprint) {
+        let name = name.into().trim().to_string();
+        match self.entries.iter_mut().find(|entry| entry.name == name) {
+            Some(entry) => entry.fingerprint = fingerprint,
+            None => self.entries.push(FingerprintEntry { name, fingerprint }),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<FingerprintEntry> {
+        let index = self.entries.iter().position(|entry| entry.name == name)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// All entries scored against a fingerprint, best match first.
+    pub fn matches(&self, fingerprint: &ChargerFingerprint) -> Vec<FingerprintMatch<'_>> {
+        let mut matches = self
+            .entries
+            .iter()
+            .map(|entry| FingerprintMatch {
+                entry,
+                score: entry.fingerprint.similarity(fingerprint),
+            })
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+        matches
+    }
+
+    /// Best-scoring entry, if it scores at least `min_score`.
+    pub fn best_match(&self, fingerprint: &ChargerFingerprint, min_score: f64) -> Option<FingerprintMatch<'_>> {
+        self.matches(fingerprint)
+            .into_iter()
+            .next()
+            .filter(|best| best.score >= min_score)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl FingerprintDatabase {
+    /// Render the database as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, KMError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parse a database from JSON.
+    pub fn from_json(text: &str) -> Result<Self, KMError> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Write the database to a JSON file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), KMError> {
+        Ok(std::fs::write(path, self.to_json()?)?)
+    }
+
+    /// Read a database from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KMError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
@@ -0,0 +1,763 @@
+//! Human-readable rendering of decoded USB PD traffic.
+//!
+//! The CLI, the GUI and capture reports print PD events through this module,
+//! so a capture reads the same everywhere. Every [`DecodedPdEvent`] renders
+//! as a one-line summary, a few detail lines such as one per PDO, and a
+//! key/value tree breaking the message and extended headers and every data
+//! object down into their bit fields. Data objects are rendered from the raw
+//! wire bytes with the crate's own decoders, so the output does not depend on
+//! how the `usbpd` crate names its types.
+
+use num_enum::FromPrimitive;
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Power, Time};
+use uom::si::power::watt;
+use uom::si::time::second;
+
+use crate::pd_decode::{DecodedPdEvent, DecodedPdMessage, PdChunkState, PdChunkStatus, PdDecodeFailure};
+use crate::pd_epr::EprModeDataObject;
+use crate::pd_extended::ExtendedPayload;
+use crate::pd_pdo::{RequestDataObject, SourcePdo};
+use crate::pd_policy::{PdDataRole, PdPowerRole, PdSpecRevision};
+use crate::pd_vdm::{VdmContent, VendorDefinedMessage};
+use crate::pd_wire::{PdDataMessageType, PdExtendedHeader, PdMessageHeader, PdMessageType, PdSopType, PdWireMessage};
+
+/// Indentation of detail lines below a summary line in plain text.
+pub const DETAIL_INDENT: &str = "             ";
+
+/// Kind of a rendered PD event, e.g. for color-coding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "python", pyo3::pyclass(eq, skip_from_py_object, name = "PdEventCategory"))]
+pub enum PdEventCategory {
+    Connect,
+    Disconnect,
+    SourceCapabilities,
+    Request,
+    Control,
+    Extended,
+    Error,
+}
+
+/// A named value with nested fields, such as a data object and its bit fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PdField {
+    pub name: String,
+    pub value: String,
+    pub children: Vec<PdField>,
+}
+
+impl PdField {
+    pub fn new(name: impl Into<String>, value: impl ToString) -> Self {
+        Self {
+            name: name.into(),
+            value: value.to_string(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_children(self, children: Vec<PdField>) -> Self {
+        Self { children, ..self }
+    }
+
+    /// `name: value` lines of the field and its children, two spaces per level.
+    pub fn lines(&self, depth: usize) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{:indent$}{}: {}",
+            "",
+            self.name,
+            self.value,
+            indent = depth * 2
+        )];
+        for child in &self.children {
+            lines.extend(child.lines(depth + 1));
+        }
+        lines
+    }
+}
+
+/// Rendering of one decoded PD event.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct FormattedPdEvent {
+    pub timestamp: Time,
+    pub category: PdEventCategory,
+    /// One line without the timestamp.
+    pub summary: String,
+    /// Short lines completing the summary, e.g. one per PDO.
+    pub details: Vec<String>,
+    /// Headers and data objects down to their bit fields.
+    pub fields: Vec<PdField>,
+}
+
+impl FormattedPdEvent {
+    /// The summary prefixed by the capture time in seconds.
+    pub fn summary_line(&self) -> String {
+        format!("[{:>8.3}s] {}", self.timestamp.get::<second>(), self.summary)
+    }
+
+    /// Summary line followed by the indented detail lines.
+    pub fn text(&self) -> String {
+        let mut lines = vec![self.summary_line()];
+        lines.extend(self.details.iter().map(|detail| format!("{DETAIL_INDENT}{detail}")));
+        lines.join("\n")
+    }
+
+    /// Summary line followed by the indented field tree.
+    pub fn verbose_text(&self) -> String {
+        let mut lines = vec![self.summary_line()];
+        for field in &self.fields {
+            lines.extend(field.lines(0).into_iter().map(|line| format!("{DETAIL_INDENT}{line}")));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Render one decoded PD event.
+pub fn format_event(event: &DecodedPdEvent) -> FormattedPdEvent {
+    match event {
+        DecodedPdEvent::Connect { timestamp } => connection(*timestamp, PdEventCategory::Connect, "** CONNECT **"),
+        DecodedPdEvent::Disconnect { timestamp } => {
+            connection(*timestamp, PdEventCategory::Disconnect, "** DISCONNECT **")
+        }
+        DecodedPdEvent::Message(message) => format_message(message),
+        DecodedPdEvent::Chunk(status) => format_chunk_status(status),
+        DecodedPdEvent::Error(failure) => format_failure(failure),
+    }
+}
+
+fn connection(timestamp: Time, category: PdEventCategory, summary: &str) -> FormattedPdEvent {
+    FormattedPdEvent {
+        timestamp,
+        category,
+        summary: summary.to_string(),
+        details: Vec::new(),
+        fields: Vec::new(),
+    }
+}
+
+fn format_message(decoded: &DecodedPdMessage) -> FormattedPdEvent {
+    let sop = PdSopType::from(decoded.sop);
+    let Ok(wire) = PdWireMessage::from_bytes(&decoded.wire_data) else {
+        return FormattedPdEvent {
+            timestamp: decoded.timestamp,
+            category: PdEventCategory::Error,
+            summary: format!("SOP{}: Malformed message header", decoded.sop),
+            details: vec![format!("Hex: {:02X?}", decoded.wire_data)],
+            fields: vec![sop_field(sop), hex_field("Wire Data", &decoded.wire_data)],
+        };
+    };
+
+    let header = wire.header;
+    let roles = if sop.is_cable_plug() {
+        format!("FROM={}", if header.port_power_role() { "Cable" } else { "Port" })
+    } else {
+        format!("ROLE={:?}/{:?}", power_role(&header), data_role(&header))
+    };
+    let summary = format!(
+        "SOP{}: {} (ID={}, {roles})",
+        decoded.sop,
+        message_type_name(wire.message_type()),
+        header.message_id()
+    );
+
+    let mut fields = vec![sop_field(sop), header_field(sop, &header)];
+    if let Some(extended_header) = wire.extended_header {
+        fields.push(extended_header_field(&extended_header));
+    }
+    let (category, details, body_fields) = message_body(decoded, &wire);
+    fields.extend(body_fields);
+
+    FormattedPdEvent {
+        timestamp: decoded.timestamp,
+        category,
+        summary,
+        details,
+        fields,
+    }
+}
+
+/// Category, detail lines and data fields of a message.
+fn message_body(decoded: &DecodedPdMessage, wire: &PdWireMessage) -> (PdEventCategory, Vec<String>, Vec<PdField>) {
+    if let Some(vdm) = &decoded.vdm {
+        return (
+            PdEventCategory::Control,
+            format_vdm(vdm, PdSopType::from(decoded.sop)),
+            vdm_fields(vdm),
+        );
+    }
+
+    let objects = wire.data_objects().collect::<Vec<_>>();
+    match wire.message_type() {
+        PdMessageType::Control(_) => (PdEventCategory::Control, Vec::new(), Vec::new()),
+        PdMessageType::Data(PdDataMessageType::SourceCapabilities) => {
+            let pdos = objects.into_iter().map(SourcePdo::from_raw).collect::<Vec<_>>();
+            (
+                PdEventCategory::SourceCapabilities,
+                format_capabilities(&pdos, "SPR Source Capabilities"),
+                pdo_fields(&pdos),
+            )
+        }
+        PdMessageType::Data(PdDataMessageType::Request) if !objects.is_empty() => {
+            let request = RequestDataObject(objects[0]);
+            let pdo = decoded
+                .request_capabilities
+                .as_deref()
+                .and_then(|pdos| requested_pdo(pdos, request));
+            (
+                PdEventCategory::Request,
+                vec![format_request(request, pdo, false)],
+                vec![request_field(request, pdo)],
+            )
+        }
+        PdMessageType::Data(PdDataMessageType::EprRequest) if objects.len() >= 2 => {
+            let request = RequestDataObject(objects[0]);
+            let pdo = SourcePdo::from_raw(objects[1]);
+            (
+                PdEventCategory::Request,
+                vec![format_request(request, Some(&pdo), true)],
+                vec![
+                    request_field(request, Some(&pdo)),
+                    PdField::new("Requested PDO", hex_u32(pdo.raw())).with_children(source_pdo_fields(&pdo)),
+                ],
+            )
+        }
+        PdMessageType::Data(PdDataMessageType::EprMode) if !objects.is_empty() => {
+            let mode = EprModeDataObject(objects[0]);
+            let detail = match mode.failure_reason() {
+                Some(reason) => format!("EPR Mode: {:?} ({reason:?})", mode.action()),
+                None => format!("EPR Mode: {:?} (data={})", mode.action(), mode.data()),
+            };
+            (PdEventCategory::Extended, vec![detail], vec![epr_mode_field(mode)])
+        }
+        PdMessageType::Data(message_type) => (
+            PdEventCategory::Control,
+            vec![format!(
+                "Data: {message_type:?} [{}]",
+                objects
+                    .iter()
+                    .map(|&object| hex_u32(object))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )],
+            objects
+                .iter()
+                .enumerate()
+                .map(|(index, &object)| PdField::new(format!("Data Object {}", index + 1), hex_u32(object)))
+                .collect(),
+        ),
+        PdMessageType::Extended(message_type) => {
+            let Some(extended) = &decoded.extended else {
+                return (
+                    PdEventCategory::Extended,
+                    vec![format!("Extended: {message_type:?}")],
+                    vec![hex_field("Chunk Data", &wire.payload)],
+                );
+            };
+            let mut fields = vec![
+                PdField::new("Data Size", extended.data.len()),
+                hex_field("Data Block", &extended.data),
+            ];
+            let details = match &extended.payload {
+                ExtendedPayload::EprSourceCapabilities(pdos) => {
+                    fields.extend(pdo_fields(pdos));
+                    format_capabilities(pdos, "EPR Source Capabilities")
+                }
+                ExtendedPayload::ExtendedControl { control, data } => {
+                    vec![format!("Extended Control: {control:?} (data=0x{data:02X})")]
+                }
+                payload => {
+                    fields.push(PdField::new("Payload", format!("{payload:?}")));
+                    vec![format!("Extended: {payload:?}")]
+                }
+            };
+            (PdEventCategory::Extended, details, fields)
+        }
+    }
+}
+
+fn format_chunk_status(status: &PdChunkStatus) -> FormattedPdEvent {
+    let message_type = status.message_type;
+    let (summary, state) = match status.state {
+        PdChunkState::Request { chunk_number } => (
+            format!("Chunk Request (chunk={chunk_number}, type={message_type:?})"),
+            vec![PdField::new("Requested Chunk", chunk_number)],
+        ),
+        PdChunkState::Pending {
+            received_chunk,
+            next_chunk,
+        } => (
+            format!("{message_type:?} chunk {received_chunk} received, waiting for chunk {next_chunk}"),
+            vec![
+                PdField::new("Received Chunk", received_chunk),
+                PdField::new("Next Chunk", next_chunk),
+            ],
+        ),
+        PdChunkState::Discarded {
+            chunk_number,
+            expected_chunk,
+        } => (
+            format!("{message_type:?} chunk {chunk_number} discarded (expected {expected_chunk:?})"),
+            vec![
+                PdField::new("Discarded Chunk", chunk_number),
+                PdField::new(
+                    "Expected Chunk",
+                    expected_chunk.map_or_else(|| "none".to_string(), |chunk| chunk.to_string()),
+                ),
+            ],
+        ),
+        PdChunkState::Duplicate { chunk_number } => (
+            format!("{message_type:?} chunk {chunk_number} retransmitted, ignored"),
+            vec![PdField::new("Duplicate Chunk", chunk_number)],
+        ),
+    };
+
+    let mut fields = vec![
+        sop_field(PdSopType::from(status.sop)),
+        PdField::new("Message Type", format!("{message_type:?}")),
+    ];
+    fields.extend(state);
+    FormattedPdEvent {
+        timestamp: status.timestamp,
+        category: PdEventCategory::Extended,
+        summary: format!("SOP{}: {summary}", status.sop),
+        details: Vec::new(),
+        fields,
+    }
+}
+
+fn format_failure(failure: &PdDecodeFailure) -> FormattedPdEvent {
+    FormattedPdEvent {
+        timestamp: failure.timestamp,
+        category: PdEventCategory::Error,
+        summary: format!("SOP{}: Parse error: {}", failure.sop, failure.error),
+        details: vec![format!("Hex: {:02X?}", failure.wire_data)],
+        fields: vec![
+            sop_field(PdSopType::from(failure.sop)),
+            PdField::new("Error", &failure.error),
+            hex_field("Wire Data", &failure.wire_data),
+        ],
+    }
+}
+
+/// One-line description of a source PDO, e.g. `Fixed 9V @ 3.0A (27W)`.
+pub fn format_source_pdo(pdo: &SourcePdo) -> String {
+    match pdo {
+        SourcePdo::FixedSupply(fixed) => {
+            let voltage = volts(fixed.voltage());
+            let current = amps(fixed.max_current());
+            let flags = [
+                (fixed.dual_role_power(), "DRP"),
+                (fixed.usb_communications_capable(), "USB"),
+                (fixed.dual_role_data(), "DRD"),
+                (fixed.unconstrained_power(), "UP"),
+                (fixed.epr_mode_capable(), "EPR"),
+            ]
+            .into_iter()
+            .filter_map(|(set, flag)| set.then_some(flag))
+            .collect::<Vec<_>>();
+            let flags = if flags.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", flags.join(","))
+            };
+            format!("Fixed {voltage:.0}V @ {current:.1}A ({:.0}W){flags}", voltage * current)
+        }
+        SourcePdo::Battery(battery) => format!(
+            "Battery {:.0}-{:.0}V @ {:.0}W",
+            volts(battery.min_voltage()),
+            volts(battery.max_voltage()),
+            watts(battery.max_power())
+        ),
+        SourcePdo::VariableSupply(variable) => format!(
+            "Variable {:.0}-{:.0}V @ {:.1}A",
+            volts(variable.min_voltage()),
+            volts(variable.max_voltage()),
+            amps(variable.max_current())
+        ),
+        SourcePdo::SprPps(pps) => {
+            let max_voltage = volts(pps.max_voltage());
+            let current = amps(pps.max_current());
+            let limited = if pps.power_limited() { " (limited)" } else { "" };
+            format!(
+                "PPS {:.1}-{max_voltage:.1}V @ {current:.1}A ({:.0}W){limited}",
+                volts(pps.min_voltage()),
+                max_voltage * current
+            )
+        }
+        SourcePdo::SprAvs(avs) => format!(
+            "SPR AVS 9-15V @ {:.1}A, 15-20V @ {:.1}A",
+            amps(avs.max_current_15v()),
+            amps(avs.max_current_20v())
+        ),
+        SourcePdo::EprAvs(avs) => format!(
+            "EPR AVS {:.0}-{:.0}V @ {:.0}W",
+            volts(avs.min_voltage()),
+            volts(avs.max_voltage()),
+            watts(avs.pdp())
+        ),
+        SourcePdo::Unknown(raw) => format!("Unknown(0x{raw:08X})"),
+    }
+}
+
+/// A titled list of PDOs, one line each; zero objects separate SPR and EPR PDOs.
+pub fn format_capabilities(pdos: &[SourcePdo], title: &str) -> Vec<String> {
+    let mut lines = vec![format!("[{title}]")];
+    for (index, pdo) in pdos.iter().enumerate() {
+        if pdo.raw() == 0 {
+            lines.push(format!("PDO[{}]: --- (separator) ---", index + 1));
+        } else {
+            lines.push(format!("PDO[{}]: {}", index + 1, format_source_pdo(pdo)));
+        }
+    }
+    lines
+}
+
+/// One-line description of a Request, or of an EPR_Request with `epr` set.
+///
+/// Without the requested PDO only the object position can be interpreted.
+pub fn format_request(request: RequestDataObject, pdo: Option<&SourcePdo>, epr: bool) -> String {
+    let position = request.object_position();
+    let prefix = if epr { "EPR " } else { "" };
+    let Some(pdo) = pdo else {
+        return format!("RDO: {prefix}PDO#{position} (Raw=0x{:08X})", request.0);
+    };
+
+    let operating_point = match pdo {
+        SourcePdo::FixedSupply(_) | SourcePdo::VariableSupply(_) => format!(
+            "{:.2}A (Max {:.2}A)",
+            amps(request.operating_current()),
+            amps(request.max_operating_current())
+        ),
+        SourcePdo::Battery(_) => format!(
+            "{:.2}W (Max {:.2}W)",
+            watts(request.operating_power()),
+            watts(request.max_operating_power())
+        ),
+        SourcePdo::SprPps(_) | SourcePdo::SprAvs(_) | SourcePdo::EprAvs(_) => {
+            let (voltage, current) = request.operating_point(pdo);
+            format!("{:.2}V / {:.2}A", volts(voltage), amps(current))
+        }
+        SourcePdo::Unknown(_) => format!("Raw=0x{:08X}", request.0),
+    };
+    format!(
+        "RDO: {prefix}PDO#{position} ({}) @ {operating_point}",
+        format_source_pdo(pdo)
+    )
+}
+
+fn format_vdm(vdm: &VendorDefinedMessage, sop: PdSopType) -> Vec<String> {
+    let header = vdm.header;
+    let mut lines = vec![if header.is_structured() {
+        format!(
+            "VDM: SVID=0x{:04X} {:?} {:?} (pos={})",
+            header.svid(),
+            header.command(),
+            header.command_type(),
+            header.object_position()
+        )
+    } else {
+        format!("Unstructured VDM: SVID=0x{:04X}", header.svid())
+    }];
+
+    match vdm.content(sop) {
+        VdmContent::Empty => {}
+        VdmContent::DiscoverSvids(svids) => lines.push(format!(
+            "SVIDs: {}",
+            svids
+                .iter()
+                .map(|svid| format!("0x{svid:04X}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        VdmContent::DisplayPortStatus(status) => lines.push(format!(
+            "DP Status: connected={:?}, enabled={}, HPD={}, IRQ_HPD={}",
+            status.connected(),
+            status.enabled(),
+            status.hpd_state(),
+            status.irq_hpd()
+        )),
+        VdmContent::DisplayPortConfigure(configure) => lines.push(format!(
+            "DP Configure: {:?}, pin assignment {:?}",
+            configure.configuration(),
+            configure.pin_assignment()
+        )),
+        content => lines.push(format!("{content:?}")),
+    }
+    lines
+}
+
+/// Bit fields of a message header; SOP'/SOP'' carry the Cable Plug flag instead of the port roles.
+pub fn header_fields(sop: PdSopType, header: &PdMessageHeader) -> Vec<PdField> {
+    let mut fields = vec![PdField::new(
+        "Message Type",
+        format!("{} (0x{:02X})", message_type_name(header.kind()), header.message_type()),
+    )];
+    if sop.is_cable_plug() {
+        fields.push(PdField::new(
+            "Cable Plug",
+            if header.port_power_role() { "Cable" } else { "Port" },
+        ));
+    } else {
+        fields.push(PdField::new("Port Data Role", format!("{:?}", data_role(header))));
+        fields.push(PdField::new("Port Power Role", format!("{:?}", power_role(header))));
+    }
+    fields.extend([
+        PdField::new(
+            "Specification Revision",
+            revision_name(PdSpecRevision::from_primitive(header.spec_revision())),
+        ),
+        PdField::new("Message ID", header.message_id()),
+        PdField::new("Number of Data Objects", header.num_data_objects()),
+        PdField::new("Extended", yes_no(header.extended())),
+    ]);
+    fields
+}
+
+/// Bit fields of a source PDO.
+pub fn source_pdo_fields(pdo: &SourcePdo) -> Vec<PdField> {
+    match pdo {
+        SourcePdo::FixedSupply(fixed) => vec![
+            PdField::new("Type", "Fixed Supply"),
+            PdField::new("Dual-Role Power", yes_no(fixed.dual_role_power())),
+            PdField::new("USB Suspend Supported", yes_no(fixed.usb_suspend_supported())),
+            PdField::new("Unconstrained Power", yes_no(fixed.unconstrained_power())),
+            PdField::new("USB Communications Capable", yes_no(fixed.usb_communications_capable())),
+            PdField::new("Dual-Role Data", yes_no(fixed.dual_role_data())),
+            PdField::new(
+                "Unchunked Extended Messages",
+                yes_no(fixed.unchunked_extended_messages_supported()),
+            ),
+            PdField::new("EPR Mode Capable", yes_no(fixed.epr_mode_capable())),
+            PdField::new("Peak Current", fixed.peak_current()),
+            PdField::new("Voltage", voltage_value(fixed.voltage())),
+            PdField::new("Maximum Current", current_value(fixed.max_current())),
+        ],
+        SourcePdo::Battery(battery) => vec![
+            PdField::new("Type", "Battery"),
+            PdField::new("Maximum Voltage", voltage_value(battery.max_voltage())),
+            PdField::new("Minimum Voltage", voltage_value(battery.min_voltage())),
+            PdField::new("Maximum Power", power_value(battery.max_power())),
+        ],
+        SourcePdo::VariableSupply(variable) => vec![
+            PdField::new("Type", "Variable Supply"),
+            PdField::new("Maximum Voltage", voltage_value(variable.max_voltage())),
+            PdField::new("Minimum Voltage", voltage_value(variable.min_voltage())),
+            PdField::new("Maximum Current", current_value(variable.max_current())),
+        ],
+        SourcePdo::SprPps(pps) => vec![
+            PdField::new("Type", "SPR PPS APDO"),
+            PdField::new("PPS Power Limited", yes_no(pps.power_limited())),
+            PdField::new("Maximum Voltage", voltage_value(pps.max_voltage())),
+            PdField::new("Minimum Voltage", voltage_value(pps.min_voltage())),
+            PdField::new("Maximum Current", current_value(pps.max_current())),
+        ],
+        SourcePdo::SprAvs(avs) => vec![
+            PdField::new("Type", "SPR AVS APDO"),
+            PdField::new("Peak Current", avs.peak_current()),
+            PdField::new("Maximum Current 9-15 V", current_value(avs.max_current_15v())),
+            PdField::new("Maximum Current 15-20 V", current_value(avs.max_current_20v())),
+        ],
+        SourcePdo::EprAvs(avs) => vec![
+            PdField::new("Type", "EPR AVS APDO"),
+            PdField::new("Peak Current", avs.peak_current()),
+            PdField::new("Maximum Voltage", voltage_value(avs.max_voltage())),
+            PdField::new("Minimum Voltage", voltage_value(avs.min_voltage())),
+            PdField::new("PDP", power_value(avs.pdp())),
+        ],
+        SourcePdo::Unknown(0) => vec![PdField::new("Type", "Separator")],
+        SourcePdo::Unknown(_) => vec![PdField::new("Type", "Reserved")],
+    }
+}
+
+/// Bit fields of a Request Data Object; the operating point needs the requested PDO.
+pub fn request_fields(request: RequestDataObject, pdo: Option<&SourcePdo>) -> Vec<PdField> {
+    let mut fields = vec![
+        PdField::new("Object Position", request.object_position()),
+        PdField::new("Capability Mismatch", yes_no(request.capability_mismatch())),
+        PdField::new(
+            "USB Communications Capable",
+            yes_no(request.usb_communications_capable()),
+        ),
+        PdField::new("No USB Suspend", yes_no(request.no_usb_suspend())),
+        PdField::new(
+            "Unchunked Extended Messages",
+            yes_no(request.unchunked_extended_messages_supported()),
+        ),
+        PdField::new("EPR Mode Capable", yes_no(request.epr_mode_capable())),
+    ];
+    match pdo {
+        Some(SourcePdo::FixedSupply(_) | SourcePdo::VariableSupply(_)) => fields.extend([
+            PdField::new("Operating Current", current_value(request.operating_current())),
+            PdField::new(
+                "Maximum Operating Current",
+                current_value(request.max_operating_current()),
+            ),
+        ]),
+        Some(SourcePdo::Battery(_)) => fields.extend([
+            PdField::new("Operating Power", power_value(request.operating_power())),
+            PdField::new("Maximum Operating Power", power_value(request.max_operating_power())),
+        ]),
+        Some(pdo @ (SourcePdo::SprPps(_) | SourcePdo::SprAvs(_) | SourcePdo::EprAvs(_))) => {
+            let (voltage, current) = request.operating_point(pdo);
+            fields.extend([
+                PdField::new("Output Voltage", voltage_value(voltage)),
+                PdField::new("Operating Current", current_value(current)),
+            ]);
+        }
+        Some(SourcePdo::Unknown(_)) | None => {}
+    }
+    fields
+}
+
+fn header_field(sop: PdSopType, header: &PdMessageHeader) -> PdField {
+    PdField::new("Header", format!("0x{:04X}", u16::from_le_bytes(header.into_bytes())))
+        .with_children(header_fields(sop, header))
+}
+
+fn extended_header_field(header: &PdExtendedHeader) -> PdField {
+    PdField::new(
+        "Extended Header",
+        format!("0x{:04X}", u16::from_le_bytes(header.into_bytes())),
+    )
+    .with_children(vec![
+        PdField::new("Chunked", yes_no(header.chunked())),
+        PdField::new("Chunk Number", header.chunk_number()),
+        PdField::new("Request Chunk", yes_no(header.request_chunk())),
+        PdField::new("Data Size", header.data_size()),
+    ])
+}
+
+fn pdo_fields(pdos: &[SourcePdo]) -> Vec<PdField> {
+    pdos.iter()
+        .enumerate()
+        .map(|(index, pdo)| {
+            PdField::new(format!("PDO {}", index + 1), hex_u32(pdo.raw())).with_children(source_pdo_fields(pdo))
+        })
+        .collect()
+}
+
+fn request_field(request: RequestDataObject, pdo: Option<&SourcePdo>) -> PdField {
+    PdField::new("RDO", hex_u32(request.0)).with_children(request_fields(request, pdo))
+}
+
+fn epr_mode_field(mode: EprModeDataObject) -> PdField {
+    let mut children = vec![
+        PdField::new("Action", format!("{:?}", mode.action())),
+        PdField::new("Data", mode.data()),
+    ];
+    if let Some(reason) = mode.failure_reason() {
+        children.push(PdField::new("Failure Reason", format!("{reason:?}")));
+    }
+    PdField::new("EPRMDO", hex_u32(mode.0)).with_children(children)
+}
+
+fn vdm_fields(vdm: &VendorDefinedMessage) -> Vec<PdField> {
+    let header = vdm.header;
+    let mut children = vec![
+        PdField::new("SVID", format!("0x{:04X}", header.svid())),
+        PdField::new("Structured", yes_no(header.is_structured())),
+    ];
+    if header.is_structured() {
+        children.extend([
+            PdField::new(
+                "Version",
+                format!("{}.{}", header.version_major(), header.version_minor()),
+            ),
+            PdField::new("Object Position", header.object_position()),
+            PdField::new("Command Type", format!("{:?}", header.command_type())),
+            PdField::new("Command", format!("{:?}", header.command())),
+        ]);
+    } else {
+        children.push(PdField::new("Vendor Use", format!("0x{:04X}", header.vendor_use())));
+    }
+
+    let mut fields = vec![PdField::new("VDM Header", hex_u32(header.0)).with_children(children)];
+    fields.extend(
+        vdm.objects
+            .iter()
+            .enumerate()
+            .map(|(index, &object)| PdField::new(format!("VDO {}", index + 1), hex_u32(object))),
+    );
+    fields
+}
+
+/// Message type name without its control/data/extended class.
+fn message_type_name(message_type: PdMessageType) -> String {
+    match message_type {
+        PdMessageType::Control(message_type) => format!("{message_type:?}"),
+        PdMessageType::Data(message_type) => format!("{message_type:?}"),
+        PdMessageType::Extended(message_type) => format!("{message_type:?}"),
+    }
+}
+
+fn requested_pdo(pdos: &[SourcePdo], request: RequestDataObject) -> Option<&SourcePdo> {
+    pdos.get(usize::from(request.object_position()).checked_sub(1)?)
+}
+
+fn power_role(header: &PdMessageHeader) -> PdPowerRole {
+    if header.port_power_role() {
+        PdPowerRole::Source
+    } else {
+        PdPowerRole::Sink
+    }
+}
+
+fn data_role(header: &PdMessageHeader) -> PdDataRole {
+    if header.port_data_role() {
+        PdDataRole::Dfp
+    } else {
+        PdDataRole::Ufp
+    }
+}
+
+fn revision_name(revision: PdSpecRevision) -> String {
+    match revision {
+        PdSpecRevision::Revision1 => "1.0".to_string(),
+        PdSpecRevision::Revision2 => "2.0".to_string(),
+        PdSpecRevision::Revision3 => "3.x".to_string(),
+        PdSpecRevision::Reserved(value) => format!("Reserved ({value})"),
+    }
+}
+
+fn sop_field(sop: PdSopType) -> PdField {
+    PdField::new("SOP*", format!("{sop:?}"))
+}
+
+fn hex_field(name: &str, bytes: &[u8]) -> PdField {
+    PdField::new(name, format!("{bytes:02X?}"))
+}
+
+fn hex_u32(value: u32) -> String {
+    format!("0x{value:08X}")
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+fn volts(value: ElectricPotential) -> f64 {
+    value.get::<volt>()
+}
+
+fn amps(value: ElectricCurrent) -> f64 {
+    value.get::<ampere>()
+}
+
+fn watts(value: Power) -> f64 {
+    value.get::<watt>()
+}
+
+fn voltage_value(value: ElectricPotential) -> String {
+    format!("{:.2} V", volts(value))
+}
+
+fn current_value(value: ElectricCurrent) -> String {
+    format!("{:.2} A", amps(value))
+}
+
+fn power_value(value: Power) -> String {
+    format!("{:.2} W", watts(value))
+}
@@ -0,0 +1,309 @@
+//! Source Power Data Objects and Request Data Objects.
+//!
+//! The `usbpd` decoder interprets Request messages against its own cached
+//! capabilities. Analyzers that follow a contract across a capture decode the
+//! raw 32-bit objects here instead, so they work with or without that feature.
+
+use uom::si::electric_current::milliampere;
+use uom::si::electric_potential::millivolt;
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Power};
+use uom::si::power::milliwatt;
+
+use crate::pd_vdm::{bit, bits};
+
+fn millivolts(value: u32, unit_mv: u32) -> ElectricPotential {
+    ElectricPotential::new::<millivolt>(f64::from(value * unit_mv))
+}
+
+fn milliamps(value: u32, unit_ma: u32) -> ElectricCurrent {
+    ElectricCurrent::new::<milliampere>(f64::from(value * unit_ma))
+}
+
+/// A Power Data Object from Source_Capabilities or EPR_Source_Capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum SourcePdo {
+    FixedSupply(FixedSupplyPdo),
+    Battery(BatteryPdo),
+    VariableSupply(VariableSupplyPdo),
+    SprPps(SprPpsApdo),
+    SprAvs(SprAvsApdo),
+    EprAvs(EprAvsApdo),
+    /// Reserved APDO type, or a zero padding object of EPR capabilities.
+    Unknown(u32),
+}
+
+impl SourcePdo {
+    pub fn from_raw(raw: u32) -> Self {
+        if raw == 0 {
+            return Self::Unknown(raw);
+        }
+        match (bits(raw, 30, 2), bits(raw, 28, 2)) {
+            (0b00, _) => Self::FixedSupply(FixedSupplyPdo(raw)),
+            (0b01, _) => Self::Battery(BatteryPdo(raw)),
+            (0b10, _) => Self::VariableSupply(VariableSupplyPdo(raw)),
+            (_, 0b00) => Self::SprPps(SprPpsApdo(raw)),
+            (_, 0b01) => Self::EprAvs(EprAvsApdo(raw)),
+            (_, 0b10) => Self::SprAvs(SprAvsApdo(raw)),
+            _ => Self::Unknown(raw),
+        }
+    }
+
+    pub fn raw(&self) -> u32 {
+        match *self {
+            Self::FixedSupply(FixedSupplyPdo(raw))
+            | Self::Battery(BatteryPdo(raw))
+            | Self::VariableSupply(VariableSupplyPdo(raw))
+            | Self::SprPps(SprPpsApdo(raw))
+            | Self::SprAvs(SprAvsApdo(raw))
+            | Self::EprAvs(EprAvsApdo(raw))
+            | Self::Unknown(raw) => raw,
+        }
+    }
+
+    /// Whether a sink requests this object with a programmable output voltage.
+    pub fn is_programmable(&self) -> bool {
+        matches!(self, Self::SprPps(_) | Self::SprAvs(_) | Self::EprAvs(_))
+    }
+}
+
+/// Fixed Supply PDO.
+///
+/// The capability flags are only meaningful in the first, vSafe5V, PDO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct FixedSupplyPdo(pub u32);
+
+impl FixedSupplyPdo {
+    pub fn dual_role_power(&self) -> bool {
+        bit(self.0, 29)
+    }
+
+    pub fn usb_suspend_supported(&self) -> bool {
+        bit(self.0, 28)
+    }
+
+    pub fn unconstrained_power(&self) -> bool {
+        bit(self.0, 27)
+    }
+
+    pub fn usb_communications_capable(&self) -> bool {
+        bit(self.0, 26)
+    }
+
+    pub fn dual_role_data(&self) -> bool {
+        bit(self.0, 25)
+    }
+
+    pub fn unchunked_extended_messages_supported(&self) -> bool {
+        bit(self.0, 24)
+    }
+
+    pub fn epr_mode_capable(&self) -> bool {
+        bit(self.0, 23)
+    }
+
+    pub fn peak_current(&self) -> u8 {
+        bits(self.0, 20, 2) as u8
+    }
+
+    pub fn voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 10, 10), 50)
+    }
+
+    pub fn max_current(&self) -> ElectricCurrent {
+        milliamps(bits(self.0, 0, 10), 10)
+    }
+}
+
+/// Battery Supply PDO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct BatteryPdo(pub u32);
+
+impl BatteryPdo {
+    pub fn max_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 20, 10), 50)
+    }
+
+    pub fn min_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 10, 10), 50)
+    }
+
+    pub fn max_power(&self) -> Power {
+        Power::new::<milliwatt>(f64::from(bits(self.0, 0, 10) * 250))
+    }
+}
+
+/// Variable Supply (non-battery) PDO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct VariableSupplyPdo(pub u32);
+
+impl VariableSupplyPdo {
+    pub fn max_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 20, 10), 50)
+    }
+
+    pub fn min_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 10, 10), 50)
+    }
+
+    pub fn max_current(&self) -> ElectricCurrent {
+        milliamps(bits(self.0, 0, 10), 10)
+    }
+}
+
+/// SPR Programmable Power Supply APDO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct SprPpsApdo(pub u32);
+
+impl SprPpsApdo {
+    pub fn power_limited(&self) -> bool {
+        bit(self.0, 27)
+    }
+
+    pub fn max_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 17, 8), 100)
+    }
+
+    pub fn min_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 8, 8), 100)
+    }
+
+    pub fn max_current(&self) -> ElectricCurrent {
+        milliamps(bits(self.0, 0, 7), 50)
+    }
+}
+
+/// SPR Adjustable Voltage Supply APDO, offering 9 to 20 V.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct SprAvsApdo(pub u32);
+
+impl SprAvsApdo {
+    pub fn peak_current(&self) -> u8 {
+        bits(self.0, 26, 2) as u8
+    }
+
+    /// Maximum current between 9 and 15 V.
+    pub fn max_current_15v(&self) -> ElectricCurrent {
+        milliamps(bits(self.0, 10, 10), 10)
+    }
+
+    /// Maximum current between 15 and 20 V.
+    pub fn max_current_20v(&self) -> ElectricCurrent {
+        milliamps(bits(self.0, 0, 10), 10)
+    }
+}
+
+/// EPR Adjustable Voltage Supply APDO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct EprAvsApdo(pub u32);
+
+impl EprAvsApdo {
+    pub fn peak_current(&self) -> u8 {
+        bits(self.0, 26, 2) as u8
+    }
+
+    pub fn max_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 17, 9), 100)
+    }
+
+    pub fn min_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 8, 8), 100)
+    }
+
+    pub fn pdp(&self) -> Power {
+        Power::new::<milliwatt>(f64::from(bits(self.0, 0, 8) * 1000))
+    }
+}
+
+/// Request Data Object of a Request or EPR_Request message.
+///
+/// The layout of the operating point depends on the type of the requested
+/// PDO, which the RDO itself does not encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct RequestDataObject(pub u32);
+
+impl RequestDataObject {
+    /// One-based position of the requested PDO in the source capabilities.
+    pub fn object_position(&self) -> u8 {
+        bits(self.0, 28, 4) as u8
+    }
+
+    pub fn capability_mismatch(&self) -> bool {
+        bit(self.0, 26)
+    }
+
+    pub fn usb_communications_capable(&self) -> bool {
+        bit(self.0, 25)
+    }
+
+    pub fn no_usb_suspend(&self) -> bool {
+        bit(self.0, 24)
+    }
+
+    pub fn unchunked_extended_messages_supported(&self) -> bool {
+        bit(self.0, 23)
+    }
+
+    pub fn epr_mode_capable(&self) -> bool {
+        bit(self.0, 22)
+    }
+
+    /// Operating current requested from a Fixed or Variable Supply PDO.
+    pub fn operating_current(&self) -> ElectricCurrent {
+        milliamps(bits(self.0, 10, 10), 10)
+    }
+
+    /// Maximum operating current requested from a Fixed or Variable Supply PDO.
+    pub fn max_operating_current(&self) -> ElectricCurrent {
+        milliamps(bits(self.0, 0, 10), 10)
+    }
+
+    /// Output voltage requested from a PPS APDO, in 20 mV units.
+    pub fn pps_output_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 9, 12), 20)
+    }
+
+    /// Output voltage requested from an AVS APDO, in 25 mV units.
+    pub fn avs_output_voltage(&self) -> ElectricPotential {
+        millivolts(bits(self.0, 9, 12), 25)
+    }
+
+    /// Operating current requested from a PPS or AVS APDO, in 50 mA units.
+    pub fn programmable_operating_current(&self) -> ElectricCurrent {
+        milliamps(bits(self.0, 0, 7), 50)
+    }
+
+    /// Operating power requested from a Battery PDO, in 250 mW units.
+    pub fn operating_power(&self) -> Power {
+        Power::new::<milliwatt>(f64::from(bits(self.0, 10, 10) * 250))
+    }
+
+    /// Maximum operating power requested from a Battery PDO.
+    pub fn max_operating_power(&self) -> Power {
+        Power::new::<milliwatt>(f64::from(bits(self.0, 0, 10) * 250))
+    }
+
+    /// Voltage and operating current of this request against the requested PDO.
+    ///
+    /// Battery requests carry power rather than current and report zero
+    /// current; unknown PDOs report zero for both.
+    pub fn operating_point(&self, pdo: &SourcePdo) -> (ElectricPotential, ElectricCurrent) {
+        match pdo {
+            SourcePdo::FixedSupply(fixed) => (fixed.voltage(), self.operating_current()),
+            SourcePdo::VariableSupply(variable) => (variable.max_voltage(), self.operating_current()),
+            SourcePdo::SprPps(_) => (self.pps_output_voltage(), self.programmable_operating_current()),
+            SourcePdo::SprAvs(_) | SourcePdo::EprAvs(_) => {
+                (self.avs_output_voltage(), self.programmable_operating_current())
+            }
+            SourcePdo::Battery(battery) => (battery.max_voltage(), milliamps(0, 0)),
+            SourcePdo::Unknown(_) => (millivolts(0, 0), milliamps(0, 0)),
+        }
+    }
+}
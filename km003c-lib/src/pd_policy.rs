@@ -0,0 +1,530 @@
+//! Policy-engine state reconstruction.
+//!
+//! Individual messages say little about the session they belong to.
+//! [`PolicyTracker`] replays a KM003C capture through the contract
+//! negotiation, role swap and reset sequences of the USB PD policy engine and
+//! records every change of the resulting [`PolicyState`], so that the
+//! contract in force at any point of the capture can be looked up.
+//!
+//! Hard Reset and Cable Reset are signalling sequences rather than messages
+//! and do not appear in KM003C captures. They are inferred when a port's
+//! MessageID counter restarts at zero without a Soft_Reset or a wrap-around.
+
+use std::collections::HashMap;
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+
+use crate::pd::{PdEvent, PdEventData};
+use crate::pd_pdo::{RequestDataObject, SourcePdo};
+use crate::pd_wire::{PdControlMessageType, PdDataMessageType, PdMessageType, PdSopType, PdWireMessage};
+
+/// Highest MessageID before the three-bit counter wraps to zero.
+const MAX_MESSAGE_ID: u8 = 7;
+
+/// Specification Revision field of a message header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum PdSpecRevision {
+    Revision1 = 0,
+    Revision2 = 1,
+    Revision3 = 2,
+    #[num_enum(catch_all)]
+    Reserved(u8),
+}
+
+/// Power role of a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PdPowerRole {
+    Sink,
+    Source,
+}
+
+impl PdPowerRole {
+    fn opposite(self) -> Self {
+        match self {
+            Self::Sink => Self::Source,
+            Self::Source => Self::Sink,
+        }
+    }
+}
+
+/// Data role of a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PdDataRole {
+    Ufp,
+    Dfp,
+}
+
+impl PdDataRole {
+    fn opposite(self) -> Self {
+        match self {
+            Self::Ufp => Self::Dfp,
+            Self::Dfp => Self::Ufp,
+        }
+    }
+}
+
+/// Role swap negotiated with a control message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PdRoleSwap {
+    PowerRole,
+    FastRole,
+    DataRole,
+    Vconn,
+}
+
+/// An explicit contract: an accepted Request or EPR_Request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PdContract {
+    pub requested_at: Time,
+    pub accepted_at: Time,
+    /// Time of the PS_RDY that completed the transition.
+    pub ready_at: Option<Time>,
+    pub request: RequestDataObject,
+    /// Requested PDO, when the capabilities it refers to were captured.
+    pub pdo: Option<SourcePdo>,
+    pub epr: bool,
+    pub voltage: Option<ElectricPotential>,
+    pub current: Option<ElectricCurrent>,
+}
+
+/// Why an explicit contract ended without being replaced by a new Request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ContractEndReason {
+    SoftReset,
+    HardReset,
+    PowerRoleSwap,
+    Detach,
+}
+
+/// Reconstructed policy-engine state of the port partners.
+///
+/// Roles are described from the point of view of the current source, since a
+/// KM003C capture does not identify the physical port that sent a message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PolicyState {
+    pub attached: bool,
+    pub contract: Option<PdContract>,
+    /// Revision negotiated by the last accepted Request.
+    pub revision: Option<PdSpecRevision>,
+    /// Whether the port that was the sink at attach is now the source.
+    pub power_roles_swapped: bool,
+    /// Data role of the current source.
+    pub source_data_role: PdDataRole,
+    /// Power role of the port currently sourcing VCONN.
+    pub vconn_source: PdPowerRole,
+}
+
+impl Default for PolicyState {
+    fn default() -> Self {
+        Self {
+            attached: false,
+            contract: None,
+            revision: None,
+            power_roles_swapped: false,
+            source_data_role: PdDataRole::Dfp,
+            vconn_source: PdPowerRole::Source,
+        }
+    }
+}
+
+/// A change of policy-engine state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PolicyChange {
+    Attached,
+    Detached,
+    RevisionNegotiated(PdSpecRevision),
+    ContractAccepted(PdContract),
+    PowerReady(PdContract),
+    /// Reject, Wait or Not_Supported answering a Request.
+    RequestRefused {
+        response: PdControlMessageType,
+    },
+    GotoMin,
+    ContractEnded {
+        contract: PdContract,
+        reason: ContractEndReason,
+    },
+    SwapCompleted(PdRoleSwap),
+    /// Reject, Wait or Not_Supported answering a swap request.
+    SwapRefused {
+        swap: PdRoleSwap,
+        response: PdControlMessageType,
+    },
+    SoftReset {
+        sop: PdSopType,
+    },
+    /// Inferred from the source restarting its MessageID counter with Source_Capabilities.
+    HardReset,
+    /// Inferred from a port restarting its MessageID counter towards a cable plug.
+    CableReset {
+        sop: PdSopType,
+    },
+}
+
+/// A policy-engine state change with the state it produced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PolicyEvent {
+    pub timestamp: Time,
+    pub change: PolicyChange,
+    pub state: PolicyState,
+}
+
+/// Atomic message sequence in progress.
+#[derive(Debug, Clone, Copy)]
+enum PendingSequence {
+    Request {
+        requested_at: Time,
+        request: RequestDataObject,
+        pdo: Option<SourcePdo>,
+        epr: bool,
+        revision: PdSpecRevision,
+    },
+    Transition,
+    Swap {
+        swap: PdRoleSwap,
+        accepted: bool,
+        power_ready: u8,
+    },
+}
+
+/// Reconstructs contract, role and reset state from a KM003C capture.
+///
+/// Connection changes reset the state but keep the event log.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyTracker {
+    state: PolicyState,
+    pending: Option<PendingSequence>,
+    source_capabilities: Vec<SourcePdo>,
+    /// Last MessageID per SOP* channel and sender, as identified by `sender`.
+    message_ids: HashMap<(PdSopType, bool), u8>,
+    events: Vec<PolicyEvent>,
+}
+
+impl PolicyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clear the event log and all connection state.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn events(&self) -> &[PolicyEvent] {
+        &self.events
+    }
+
+    pub fn state(&self) -> &PolicyState {
+        &self.state
+    }
+
+    /// State in force at `timestamp`, from the last change at or before it.
+    pub fn state_at(&self, timestamp: Time) -> PolicyState {
+        let index = self.events.partition_point(|event| event.timestamp <= timestamp);
+        index
+            .checked_sub(1)
+            .map(|index| self.events[index].state)
+            .unwrap_or_default()
+    }
+
+    /// Explicit contract in force at `timestamp`.
+    pub fn contract_at(&self, timestamp: Time) -> Option<PdContract> {
+        self.state_at(timestamp).contract
+    }
+
+    /// Process one KM003C PD event, returning the state changes it caused.
+    pub fn process_event(&mut self, event: &PdEvent) -> &[PolicyEvent] {
+        let start = self.events.len();
+        match &event.data {
+            PdEventData::Connect(()) => {
+                self.end_connection(event.timestamp);
+                self.state.attached = true;
+                self.push(event.timestamp, PolicyChange::Attached);
+            }
+            PdEventData::Disconnect(()) => {
+                self.end_connection(event.timestamp);
+                self.push(event.timestamp, PolicyChange::Detached);
+            }
+            PdEventData::PdMessage { sop, wire_data } => {
+                if let Ok(message) = PdWireMessage::from_bytes(wire_data) {
+                    self.process_message(event.timestamp, PdSopType::from(*sop), &message);
+                }
+            }
+        }
+        &self.events[start..]
+    }
+
+    fn push(&mut self, timestamp: Time, change: PolicyChange) {
+        self.events.push(PolicyEvent {
+            timestamp,
+            change,
+            state: self.state,
+        });
+    }
+
+    fn end_contract(&mut self, timestamp: Time, reason: ContractEndReason) {
+        if let Some(contract) = self.state.contract.take() {
+            self.push(timestamp, PolicyChange::ContractEnded { contract, reason });
+        }
+    }
+
+    fn end_connection(&mut self, timestamp: Time) {
+        self.end_contract(timestamp, ContractEndReason::Detach);
+        self.state = PolicyState::default();
+        self.pending = None;
+        self.source_capabilities.clear();
+        self.message_ids.clear();
+    }
+
+    /// Sender of a message: the cable plug flag on SOP'/SOP'', otherwise
+    /// whether the port was the source at attach.
+    fn sender(&self, sop: PdSopType, message: &PdWireMessage) -> bool {
+        if sop.is_cable_plug() {
+            message.header.is_from_cable_plug()
+        } else {
+            message.header.port_power_role() != self.state.power_roles_swapped
+        }
+    }
+
+    /// Whether the sender's MessageID counter restarted at zero with this message.
+    fn restarted_counter(&self, sop: PdSopType, message: &PdWireMessage) -> bool {
+        message.header.message_id() == 0
+            && self
+                .message_ids
+                .get(&(sop, self.sender(sop, message)))
+                .is_some_and(|&previous| previous != 0 && previous != MAX_MESSAGE_ID)
+    }
+
+    fn process_message(&mut self, timestamp: Time, sop: PdSopType, message: &PdWireMessage) {
+        let message_type = message.message_type();
+        if message_type == PdMessageType::Control(PdControlMessageType::GoodCrc) {
+            return;
+        }
+
+        if message_type == PdMessageType::Control(PdControlMessageType::SoftReset) {
+            self.message_ids.retain(|(channel, _), _| *channel != sop);
+            if sop == PdSopType::Sop {
+                self.pending = None;
+                self.end_contract(timestamp, ContractEndReason::SoftReset);
+            }
+            self.push(timestamp, PolicyChange::SoftReset { sop });
+        } else if sop.is_cable_plug() {
+            if self.restarted_counter(sop, message) && !message.header.is_from_cable_plug() {
+                self.message_ids.retain(|(channel, _), _| *channel != sop);
+                self.push(timestamp, PolicyChange::CableReset { sop });
+            }
+        } else if sop == PdSopType::Sop {
+            self.process_port_message(timestamp, message);
+        }
+
+        // Recorded after processing, so that the PS_RDY completing a power
+        // role swap is attributed to the new source.
+        self.message_ids
+            .insert((sop, self.sender(sop, message)), message.header.message_id());
+    }
+
+    fn process_port_message(&mut self, timestamp: Time, message: &PdWireMessage) {
+        let message_type = message.message_type();
+        let restarted = self.restarted_counter(PdSopType::Sop, message);
+
+        let from_source = message.header.port_power_role();
+        if restarted && message_type == PdMessageType::Data(PdDataMessageType::SourceCapabilities) && from_source {
+            self.hard_reset(timestamp);
+        }
+        if from_source {
+            self.state.source_data_role = if message.header.port_data_role() {
+                PdDataRole::Dfp
+            } else {
+                PdDataRole::Ufp
+            };
+        }
+
+        match message_type {
+            PdMessageType::Data(PdDataMessageType::SourceCapabilities) => {
+                self.source_capabilities = message.data_objects().map(SourcePdo::from_raw).collect();
+            }
+            PdMessageType::Data(PdDataMessageType::Request) => {
+                let request = message.data_objects().next().map(RequestDataObject);
+                self.pending = request.map(|request| PendingSequence::Request {
+                    requested_at: timestamp,
+                    request,
+                    pdo: usize::from(request.object_position())
+                        .checked_sub(1)
+                        .and_then(|index| self.source_capabilities.get(index))
+                        .copied(),
+                    epr: false,
+                    revision: PdSpecRevision::from(message.header.spec_revision()),
+                });
+            }
+            PdMessageType::Data(PdDataMessageType::EprRequest) => {
+                let mut objects = message.data_objects();
+                self.pending = objects
+                    .next()
+                    .zip(objects.next())
+                    .map(|(request, pdo)| PendingSequence::Request {
+                        requested_at: timestamp,
+                        request: RequestDataObject(request),
+                        pdo: Some(SourcePdo::from_raw(pdo)),
+                        epr: true,
+                        revision: PdSpecRevision::from(message.header.spec_revision()),
+                    });
+            }
+            PdMessageType::Control(control) => self.process_control(timestamp, control, message),
+            _ => {}
+        }
+    }
+
+    fn process_control(&mut self, timestamp: Time, control: PdControlMessageType, message: &PdWireMessage) {
+        let swap = match control {
+            PdControlMessageType::PrSwap => Some(PdRoleSwap::PowerRole),
+            PdControlMessageType::FrSwap => Some(PdRoleSwap::FastRole),
+            PdControlMessageType::DrSwap => Some(PdRoleSwap::DataRole),
+            PdControlMessageType::VconnSwap => Some(PdRoleSwap::Vconn),
+            _ => None,
+        };
+        if let Some(swap) = swap {
+            self.pending = Some(PendingSequence::Swap {
+                swap,
+                accepted: false,
+                power_ready: 0,
+            });
+            return;
+        }
+
+        match (control, self.pending) {
+            (PdControlMessageType::GotoMin, _) => {
+                self.pending = Some(PendingSequence::Transition);
+                self.push(timestamp, PolicyChange::GotoMin);
+            }
+            (
+                PdControlMessageType::Accept,
+                Some(PendingSequence::Request {
+                    requested_at,
+                    request,
+                    pdo,
+                    epr,
+                    revision,
+                }),
+            ) => {
+                if self.state.revision != Some(revision) {
+                    self.state.revision = Some(revision);
+                    self.push(timestamp, PolicyChange::RevisionNegotiated(revision));
+                }
+                let (voltage, current) = pdo.map(|pdo| request.operating_point(&pdo)).unzip();
+                let contract = PdContract {
+                    requested_at,
+                    accepted_at: timestamp,
+                    ready_at: None,
+                    request,
+                    pdo,
+                    epr,
+                    voltage,
+                    current,
+                };
+                self.state.contract = Some(contract);
+                self.pending = Some(PendingSequence::Transition);
+                self.push(timestamp, PolicyChange::ContractAccepted(contract));
+            }
+            (
+                PdControlMessageType::Reject | PdControlMessageType::Wait | PdControlMessageType::NotSupported,
+                Some(PendingSequence::Request { .. }),
+            ) => {
+                self.pending = None;
+                self.push(timestamp, PolicyChange::RequestRefused { response: control });
+            }
+            (PdControlMessageType::PsRdy, Some(PendingSequence::Transition)) => {
+                self.pending = None;
+                if let Some(contract) = &mut self.state.contract {
+                    contract.ready_at = Some(timestamp);
+                    let contract = *contract;
+                    self.push(timestamp, PolicyChange::PowerReady(contract));
+                }
+            }
+            (PdControlMessageType::Accept, Some(PendingSequence::Swap { swap, .. })) => {
+                if swap == PdRoleSwap::DataRole {
+                    self.pending = None;
+                    self.state.source_data_role = self.state.source_data_role.opposite();
+                    self.push(timestamp, PolicyChange::SwapCompleted(swap));
+                } else {
+                    self.pending = Some(PendingSequence::Swap {
+                        swap,
+                        accepted: true,
+                        power_ready: 0,
+                    });
+                }
+            }
+            (
+                PdControlMessageType::Reject | PdControlMessageType::Wait | PdControlMessageType::NotSupported,
+                Some(PendingSequence::Swap { swap, .. }),
+            ) => {
+                self.pending = None;
+                self.push(
+                    timestamp,
+                    PolicyChange::SwapRefused {
+                        swap,
+                        response: control,
+                    },
+                );
+            }
+            (
+                PdControlMessageType::PsRdy,
+                Some(PendingSequence::Swap {
+                    swap,
+                    accepted: true,
+                    power_ready,
+                }),
+            ) => match swap {
+                PdRoleSwap::Vconn => {
+                    self.pending = None;
+                    self.state.vconn_source = if message.header.port_power_role() {
+                        PdPowerRole::Source
+                    } else {
+                        PdPowerRole::Sink
+                    };
+                    self.push(timestamp, PolicyChange::SwapCompleted(swap));
+                }
+                // The old source reports VBUS off, then the new source reports VBUS on.
+                _ if power_ready == 0 => {
+                    self.pending = Some(PendingSequence::Swap {
+                        swap,
+                        accepted: true,
+                        power_ready: 1,
+                    });
+                }
+                // The new source's PS_RDY header already gave its data role.
+                _ => {
+                    self.pending = None;
+                    self.end_contract(timestamp, ContractEndReason::PowerRoleSwap);
+                    self.state.power_roles_swapped = !self.state.power_roles_swapped;
+                    self.state.vconn_source = self.state.vconn_source.opposite();
+                    self.push(timestamp, PolicyChange::SwapCompleted(swap));
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn hard_reset(&mut self, timestamp: Time) {
+        self.end_contract(timestamp, ContractEndReason::HardReset);
+        self.pending = None;
+        self.state = PolicyState {
+            attached: self.state.attached,
+            revision: self.state.revision,
+            ..PolicyState::default()
+        };
+        // Both protocol layers restart.
+        self.message_ids.clear();
+        self.push(timestamp, PolicyChange::HardReset);
+    }
+}
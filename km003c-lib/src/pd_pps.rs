@@ -0,0 +1,353 @@
+//! PPS and AVS session tracking.
+//!
+//! A sink on a Programmable Power Supply contract walks the output voltage by
+//! sending new Requests against the PPS APDO, and must repeat its Request at
+//! least every ten seconds to keep the contract alive. [`PpsTracker`] turns a
+//! KM003C capture into a timeline of those requests, the source's responses,
+//! PPS_Status reports and the VBUS measured by the KM003C itself.
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+use uom::si::electric_current::milliampere;
+use uom::si::electric_potential::millivolt;
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use uom::si::time::second;
+
+use crate::pd::{PdEvent, PdEventData, PdStatus};
+use crate::pd_pdo::{RequestDataObject, SourcePdo};
+use crate::pd_wire::{
+    PdControlMessageType, PdDataMessageType, PdExtendedMessageType, PdMessageType, PdSopType, PdWireMessage,
+};
+
+/// Longest interval a PPS sink may leave between Requests (tPPSRequest).
+pub const PPS_REQUEST_INTERVAL_MAX_S: f64 = 10.0;
+
+/// Size of the PPS Status Data Block.
+pub const PPS_STATUS_SIZE: usize = 4;
+
+/// Kind of programmable supply a Request selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ProgrammableSupplyKind {
+    SprPps,
+    SprAvs,
+    EprAvs,
+}
+
+/// A Request or EPR_Request for a PPS or AVS APDO.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ProgrammableRequest {
+    pub timestamp: Time,
+    pub object_position: u8,
+    pub kind: ProgrammableSupplyKind,
+    pub requested_voltage: ElectricPotential,
+    pub requested_current: ElectricCurrent,
+    /// Time since the previous programmable Request of the connection.
+    pub interval: Option<Time>,
+    /// Same operating point as the previous Request: a keep-alive re-request.
+    pub repeated: bool,
+}
+
+/// Temperature flag of a PPS_Status report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum PpsTemperatureFlag {
+    #[num_enum(default)]
+    NotSupported = 0,
+    Normal = 1,
+    Warning = 2,
+    OverTemperature = 3,
+}
+
+/// PPS Status Data Block reported by the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PpsStatus {
+    /// Output voltage, or `None` when the source does not report it.
+    pub output_voltage: Option<ElectricPotential>,
+    /// Output current, or `None` when the source does not report it.
+    pub output_current: Option<ElectricCurrent>,
+    pub temperature: PpsTemperatureFlag,
+    /// Whether the source is operating in current limit mode.
+    pub current_limited: bool,
+}
+
+impl PpsStatus {
+    /// Parse the data block of a PPS_Status extended message.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let block: &[u8; PPS_STATUS_SIZE] = bytes.get(..PPS_STATUS_SIZE)?.try_into().ok()?;
+        let voltage = u16::from_le_bytes([block[0], block[1]]);
+        let current = block[2];
+        let flags = block[3];
+
+        Some(Self {
+            output_voltage: (voltage != 0xffff).then(|| ElectricPotential::new::<millivolt>(f64::from(voltage) * 20.0)),
+            output_current: (current != 0xff).then(|| ElectricCurrent::new::<milliampere>(f64::from(current) * 50.0)),
+            temperature: PpsTemperatureFlag::from_primitive((flags >> 1) & 0x3),
+            current_limited: flags & 0x08 != 0,
+        })
+    }
+}
+
+/// One entry of a programmable-supply timeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PpsTimelineEntry {
+    Request(ProgrammableRequest),
+    /// Accept, Reject, Wait or Not_Supported answering the last programmable Request.
+    Response {
+        timestamp: Time,
+        response: PdControlMessageType,
+    },
+    /// PS_RDY after an accepted programmable Request.
+    PowerReady {
+        timestamp: Time,
+    },
+    Status {
+        timestamp: Time,
+        status: PpsStatus,
+    },
+    /// VBUS and IBUS measured by the KM003C during a programmable contract.
+    Measurement {
+        timestamp: Time,
+        vbus: ElectricPotential,
+        ibus: ElectricCurrent,
+    },
+    /// No PPS Request within the keep-alive interval.
+    MissedKeepAlive {
+        /// Time at which the gap was first observed.
+        timestamp: Time,
+        last_request: Time,
+        elapsed: Time,
+    },
+}
+
+impl PpsTimelineEntry {
+    pub fn timestamp(&self) -> Time {
+        match *self {
+            Self::Request(request) => request.timestamp,
+            Self::Response { timestamp, .. }
+            | Self::PowerReady { timestamp }
+            | Self::Status { timestamp, .. }
+            | Self::Measurement { timestamp, .. }
+            | Self::MissedKeepAlive { timestamp, .. } => timestamp,
+        }
+    }
+}
+
+/// Follows PPS and AVS contracts in a KM003C capture.
+///
+/// Requests are classified against the most recent Source_Capabilities, or
+/// against the PDO copy carried by EPR_Request. Connection changes end the
+/// contract but keep the timeline.
+#[derive(Debug, Clone)]
+pub struct PpsTracker {
+    keep_alive_timeout: Time,
+    source_capabilities: Vec<SourcePdo>,
+    /// Programmable kind of the Request awaiting a response; `Some(None)` for
+    /// a fixed or variable Request.
+    pending_request: Option<Option<ProgrammableSupplyKind>>,
+    awaiting_ps_ready: bool,
+    contract: Option<ProgrammableSupplyKind>,
+    last_request: Option<ProgrammableRequest>,
+    keep_alive_reported: bool,
+    timeline: Vec<PpsTimelineEntry>,
+}
+
+impl Default for PpsTracker {
+    fn default() -> Self {
+        Self::with_keep_alive_timeout(Time::new::<second>(PPS_REQUEST_INTERVAL_MAX_S))
+    }
+}
+
+impl PpsTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a tracker that reports missed keep-alives after a custom interval.
+    pub fn with_keep_alive_timeout(keep_alive_timeout: Time) -> Self {
+        Self {
+            keep_alive_timeout,
+            source_capabilities: Vec::new(),
+            pending_request: None,
+            awaiting_ps_ready: false,
+            contract: None,
+            last_request: None,
+            keep_alive_reported: false,
+            timeline: Vec::new(),
+        }
+    }
+
+    /// Clear the timeline and all connection state.
+    pub fn reset(&mut self) {
+        *self = Self::with_keep_alive_timeout(self.keep_alive_timeout);
+    }
+
+    pub fn timeline(&self) -> &[PpsTimelineEntry] {
+        &self.timeline
+    }
+
+    /// Kind of the accepted programmable contract, if one is active.
+    pub fn contract(&self) -> Option<ProgrammableSupplyKind> {
+        self.contract
+    }
+
+    /// Most recent programmable Request.
+    pub fn last_request(&self) -> Option<&ProgrammableRequest> {
+        self.last_request.as_ref()
+    }
+
+    /// Process one KM003C PD event, returning the timeline entries it added.
+    pub fn process_event(&mut self, event: &PdEvent) -> &[PpsTimelineEntry] {
+        let start = self.timeline.len();
+        self.check_keep_alive(event.timestamp);
+
+        match &event.data {
+            PdEventData::Connect(()) | PdEventData::Disconnect(()) => self.end_connection(),
+            PdEventData::PdMessage { sop, wire_data } if PdSopType::from(*sop) == PdSopType::Sop => {
+                if let Ok(message) = PdWireMessage::from_bytes(wire_data) {
+                    self.process_message(event.timestamp, &message);
+                }
+            }
+            PdEventData::PdMessage { .. } => {}
+        }
+
+        &self.timeline[start..]
+    }
+
+    /// Process a KM003C PD status measurement, returning the timeline entries it added.
+    ///
+    /// Measurements are recorded only while a programmable Request is pending
+    /// or accepted.
+    pub fn process_status(&mut self, status: &PdStatus) -> &[PpsTimelineEntry] {
+        let start = self.timeline.len();
+        self.check_keep_alive(status.timestamp);
+
+        if self.contract.is_some() || matches!(self.pending_request, Some(Some(_))) {
+            self.timeline.push(PpsTimelineEntry::Measurement {
+                timestamp: status.timestamp,
+                vbus: status.vbus,
+                ibus: status.ibus,
+            });
+        }
+
+        &self.timeline[start..]
+    }
+
+    fn end_connection(&mut self) {
+        self.source_capabilities.clear();
+        self.pending_request = None;
+        self.awaiting_ps_ready = false;
+        self.contract = None;
+        self.last_request = None;
+        self.keep_alive_reported = false;
+    }
+
+    fn process_message(&mut self, timestamp: Time, message: &PdWireMessage) {
+        match message.message_type() {
+            PdMessageType::Data(PdDataMessageType::SourceCapabilities) => {
+                self.source_capabilities = message.data_objects().map(SourcePdo::from_raw).collect();
+            }
+            PdMessageType::Data(PdDataMessageType::Request) => {
+                if let Some(rdo) = message.data_objects().next().map(RequestDataObject) {
+                    let pdo = usize::from(rdo.object_position())
+                        .checked_sub(1)
+                        .and_then(|index| self.source_capabilities.get(index))
+                        .copied();
+                    self.process_request(timestamp, rdo, pdo);
+                }
+            }
+            PdMessageType::Data(PdDataMessageType::EprRequest) => {
+                let mut objects = message.data_objects();
+                if let Some(rdo) = objects.next().map(RequestDataObject) {
+                    let pdo = objects.next().map(SourcePdo::from_raw);
+                    self.process_request(timestamp, rdo, pdo);
+                }
+            }
+            PdMessageType::Control(
+                response @ (PdControlMessageType::Accept
+                | PdControlMessageType::Reject
+                | PdControlMessageType::Wait
+                | PdControlMessageType::NotSupported),
+            ) => self.process_response(timestamp, response),
+            PdMessageType::Control(PdControlMessageType::PsRdy) if self.awaiting_ps_ready => {
+                self.awaiting_ps_ready = false;
+                self.timeline.push(PpsTimelineEntry::PowerReady { timestamp });
+            }
+            PdMessageType::Extended(PdExtendedMessageType::PpsStatus) => {
+                if let Some(status) = PpsStatus::from_bytes(&message.payload) {
+                    self.timeline.push(PpsTimelineEntry::Status { timestamp, status });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn process_request(&mut self, timestamp: Time, rdo: RequestDataObject, pdo: Option<SourcePdo>) {
+        let (kind, requested_voltage) = match pdo {
+            Some(SourcePdo::SprPps(_)) => (ProgrammableSupplyKind::SprPps, rdo.pps_output_voltage()),
+            Some(SourcePdo::SprAvs(_)) => (ProgrammableSupplyKind::SprAvs, rdo.avs_output_voltage()),
+            Some(SourcePdo::EprAvs(_)) => (ProgrammableSupplyKind::EprAvs, rdo.avs_output_voltage()),
+            _ => {
+                self.pending_request = Some(None);
+                return;
+            }
+        };
+        let requested_current = rdo.programmable_operating_current();
+
+        let request = ProgrammableRequest {
+            timestamp,
+            object_position: rdo.object_position(),
+            kind,
+            requested_voltage,
+            requested_current,
+            interval: self.last_request.map(|last| timestamp - last.timestamp),
+            repeated: self.last_request.is_some_and(|last| {
+                last.object_position == rdo.object_position()
+                    && last.requested_voltage == requested_voltage
+                    && last.requested_current == requested_current
+            }),
+        };
+        self.timeline.push(PpsTimelineEntry::Request(request));
+        self.last_request = Some(request);
+        self.keep_alive_reported = false;
+        self.pending_request = Some(Some(kind));
+    }
+
+    fn process_response(&mut self, timestamp: Time, response: PdControlMessageType) {
+        let Some(kind) = self.pending_request.take() else {
+            return;
+        };
+        if kind.is_some() {
+            self.timeline.push(PpsTimelineEntry::Response { timestamp, response });
+        }
+        if response == PdControlMessageType::Accept {
+            self.contract = kind;
+            self.awaiting_ps_ready = kind.is_some();
+            if kind.is_none() {
+                self.last_request = None;
+            }
+        }
+    }
+
+    fn check_keep_alive(&mut self, now: Time) {
+        if self.contract != Some(ProgrammableSupplyKind::SprPps) || self.keep_alive_reported {
+            return;
+        }
+        let Some(last) = self.last_request else {
+            return;
+        };
+
+        let elapsed = now - last.timestamp;
+        if elapsed > self.keep_alive_timeout {
+            self.keep_alive_reported = true;
+            self.timeline.push(PpsTimelineEntry::MissedKeepAlive {
+                timestamp: now,
+                last_request: last.timestamp,
+                elapsed,
+            });
+        }
+    }
+}
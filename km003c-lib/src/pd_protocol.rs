@@ -0,0 +1,377 @@
+//! Protocol-layer analysis of MessageIDs, retries and GoodCRC pairing.
+//!
+//! Every message on a SOP* channel must be acknowledged by a GoodCRC carrying
+//! the same MessageID, or it is retransmitted unchanged. Retries, lost
+//! acknowledgements and header fields that disagree between the two ends are
+//! the first visible symptoms of marginal CC signalling. [`ProtocolAnalyzer`]
+//! pairs messages with their GoodCRC in a KM003C capture and counts these
+//! symptoms; it relies on the capture containing the GoodCRC messages.
+
+use std::collections::HashMap;
+
+use uom::si::f64::Time;
+
+use crate::pd::{PdEvent, PdEventData};
+use crate::pd_policy::{PdSpecRevision, PolicyTracker};
+use crate::pd_wire::{
+    PdControlMessageType, PdDataMessageType, PdMessageHeader, PdMessageType, PdSopType, PdWireMessage,
+};
+
+/// Number of MessageID values before the counter wraps.
+const MESSAGE_ID_COUNT: u8 = 8;
+
+/// A message and its acknowledgement, with any retransmissions folded in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PdTransmission {
+    pub timestamp: Time,
+    pub sop: PdSopType,
+    pub message_type: PdMessageType,
+    pub message_id: u8,
+    /// Power role on SOP, Cable Plug flag on SOP'/SOP''.
+    pub from_source_or_cable: bool,
+    /// Number of times the message was sent, including the first.
+    pub attempts: u8,
+    /// Time of the GoodCRC for the last attempt.
+    pub good_crc_at: Option<Time>,
+}
+
+/// Which header field two ends of a channel disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PdHeaderField {
+    PowerRole,
+    DataRole,
+    CablePlug,
+}
+
+/// A protocol-layer symptom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ProtocolIssue {
+    /// A message resent without a GoodCRC for the previous attempt.
+    Retry { message_id: u8, attempt: u8 },
+    /// A message resent although its GoodCRC was captured; the receiver
+    /// discards it as a duplicate.
+    Duplicate { message_id: u8 },
+    /// A message neither acknowledged nor retried before other traffic.
+    MissingGoodCrc {
+        message_id: u8,
+        message_type: PdMessageType,
+    },
+    /// A GoodCRC that does not acknowledge the outstanding message.
+    UnexpectedGoodCrc { message_id: u8, expected: Option<u8> },
+    /// A sender skipped or reused a MessageID.
+    UnexpectedMessageId { expected: u8, found: u8 },
+    /// A header Specification Revision other than the negotiated one.
+    UnexpectedRevision {
+        expected: PdSpecRevision,
+        found: PdSpecRevision,
+    },
+    /// A GoodCRC claiming the same role as the message it acknowledges.
+    RoleConflict { field: PdHeaderField },
+}
+
+/// A protocol-layer symptom with its capture metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ProtocolIssueEvent {
+    pub timestamp: Time,
+    pub sop: PdSopType,
+    pub issue: ProtocolIssue,
+}
+
+/// Running counts of analyzed messages and symptoms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ProtocolStatistics {
+    pub messages: u32,
+    pub good_crcs: u32,
+    pub retries: u32,
+    pub duplicates: u32,
+    pub missing_good_crcs: u32,
+    pub unexpected_good_crcs: u32,
+    pub unexpected_message_ids: u32,
+    pub unexpected_revisions: u32,
+    pub role_conflicts: u32,
+}
+
+impl ProtocolStatistics {
+    fn count(&mut self, issue: &ProtocolIssue) {
+        let counter = match issue {
+            ProtocolIssue::Retry { .. } => &mut self.retries,
+            ProtocolIssue::Duplicate { .. } => &mut self.duplicates,
+            ProtocolIssue::MissingGoodCrc { .. } => &mut self.missing_good_crcs,
+            ProtocolIssue::UnexpectedGoodCrc { .. } => &mut self.unexpected_good_crcs,
+            ProtocolIssue::UnexpectedMessageId { .. } => &mut self.unexpected_message_ids,
+            ProtocolIssue::UnexpectedRevision { .. } => &mut self.unexpected_revisions,
+            ProtocolIssue::RoleConflict { .. } => &mut self.role_conflicts,
+        };
+        *counter += 1;
+    }
+}
+
+/// Last message of a sender, kept to recognize retransmissions.
+#[derive(Debug, Clone)]
+struct SentMessage {
+    transmission: usize,
+    header: PdMessageHeader,
+    wire_data: Vec<u8>,
+    acknowledged: bool,
+}
+
+/// Protocol-layer state of one SOP* channel.
+#[derive(Debug, Clone, Default)]
+struct ChannelState {
+    /// Keyed by the sending port, see [`ProtocolAnalyzer::sender`].
+    last_sent: HashMap<bool, SentMessage>,
+    /// Sender of the message awaiting its GoodCRC.
+    outstanding: Option<bool>,
+    revision: Option<PdSpecRevision>,
+}
+
+/// Pairs messages with their GoodCRC and reports protocol-layer symptoms.
+///
+/// Connection changes reset the channel state but keep the transmission log,
+/// issues and statistics. MessageID counters survive power role swaps, so the
+/// analyzer follows the policy engine to attribute SOP messages to ports.
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolAnalyzer {
+    policy: PolicyTracker,
+    channels: HashMap<PdSopType, ChannelState>,
+    transmissions: Vec<PdTransmission>,
+    issues: Vec<ProtocolIssueEvent>,
+    statistics: ProtocolStatistics,
+}
+
+impl ProtocolAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clear the logs, statistics and all channel state.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn transmissions(&self) -> &[PdTransmission] {
+        &self.transmissions
+    }
+
+    pub fn issues(&self) -> &[ProtocolIssueEvent] {
+        &self.issues
+    }
+
+    pub fn statistics(&self) -> &ProtocolStatistics {
+        &self.statistics
+    }
+
+    /// Process one KM003C PD event, returning the issues it revealed.
+    pub fn process_event(&mut self, event: &PdEvent) -> &[ProtocolIssueEvent] {
+        let start = self.issues.len();
+        self.policy.process_event(event);
+        match &event.data {
+            PdEventData::Connect(()) | PdEventData::Disconnect(()) => self.channels.clear(),
+            PdEventData::PdMessage { sop, wire_data } => {
+                if let Ok(message) = PdWireMessage::from_bytes(wire_data) {
+                    self.process_message(event.timestamp, PdSopType::from(*sop), &message, wire_data);
+                }
+            }
+        }
+        &self.issues[start..]
+    }
+
+    /// Key of the port that sent a message: the Cable Plug flag on SOP'/SOP'',
+    /// otherwise whether the port was the source at attach.
+    fn sender(&self, sop: PdSopType, header: PdMessageHeader) -> bool {
+        if sop == PdSopType::Sop {
+            header.port_power_role() != self.policy.state().power_roles_swapped
+        } else {
+            header.port_power_role()
+        }
+    }
+
+    fn issue(&mut self, timestamp: Time, sop: PdSopType, issue: ProtocolIssue) {
+        self.statistics.count(&issue);
+        self.issues.push(ProtocolIssueEvent { timestamp, sop, issue });
+    }
+
+    fn process_message(&mut self, timestamp: Time, sop: PdSopType, message: &PdWireMessage, wire_data: &[u8]) {
+        let header = message.header;
+        let sender = self.sender(sop, header);
+        let message_type = message.message_type();
+
+        if message_type == PdMessageType::Control(PdControlMessageType::GoodCrc) {
+            self.statistics.good_crcs += 1;
+            self.process_good_crc(timestamp, sop, header);
+            return;
+        }
+
+        // A restarted counter makes an identical message a new transmission,
+        // e.g. Source_Capabilities after a Hard Reset missing from the capture.
+        let channel = self.channels.entry(sop).or_default();
+        if !restarts_message_ids(message)
+            && let Some(last) = channel.last_sent.get_mut(&sender)
+            && last.header.message_id() == header.message_id()
+            && last.wire_data == wire_data
+        {
+            let transmission = &mut self.transmissions[last.transmission];
+            transmission.attempts = transmission.attempts.saturating_add(1);
+            transmission.good_crc_at = None;
+            let issue = if last.acknowledged {
+                ProtocolIssue::Duplicate {
+                    message_id: header.message_id(),
+                }
+            } else {
+                ProtocolIssue::Retry {
+                    message_id: header.message_id(),
+                    attempt: transmission.attempts,
+                }
+            };
+            last.acknowledged = false;
+            channel.outstanding = Some(sender);
+            self.issue(timestamp, sop, issue);
+            return;
+        }
+
+        self.report_missing_good_crc(sop);
+        self.check_message_id(timestamp, sop, message);
+        if sop == PdSopType::Sop {
+            self.check_revision(timestamp, message);
+        }
+
+        self.statistics.messages += 1;
+        self.transmissions.push(PdTransmission {
+            timestamp,
+            sop,
+            message_type,
+            message_id: header.message_id(),
+            from_source_or_cable: header.port_power_role(),
+            attempts: 1,
+            good_crc_at: None,
+        });
+        let channel = self.channels.entry(sop).or_default();
+        channel.last_sent.insert(
+            sender,
+            SentMessage {
+                transmission: self.transmissions.len() - 1,
+                header,
+                wire_data: wire_data.to_vec(),
+                acknowledged: false,
+            },
+        );
+        channel.outstanding = Some(sender);
+    }
+
+    fn process_good_crc(&mut self, timestamp: Time, sop: PdSopType, header: PdMessageHeader) {
+        let channel = self.channels.entry(sop).or_default();
+        let outstanding = channel
+            .outstanding
+            .and_then(|sender| channel.last_sent.get_mut(&sender));
+        let expected = outstanding.as_ref().map(|last| last.header.message_id());
+        let Some(last) = outstanding.filter(|_| expected == Some(header.message_id())) else {
+            self.issue(
+                timestamp,
+                sop,
+                ProtocolIssue::UnexpectedGoodCrc {
+                    message_id: header.message_id(),
+                    expected,
+                },
+            );
+            return;
+        };
+
+        last.acknowledged = true;
+        self.transmissions[last.transmission].good_crc_at = Some(timestamp);
+        let sent = last.header;
+        channel.outstanding = None;
+
+        let mut conflicts = Vec::new();
+        if header.port_power_role() == sent.port_power_role() {
+            conflicts.push(if sop.is_cable_plug() {
+                PdHeaderField::CablePlug
+            } else {
+                PdHeaderField::PowerRole
+            });
+        }
+        if sop == PdSopType::Sop && header.port_data_role() == sent.port_data_role() {
+            conflicts.push(PdHeaderField::DataRole);
+        }
+        for field in conflicts {
+            self.issue(timestamp, sop, ProtocolIssue::RoleConflict { field });
+        }
+    }
+
+    fn report_missing_good_crc(&mut self, sop: PdSopType) {
+        let Some(channel) = self.channels.get_mut(&sop) else {
+            return;
+        };
+        let Some(last) = channel
+            .outstanding
+            .take()
+            .and_then(|sender| channel.last_sent.get(&sender))
+        else {
+            return;
+        };
+        let transmission = self.transmissions[last.transmission];
+        self.issue(
+            transmission.timestamp,
+            sop,
+            ProtocolIssue::MissingGoodCrc {
+                message_id: transmission.message_id,
+                message_type: transmission.message_type,
+            },
+        );
+    }
+
+    fn check_message_id(&mut self, timestamp: Time, sop: PdSopType, message: &PdWireMessage) {
+        let found = message.header.message_id();
+        let sender = self.sender(sop, message.header);
+        let channel = self.channels.entry(sop).or_default();
+        if restarts_message_ids(message) {
+            channel.last_sent.clear();
+            return;
+        }
+
+        let Some(last) = channel.last_sent.get(&sender) else {
+            return;
+        };
+        let expected = (last.header.message_id() + 1) % MESSAGE_ID_COUNT;
+        if found != expected {
+            self.issue(timestamp, sop, ProtocolIssue::UnexpectedMessageId { expected, found });
+        }
+    }
+
+    fn check_revision(&mut self, timestamp: Time, message: &PdWireMessage) {
+        let found = PdSpecRevision::from(message.header.spec_revision());
+        let channel = self.channels.entry(PdSopType::Sop).or_default();
+        match message.message_type() {
+            // The source offers its highest revision; the sink answers with
+            // the lower of both, which both ends use from then on.
+            PdMessageType::Data(PdDataMessageType::SourceCapabilities) => channel.revision = None,
+            PdMessageType::Data(PdDataMessageType::Request) if channel.revision.is_none() => {
+                channel.revision = Some(found);
+            }
+            _ => {
+                if let Some(expected) = channel.revision
+                    && expected != found
+                {
+                    self.issue(
+                        timestamp,
+                        PdSopType::Sop,
+                        ProtocolIssue::UnexpectedRevision { expected, found },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Soft_Reset restarts the counters of both ends on its channel, and
+/// Source_Capabilities with MessageID 0 follows a Hard Reset.
+fn restarts_message_ids(message: &PdWireMessage) -> bool {
+    let message_type = message.message_type();
+    message.header.message_id() == 0
+        && (message_type == PdMessageType::Control(PdControlMessageType::SoftReset)
+            || message_type == PdMessageType::Data(PdDataMessageType::SourceCapabilities))
+}
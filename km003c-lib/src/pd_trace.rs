@@ -55,6 +55,31 @@ pub enum PdTypeCState {
     Unknown(u8),
 }
 
+impl PdTypeCState {
+    /// Whether the state completes an attach.
+    pub fn is_attached(&self) -> bool {
+        matches!(
+            self,
+            Self::AttachedResistance
+                | Self::AttachedDebSource
+                | Self::AttachedSource
+                | Self::AttachedCable
+                | Self::AttachedLightningPlug
+                | Self::AttachedDebSink
+                | Self::AttachedSink
+                | Self::AttachedMonitor
+        )
+    }
+
+    /// Whether the state follows a detach.
+    pub fn is_unattached(&self) -> bool {
+        matches!(
+            self,
+            Self::Disabled | Self::DelayUnattached | Self::UnattachedDebSource | Self::UnattachedSource
+        )
+    }
+}
+
 /// Firmware protocol-engine trace codes confirmed in KM003C V1.9.9.
 ///
 /// Most values in this queue are internal protocol-engine states whose names
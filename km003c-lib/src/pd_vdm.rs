@@ -0,0 +1,839 @@
+//! USB PD Vendor Defined Message decoding.
+//!
+//! Vendor Defined Messages carry Discover Identity, SVID and mode discovery,
+//! and alternate-mode negotiation. The objects are decoded directly from the
+//! wire payload exposed by [`crate::pd_wire`] using the USB PD Revision 3.1
+//! layouts. Each VDO type is a thin wrapper over its raw 32-bit value, so
+//! fields that are not given an accessor remain available.
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::f64::{ElectricCurrent, ElectricPotential, ThermodynamicTemperature};
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::pd_wire::{PdDataMessageType, PdMessageType, PdSopType, PdWireMessage};
+
+/// Standard ID used in the VDM header of USB PD structured VDMs.
+pub const PD_SID: u16 = 0xff00;
+
+/// VESA SVID of the DisplayPort alternate mode.
+pub const DISPLAYPORT_SVID: u16 = 0xff01;
+
+/// Intel SVID of the Thunderbolt 3 alternate mode.
+pub const THUNDERBOLT_SVID: u16 = 0x8087;
+
+/// DisplayPort Status Update command, defined by the DisplayPort SVID.
+pub const DP_STATUS_UPDATE: u8 = 0x10;
+
+/// DisplayPort Configure command, defined by the DisplayPort SVID.
+pub const DP_CONFIGURE: u8 = 0x11;
+
+pub(crate) fn bits(value: u32, shift: u32, width: u32) -> u32 {
+    (value >> shift) & ((1 << width) - 1)
+}
+
+pub(crate) fn bit(value: u32, shift: u32) -> bool {
+    bits(value, shift, 1) != 0
+}
+
+/// Command type of a structured VDM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum VdmCommandType {
+    #[num_enum(default)]
+    Request = 0,
+    Ack = 1,
+    Nak = 2,
+    Busy = 3,
+}
+
+/// Command of a structured VDM.
+///
+/// Values 16 to 31 are defined by the SVID and are kept in [`Self::SvidSpecific`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum VdmCommand {
+    DiscoverIdentity = 1,
+    DiscoverSvids = 2,
+    DiscoverModes = 3,
+    EnterMode = 4,
+    ExitMode = 5,
+    Attention = 6,
+    #[num_enum(catch_all)]
+    SvidSpecific(u8),
+}
+
+/// VDM header, the first data object of every Vendor Defined Message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct VdmHeader(pub u32);
+
+impl VdmHeader {
+    /// Standard or Vendor ID that defines the message.
+    pub fn svid(&self) -> u16 {
+        bits(self.0, 16, 16) as u16
+    }
+
+    /// Whether this is a structured VDM; unstructured VDMs are vendor-defined throughout.
+    pub fn is_structured(&self) -> bool {
+        bit(self.0, 15)
+    }
+
+    /// Structured VDM major version: 0 for 1.0, 1 for 2.x.
+    pub fn version_major(&self) -> u8 {
+        bits(self.0, 13, 2) as u8
+    }
+
+    /// Structured VDM minor version: 0 for 2.0, 1 for 2.1.
+    pub fn version_minor(&self) -> u8 {
+        bits(self.0, 11, 2) as u8
+    }
+
+    /// Object position of the mode addressed by Enter/Exit Mode and Attention.
+    pub fn object_position(&self) -> u8 {
+        bits(self.0, 8, 3) as u8
+    }
+
+    pub fn command_type(&self) -> VdmCommandType {
+        VdmCommandType::from_primitive(bits(self.0, 6, 2) as u8)
+    }
+
+    pub fn command(&self) -> VdmCommand {
+        VdmCommand::from_primitive(bits(self.0, 0, 5) as u8)
+    }
+
+    /// The 15 vendor-use bits of an unstructured VDM.
+    pub fn vendor_use(&self) -> u16 {
+        bits(self.0, 0, 15) as u16
+    }
+}
+
+/// A Vendor Defined Message: VDM header followed by up to six VDOs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct VendorDefinedMessage {
+    pub header: VdmHeader,
+    pub objects: Vec<u32>,
+}
+
+impl VendorDefinedMessage {
+    /// Extract the VDM from a wire message, if it is a Vendor_Defined data message.
+    pub fn from_wire(message: &PdWireMessage) -> Option<Self> {
+        if message.message_type() != PdMessageType::Data(PdDataMessageType::VendorDefined) {
+            return None;
+        }
+
+        let mut objects = message.data_objects();
+        let header = VdmHeader(objects.next()?);
+        Some(Self {
+            header,
+            objects: objects.collect(),
+        })
+    }
+
+    /// Whether this is a structured VDM with the given command and command type.
+    pub fn is_structured_command(&self, command: VdmCommand, command_type: VdmCommandType) -> bool {
+        self.header.is_structured() && self.header.command() == command && self.header.command_type() == command_type
+    }
+
+    /// Decode the identity carried by a Discover Identity ACK.
+    pub fn discover_identity(&self) -> Option<DiscoverIdentity> {
+        if self.header.svid() != PD_SID
+            || !self.is_structured_command(VdmCommand::DiscoverIdentity, VdmCommandType::Ack)
+        {
+            return None;
+        }
+
+        let mut objects = self.objects.iter().copied();
+        Some(DiscoverIdentity {
+            id_header: IdHeaderVdo(objects.next()?),
+            cert_stat: objects.next().map(CertStatVdo),
+            product: objects.next().map(ProductVdo),
+            product_type_vdos: objects.collect(),
+        })
+    }
+
+    /// SVIDs listed by a Discover SVIDs ACK, up to the first zero entry.
+    pub fn discover_svids(&self) -> Option<Vec<u16>> {
+        if self.header.svid() != PD_SID || !self.is_structured_command(VdmCommand::DiscoverSvids, VdmCommandType::Ack) {
+            return None;
+        }
+
+        Some(
+            self.objects
+                .iter()
+                .flat_map(|&object| [bits(object, 16, 16) as u16, bits(object, 0, 16) as u16])
+                .take_while(|&svid| svid != 0)
+                .collect(),
+        )
+    }
+
+    /// Mode VDOs listed by a Discover Modes ACK, typed by the SVID they belong to.
+    ///
+    /// The SOP* type is needed because Thunderbolt cables and devices use
+    /// different mode VDO layouts.
+    pub fn discover_modes(&self, sop: PdSopType) -> Option<Vec<ModeVdo>> {
+        if !self.is_structured_command(VdmCommand::DiscoverModes, VdmCommandType::Ack) {
+            return None;
+        }
+
+        let svid = self.header.svid();
+        Some(self.objects.iter().map(|&vdo| ModeVdo::new(svid, sop, vdo)).collect())
+    }
+
+    /// Decode the objects of a structured VDM according to its command and SVID.
+    pub fn content(&self, sop: PdSopType) -> VdmContent {
+        if !self.header.is_structured() {
+            return VdmContent::Unstructured(self.objects.clone());
+        }
+
+        let svid = self.header.svid();
+        match (
+            self.header.command(),
+            self.header.command_type(),
+            self.objects.first().copied(),
+        ) {
+            (_, VdmCommandType::Nak | VdmCommandType::Busy, _) => VdmContent::Empty,
+            (VdmCommand::DiscoverIdentity, VdmCommandType::Ack, _) => self
+                .discover_identity()
+                .map_or(VdmContent::Empty, VdmContent::DiscoverIdentity),
+            (VdmCommand::DiscoverSvids, VdmCommandType::Ack, _) => self
+                .discover_svids()
+                .map_or(VdmContent::Empty, VdmContent::DiscoverSvids),
+            (VdmCommand::DiscoverModes, VdmCommandType::Ack, _) => {
+                VdmContent::DiscoverModes(self.discover_modes(sop).unwrap_or_default())
+            }
+            (VdmCommand::EnterMode, _, Some(vdo)) => VdmContent::EnterMode(ModeVdo::new(svid, sop, vdo)),
+            (VdmCommand::Attention | VdmCommand::SvidSpecific(DP_STATUS_UPDATE), _, Some(vdo))
+                if svid == DISPLAYPORT_SVID =>
+            {
+                VdmContent::DisplayPortStatus(DisplayPortStatusVdo(vdo))
+            }
+            (VdmCommand::SvidSpecific(DP_CONFIGURE), VdmCommandType::Request, Some(vdo))
+                if svid == DISPLAYPORT_SVID =>
+            {
+                VdmContent::DisplayPortConfigure(DisplayPortConfigureVdo(vdo))
+            }
+            (_, _, None) => VdmContent::Empty,
+            _ => VdmContent::Other(self.objects.clone()),
+        }
+    }
+}
+
+/// Objects of a Vendor Defined Message, decoded according to its command and SVID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum VdmContent {
+    /// No VDOs follow the header, as in requests, NAK and BUSY responses.
+    Empty,
+    DiscoverIdentity(DiscoverIdentity),
+    DiscoverSvids(Vec<u16>),
+    DiscoverModes(Vec<ModeVdo>),
+    /// The mode VDO sent with Enter Mode, used by Thunderbolt.
+    EnterMode(ModeVdo),
+    /// DisplayPort Status Update request or response, or DisplayPort Attention.
+    DisplayPortStatus(DisplayPortStatusVdo),
+    DisplayPortConfigure(DisplayPortConfigureVdo),
+    /// Structured VDM objects without a known layout.
+    Other(Vec<u32>),
+    /// Objects of an unstructured VDM.
+    Unstructured(Vec<u32>),
+}
+
+/// Product type reported by a cable plug or VCONN-powered device on SOP'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum CablePlugProductType {
+    NotCablePlug = 0,
+    PassiveCable = 3,
+    ActiveCable = 4,
+    VconnPoweredDevice = 6,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Product type reported by a port partner in its UFP role on SOP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum UfpProductType {
+    NotUfp = 0,
+    Hub = 1,
+    Peripheral = 2,
+    PowerSinkingDevice = 3,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Product type reported by a port partner in its DFP role on SOP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum DfpProductType {
+    NotDfp = 0,
+    Hub = 1,
+    Host = 2,
+    PowerBrick = 3,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// ID Header VDO of a Discover Identity response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct IdHeaderVdo(pub u32);
+
+impl IdHeaderVdo {
+    pub fn usb_host_capable(&self) -> bool {
+        bit(self.0, 31)
+    }
+
+    pub fn usb_device_capable(&self) -> bool {
+        bit(self.0, 30)
+    }
+
+    /// Product type field as seen on SOP'/SOP''.
+    pub fn cable_plug_product_type(&self) -> CablePlugProductType {
+        CablePlugProductType::from_primitive(bits(self.0, 27, 3) as u8)
+    }
+
+    /// Product type field as seen on SOP from a UFP.
+    pub fn ufp_product_type(&self) -> UfpProductType {
+        UfpProductType::from_primitive(bits(self.0, 27, 3) as u8)
+    }
+
+    pub fn modal_operation_supported(&self) -> bool {
+        bit(self.0, 26)
+    }
+
+    /// DFP product type, only meaningful on SOP.
+    pub fn dfp_product_type(&self) -> DfpProductType {
+        DfpProductType::from_primitive(bits(self.0, 23, 3) as u8)
+    }
+
+    /// Connector type: 2 for a receptacle, 3 for a plug, 0 on pre-3.1 devices.
+    pub fn connector_type(&self) -> u8 {
+        bits(self.0, 21, 2) as u8
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        bits(self.0, 0, 16) as u16
+    }
+}
+
+/// Cert Stat VDO carrying the USB-IF assigned XID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CertStatVdo(pub u32);
+
+impl CertStatVdo {
+    pub fn xid(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Product VDO of a Discover Identity response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ProductVdo(pub u32);
+
+impl ProductVdo {
+    pub fn product_id(&self) -> u16 {
+        bits(self.0, 16, 16) as u16
+    }
+
+    pub fn bcd_device(&self) -> u16 {
+        bits(self.0, 0, 16) as u16
+    }
+}
+
+/// Identity objects of a Discover Identity ACK.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DiscoverIdentity {
+    pub id_header: IdHeaderVdo,
+    pub cert_stat: Option<CertStatVdo>,
+    pub product: Option<ProductVdo>,
+    /// Product-type VDOs, such as the passive or active cable VDOs.
+    pub product_type_vdos: Vec<u32>,
+}
+
+/// Cable plug type at the far end of a cable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum CablePlugType {
+    UsbTypeC = 2,
+    Captive = 3,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Signal latency class of a cable, which the specification relates to its length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum CableLatency {
+    /// Below 10 ns, about 1 m.
+    Below10Ns = 1,
+    /// 10 to 20 ns, about 2 m.
+    From10To20Ns = 2,
+    /// 20 to 30 ns, about 3 m.
+    From20To30Ns = 3,
+    /// 30 to 40 ns, about 4 m.
+    From30To40Ns = 4,
+    /// 40 to 50 ns, about 5 m.
+    From40To50Ns = 5,
+    /// 50 to 60 ns, about 6 m.
+    From50To60Ns = 6,
+    /// 60 to 70 ns, about 7 m.
+    From60To70Ns = 7,
+    /// Above 70 ns, longer than 7 m.
+    Above70Ns = 8,
+    /// About 1000 ns (active optical cables, about 200 m).
+    Around1000Ns = 9,
+    /// About 2000 ns (active optical cables, about 400 m).
+    Around2000Ns = 10,
+    /// About 3000 ns (active optical cables, about 600 m).
+    Around3000Ns = 11,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Cable termination and VCONN requirement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum CableTermination {
+    #[num_enum(default)]
+    PassiveVconnNotRequired = 0,
+    PassiveVconnRequired = 1,
+    OneEndActive = 2,
+    BothEndsActive = 3,
+}
+
+/// Highest USB data rate supported by a cable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum UsbHighestSpeed {
+    Usb20Only = 0,
+    Usb32Gen1 = 1,
+    /// USB 3.2 Gen2, or USB4 Gen2 for USB4-capable cables.
+    Usb32Gen2 = 2,
+    Usb4Gen3 = 3,
+    Usb4Gen4 = 4,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Fields shared by the Passive Cable VDO and Active Cable VDO1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CableVdo(pub u32);
+
+impl CableVdo {
+    pub fn hardware_version(&self) -> u8 {
+        bits(self.0, 28, 4) as u8
+    }
+
+    pub fn firmware_version(&self) -> u8 {
+        bits(self.0, 24, 4) as u8
+    }
+
+    /// VDO version: 0 for passive 1.0, 3 for active 1.3.
+    pub fn vdo_version(&self) -> u8 {
+        bits(self.0, 21, 3) as u8
+    }
+
+    pub fn plug_type(&self) -> CablePlugType {
+        CablePlugType::from_primitive(bits(self.0, 18, 2) as u8)
+    }
+
+    pub fn epr_mode_capable(&self) -> bool {
+        bit(self.0, 17)
+    }
+
+    pub fn latency(&self) -> CableLatency {
+        CableLatency::from_primitive(bits(self.0, 13, 4) as u8)
+    }
+
+    pub fn termination(&self) -> CableTermination {
+        CableTermination::from_primitive(bits(self.0, 11, 2) as u8)
+    }
+
+    /// Maximum VBUS voltage: 20, 30, 40 or 50 V.
+    ///
+    /// The 30 V and 40 V encodings are deprecated; EPR cables report 50 V.
+    pub fn max_vbus_voltage(&self) -> ElectricPotential {
+        let volts = match bits(self.0, 9, 2) {
+            0 => 20.0,
+            1 => 30.0,
+            2 => 40.0,
+            _ => 50.0,
+        };
+        ElectricPotential::new::<volt>(volts)
+    }
+
+    /// VBUS current handling: 3 or 5 A, or `None` for reserved encodings.
+    pub fn vbus_current(&self) -> Option<ElectricCurrent> {
+        match bits(self.0, 5, 2) {
+            1 => Some(ElectricCurrent::new::<ampere>(3.0)),
+            2 => Some(ElectricCurrent::new::<ampere>(5.0)),
+            _ => None,
+        }
+    }
+
+    pub fn usb_highest_speed(&self) -> UsbHighestSpeed {
+        UsbHighestSpeed::from_primitive(bits(self.0, 0, 3) as u8)
+    }
+
+    /// Active cables only: whether SBU lines are supported.
+    pub fn sbu_supported(&self) -> bool {
+        !bit(self.0, 8)
+    }
+
+    /// Active cables only: whether the SBU lines are actively driven.
+    pub fn sbu_active(&self) -> bool {
+        bit(self.0, 7)
+    }
+
+    /// Active cables only: whether VBUS is carried end to end.
+    pub fn vbus_through_cable(&self) -> bool {
+        bit(self.0, 4)
+    }
+
+    /// Active cables only: whether an SOP'' controller is present in the far plug.
+    pub fn sop_double_prime_controller(&self) -> bool {
+        bit(self.0, 3)
+    }
+}
+
+/// Active Cable VDO2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ActiveCableVdo2(pub u32);
+
+impl ActiveCableVdo2 {
+    pub fn max_operating_temperature(&self) -> ThermodynamicTemperature {
+        ThermodynamicTemperature::new::<degree_celsius>(f64::from(bits(self.0, 24, 8)))
+    }
+
+    pub fn shutdown_temperature(&self) -> ThermodynamicTemperature {
+        ThermodynamicTemperature::new::<degree_celsius>(f64::from(bits(self.0, 16, 8)))
+    }
+
+    pub fn optical(&self) -> bool {
+        bit(self.0, 10)
+    }
+
+    /// Whether the active element is a retimer rather than a redriver.
+    pub fn retimer(&self) -> bool {
+        bit(self.0, 9)
+    }
+
+    pub fn usb4_supported(&self) -> bool {
+        !bit(self.0, 8)
+    }
+
+    pub fn usb2_supported(&self) -> bool {
+        !bit(self.0, 5)
+    }
+
+    pub fn usb32_supported(&self) -> bool {
+        !bit(self.0, 4)
+    }
+
+    /// Number of USB SuperSpeed lanes: 1 or 2.
+    pub fn usb_lanes(&self) -> u8 {
+        if bit(self.0, 3) { 2 } else { 1 }
+    }
+
+    pub fn optically_isolated(&self) -> bool {
+        bit(self.0, 2)
+    }
+}
+
+/// Mode VDO from Discover Modes or Enter Mode, typed by its SVID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ModeVdo {
+    DisplayPort(DisplayPortCapabilitiesVdo),
+    Thunderbolt(ThunderboltDeviceModeVdo),
+    ThunderboltCable(ThunderboltCableModeVdo),
+    Other { svid: u16, vdo: u32 },
+}
+
+impl ModeVdo {
+    /// Type a mode VDO; Thunderbolt VDOs sent on SOP'/SOP'' describe the cable.
+    pub fn new(svid: u16, sop: PdSopType, vdo: u32) -> Self {
+        match svid {
+            DISPLAYPORT_SVID => Self::DisplayPort(DisplayPortCapabilitiesVdo(vdo)),
+            THUNDERBOLT_SVID if sop.is_cable_plug() => Self::ThunderboltCable(ThunderboltCableModeVdo(vdo)),
+            THUNDERBOLT_SVID => Self::Thunderbolt(ThunderboltDeviceModeVdo(vdo)),
+            _ => Self::Other { svid, vdo },
+        }
+    }
+}
+
+/// DisplayPort pin assignment of a USB Type-C connector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum DisplayPortPinAssignment {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+}
+
+impl DisplayPortPinAssignment {
+    const ALL: [Self; 6] = [Self::A, Self::B, Self::C, Self::D, Self::E, Self::F];
+
+    /// Pin assignments set in a DisplayPort pin assignment bit mask.
+    pub fn from_mask(mask: u8) -> Vec<Self> {
+        Self::ALL
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| mask & (1 << index) != 0)
+            .map(|(_, assignment)| assignment)
+            .collect()
+    }
+
+    /// Whether this assignment leaves two lanes for USB 3.x alongside DisplayPort.
+    pub fn is_multi_function(&self) -> bool {
+        matches!(self, Self::B | Self::D | Self::F)
+    }
+}
+
+/// DisplayPort roles a port is capable of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum DisplayPortRoles {
+    #[num_enum(default)]
+    None = 0,
+    UfpD = 1,
+    DfpD = 2,
+    Both = 3,
+}
+
+/// DisplayPort role connected, as reported in a DisplayPort Status VDO.
+///
+/// The encoding differs from [`DisplayPortRoles`]: here 1 is DFP_D.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum DisplayPortConnection {
+    #[num_enum(default)]
+    None = 0,
+    DfpD = 1,
+    UfpD = 2,
+    Both = 3,
+}
+
+/// DisplayPort Capabilities, the mode VDO of the DisplayPort SVID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DisplayPortCapabilitiesVdo(pub u32);
+
+impl DisplayPortCapabilitiesVdo {
+    pub fn port_capability(&self) -> DisplayPortRoles {
+        DisplayPortRoles::from_primitive(bits(self.0, 0, 2) as u8)
+    }
+
+    /// Signaling rates supported for the DisplayPort protocol, as a bit mask.
+    pub fn signaling(&self) -> u8 {
+        bits(self.0, 2, 4) as u8
+    }
+
+    /// Whether the mode is offered on a receptacle rather than a captive plug.
+    pub fn receptacle(&self) -> bool {
+        bit(self.0, 6)
+    }
+
+    pub fn usb2_signaling_not_used(&self) -> bool {
+        bit(self.0, 7)
+    }
+
+    /// Pin assignments supported as DFP_D.
+    ///
+    /// For a captive plug ([`Self::receptacle`] false) this field and
+    /// [`Self::ufp_d_pin_assignments`] are swapped by the specification.
+    pub fn dfp_d_pin_assignments(&self) -> Vec<DisplayPortPinAssignment> {
+        DisplayPortPinAssignment::from_mask(bits(self.0, 8, 8) as u8)
+    }
+
+    /// Pin assignments supported as UFP_D.
+    pub fn ufp_d_pin_assignments(&self) -> Vec<DisplayPortPinAssignment> {
+        DisplayPortPinAssignment::from_mask(bits(self.0, 16, 8) as u8)
+    }
+
+    /// Pin assignments a display sink offers, accounting for the plug/receptacle swap.
+    pub fn sink_pin_assignments(&self) -> Vec<DisplayPortPinAssignment> {
+        if self.receptacle() {
+            self.ufp_d_pin_assignments()
+        } else {
+            self.dfp_d_pin_assignments()
+        }
+    }
+}
+
+/// DisplayPort Status VDO, sent with Status Update and Attention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DisplayPortStatusVdo(pub u32);
+
+impl DisplayPortStatusVdo {
+    pub fn connected(&self) -> DisplayPortConnection {
+        DisplayPortConnection::from_primitive(bits(self.0, 0, 2) as u8)
+    }
+
+    pub fn power_low(&self) -> bool {
+        bit(self.0, 2)
+    }
+
+    /// Whether the DisplayPort functionality is enabled and operational.
+    pub fn enabled(&self) -> bool {
+        bit(self.0, 3)
+    }
+
+    pub fn multi_function_preferred(&self) -> bool {
+        bit(self.0, 4)
+    }
+
+    pub fn usb_configuration_request(&self) -> bool {
+        bit(self.0, 5)
+    }
+
+    pub fn exit_mode_request(&self) -> bool {
+        bit(self.0, 6)
+    }
+
+    /// Hot Plug Detect level reported by the UFP_D.
+    pub fn hpd_state(&self) -> bool {
+        bit(self.0, 7)
+    }
+
+    pub fn irq_hpd(&self) -> bool {
+        bit(self.0, 8)
+    }
+}
+
+/// Configuration selected by a DisplayPort Configure command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum DisplayPortConfiguration {
+    /// Return the connector to USB operation.
+    Usb = 0,
+    UfpUAsDfpD = 1,
+    UfpUAsUfpD = 2,
+    #[num_enum(catch_all)]
+    Reserved(u8),
+}
+
+/// DisplayPort Configure VDO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DisplayPortConfigureVdo(pub u32);
+
+impl DisplayPortConfigureVdo {
+    pub fn configuration(&self) -> DisplayPortConfiguration {
+        DisplayPortConfiguration::from_primitive(bits(self.0, 0, 2) as u8)
+    }
+
+    pub fn signaling(&self) -> u8 {
+        bits(self.0, 2, 4) as u8
+    }
+
+    /// Selected pin assignment; `None` when no single assignment is set.
+    pub fn pin_assignment(&self) -> Option<DisplayPortPinAssignment> {
+        match DisplayPortPinAssignment::from_mask(bits(self.0, 8, 8) as u8).as_slice() {
+            [assignment] => Some(*assignment),
+            _ => None,
+        }
+    }
+}
+
+/// Thunderbolt 3 Discover Modes VDO returned by a port partner on SOP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ThunderboltDeviceModeVdo(pub u32);
+
+impl ThunderboltDeviceModeVdo {
+    /// Whether the VDO identifies the Thunderbolt alternate mode (0x0001).
+    pub fn is_thunderbolt_mode(&self) -> bool {
+        bits(self.0, 0, 16) == 0x0001
+    }
+
+    /// Whether the adapter is a legacy Thunderbolt 2 adapter.
+    pub fn legacy_adapter(&self) -> bool {
+        bit(self.0, 16)
+    }
+
+    pub fn intel_specific_b0(&self) -> bool {
+        bit(self.0, 26)
+    }
+
+    pub fn vendor_specific_b0(&self) -> bool {
+        bit(self.0, 30)
+    }
+
+    pub fn vendor_specific_b1(&self) -> bool {
+        bit(self.0, 31)
+    }
+}
+
+/// Thunderbolt link speed supported by a cable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum ThunderboltCableSpeed {
+    /// USB 3.2 Gen 1 cable without Thunderbolt support.
+    Usb32Gen1 = 1,
+    /// 10 Gb/s: USB 3.2 Gen 2 and Thunderbolt 3 at 20 Gb/s.
+    Gen2 = 2,
+    /// 10 and 20 Gb/s: Thunderbolt 3 at 40 Gb/s.
+    Gen3 = 3,
+    #[num_enum(catch_all)]
+    Reserved(u8),
+}
+
+/// Thunderbolt 3 cable mode VDO, from SOP' Discover Modes and echoed in Enter Mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ThunderboltCableModeVdo(pub u32);
+
+impl ThunderboltCableModeVdo {
+    pub fn cable_speed(&self) -> ThunderboltCableSpeed {
+        ThunderboltCableSpeed::from_primitive(bits(self.0, 16, 3) as u8)
+    }
+
+    /// Rounded-frequency support generation field.
+    pub fn rounded_support(&self) -> u8 {
+        bits(self.0, 19, 2) as u8
+    }
+
+    pub fn optical(&self) -> bool {
+        bit(self.0, 21)
+    }
+
+    /// Whether an active cable uses retimers rather than redrivers.
+    pub fn retimer(&self) -> bool {
+        bit(self.0, 22)
+    }
+
+    pub fn unidirectional_lsrx(&self) -> bool {
+        bit(self.0, 23)
+    }
+
+    pub fn active(&self) -> bool {
+        bit(self.0, 25)
+    }
+}
@@ -0,0 +1,273 @@
+//! Raw USB PD wire-format fields.
+//!
+//! [`crate::pd`] extracts standard USB PD wire messages from the KM003C event
+//! framing. This module exposes their message headers and payload words
+//! directly, for analyzers that need fields the semantic `usbpd` decoder does
+//! not retain, such as the SOP* routing of a message or its Vendor Defined
+//! Objects.
+
+use modular_bitfield::prelude::*;
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+use crate::error::KMError;
+
+/// Size of the USB PD message header.
+pub const PD_HEADER_SIZE: usize = 2;
+
+/// Size of one USB PD data object.
+pub const PD_DATA_OBJECT_SIZE: usize = 4;
+
+/// Size of the extended-message header that follows the message header.
+pub const PD_EXTENDED_HEADER_SIZE: usize = 2;
+
+/// SOP* packet type on which a KM003C captured a USB PD message.
+///
+/// The numbering follows the order in which the USB PD specification lists the
+/// SOP* ordered sets; zero is the port-partner channel seen in every capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum PdSopType {
+    Sop = 0,
+    SopPrime = 1,
+    SopDoublePrime = 2,
+    SopPrimeDebug = 3,
+    SopDoublePrimeDebug = 4,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl PdSopType {
+    /// Whether messages of this type are addressed to or sent by a cable plug.
+    pub fn is_cable_plug(&self) -> bool {
+        matches!(self, Self::SopPrime | Self::SopDoublePrime)
+    }
+}
+
+/// USB PD message header.
+///
+/// Bits 5 and 8 are the port data and power roles on SOP. On SOP'/SOP'' bit 5
+/// is reserved and bit 8 is the Cable Plug flag; see
+/// [`Self::is_from_cable_plug`].
+#[bitfield(bytes = 2)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdMessageHeader {
+    pub message_type: B5,
+    pub port_data_role: bool,
+    pub spec_revision: B2,
+    pub port_power_role: bool,
+    pub message_id: B3,
+    pub num_data_objects: B3,
+    pub extended: bool,
+}
+
+impl PdMessageHeader {
+    /// Classify the five-bit message type using the extended flag and object count.
+    pub fn kind(&self) -> PdMessageType {
+        let message_type = self.message_type();
+        if self.extended() {
+            PdMessageType::Extended(PdExtendedMessageType::from_primitive(message_type))
+        } else if self.num_data_objects() == 0 {
+            PdMessageType::Control(PdControlMessageType::from_primitive(message_type))
+        } else {
+            PdMessageType::Data(PdDataMessageType::from_primitive(message_type))
+        }
+    }
+
+    /// Cable Plug flag of an SOP'/SOP'' message: set when the cable sent it.
+    pub fn is_from_cable_plug(&self) -> bool {
+        self.port_power_role()
+    }
+}
+
+/// Header of an extended USB PD message.
+#[bitfield(bytes = 2)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdExtendedHeader {
+    pub data_size: B9,
+    #[skip]
+    __: B1,
+    pub request_chunk: bool,
+    pub chunk_number: B4,
+    pub chunked: bool,
+}
+
+/// Message type of a USB PD message, classified by its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PdMessageType {
+    Control(PdControlMessageType),
+    Data(PdDataMessageType),
+    Extended(PdExtendedMessageType),
+}
+
+/// Control message types defined by USB PD Revision 3.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum PdControlMessageType {
+    GoodCrc = 0x01,
+    GotoMin = 0x02,
+    Accept = 0x03,
+    Reject = 0x04,
+    Ping = 0x05,
+    PsRdy = 0x06,
+    GetSourceCap = 0x07,
+    GetSinkCap = 0x08,
+    DrSwap = 0x09,
+    PrSwap = 0x0a,
+    VconnSwap = 0x0b,
+    Wait = 0x0c,
+    SoftReset = 0x0d,
+    DataReset = 0x0e,
+    DataResetComplete = 0x0f,
+    NotSupported = 0x10,
+    GetSourceCapExtended = 0x11,
+    GetStatus = 0x12,
+    FrSwap = 0x13,
+    GetPpsStatus = 0x14,
+    GetCountryCodes = 0x15,
+    GetSinkCapExtended = 0x16,
+    GetSourceInfo = 0x17,
+    GetRevision = 0x18,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Data message types defined by USB PD Revision 3.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum PdDataMessageType {
+    SourceCapabilities = 0x01,
+    Request = 0x02,
+    Bist = 0x03,
+    SinkCapabilities = 0x04,
+    BatteryStatus = 0x05,
+    Alert = 0x06,
+    GetCountryInfo = 0x07,
+    EnterUsb = 0x08,
+    EprRequest = 0x09,
+    EprMode = 0x0a,
+    SourceInfo = 0x0b,
+    Revision = 0x0c,
+    VendorDefined = 0x0f,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Extended message types defined by USB PD Revision 3.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum PdExtendedMessageType {
+    SourceCapabilitiesExtended = 0x01,
+    Status = 0x02,
+    GetBatteryCap = 0x03,
+    GetBatteryStatus = 0x04,
+    BatteryCapabilities = 0x05,
+    GetManufacturerInfo = 0x06,
+    ManufacturerInfo = 0x07,
+    SecurityRequest = 0x08,
+    SecurityResponse = 0x09,
+    FirmwareUpdateRequest = 0x0a,
+    FirmwareUpdateResponse = 0x0b,
+    PpsStatus = 0x0c,
+    CountryInfo = 0x0d,
+    CountryCodes = 0x0e,
+    SinkCapabilitiesExtended = 0x0f,
+    ExtendedControl = 0x10,
+    EprSourceCapabilities = 0x11,
+    EprSinkCapabilities = 0x12,
+    VendorDefinedExtended = 0x1e,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// A USB PD wire message split into its headers and payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdWireMessage {
+    pub header: PdMessageHeader,
+    /// Present when the header's extended flag is set.
+    pub extended_header: Option<PdExtendedHeader>,
+    /// Data objects of a data message, or the (chunk) data of an extended message.
+    pub payload: Vec<u8>,
+}
+
+impl PdWireMessage {
+    /// Split a wire message extracted by [`crate::pd::PdEventStream`].
+    ///
+    /// The payload is limited to the length announced by the header; any
+    /// bytes captured beyond that are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KMError> {
+        if bytes.len() < PD_HEADER_SIZE {
+            return Err(KMError::InvalidPacket(format!(
+                "USB PD message too short for header: need {PD_HEADER_SIZE}, got {}",
+                bytes.len()
+            )));
+        }
+
+        let header = PdMessageHeader::from_bytes([bytes[0], bytes[1]]);
+        let object_bytes = usize::from(header.num_data_objects()) * PD_DATA_OBJECT_SIZE;
+        if bytes.len() - PD_HEADER_SIZE < object_bytes {
+            return Err(KMError::InvalidPacket(format!(
+                "USB PD message announces {} data objects but carries {} payload bytes",
+                header.num_data_objects(),
+                bytes.len() - PD_HEADER_SIZE
+            )));
+        }
+        let body = &bytes[PD_HEADER_SIZE..PD_HEADER_SIZE + object_bytes];
+
+        if !header.extended() {
+            return Ok(Self {
+                header,
+                extended_header: None,
+                payload: body.to_vec(),
+            });
+        }
+
+        if body.len() < PD_EXTENDED_HEADER_SIZE {
+            return Err(KMError::InvalidPacket(
+                "Extended USB PD message is missing its extended header".to_string(),
+            ));
+        }
+        let extended_header = PdExtendedHeader::from_bytes([body[0], body[1]]);
+        let data = &body[PD_EXTENDED_HEADER_SIZE..];
+        // Unchunked payloads are padded to a whole data object; chunks are
+        // trimmed only once reassembled.
+        let data_size = usize::from(extended_header.data_size());
+        let payload = if extended_header.request_chunk() {
+            Vec::new()
+        } else if extended_header.chunked() {
+            data.to_vec()
+        } else {
+            data[..data_size.min(data.len())].to_vec()
+        };
+
+        Ok(Self {
+            header,
+            extended_header: Some(extended_header),
+            payload,
+        })
+    }
+
+    /// Message type classified by the header.
+    pub fn message_type(&self) -> PdMessageType {
+        self.header.kind()
+    }
+
+    /// Little-endian 32-bit data objects of a data message.
+    ///
+    /// Extended messages carry bytes rather than data objects; for them the
+    /// iterator is empty.
+    pub fn data_objects(&self) -> impl Iterator<Item = u32> + '_ {
+        let payload: &[u8] = if self.extended_header.is_some() {
+            &[]
+        } else {
+            &self.payload
+        };
+        payload
+            .chunks_exact(PD_DATA_OBJECT_SIZE)
+            .map(|object| u32::from_le_bytes([object[0], object[1], object[2], object[3]]))
+    }
+}
@@ -2,7 +2,8 @@
 //!
 //! This module exposes the KM003C USB-C analyzer protocol parsing capabilities
 //! to Python through PyO3 bindings. It provides both low-level packet parsing
-//! and high-level semantic interpretation of KM003C protocol data.
+//! and high-level semantic interpretation of KM003C protocol data, as well as
+//! a blocking `KM003C` class for talking to a live meter.
 //!
 //! # Main Functions
 //!
@@ -10,6 +11,15 @@
 //! - `parse_raw_packet()`: Parse bytes into low-level protocol structure (RawPacket)
 //! - `parse_raw_adc_data()`: Parse raw ADC bytes directly into measurements (AdcDataSimple)
 //! - `get_sample_rates()`: Get available device sample rates
+//! - `list_devices()`: List the meters attached to this host
+//!
+//! # Device Control
+//!
+//! `KM003C` wraps the async [`crate::device::KM003C`] on a shared Tokio
+//! runtime. Every call releases the GIL while it waits for the device, so
+//! other Python threads (and the asyncio wrapper in `km003c.aio`) keep
+//! running. `GraphStream` and `PdEventMonitor` turn AdcQueue polling and PD
+//! monitoring into Python iterators.
 //!
 //! # Protocol Overview
 //!
@@ -19,12 +29,24 @@
 
 use crate::adc::{AdcDataRaw, AdcDataSimple, SampleRate};
 use crate::adcqueue::{AdcQueueData, AdcQueueRawData, AdcQueueSample, AdcQueueSampleRaw, GraphSampleRate};
+use crate::device::{AttachedDevice, DeviceConfig, KM003C};
+use crate::error::KMError;
 use crate::message::Packet;
-use crate::packet::{CtrlHeader, LogicalPacket, RawPacket};
+use crate::offline::{LogMetadata, OfflineLog, OfflineLogSample};
+use crate::packet::{Attribute, AttributeSet, CtrlHeader, LogicalPacket, RawPacket};
 use crate::pd::{PdEvent, PdEventStream, PdStatus};
+use crate::pd_decode::PdSessionDecoder;
+use crate::pd_format::{PdEventCategory, format_event};
+use crate::pd_trace::{PdTrace, PdTraceProtocolEvent, PdTraceStateEvent};
+use crate::settings::Settings;
 use bytes::Bytes;
+use pyo3::exceptions::{PyConnectionError, PyOSError, PyRuntimeError, PyTimeoutError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::PyBytes;
+use pyo3::types::{PyBytes, PyDict};
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use uom::si::time::second;
 
 /// Parse raw ADC data bytes directly into processed measurements.
 ///
@@ -201,10 +223,505 @@ pub fn create_packet<'py>(py: Python<'py>, packet_type: u8, transaction_id: u8,
     PyBytes::new(py, &header.into_bytes())
 }
 
-/// Python module for KM003C USB-C power analyzer protocol parsing.
+/// Poll interval of `PdEventMonitor`; the meter buffers events in between.
+const PD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runtime driving every `KM003C` instance of the process.
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .expect("failed to start the Tokio runtime")
+    })
+}
+
+/// Map a library error to the closest built-in Python exception.
+fn device_error(error: KMError) -> PyErr {
+    match error {
+        KMError::DeviceNotFound => PyConnectionError::new_err(error.to_string()),
+        KMError::Timeout(_) => PyTimeoutError::new_err(error.to_string()),
+        KMError::Io(error) => error.into(),
+        KMError::Usb(_) => PyOSError::new_err(error.to_string()),
+        _ => PyRuntimeError::new_err(error.to_string()),
+    }
+}
+
+fn graph_rate(rate_index: u16) -> PyResult<GraphSampleRate> {
+    GraphSampleRate::try_from(rate_index)
+        .map_err(|_| PyValueError::new_err(format!("Invalid graph sample rate index: {rate_index}")))
+}
+
+fn deadline(duration: Option<f64>) -> PyResult<Option<Instant>> {
+    duration
+        .map(|seconds| {
+            Duration::try_from_secs_f64(seconds)
+                .map(|duration| Instant::now() + duration)
+                .map_err(|_| PyValueError::new_err(format!("Invalid duration: {seconds}")))
+        })
+        .transpose()
+}
+
+/// List the KM003C meters attached to this host.
+///
+/// Returns:
+///     List[AttachedDevice]: One entry per meter with .index, .bus_id and .address.
+///                           Pass .index as `device_index` to select a meter.
+#[pyfunction(name = "list_devices")]
+pub fn py_list_devices(py: Python<'_>) -> PyResult<Vec<AttachedDevice>> {
+    py.detach(|| runtime().block_on(crate::device::list_devices()))
+        .map_err(device_error)
+}
+
+/// Blocking connection to a POWER-Z KM003C.
+///
+/// Args:
+///     interface: "vendor" (full mode, AdcQueue streaming) or "hid" (basic mode, ADC/PD polling only)
+///     device_index: Which meter to open when several are attached, see list_devices()
+///     reset: Reset the USB device before opening it (disable on macOS if connecting fails)
+///     remote: Open a meter shared by bridge-server at tcp://host[:port] instead of USB
+///     bridge_key: Pre-shared key of the bridge server
+///
+/// Raises:
+///     ConnectionError: If no meter is attached
+///     TimeoutError: If the meter stops answering
+///     OSError: On USB or network failures
+///     ValueError: On an unknown interface or malformed remote URL
+///
+/// Example:
+///     ```text
+///     with KM003C() as meter:
+///         print(meter.request_adc_data())
+///         with meter.stream(RATE_1000_SPS, duration=5.0) as samples:
+///             for sample in samples:
+///                 print(sample.vbus_v, sample.ibus_a)
+///     ```
+#[pyclass(frozen, name = "KM003C")]
+pub struct PyKM003C {
+    /// `None` once closed.
+    device: tokio::sync::Mutex<Option<KM003C>>,
+}
+
+impl PyKM003C {
+    /// Run one operation on the device with the GIL released.
+    fn call<T: Send>(
+        &self,
+        py: Python<'_>,
+        operation: impl AsyncFnOnce(&mut KM003C) -> Result<T, KMError> + Send,
+    ) -> PyResult<T> {
+        py.detach(|| {
+            runtime().block_on(async {
+                let mut device = self.device.lock().await;
+                let device = device
+                    .as_mut()
+                    .ok_or_else(|| PyRuntimeError::new_err("Device is closed"))?;
+                operation(device).await.map_err(device_error)
+            })
+        })
+    }
+}
+
+#[pymethods]
+impl PyKM003C {
+    #[new]
+    #[pyo3(signature = (interface = "vendor", *, device_index = 0, reset = true, remote = None, bridge_key = None))]
+    fn new(
+        py: Python<'_>,
+        interface: &str,
+        device_index: usize,
+        reset: bool,
+        remote: Option<&str>,
+        bridge_key: Option<&[u8]>,
+    ) -> PyResult<Self> {
+        let mut config = match (remote, interface) {
+            (Some(url), _) => DeviceConfig::remote(url).map_err(|error| PyValueError::new_err(error.to_string()))?,
+            (None, "vendor") => DeviceConfig::vendor(),
+            (None, "hid") => DeviceConfig::hid(),
+            (None, other) => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown interface {other:?}, expected \"vendor\" or \"hid\""
+                )));
+            }
+        };
+        config = config.device_index(device_index);
+        if !reset {
+            config = config.skip_reset();
+        }
+        if let Some(key) = bridge_key {
+            config = config.bridge_key(key);
+        }
+
+        let device = py
+            .detach(|| runtime().block_on(KM003C::new(config)))
+            .map_err(device_error)?;
+        Ok(Self {
+            device: tokio::sync::Mutex::new(Some(device)),
+        })
+    }
+
+    /// Whether the vendor interface is open (all features available).
+    #[getter]
+    fn is_full_mode(&self, py: Python<'_>) -> PyResult<bool> {
+        self.call(py, async |device| Ok(device.is_full_mode()))
+    }
+
+    /// Whether StreamingAuth unlocked AdcQueue graph streaming.
+    #[getter]
+    fn adcqueue_enabled(&self, py: Python<'_>) -> PyResult<bool> {
+        self.call(py, async |device| Ok(device.adcqueue_enabled()))
+    }
+
+    /// Model, versions and serial read during init, or None in basic mode.
+    #[getter]
+    fn device_info<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let Some(state) = self.call(py, async |device| Ok(device.state().cloned()))? else {
+            return Ok(None);
+        };
+        let dict = PyDict::new(py);
+        dict.set_item("model", &state.info.model)?;
+        dict.set_item("hw_version", &state.info.hw_version)?;
+        dict.set_item("mfg_date", &state.info.mfg_date)?;
+        dict.set_item("fw_version", &state.info.fw_version)?;
+        dict.set_item("fw_date", &state.info.fw_date)?;
+        dict.set_item("serial_id", &state.info.serial_id)?;
+        dict.set_item("uuid", &state.info.uuid)?;
+        dict.set_item("hardware_id", state.hardware_id.to_string())?;
+        dict.set_item("auth_level", state.auth_level)?;
+        Ok(Some(dict))
+    }
+
+    /// Read one ADC measurement.
+    fn request_adc_data(&self, py: Python<'_>) -> PyResult<AdcDataSimple> {
+        self.call(py, async |device| device.request_adc_data().await)
+    }
+
+    /// Read the PD attribute: a packet holding PdStatus or a PdEventStream.
+    fn request_pd_data(&self, py: Python<'_>) -> PyResult<Packet> {
+        self.call(py, async |device| device.request_pd_data().await)
+    }
+
+    /// Read the persisted device settings as a dict.
+    fn request_settings(&self, py: Python<'_>) -> PyResult<Settings> {
+        self.call(py, async |device| device.request_settings().await)
+    }
+
+    /// Read the PD state machine trace queues.
+    fn request_pd_trace(&self, py: Python<'_>) -> PyResult<PdTrace> {
+        self.call(py, async |device| device.request_pd_trace().await)
+    }
+
+    /// Catalog of offline logs recorded on the meter.
+    fn request_log_metadata(&self, py: Python<'_>) -> PyResult<Vec<LogMetadata>> {
+        self.call(py, async |device| device.request_log_metadata().await)
+    }
+
+    /// Download the samples of one offline log from request_log_metadata().
+    fn download_offline_log(&self, py: Python<'_>, metadata: PyRef<'_, LogMetadata>) -> PyResult<OfflineLog> {
+        let metadata = metadata.clone();
+        self.call(py, async |device| device.download_offline_log(metadata).await)
+    }
+
+    /// Start AdcQueue graph mode at a RATE_* index.
+    fn start_graph_mode(&self, py: Python<'_>, rate_index: u16) -> PyResult<()> {
+        let rate = graph_rate(rate_index)?;
+        self.call(py, async |device| device.start_graph_mode(rate).await)
+    }
+
+    /// Stop AdcQueue graph mode.
+    fn stop_graph_mode(&self, py: Python<'_>) -> PyResult<()> {
+        self.call(py, async |device| device.stop_graph_mode().await)
+    }
+
+    /// Fetch the samples buffered since the last poll while graph mode is running.
+    fn read_graph_samples(&self, py: Python<'_>) -> PyResult<Vec<AdcQueueSample>> {
+        self.call(py, async |device| {
+            let packet = device.request_data(AttributeSet::single(Attribute::AdcQueue)).await?;
+            Ok(packet
+                .get_adc_queue()
+                .map(|queue| queue.samples.clone())
+                .unwrap_or_default())
+        })
+    }
+
+    /// Ask the meter to record PD events.
+    fn enable_pd_monitor(&self, py: Python<'_>) -> PyResult<()> {
+        self.call(py, async |device| device.enable_pd_monitor().await)
+    }
+
+    /// Stop recording PD events.
+    fn disable_pd_monitor(&self, py: Python<'_>) -> PyResult<()> {
+        self.call(py, async |device| device.disable_pd_monitor().await)
+    }
+
+    /// Fetch the PD events recorded since the last poll.
+    fn read_pd_events(&self, py: Python<'_>) -> PyResult<Vec<PdEvent>> {
+        self.call(py, async |device| {
+            let packet = device.request_pd_data().await?;
+            Ok(KM003C::extract_pd_events(&packet)
+                .map(|stream| stream.events.clone())
+                .unwrap_or_default())
+        })
+    }
+
+    /// Start graph mode and iterate over its samples.
+    ///
+    /// Args:
+    ///     rate_index: RATE_* constant, 50 SPS by default
+    ///     duration: Stop after this many seconds; None streams until close()
+    #[pyo3(signature = (rate_index = GraphSampleRate::Sps50 as u16, duration = None))]
+    fn stream(slf: Bound<'_, Self>, rate_index: u16, duration: Option<f64>) -> PyResult<GraphStream> {
+        let rate = graph_rate(rate_index)?;
+        let deadline = deadline(duration)?;
+        let py = slf.py();
+        let device = slf.get();
+        device.call(py, async |device| {
+            let _ = device.stop_graph_mode().await;
+            device.start_graph_mode(rate).await
+        })?;
+        Ok(GraphStream {
+            device: slf.unbind(),
+            rate,
+            deadline,
+            pending: VecDeque::new(),
+            active: true,
+        })
+    }
+
+    /// Enable PD monitoring and iterate over the captured events.
+    ///
+    /// Args:
+    ///     duration: Stop after this many seconds; None monitors until close()
+    #[pyo3(signature = (duration = None))]
+    fn pd_events(slf: Bound<'_, Self>, duration: Option<f64>) -> PyResult<PdEventMonitor> {
+        let deadline = deadline(duration)?;
+        slf.get().enable_pd_monitor(slf.py())?;
+        Ok(PdEventMonitor {
+            device: slf.unbind(),
+            deadline,
+            pending: VecDeque::new(),
+            active: true,
+        })
+    }
+
+    /// Release the USB interface or bridge connection.
+    fn close(&self, py: Python<'_>) {
+        py.detach(|| runtime().block_on(async { drop(self.device.lock().await.take()) }));
+    }
+
+    fn __enter__(slf: Bound<'_, Self>) -> Bound<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        _exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) {
+        self.close(py);
+    }
+}
+
+/// Iterator over AdcQueue samples returned by `KM003C.stream()`.
+///
+/// Graph mode is stopped when the iterator is exhausted, closed, leaves a
+/// `with` block, or is garbage-collected.
+#[pyclass]
+pub struct GraphStream {
+    device: Py<PyKM003C>,
+    rate: GraphSampleRate,
+    deadline: Option<Instant>,
+    pending: VecDeque<AdcQueueSample>,
+    active: bool,
+}
+
+#[pymethods]
+impl GraphStream {
+    #[getter]
+    fn rate_index(&self) -> u16 {
+        self.rate as u16
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<AdcQueueSample>> {
+        loop {
+            if let Some(sample) = self.pending.pop_front() {
+                return Ok(Some(sample));
+            }
+            if !self.active || self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                self.close(py)?;
+                return Ok(None);
+            }
+            py.check_signals()?;
+            self.pending.extend(self.device.get().read_graph_samples(py)?);
+            if self.pending.is_empty() {
+                let interval = self.rate.poll_interval();
+                py.detach(|| std::thread::sleep(interval));
+            }
+        }
+    }
+
+    /// Stop graph mode; samples already fetched are still returned.
+    fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+        if std::mem::take(&mut self.active) {
+            self.device.get().stop_graph_mode(py)?;
+        }
+        Ok(())
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<()> {
+        self.close(py)
+    }
+}
+
+impl Drop for GraphStream {
+    fn drop(&mut self) {
+        if self.active {
+            // Errors cannot be raised from a destructor; the meter may already be closed.
+            Python::attach(|py| {
+                let _ = self.close(py);
+            });
+        }
+    }
+}
+
+/// Iterator over PD events returned by `KM003C.pd_events()`.
+///
+/// PD monitoring is disabled when the iterator is exhausted, closed, leaves a
+/// `with` block, or is garbage-collected. Feed the events to
+/// `PdSessionDecoder` to decode them.
+#[pyclass]
+pub struct PdEventMonitor {
+    device: Py<PyKM003C>,
+    deadline: Option<Instant>,
+    pending: VecDeque<PdEvent>,
+    active: bool,
+}
+
+#[pymethods]
+impl PdEventMonitor {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PdEvent>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event));
+            }
+            if !self.active || self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                self.close(py)?;
+                return Ok(None);
+            }
+            py.check_signals()?;
+            self.pending.extend(self.device.get().read_pd_events(py)?);
+            if self.pending.is_empty() {
+                py.detach(|| std::thread::sleep(PD_POLL_INTERVAL));
+            }
+        }
+    }
+
+    /// Disable PD monitoring; events already fetched are still returned.
+    fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+        if std::mem::take(&mut self.active) {
+            self.device.get().disable_pd_monitor(py)?;
+        }
+        Ok(())
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<()> {
+        self.close(py)
+    }
+}
+
+impl Drop for PdEventMonitor {
+    fn drop(&mut self) {
+        if self.active {
+            // Errors cannot be raised from a destructor; the meter may already be closed.
+            Python::attach(|py| {
+                let _ = self.close(py);
+            });
+        }
+    }
+}
+
+/// Stateful decoder turning PdEvents into readable USB PD messages.
+///
+/// Keeps source capabilities between events so Requests are decoded against
+/// the PDO they select, and reassembles chunked extended messages.
+///
+/// Example:
+///     ```text
+///     decoder = PdSessionDecoder()
+///     for event in meter.pd_events(duration=10.0):
+///         print(decoder.decode(event)["text"])
+///     ```
+#[pyclass(name = "PdSessionDecoder")]
+#[derive(Default)]
+pub struct PyPdSessionDecoder {
+    decoder: PdSessionDecoder,
+}
+
+#[pymethods]
+impl PyPdSessionDecoder {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode one event.
+    ///
+    /// Returns:
+    ///     dict: timestamp_s, category (PdEventCategory), summary, details (list of str),
+    ///           text and verbose_text (multi-line renderings)
+    fn decode<'py>(&mut self, py: Python<'py>, event: PyRef<'_, PdEvent>) -> PyResult<Bound<'py, PyDict>> {
+        let formatted = format_event(&self.decoder.decode_event(&event));
+        let dict = PyDict::new(py);
+        dict.set_item("timestamp_s", formatted.timestamp.get::<second>())?;
+        dict.set_item("category", formatted.category)?;
+        dict.set_item("summary", &formatted.summary)?;
+        dict.set_item("details", &formatted.details)?;
+        dict.set_item("text", formatted.text())?;
+        dict.set_item("verbose_text", formatted.verbose_text())?;
+        Ok(dict)
+    }
+
+    /// Forget the state of the current connection.
+    fn reset(&mut self) {
+        self.decoder.reset();
+    }
+}
+
+/// Python module for KM003C USB-C power analyzer protocol parsing and device control.
 ///
 /// This module provides comprehensive support for parsing and analyzing
-/// KM003C protocol data captured from USB traffic.
+/// KM003C protocol data captured from USB traffic, and for driving a live meter.
 ///
 /// Constants:
 ///   VID: USB Vendor ID for ChargerLAB (0x5FC9)
@@ -224,6 +741,21 @@ fn km003c_lib(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PdEvent>()?;
     m.add_class::<PdEventStream>()?;
     m.add_class::<LogicalPacket>()?;
+    m.add_class::<PdTrace>()?;
+    m.add_class::<PdTraceStateEvent>()?;
+    m.add_class::<PdTraceProtocolEvent>()?;
+    m.add_class::<LogMetadata>()?;
+    m.add_class::<OfflineLog>()?;
+    m.add_class::<OfflineLogSample>()?;
+
+    // Device control
+    m.add_class::<AttachedDevice>()?;
+    m.add_class::<PyKM003C>()?;
+    m.add_class::<GraphStream>()?;
+    m.add_class::<PdEventMonitor>()?;
+    m.add_class::<PyPdSessionDecoder>()?;
+    m.add_class::<PdEventCategory>()?;
+    m.add_function(wrap_pyfunction!(py_list_devices, m)?)?;
 
     // Parsing functions
     m.add_function(wrap_pyfunction!(parse_raw_adc_data, m)?)?;
@@ -0,0 +1,437 @@
+//! Parquet and CSV recordings in the schema shared by the CLI tools and the GUI.
+//!
+//! Every file has the same 23 columns whether it was captured live or
+//! converted from an offline recording. Channels a source cannot provide, such
+//! as CC and D+/D- voltages in offline recordings, are null. Parquet files also
+//! carry `km003c.*` key-value metadata identifying the device and the source.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use polars::df;
+use polars::io::csv::write::BatchedWriter as CsvBatchedWriter;
+use polars::io::parquet::write::BatchedWriter as ParquetBatchedWriter;
+use polars::prelude::{CsvWriter, DataFrame, KeyValueMetadata, ParquetWriter, PolarsResult, SerWriter};
+use uom::si::power::microwatt;
+use uom::si::time::microsecond;
+
+use crate::auth::DeviceInfo;
+use crate::error::KMError;
+use crate::measurement::MeasurementSample;
+use crate::offline::{LogMetadata, OfflineLog};
+
+/// Value of the `km003c.schema_version` key; bumped when columns change.
+pub const RECORDING_SCHEMA_VERSION: &str = "1";
+/// Column names in file order.
+pub const RECORDING_COLUMNS: [&str; 23] = [
+    "elapsed_us",
+    "sample_index",
+    "sequence",
+    "marker",
+    "sample_rate_hz",
+    "missing_samples",
+    "gap_duration_us",
+    "interpolated",
+    "cumulative_missing_samples",
+    "cumulative_interpolated_duration_us",
+    "discarded_sequence_samples",
+    "cumulative_discarded_sequence_samples",
+    "vbus_uv",
+    "ibus_ua",
+    "power_uw",
+    "charge_uah",
+    "energy_uwh",
+    "charge_throughput_uah",
+    "energy_throughput_uwh",
+    "cc1_uv",
+    "cc2_uv",
+    "dp_uv",
+    "dm_uv",
+];
+/// Rows per Parquet row group, and per batch handed to the underlying writer.
+pub const ROW_GROUP_SIZE: usize = 8_192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingFormat {
+    Parquet,
+    Csv,
+}
+
+impl RecordingFormat {
+    pub const ALL: [Self; 2] = [Self::Parquet, Self::Csv];
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Parquet => "Parquet",
+            Self::Csv => "CSV",
+        }
+    }
+
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Parquet => "parquet",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+/// Device identity and origin stored in Parquet key-value metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingMetadata {
+    pub model: String,
+    pub firmware: String,
+    pub serial: String,
+    /// Catalog entry of a converted offline recording; `None` for live captures.
+    pub offline: Option<LogMetadata>,
+}
+
+impl RecordingMetadata {
+    /// Metadata for AdcQueue samples integrated on the host.
+    pub fn live(device: &DeviceInfo) -> Self {
+        Self {
+            model: device.model.clone(),
+            firmware: device.fw_version.clone(),
+            serial: device.serial_id.clone(),
+            offline: None,
+        }
+    }
+
+    /// Metadata for an offline recording with device-side accumulators.
+    pub fn offline(device: &DeviceInfo, log: &LogMetadata) -> Self {
+        Self {
+            offline: Some(log.clone()),
+            ..Self::live(device)
+        }
+    }
+
+    /// The `km003c.*` key-value pairs written to Parquet files.
+    pub fn key_values(&self) -> Vec<(String, String)> {
+        let (source, accumulator_source) = match self.offline {
+            Some(_) => ("offline", "device"),
+            None => ("live", "host_trapezoidal"),
+        };
+        let mut pairs = vec![
+            ("km003c.schema_version", RECORDING_SCHEMA_VERSION.to_string()),
+            ("km003c.source", source.to_string()),
+            ("km003c.accumulator_source", accumulator_source.to_string()),
+            ("km003c.model", self.model.clone()),
+            ("km003c.firmware", self.firmware.clone()),
+            ("km003c.serial", self.serial.clone()),
+        ];
+        if let Some(log) = &self.offline {
+            pairs.extend([
+                ("km003c.offline.filename", log.filename_lossy().into_owned()),
+                (
+                    "km003c.offline.interval_us",
+                    log.interval.get::<microsecond>().round().to_string(),
+                ),
+                ("km003c.offline.flags", format!("0x{:04x}", log.flags)),
+            ]);
+        }
+        pairs.into_iter().map(|(key, value)| (key.to_string(), value)).collect()
+    }
+}
+
+/// One row of the recording schema.
+///
+/// Stream-quality and auxiliary channels are `None` when the source does not
+/// record them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingRow {
+    pub elapsed_us: u64,
+    pub sample_index: u64,
+    pub sequence: Option<u32>,
+    pub marker: Option<u32>,
+    pub sample_rate_hz: Option<u32>,
+    pub missing_samples: Option<u32>,
+    pub gap_duration_us: Option<u64>,
+    pub interpolated: Option<bool>,
+    pub cumulative_missing_samples: Option<u64>,
+    pub cumulative_interpolated_duration_us: Option<u64>,
+    pub discarded_sequence_samples: Option<u32>,
+    pub cumulative_discarded_sequence_samples: Option<u64>,
+    pub vbus_uv: i64,
+    pub ibus_ua: i64,
+    pub power_uw: i64,
+    pub charge_uah: f64,
+    pub energy_uwh: f64,
+    pub charge_throughput_uah: f64,
+    pub energy_throughput_uwh: f64,
+    pub cc1_uv: Option<i64>,
+    pub cc2_uv: Option<i64>,
+    pub dp_uv: Option<i64>,
+    pub dm_uv: Option<i64>,
+}
+
+impl RecordingRow {
+    /// Row for a live measurement, with time, quality counters and accumulators
+    /// relative to `origin`, the last sample before the recording started.
+    pub fn from_measurement(sample: &MeasurementSample, origin: Option<&MeasurementSample>, sample_index: u64) -> Self {
+        let relative = |value: fn(&MeasurementSample) -> u64| value(sample).saturating_sub(origin.map_or(0, value));
+        let offset = |value: fn(&MeasurementSample) -> f64| value(sample) - origin.map_or(0.0, value);
+        Self {
+            elapsed_us: relative(|sample| sample.elapsed_us),
+            sample_index,
+            sequence: Some(u32::from(sample.sequence)),
+            marker: Some(u32::from(sample.marker)),
+            sample_rate_hz: Some(u32::from(sample.sample_rate_hz)),
+            missing_samples: Some(u32::from(sample.missing_samples)),
+            gap_duration_us: Some(sample.gap_duration_us),
+            interpolated: Some(sample.interpolated),
+            cumulative_missing_samples: Some(relative(|sample| sample.cumulative_missing_samples)),
+            cumulative_interpolated_duration_us: Some(relative(|sample| sample.cumulative_interpolated_duration_us)),
+            discarded_sequence_samples: Some(sample.discarded_sequence_samples),
+            cumulative_discarded_sequence_samples: Some(relative(|sample| {
+                sample.cumulative_discarded_sequence_samples
+            })),
+            vbus_uv: sample.vbus_uv,
+            ibus_ua: sample.ibus_ua,
+            power_uw: sample.power_uw,
+            charge_uah: offset(|sample| sample.charge_uah),
+            energy_uwh: offset(|sample| sample.energy_uwh),
+            charge_throughput_uah: offset(|sample| sample.charge_throughput_uah),
+            energy_throughput_uwh: offset(|sample| sample.energy_throughput_uwh),
+            cc1_uv: Some(sample.cc1_uv),
+            cc2_uv: Some(sample.cc2_uv),
+            dp_uv: Some(sample.dp_uv),
+            dm_uv: Some(sample.dm_uv),
+        }
+    }
+
+    /// Rows for an offline recording.
+    ///
+    /// Charge and energy are the device's own counters; throughput sums the
+    /// absolute change of those counters between samples.
+    pub fn from_offline_log(log: &OfflineLog) -> Vec<Self> {
+        let interval_us = log.metadata.interval.get::<microsecond>().round() as u64;
+        let mut previous_charge_uah = 0_i32;
+        let mut previous_energy_uwh = 0_i32;
+        let mut charge_throughput_uah = 0_u64;
+        let mut energy_throughput_uwh = 0_u64;
+        log.samples
+            .iter()
+            .enumerate()
+            .map(|(index, sample)| {
+                let raw = sample.raw();
+                charge_throughput_uah =
+                    charge_throughput_uah.saturating_add(u64::from(raw.charge_uah.abs_diff(previous_charge_uah)));
+                energy_throughput_uwh =
+                    energy_throughput_uwh.saturating_add(u64::from(raw.energy_uwh.abs_diff(previous_energy_uwh)));
+                previous_charge_uah = raw.charge_uah;
+                previous_energy_uwh = raw.energy_uwh;
+                Self {
+                    elapsed_us: (index as u64).saturating_mul(interval_us),
+                    sample_index: index as u64,
+                    sequence: None,
+                    marker: None,
+                    sample_rate_hz: None,
+                    missing_samples: None,
+                    gap_duration_us: None,
+                    interpolated: None,
+                    cumulative_missing_samples: None,
+                    cumulative_interpolated_duration_us: None,
+                    discarded_sequence_samples: None,
+                    cumulative_discarded_sequence_samples: None,
+                    vbus_uv: i64::from(raw.voltage_uv),
+                    ibus_ua: i64::from(raw.current_ua),
+                    power_uw: sample.power.get::<microwatt>().round() as i64,
+                    charge_uah: f64::from(raw.charge_uah),
+                    energy_uwh: f64::from(raw.energy_uwh),
+                    charge_throughput_uah: charge_throughput_uah as f64,
+                    energy_throughput_uwh: energy_throughput_uwh as f64,
+                    cc1_uv: None,
+                    cc2_uv: None,
+                    dp_uv: None,
+                    dm_uv: None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Convert rows to a `DataFrame` with the recording schema.
+pub fn rows_to_dataframe(rows: &[RecordingRow]) -> PolarsResult<DataFrame> {
+    df!(
+        "elapsed_us" => rows.iter().map(|row| row.elapsed_us).collect::<Vec<_>>(),
+        "sample_index" => rows.iter().map(|row| row.sample_index).collect::<Vec<_>>(),
+        "sequence" => rows.iter().map(|row| row.sequence).collect::<Vec<_>>(),
+        "marker" => rows.iter().map(|row| row.marker).collect::<Vec<_>>(),
+        "sample_rate_hz" => rows.iter().map(|row| row.sample_rate_hz).collect::<Vec<_>>(),
+        "missing_samples" => rows.iter().map(|row| row.missing_samples).collect::<Vec<_>>(),
+        "gap_duration_us" => rows.iter().map(|row| row.gap_duration_us).collect::<Vec<_>>(),
+        "interpolated" => rows.iter().map(|row| row.interpolated).collect::<Vec<_>>(),
+        "cumulative_missing_samples" => rows.iter().map(|row| row.cumulative_missing_samples).collect::<Vec<_>>(),
+        "cumulative_interpolated_duration_us" => rows.iter().map(|row| row.cumulative_interpolated_duration_us).collect::<Vec<_>>(),
+        "discarded_sequence_samples" => rows.iter().map(|row| row.discarded_sequence_samples).collect::<Vec<_>>(),
+        "cumulative_discarded_sequence_samples" => rows.iter().map(|row| row.cumulative_discarded_sequence_samples).collect::<Vec<_>>(),
+        "vbus_uv" => rows.iter().map(|row| row.vbus_uv).collect::<Vec<_>>(),
+        "ibus_ua" => rows.iter().map(|row| row.ibus_ua).collect::<Vec<_>>(),
+        "power_uw" => rows.iter().map(|row| row.power_uw).collect::<Vec<_>>(),
+        "charge_uah" => rows.iter().map(|row| row.charge_uah).collect::<Vec<_>>(),
+        "energy_uwh" => rows.iter().map(|row| row.energy_uwh).collect::<Vec<_>>(),
+        "charge_throughput_uah" => rows.iter().map(|row| row.charge_throughput_uah).collect::<Vec<_>>(),
+        "energy_throughput_uwh" => rows.iter().map(|row| row.energy_throughput_uwh).collect::<Vec<_>>(),
+        "cc1_uv" => rows.iter().map(|row| row.cc1_uv).collect::<Vec<_>>(),
+        "cc2_uv" => rows.iter().map(|row| row.cc2_uv).collect::<Vec<_>>(),
+        "dp_uv" => rows.iter().map(|row| row.dp_uv).collect::<Vec<_>>(),
+        "dm_uv" => rows.iter().map(|row| row.dm_uv).collect::<Vec<_>>(),
+    )
+}
+
+enum RecordingSink<W: Write> {
+    Parquet(Box<ParquetBatchedWriter<W>>),
+    Csv(Box<CsvBatchedWriter<W>>),
+}
+
+impl<W: Write> RecordingSink<W> {
+    fn new(writer: W, format: RecordingFormat, metadata: &RecordingMetadata) -> Result<Self, KMError> {
+        let empty = rows_to_dataframe(&[])?;
+        Ok(match format {
+            RecordingFormat::Parquet => Self::Parquet(Box::new(
+                ParquetWriter::new(writer)
+                    .with_key_value_metadata(Some(KeyValueMetadata::from_static(metadata.key_values())))
+                    .with_row_group_size(Some(ROW_GROUP_SIZE))
+                    .batched(empty.schema())?,
+            )),
+            RecordingFormat::Csv => Self::Csv(Box::new(CsvWriter::new(writer).batched(empty.schema())?)),
+        })
+    }
+
+    fn write_batch(&mut self, rows: &[RecordingRow]) -> Result<(), KMError> {
+        let dataframe = rows_to_dataframe(rows)?;
+        match self {
+            Self::Parquet(writer) => writer.write_batch(&dataframe)?,
+            Self::Csv(writer) => writer.write_batch(&dataframe)?,
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), KMError> {
+        match self {
+            Self::Parquet(writer) => {
+                writer.finish()?;
+            }
+            Self::Csv(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// Streams rows to `<path>.partial` in batches and renames it to `path` on `finish`.
+///
+/// A writer that is dropped, or a process that dies, before `finish` leaves
+/// only the `.partial` file, so `path` is never a truncated recording.
+pub struct RecordingWriter {
+    path: PathBuf,
+    partial_path: PathBuf,
+    sink: RecordingSink<BufWriter<File>>,
+    buffered: Vec<RecordingRow>,
+    rows: u64,
+}
+
+impl RecordingWriter {
+    pub fn create(
+        path: impl Into<PathBuf>,
+        format: RecordingFormat,
+        metadata: &RecordingMetadata,
+    ) -> Result<Self, KMError> {
+        let path = path.into();
+        let partial_path = partial_path(&path);
+        let file = BufWriter::new(File::create(&partial_path)?);
+        let sink = RecordingSink::new(file, format, metadata)?;
+        Ok(Self {
+            path,
+            partial_path,
+            sink,
+            buffered: Vec::with_capacity(ROW_GROUP_SIZE),
+            rows: 0,
+        })
+    }
+
+    /// Final path of the recording.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path written until `finish` succeeds.
+    pub fn partial_path(&self) -> &Path {
+        &self.partial_path
+    }
+
+    /// Rows accepted so far, including those still buffered.
+    pub const fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Buffer rows, writing a batch once `ROW_GROUP_SIZE` rows are pending.
+    pub fn write_rows(&mut self, rows: &[RecordingRow]) -> Result<(), KMError> {
+        self.buffered.extend_from_slice(rows);
+        self.rows += rows.len() as u64;
+        if self.buffered.len() >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the remaining rows, close the file and move it to its final path.
+    pub fn finish(mut self) -> Result<PathBuf, KMError> {
+        self.flush()?;
+        self.sink.finish()?;
+        drop(self.sink);
+        replace_file(&self.partial_path, &self.path)?;
+        Ok(self.path)
+    }
+
+    fn flush(&mut self) -> Result<(), KMError> {
+        if self.buffered.is_empty() {
+            return Ok(());
+        }
+        self.sink.write_batch(&self.buffered)?;
+        self.buffered.clear();
+        Ok(())
+    }
+}
+
+/// Write a complete recording through a `RecordingWriter`.
+pub fn write_recording(
+    path: impl Into<PathBuf>,
+    format: RecordingFormat,
+    metadata: &RecordingMetadata,
+    rows: &[RecordingRow],
+) -> Result<PathBuf, KMError> {
+    let mut writer = RecordingWriter::create(path, format, metadata)?;
+    writer.write_rows(rows)?;
+    writer.finish()
+}
+
+/// Write a complete recording to `writer`, such as an in-memory buffer.
+///
+/// Unlike [`write_recording`] nothing is renamed into place, so a failure can
+/// leave a truncated recording in `writer`.
+pub fn write_recording_to(
+    writer: impl Write,
+    format: RecordingFormat,
+    metadata: &RecordingMetadata,
+    rows: &[RecordingRow],
+) -> Result<(), KMError> {
+    let mut sink = RecordingSink::new(writer, format, metadata)?;
+    for batch in rows.chunks(ROW_GROUP_SIZE) {
+        sink.write_batch(batch)?;
+    }
+    sink.finish()
+}
+
+/// Path of the in-progress file for a recording at `path`.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+fn replace_file(partial: &Path, final_path: &Path) -> std::io::Result<()> {
+    if final_path.exists() {
+        std::fs::remove_file(final_path)?;
+    }
+    std::fs::rename(partial, final_path)
+}
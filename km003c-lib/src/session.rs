@@ -0,0 +1,661 @@
+//! Append-only session files holding everything captured during one session.
+//!
+//! A session stores AdcQueue samples, PD event streams, firmware PD traces,
+//! periodic ADC telemetry, device state, settings and user markers in one
+//! file. Records are timestamped on the host clock relative to the session
+//! start and keep the device's own encoding, so nothing is lost on the way to
+//! disk.
+//!
+//! Records are grouped into LZ4-compressed chunks. Each chunk is written and
+//! synced as a whole, so an abrupt termination loses at most the chunk being
+//! written; [`SessionReader`] ignores such a torn tail and
+//! [`SessionWriter::append`] cuts it off before continuing.
+//!
+//! File layout (all integers little-endian):
+//!
+//! | Size | Content                                                          |
+//! |------|------------------------------------------------------------------|
+//! | 20   | Header: magic `KM003CSS`, version (`1`), reserved, creation time in Unix ms |
+//! | 36   | Chunk header: magic `KMCH`, record count, first and last timestamp, uncompressed length, compressed length, CRC-32 of the compressed data |
+//! | …    | LZ4 block holding the chunk's records                            |
+//! | …    | Further chunks                                                   |
+//!
+//! Each record inside a chunk is a kind byte, the elapsed time in
+//! microseconds (u64), the payload length (u32) and the payload.
+//!
+//! Decoded PD events are not stored separately. Like the serde representation
+//! of [`DecodedPdMessage`](crate::pd_decode::DecodedPdMessage), they are
+//! decoded again from the stored wire bytes by
+//! [`SessionReader::decoded_pd_events`].
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use num_enum::{FromPrimitive, IntoPrimitive};
+use zerocopy::byteorder::little_endian::{U16, U32, U64};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
+
+use crate::adc::{AdcDataRaw, AdcDataSimple};
+use crate::adcqueue::{AdcQueueRawData, GraphSampleRate};
+use crate::auth::{DeviceInfo, HARDWARE_ID_SIZE, HardwareId};
+use crate::device::DeviceState;
+use crate::error::KMError;
+use crate::pd::PdEventStream;
+#[cfg(feature = "usbpd")]
+use crate::pd_decode::{DecodedPdEvent, PdSessionDecoder};
+use crate::pd_trace::PdTrace;
+use crate::settings::Settings;
+
+/// Magic bytes at the start of every session file.
+pub const SESSION_MAGIC: &[u8; 8] = b"KM003CSS";
+/// Session file format version written by this library.
+pub const SESSION_VERSION: u16 = 1;
+/// Uncompressed size at which the writer closes a chunk.
+pub const SESSION_CHUNK_SIZE: usize = 64 * 1024;
+
+const CHUNK_MAGIC: &[u8; 4] = b"KMCH";
+
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct SessionHeaderWire {
+    magic: [u8; 8],
+    version: U16,
+    reserved: U16,
+    created_unix_ms: U64,
+}
+
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct ChunkHeaderWire {
+    magic: [u8; 4],
+    record_count: U32,
+    first_elapsed_us: U64,
+    last_elapsed_us: U64,
+    uncompressed_length: U32,
+    compressed_length: U32,
+    crc: U32,
+}
+
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct RecordHeaderWire {
+    kind: u8,
+    elapsed_us: U64,
+    length: U32,
+}
+
+const HEADER_SIZE: usize = size_of::<SessionHeaderWire>();
+const CHUNK_HEADER_SIZE: usize = size_of::<ChunkHeaderWire>();
+
+/// Type tag of a stored record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u8)]
+pub enum SessionRecordKind {
+    AdcQueue = 1,
+    PdEvents = 2,
+    PdTrace = 3,
+    Adc = 4,
+    DeviceState = 5,
+    Settings = 6,
+    Marker = 7,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// One piece of captured data.
+#[derive(Debug, Clone)]
+pub enum SessionRecord {
+    /// AdcQueue samples with the graph rate needed to convert their auxiliary counts.
+    AdcQueue {
+        rate: GraphSampleRate,
+        data: AdcQueueRawData,
+    },
+    /// PD status preamble and the raw PD events that followed it.
+    PdEvents(PdEventStream),
+    PdTrace(PdTrace),
+    /// Periodic ADC telemetry, including temperature and averages.
+    Adc(AdcDataSimple),
+    DeviceState(DeviceState),
+    Settings(Settings),
+    /// User-supplied annotation.
+    Marker(String),
+    /// Record written by a newer version of the format, kept verbatim.
+    Unknown {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl SessionRecord {
+    pub fn kind(&self) -> SessionRecordKind {
+        match self {
+            Self::AdcQueue { .. } => SessionRecordKind::AdcQueue,
+            Self::PdEvents(_) => SessionRecordKind::PdEvents,
+            Self::PdTrace(_) => SessionRecordKind::PdTrace,
+            Self::Adc(_) => SessionRecordKind::Adc,
+            Self::DeviceState(_) => SessionRecordKind::DeviceState,
+            Self::Settings(_) => SessionRecordKind::Settings,
+            Self::Marker(_) => SessionRecordKind::Marker,
+            Self::Unknown { kind, .. } => SessionRecordKind::Unknown(*kind),
+        }
+    }
+
+    /// Serialize the record payload, using the device encoding where there is one.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KMError> {
+        Ok(match self {
+            Self::AdcQueue { rate, data } => {
+                let mut bytes = (*rate as u16).to_le_bytes().to_vec();
+                bytes.extend_from_slice(&data.to_bytes());
+                bytes
+            }
+            Self::PdEvents(stream) => stream.to_bytes()?,
+            Self::PdTrace(trace) => trace.to_bytes()?,
+            Self::Adc(adc) => AdcDataRaw::from(*adc).as_bytes().to_vec(),
+            Self::DeviceState(state) => device_state_to_bytes(state)?,
+            Self::Settings(settings) => settings.to_bytes().to_vec(),
+            Self::Marker(label) => label.as_bytes().to_vec(),
+            Self::Unknown { data, .. } => data.clone(),
+        })
+    }
+
+    /// Parse a record payload of the given kind.
+    pub fn from_bytes(kind: SessionRecordKind, bytes: &[u8]) -> Result<Self, KMError> {
+        Ok(match kind {
+            SessionRecordKind::AdcQueue => {
+                let (rate, samples) = bytes
+                    .split_first_chunk::<2>()
+                    .ok_or_else(|| KMError::InvalidPacket("AdcQueue record has no sample rate".to_string()))?;
+                let rate = GraphSampleRate::try_from(u16::from_le_bytes(*rate))
+                    .map_err(|error| KMError::InvalidPacket(format!("AdcQueue record: {error}")))?;
+                Self::AdcQueue {
+                    rate,
+                    data: AdcQueueRawData::from_bytes(samples)?,
+                }
+            }
+            SessionRecordKind::PdEvents => Self::PdEvents(PdEventStream::from_bytes(Bytes::copy_from_slice(bytes))?),
+            SessionRecordKind::PdTrace => Self::PdTrace(PdTrace::from_bytes(bytes)?),
+            SessionRecordKind::Adc => {
+                let raw = AdcDataRaw::read_from_bytes(bytes).map_err(|_| {
+                    KMError::InvalidPacket(format!(
+                        "ADC record must be {} bytes, got {}",
+                        size_of::<AdcDataRaw>(),
+                        bytes.len()
+                    ))
+                })?;
+                Self::Adc(AdcDataSimple::from(raw))
+            }
+            SessionRecordKind::DeviceState => Self::DeviceState(device_state_from_bytes(bytes)?),
+            SessionRecordKind::Settings => Self::Settings(Settings::from_bytes(bytes)?),
+            SessionRecordKind::Marker => Self::Marker(
+                String::from_utf8(bytes.to_vec())
+                    .map_err(|_| KMError::InvalidPacket("Marker record is not valid UTF-8".to_string()))?,
+            ),
+            SessionRecordKind::Unknown(kind) => Self::Unknown {
+                kind,
+                data: bytes.to_vec(),
+            },
+        })
+    }
+}
+
+/// A record with its host time since the session start.
+#[derive(Debug, Clone)]
+pub struct SessionEntry {
+    pub elapsed_us: u64,
+    pub record: SessionRecord,
+}
+
+/// Location and time span of one chunk in a session file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionChunk {
+    /// File offset of the chunk header.
+    pub offset: u64,
+    pub record_count: u32,
+    pub first_elapsed_us: u64,
+    pub last_elapsed_us: u64,
+    uncompressed_length: u32,
+    compressed_length: u32,
+    crc: u32,
+}
+
+impl SessionChunk {
+    fn end(&self) -> u64 {
+        self.offset + (CHUNK_HEADER_SIZE as u64) + u64::from(self.compressed_length)
+    }
+
+    fn overlaps(&self, range: &impl RangeBounds<u64>) -> bool {
+        use std::ops::Bound;
+        let starts_before_end = match range.end_bound() {
+            Bound::Included(&end) => self.first_elapsed_us <= end,
+            Bound::Excluded(&end) => self.first_elapsed_us < end,
+            Bound::Unbounded => true,
+        };
+        let ends_after_start = match range.start_bound() {
+            Bound::Included(&start) => self.last_elapsed_us >= start,
+            Bound::Excluded(&start) => self.last_elapsed_us > start,
+            Bound::Unbounded => true,
+        };
+        starts_before_end && ends_after_start
+    }
+}
+
+/// Appends records to a session file, one compressed chunk at a time.
+///
+/// Records are buffered until [`SESSION_CHUNK_SIZE`] bytes are pending or
+/// [`Self::flush`] is called. Dropping the writer flushes the pending chunk on
+/// a best-effort basis; use [`Self::finish`] to observe errors.
+#[derive(Debug)]
+pub struct SessionWriter {
+    file: File,
+    path: PathBuf,
+    created_unix_ms: u64,
+    pending: Vec<u8>,
+    pending_records: u32,
+    first_elapsed_us: u64,
+    last_elapsed_us: u64,
+    chunks: usize,
+    records: u64,
+}
+
+impl SessionWriter {
+    /// Create a new session file, replacing any existing file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, KMError> {
+        let path = path.as_ref().to_path_buf();
+        let created_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let mut file = File::create(&path)?;
+        let header = SessionHeaderWire {
+            magic: *SESSION_MAGIC,
+            version: U16::new(SESSION_VERSION),
+            reserved: U16::new(0),
+            created_unix_ms: U64::new(created_unix_ms),
+        };
+        file.write_all(header.as_bytes())?;
+        file.sync_data()?;
+        Ok(Self::new(file, path, created_unix_ms, 0, 0))
+    }
+
+    /// Continue an existing session, discarding a chunk torn by an earlier crash.
+    pub fn append(path: impl AsRef<Path>) -> Result<Self, KMError> {
+        let path = path.as_ref().to_path_buf();
+        let reader = SessionReader::open(&path)?;
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(reader.valid_length)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self::new(
+            file,
+            path,
+            reader.created_unix_ms,
+            reader.chunks.len(),
+            reader.record_count(),
+        ))
+    }
+
+    fn new(file: File, path: PathBuf, created_unix_ms: u64, chunks: usize, records: u64) -> Self {
+        Self {
+            file,
+            path,
+            created_unix_ms,
+            pending: Vec::new(),
+            pending_records: 0,
+            first_elapsed_us: 0,
+            last_elapsed_us: 0,
+            chunks,
+            records,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Session start as milliseconds since the Unix epoch.
+    pub fn created_unix_ms(&self) -> u64 {
+        self.created_unix_ms
+    }
+
+    /// Records written, including those still pending.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Chunks written to the file so far.
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    /// Buffer a record, writing a chunk once enough data is pending.
+    pub fn write(&mut self, elapsed_us: u64, record: &SessionRecord) -> Result<(), KMError> {
+        let payload = record.to_bytes()?;
+        let header = RecordHeaderWire {
+            kind: record.kind().into(),
+            elapsed_us: U64::new(elapsed_us),
+            length: U32::new(u32::try_from(payload.len()).map_err(|_| {
+                KMError::InvalidPacket(format!("Session record of {} bytes is too large", payload.len()))
+            })?),
+        };
+        if self.pending_records == 0 {
+            self.first_elapsed_us = elapsed_us;
+            self.last_elapsed_us = elapsed_us;
+        }
+        self.first_elapsed_us = self.first_elapsed_us.min(elapsed_us);
+        self.last_elapsed_us = self.last_elapsed_us.max(elapsed_us);
+        self.pending.extend_from_slice(header.as_bytes());
+        self.pending.extend_from_slice(&payload);
+        self.pending_records += 1;
+        self.records += 1;
+
+        if self.pending.len() >= SESSION_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write pending records as a chunk and sync it to disk.
+    pub fn flush(&mut self) -> Result<(), KMError> {
+        if self.pending_records == 0 {
+            return Ok(());
+        }
+        let compressed = lz4_flex::block::compress(&self.pending);
+        let header = ChunkHeaderWire {
+            magic: *CHUNK_MAGIC,
+            record_count: U32::new(self.pending_records),
+            first_elapsed_us: U64::new(self.first_elapsed_us),
+            last_elapsed_us: U64::new(self.last_elapsed_us),
+            uncompressed_length: U32::new(self.pending.len() as u32),
+            compressed_length: U32::new(compressed.len() as u32),
+            crc: U32::new(crc32fast::hash(&compressed)),
+        };
+        let mut chunk = Vec::with_capacity(CHUNK_HEADER_SIZE + compressed.len());
+        chunk.extend_from_slice(header.as_bytes());
+        chunk.extend_from_slice(&compressed);
+        self.file.write_all(&chunk)?;
+        self.file.sync_data()?;
+
+        self.pending.clear();
+        self.pending_records = 0;
+        self.chunks += 1;
+        Ok(())
+    }
+
+    /// Flush the last chunk and close the file.
+    pub fn finish(mut self) -> Result<PathBuf, KMError> {
+        self.flush()?;
+        Ok(self.path.clone())
+    }
+}
+
+impl Drop for SessionWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Random-access reader over the chunks of a session file.
+#[derive(Debug)]
+pub struct SessionReader {
+    file: File,
+    created_unix_ms: u64,
+    chunks: Vec<SessionChunk>,
+    /// Length covered by the header and complete chunks.
+    valid_length: u64,
+    file_length: u64,
+}
+
+impl SessionReader {
+    /// Open a session and index its chunks.
+    ///
+    /// Only chunk headers are read, apart from the last chunk, whose checksum
+    /// is verified because it is the one an abrupt termination can tear.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, KMError> {
+        let mut file = File::open(path)?;
+        let file_length = file.metadata()?.len();
+
+        let mut header = [0; HEADER_SIZE];
+        file.read_exact(&mut header).map_err(|_| KMError::ParseError {
+            offset: 0,
+            message: format!("Session file is shorter than its {HEADER_SIZE}-byte header"),
+        })?;
+        let header = SessionHeaderWire::read_from_bytes(&header).expect("header buffer has the header size");
+        if header.magic != *SESSION_MAGIC {
+            return Err(KMError::ParseError {
+                offset: 0,
+                message: "Not a KM003C session file".to_string(),
+            });
+        }
+        if header.version.get() != SESSION_VERSION {
+            return Err(KMError::ParseError {
+                offset: 8,
+                message: format!("Unsupported session file version {}", header.version.get()),
+            });
+        }
+
+        let mut chunks = Vec::new();
+        let mut offset = HEADER_SIZE as u64;
+        while offset + CHUNK_HEADER_SIZE as u64 <= file_length {
+            let mut bytes = [0; CHUNK_HEADER_SIZE];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut bytes)?;
+            let wire = ChunkHeaderWire::read_from_bytes(&bytes).expect("chunk buffer has the chunk header size");
+            if wire.magic != *CHUNK_MAGIC {
+                break;
+            }
+            let chunk = SessionChunk {
+                offset,
+                record_count: wire.record_count.get(),
+                first_elapsed_us: wire.first_elapsed_us.get(),
+                last_elapsed_us: wire.last_elapsed_us.get(),
+                uncompressed_length: wire.uncompressed_length.get(),
+                compressed_length: wire.compressed_length.get(),
+                crc: wire.crc.get(),
+            };
+            if chunk.end() > file_length {
+                break;
+            }
+            offset = chunk.end();
+            chunks.push(chunk);
+        }
+
+        let mut reader = Self {
+            file,
+            created_unix_ms: header.created_unix_ms.get(),
+            chunks,
+            valid_length: offset,
+            file_length,
+        };
+        if let Some(last) = reader.chunks.last().copied()
+            && reader.read_compressed(&last).is_err()
+        {
+            reader.chunks.pop();
+            reader.valid_length = last.offset;
+        }
+        Ok(reader)
+    }
+
+    /// Session start as milliseconds since the Unix epoch.
+    pub fn created_unix_ms(&self) -> u64 {
+        self.created_unix_ms
+    }
+
+    pub fn chunks(&self) -> &[SessionChunk] {
+        &self.chunks
+    }
+
+    pub fn record_count(&self) -> u64 {
+        self.chunks.iter().map(|chunk| u64::from(chunk.record_count)).sum()
+    }
+
+    /// Trailing bytes that do not form a complete chunk, e.g. after a crash.
+    pub fn torn_bytes(&self) -> u64 {
+        self.file_length - self.valid_length
+    }
+
+    /// Decode every record of one chunk.
+    pub fn read_chunk(&mut self, index: usize) -> Result<Vec<SessionEntry>, KMError> {
+        let chunk = *self.chunks.get(index).ok_or_else(|| {
+            KMError::InvalidPacket(format!(
+                "Session has {} chunks, chunk {index} requested",
+                self.chunks.len()
+            ))
+        })?;
+        let compressed = self.read_compressed(&chunk)?;
+        let data = lz4_flex::block::decompress(&compressed, chunk.uncompressed_length as usize).map_err(|error| {
+            KMError::ParseError {
+                offset: chunk.offset as usize,
+                message: format!("Session chunk does not decompress: {error}"),
+            }
+        })?;
+        parse_records(&data, &chunk)
+    }
+
+    /// Every record in the session, in file order.
+    pub fn entries(&mut self) -> Result<Vec<SessionEntry>, KMError> {
+        self.entries_between(..)
+    }
+
+    /// Records whose elapsed time lies in `range`, reading only the chunks that overlap it.
+    pub fn entries_between(&mut self, range: impl RangeBounds<u64>) -> Result<Vec<SessionEntry>, KMError> {
+        let indices = (0..self.chunks.len())
+            .filter(|&index| self.chunks[index].overlaps(&range))
+            .collect::<Vec<_>>();
+        let mut entries = Vec::new();
+        for index in indices {
+            entries.extend(
+                self.read_chunk(index)?
+                    .into_iter()
+                    .filter(|entry| range.contains(&entry.elapsed_us)),
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Decode the stored PD events in order with one stateful decoder.
+    #[cfg(feature = "usbpd")]
+    pub fn decoded_pd_events(&mut self) -> Result<Vec<DecodedPdEvent>, KMError> {
+        let mut decoder = PdSessionDecoder::new();
+        Ok(self
+            .entries()?
+            .iter()
+            .filter_map(|entry| match &entry.record {
+                SessionRecord::PdEvents(stream) => Some(stream),
+                _ => None,
+            })
+            .flat_map(|stream| &stream.events)
+            .map(|event| decoder.decode_event(event))
+            .collect())
+    }
+
+    fn read_compressed(&mut self, chunk: &SessionChunk) -> Result<Vec<u8>, KMError> {
+        let mut compressed = vec![0; chunk.compressed_length as usize];
+        self.file
+            .seek(SeekFrom::Start(chunk.offset + CHUNK_HEADER_SIZE as u64))?;
+        self.file.read_exact(&mut compressed)?;
+        if crc32fast::hash(&compressed) != chunk.crc {
+            return Err(KMError::ParseError {
+                offset: chunk.offset as usize,
+                message: "Session chunk checksum mismatch".to_string(),
+            });
+        }
+        Ok(compressed)
+    }
+}
+
+fn parse_records(data: &[u8], chunk: &SessionChunk) -> Result<Vec<SessionEntry>, KMError> {
+    let mut entries = Vec::with_capacity(chunk.record_count as usize);
+    let mut rest = data;
+    while !rest.is_empty() {
+        let offset = data.len() - rest.len();
+        let (header, tail) = RecordHeaderWire::read_from_prefix(rest).map_err(|_| KMError::ParseError {
+            offset,
+            message: "Session record header is truncated".to_string(),
+        })?;
+        let (payload, tail) =
+            tail.split_at_checked(header.length.get() as usize)
+                .ok_or_else(|| KMError::ParseError {
+                    offset,
+                    message: format!("Session record of {} bytes is truncated", header.length.get()),
+                })?;
+        entries.push(SessionEntry {
+            elapsed_us: header.elapsed_us.get(),
+            record: SessionRecord::from_bytes(SessionRecordKind::from(header.kind), payload)?,
+        });
+        rest = tail;
+    }
+    if entries.len() != chunk.record_count as usize {
+        return Err(KMError::ParseError {
+            offset: chunk.offset as usize,
+            message: format!(
+                "Session chunk declares {} records but holds {}",
+                chunk.record_count,
+                entries.len()
+            ),
+        });
+    }
+    Ok(entries)
+}
+
+/// Hardware ID, authentication level, streaming flag and the `DeviceInfo`
+/// strings, each prefixed with its u16 length.
+fn device_state_to_bytes(state: &DeviceState) -> Result<Vec<u8>, KMError> {
+    let mut bytes = state.hardware_id.as_bytes().to_vec();
+    bytes.push(state.auth_level);
+    bytes.push(u8::from(state.adcqueue_enabled));
+    for field in device_info_fields(&state.info) {
+        let length = u16::try_from(field.len())
+            .map_err(|_| KMError::InvalidPacket(format!("Device info field of {} bytes is too long", field.len())))?;
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(field.as_bytes());
+    }
+    Ok(bytes)
+}
+
+fn device_state_from_bytes(bytes: &[u8]) -> Result<DeviceState, KMError> {
+    let truncated = || KMError::InvalidPacket("Device state record is truncated".to_string());
+    let (hardware_id, rest) = bytes.split_first_chunk::<HARDWARE_ID_SIZE>().ok_or_else(truncated)?;
+    let (&[auth_level, adcqueue_enabled], mut rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+    let mut info = DeviceInfo::default();
+    for field in device_info_fields_mut(&mut info) {
+        let (length, tail) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+        let (value, tail) = tail
+            .split_at_checked(usize::from(u16::from_le_bytes(*length)))
+            .ok_or_else(truncated)?;
+        *field = String::from_utf8_lossy(value).into_owned();
+        rest = tail;
+    }
+    Ok(DeviceState {
+        info,
+        hardware_id: HardwareId::from_bytes(*hardware_id),
+        auth_level,
+        adcqueue_enabled: adcqueue_enabled != 0,
+    })
+}
+
+fn device_info_fields(info: &DeviceInfo) -> [&String; 7] {
+    [
+        &info.model,
+        &info.hw_version,
+        &info.mfg_date,
+        &info.fw_version,
+        &info.fw_date,
+        &info.serial_id,
+        &info.uuid,
+    ]
+}
+
+fn device_info_fields_mut(info: &mut DeviceInfo) -> [&mut String; 7] {
+    [
+        &mut info.model,
+        &mut info.hw_version,
+        &mut info.mfg_date,
+        &mut info.fw_version,
+        &mut info.fw_date,
+        &mut info.serial_id,
+        &mut info.uuid,
+    ]
+}
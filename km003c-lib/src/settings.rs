@@ -61,6 +61,11 @@ use fields::{SettingsAFields, SettingsBFields};
 /// [`Self::settings_b_raw`]. Only fields whose meanings are corroborated by
 /// KM003C V1.9.9 firmware consumers have semantic accessors.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(into = "Vec<u8>", try_from = "Vec<u8>")
+)]
 pub struct Settings {
     bytes: [u8; SETTINGS_SIZE],
 }
@@ -183,6 +188,20 @@ impl Settings {
     }
 }
 
+impl From<Settings> for Vec<u8> {
+    fn from(settings: Settings) -> Self {
+        settings.to_bytes().to_vec()
+    }
+}
+
+impl TryFrom<Vec<u8>> for Settings {
+    type Error = KMError;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::from_bytes(&bytes)
+    }
+}
+
 fn validate_checksum(name: &str, block: &[u8], checksum_offset: usize) -> Result<(), KMError> {
     let stored = read_u32(block, checksum_offset);
     let calculated = crc32fast::hash(&block[..checksum_offset]);
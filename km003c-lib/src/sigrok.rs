@@ -0,0 +1,321 @@
+//! Export of session files as sigrok session archives for PulseView.
+//!
+//! A sigrok session (`.sr`) is a ZIP archive holding a `version` file, an
+//! INI-style `metadata` file and the sample data split into chunks: one byte
+//! per sample for the logic channels and little-endian `f32` values for each
+//! analog channel. All channels share one sample rate, so the AdcQueue samples
+//! of a session are placed on the grid of their [`GraphSampleRate`]:
+//!
+//! - Samples missing from the stream are exported as NaN with the `Gap` logic
+//!   channel high.
+//! - `Connected` follows the connect and disconnect events of the PD stream,
+//!   and `Attached` follows the Type-C states of the firmware PD trace.
+//! - `PD SOP`, `PD SOP'` and `PD SOP''` are high for the sample in which a
+//!   message of that type was captured.
+//!
+//! Analog values are in volts, amperes and watts; sigrok session files do not
+//! carry units. PD events are placed on the host timeline through the PD status
+//! preamble captured with them. Trace events are placed through the
+//! [`PdTraceCorrelator`] clock alignment, or at the time the trace was read
+//! when there is no PD traffic to align to.
+
+use std::io::{Seek, Write};
+use std::path::Path;
+
+use uom::si::frequency::hertz;
+use uom::si::time::microsecond;
+use zip::CompressionMethod;
+use zip::write::{SimpleFileOptions, ZipWriter};
+
+use crate::adcqueue::GraphSampleRate;
+use crate::error::KMError;
+use crate::measurement::MeasurementAccumulator;
+use crate::pd::PdEventData;
+use crate::pd_correlation::PdTraceCorrelator;
+use crate::session::{SessionEntry, SessionRecord};
+
+/// Analog channel names in archive order.
+pub const SIGROK_ANALOG_CHANNELS: [&str; 7] = ["VBUS", "IBUS", "Power", "CC1", "CC2", "D+", "D-"];
+/// Logic channel names; channel `n` is bit `n` of each logic sample.
+pub const SIGROK_LOGIC_CHANNELS: [&str; 6] = ["Connected", "Attached", "PD SOP", "PD SOP'", "PD SOP''", "Gap"];
+/// Samples per data file in the archive.
+pub const SIGROK_CHUNK_SAMPLES: usize = 1 << 20;
+
+const CONNECTED: u8 = 0;
+const ATTACHED: u8 = 1;
+const PD_SOP: u8 = 2;
+const GAP: u8 = 5;
+
+/// sigrok version recorded in the metadata; the session format is that of libsigrok 0.5.
+const SIGROK_VERSION: &str = "0.5.2";
+
+/// A session resampled onto the AdcQueue sample grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigrokExport {
+    rate: GraphSampleRate,
+    analog: Vec<[f32; 7]>,
+    logic: Vec<u8>,
+}
+
+/// A level change or a single-sample pulse of one logic channel.
+#[derive(Debug, Clone, Copy)]
+struct LogicEvent {
+    host_us: u64,
+    channel: u8,
+    level: Option<bool>,
+}
+
+impl SigrokExport {
+    /// Resample the AdcQueue samples of a session and mark its PD activity.
+    ///
+    /// Fails if the session has no AdcQueue samples or the graph rate changes
+    /// during the session.
+    pub fn from_session(entries: &[SessionEntry]) -> Result<Self, KMError> {
+        let mut rate = None;
+        let mut origin_us = 0;
+        let mut accumulator = MeasurementAccumulator::default();
+        let mut analog = Vec::new();
+        let mut logic = Vec::new();
+
+        for entry in entries {
+            let SessionRecord::AdcQueue {
+                rate: record_rate,
+                data,
+            } = &entry.record
+            else {
+                continue;
+            };
+            match rate {
+                None => {
+                    rate = Some(*record_rate);
+                    // The record arrives with its last sample; the grid starts at its first.
+                    let span_ms = data
+                        .sequence_range()
+                        .map_or(0, |(first, last)| u64::from(last.wrapping_sub(first)));
+                    origin_us = entry.elapsed_us.saturating_sub(span_ms * 1_000);
+                }
+                Some(rate) if rate != *record_rate => {
+                    return Err(KMError::InvalidPacket(format!(
+                        "Graph sample rate changes from {rate} to {record_rate} during the session"
+                    )));
+                }
+                Some(_) => {}
+            }
+            let period_us = period_us(*record_rate);
+            for sample in data.decode(*record_rate).samples {
+                let Some(measurement) = accumulator.push(sample, *record_rate) else {
+                    continue;
+                };
+                let slot = (measurement.elapsed_us / period_us) as usize;
+                if slot < analog.len() {
+                    continue;
+                }
+                analog.resize(slot, [f32::NAN; 7]);
+                logic.resize(slot, 1 << GAP);
+                analog.push(
+                    [
+                        measurement.vbus_uv,
+                        measurement.ibus_ua,
+                        measurement.power_uw,
+                        measurement.cc1_uv,
+                        measurement.cc2_uv,
+                        measurement.dp_uv,
+                        measurement.dm_uv,
+                    ]
+                    .map(|value| (value as f64 / 1_000_000.0) as f32),
+                );
+                logic.push(0);
+            }
+        }
+
+        let rate = rate.ok_or_else(|| KMError::InvalidPacket("Session has no AdcQueue samples".to_string()))?;
+        let mut export = Self { rate, analog, logic };
+        export.mark_events(&logic_events(entries), origin_us);
+        Ok(export)
+    }
+
+    pub fn sample_rate(&self) -> GraphSampleRate {
+        self.rate
+    }
+
+    pub fn len(&self) -> usize {
+        self.analog.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.analog.is_empty()
+    }
+
+    /// Samples filled in for gaps in the AdcQueue stream.
+    pub fn gap_samples(&self) -> usize {
+        self.logic.iter().filter(|&&sample| sample & (1 << GAP) != 0).count()
+    }
+
+    /// Values of one channel of [`SIGROK_ANALOG_CHANNELS`].
+    pub fn analog(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        self.analog.iter().map(move |sample| sample[channel])
+    }
+
+    /// Logic samples, one bit per channel of [`SIGROK_LOGIC_CHANNELS`].
+    pub fn logic(&self) -> &[u8] {
+        &self.logic
+    }
+
+    /// Contents of the archive's `metadata` file.
+    pub fn metadata(&self) -> String {
+        let hz = self.rate.frequency().get::<hertz>() as u64;
+        let samplerate = if hz.is_multiple_of(1_000) {
+            format!("{} kHz", hz / 1_000)
+        } else {
+            format!("{hz} Hz")
+        };
+        let mut metadata = format!(
+            "[global]\nsigrok version={SIGROK_VERSION}\n\n[device 1]\ncapturefile=logic-1\ntotal probes={}\n\
+             samplerate={samplerate}\ntotal analog={}\n",
+            SIGROK_LOGIC_CHANNELS.len(),
+            SIGROK_ANALOG_CHANNELS.len()
+        );
+        for (index, name) in SIGROK_LOGIC_CHANNELS.iter().enumerate() {
+            metadata.push_str(&format!("probe{}={name}\n", index + 1));
+        }
+        for (index, name) in SIGROK_ANALOG_CHANNELS.iter().enumerate() {
+            metadata.push_str(&format!("analog{}={name}\n", SIGROK_LOGIC_CHANNELS.len() + index + 1));
+        }
+        metadata.push_str("unitsize=1\n");
+        metadata
+    }
+
+    /// Write the session archive.
+    pub fn write(&self, writer: impl Write + Seek) -> Result<(), KMError> {
+        let mut zip = ZipWriter::new(writer);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file("version", options).map_err(std::io::Error::from)?;
+        zip.write_all(b"2")?;
+        zip.start_file("metadata", options).map_err(std::io::Error::from)?;
+        zip.write_all(self.metadata().as_bytes())?;
+
+        for (chunk_index, chunk) in self.logic.chunks(SIGROK_CHUNK_SAMPLES).enumerate() {
+            zip.start_file(format!("logic-1-{}", chunk_index + 1), options)
+                .map_err(std::io::Error::from)?;
+            zip.write_all(chunk)?;
+        }
+        for channel in 0..SIGROK_ANALOG_CHANNELS.len() {
+            let index = SIGROK_LOGIC_CHANNELS.len() + channel + 1;
+            for (chunk_index, chunk) in self.analog.chunks(SIGROK_CHUNK_SAMPLES).enumerate() {
+                zip.start_file(format!("analog-1-{index}-{}", chunk_index + 1), options)
+                    .map_err(std::io::Error::from)?;
+                let bytes = chunk
+                    .iter()
+                    .flat_map(|sample| sample[channel].to_le_bytes())
+                    .collect::<Vec<_>>();
+                zip.write_all(&bytes)?;
+            }
+        }
+        zip.finish().map_err(std::io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), KMError> {
+        self.write(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
+
+    fn mark_events(&mut self, events: &[LogicEvent], origin_us: u64) {
+        let period_us = period_us(self.rate);
+        let slot = |host_us: u64| (host_us.saturating_sub(origin_us) / period_us) as usize;
+
+        let mut levels = events.iter().filter(|event| event.level.is_some()).collect::<Vec<_>>();
+        levels.sort_by_key(|event| event.host_us);
+        let mut levels = levels.into_iter().peekable();
+        let mut state = 0u8;
+        for (index, sample) in self.logic.iter_mut().enumerate() {
+            while let Some(event) = levels.next_if(|event| slot(event.host_us) <= index) {
+                let bit = 1 << event.channel;
+                if event.level == Some(true) {
+                    state |= bit;
+                } else {
+                    state &= !bit;
+                }
+            }
+            *sample |= state;
+        }
+
+        for event in events.iter().filter(|event| event.level.is_none()) {
+            if event.host_us >= origin_us
+                && let Some(sample) = self.logic.get_mut(slot(event.host_us))
+            {
+                *sample |= 1 << event.channel;
+            }
+        }
+    }
+}
+
+/// Connection changes, PD message pulses and Type-C attach states on the host timeline.
+fn logic_events(entries: &[SessionEntry]) -> Vec<LogicEvent> {
+    let mut events = Vec::new();
+    let mut correlator = PdTraceCorrelator::new();
+    // Host time minus PD event time, from the most recent PD stream.
+    let mut pd_offset_us = None;
+    let mut first_pd_offset_us = None;
+    let mut traces = Vec::new();
+
+    for entry in entries {
+        match &entry.record {
+            SessionRecord::PdEvents(stream) => {
+                let offset = entry.elapsed_us as i64 - stream.preamble.timestamp.get::<microsecond>() as i64;
+                pd_offset_us = Some(offset);
+                first_pd_offset_us = first_pd_offset_us.or(pd_offset_us);
+                for event in &stream.events {
+                    correlator.process_event(event);
+                    let host_us = (offset + event.timestamp.get::<microsecond>() as i64).max(0) as u64;
+                    let (channel, level) = match &event.data {
+                        PdEventData::Connect(()) => (CONNECTED, Some(true)),
+                        PdEventData::Disconnect(()) => (CONNECTED, Some(false)),
+                        PdEventData::PdMessage { sop, .. } if *sop < 3 => (PD_SOP + sop, None),
+                        PdEventData::PdMessage { .. } => continue,
+                    };
+                    events.push(LogicEvent {
+                        host_us,
+                        channel,
+                        level,
+                    });
+                }
+            }
+            SessionRecord::PdTrace(trace) => {
+                correlator.process_trace(trace);
+                traces.push((entry.elapsed_us, pd_offset_us, trace));
+            }
+            _ => {}
+        }
+    }
+
+    let uptime_offset_us = correlator
+        .clock_alignment()
+        .map_or(0, |alignment| alignment.offset.get::<microsecond>() as i64);
+    for (read_us, pd_offset_us, trace) in traces {
+        for state_event in &trace.state_events {
+            let level = if state_event.state.is_attached() {
+                true
+            } else if state_event.state.is_unattached() {
+                false
+            } else {
+                continue;
+            };
+            let host_us = match pd_offset_us.or(first_pd_offset_us) {
+                Some(offset) => {
+                    (state_event.timestamp.get::<microsecond>() as i64 - uptime_offset_us + offset).max(0) as u64
+                }
+                None => read_us,
+            };
+            events.push(LogicEvent {
+                host_us,
+                channel: ATTACHED,
+                level: Some(level),
+            });
+        }
+    }
+    events
+}
+
+fn period_us(rate: GraphSampleRate) -> u64 {
+    u64::from(rate.sequence_step()) * 1_000
+}
@@ -0,0 +1,84 @@
+//! Long-running AdcQueue streaming that tolerates transient request failures.
+//!
+//! Exporters and servers open the meter with [`DeviceStream::open`], poll it
+//! with [`DeviceStream::request`] every [`GraphSampleRate::poll_interval`] and
+//! reopen it once [`MAX_CONSECUTIVE_ERRORS`] requests in a row have failed.
+
+use tracing::info;
+
+use crate::adcqueue::GraphSampleRate;
+use crate::device::{DeviceConfig, DeviceState, KM003C};
+use crate::error::KMError;
+use crate::message::Packet;
+use crate::packet::AttributeSet;
+
+/// Consecutive request failures after which the device should be reopened.
+pub const MAX_CONSECUTIVE_ERRORS: u32 = 10;
+
+/// A meter opened for graph-mode streaming.
+pub struct DeviceStream {
+    device: KM003C,
+    state: DeviceState,
+    consecutive_errors: u32,
+}
+
+impl DeviceStream {
+    /// Open the meter and check that it can stream AdcQueue samples.
+    ///
+    /// Connections without graph mode, such as the HID interface or a meter
+    /// that rejected streaming authentication, are reported as errors.
+    pub async fn open(config: DeviceConfig) -> Result<Self, KMError> {
+        let device = KM003C::new(config).await?;
+        let state = device
+            .state()
+            .cloned()
+            .ok_or_else(|| KMError::Protocol("Graph mode requires the vendor interface".to_string()))?;
+        if !state.adcqueue_enabled {
+            return Err(KMError::Protocol(
+                "AdcQueue not enabled - authentication may have failed".to_string(),
+            ));
+        }
+        info!(serial = %state.info.serial_id, "Connected to {} (FW {})", state.model(), state.firmware_version());
+        Ok(Self {
+            device,
+            state,
+            consecutive_errors: 0,
+        })
+    }
+
+    pub fn state(&self) -> &DeviceState {
+        &self.state
+    }
+
+    pub fn device_mut(&mut self) -> &mut KM003C {
+        &mut self.device
+    }
+
+    /// Start graph mode at `rate`, stopping any capture already running.
+    pub async fn start(&mut self, rate: GraphSampleRate) -> Result<(), KMError> {
+        let _ = self.device.stop_graph_mode().await;
+        self.device.start_graph_mode(rate).await
+    }
+
+    /// Request `mask`, counting failures in a row.
+    ///
+    /// A failure below the limit is returned as `Ok(Err(_))` so the caller
+    /// can log it and poll again; reaching [`MAX_CONSECUTIVE_ERRORS`] returns
+    /// the last error as `Err`, after which the device should be reopened.
+    pub async fn request(&mut self, mask: AttributeSet) -> Result<Result<Packet, KMError>, KMError> {
+        match self.device.request_data(mask).await {
+            Ok(packet) => {
+                self.consecutive_errors = 0;
+                Ok(Ok(packet))
+            }
+            Err(error) => {
+                self.consecutive_errors += 1;
+                if self.consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                    Err(error)
+                } else {
+                    Ok(Err(error))
+                }
+            }
+        }
+    }
+}
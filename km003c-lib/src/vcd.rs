@@ -0,0 +1,383 @@
+//! Export of session files as Value Change Dump (VCD) waveforms.
+//!
+//! Type-C states, connection state, the negotiated contract voltage and the
+//! PD messages on each SOP* channel are discrete signals that waveform viewers
+//! such as GTKWave display next to simulation traces. Timestamps are device
+//! time in microseconds, on the clock of the PD event stream:
+//!
+//! - Type-C states from the firmware PD trace are aligned to that clock with
+//!   the [`PdTraceCorrelator`] clock alignment, or taken as is when the trace
+//!   cannot be paired with traffic.
+//! - VBUS and IBUS samples, which only carry the host time they were read at,
+//!   are mapped through the PD status preamble of the first PD event stream.
+//!
+//! State and message names use the `string` variable type understood by
+//! GTKWave and Surfer; the numeric `typec_state` and the per-SOP message
+//! counters carry the same information for viewers without it.
+
+use std::io::Write;
+use std::path::Path;
+
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use uom::si::time::microsecond;
+
+use crate::error::KMError;
+use crate::pd::PdEventData;
+use crate::pd_correlation::PdTraceCorrelator;
+use crate::pd_policy::PolicyTracker;
+use crate::pd_trace::PdTypeCState;
+use crate::pd_wire::{PdMessageType, PdWireMessage};
+use crate::session::{SessionEntry, SessionRecord};
+
+/// SOP* channels with their own message signals, in scope order.
+pub const VCD_SOP_SCOPES: [&str; 3] = ["sop", "sop_prime", "sop_double_prime"];
+
+/// Optional analog signals, quantised to keep the dump small.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VcdOptions {
+    /// Emit VBUS rounded to this step; `None` leaves VBUS out.
+    pub vbus_step: Option<ElectricPotential>,
+    /// Emit IBUS rounded to this step; `None` leaves IBUS out.
+    pub ibus_step: Option<ElectricCurrent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    Connected,
+    TypeCState,
+    TypeCStateName,
+    ContractVoltage,
+    Message(u8),
+    MessageCount(u8),
+    Vbus,
+    Ibus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Bit(bool),
+    Vector(u32),
+    Real(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    time_us: u64,
+    signal: Signal,
+    value: Value,
+}
+
+/// Value changes of a session, ready to be written as VCD.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VcdExport {
+    options: VcdOptions,
+    changes: Vec<Change>,
+}
+
+impl VcdExport {
+    /// Collect the value changes of a session in device time.
+    pub fn from_session(entries: &[SessionEntry], options: VcdOptions) -> Self {
+        let mut changes = Vec::new();
+        let mut correlator = PdTraceCorrelator::new();
+        let mut policy = PolicyTracker::new();
+        let mut message_counts = [0u32; VCD_SOP_SCOPES.len()];
+        let mut contract_voltage = 0.0;
+        // Host time minus device time, from the first PD stream.
+        let mut host_offset_us = None;
+
+        for entry in entries {
+            let SessionRecord::PdEvents(stream) = &entry.record else {
+                continue;
+            };
+            host_offset_us.get_or_insert(entry.elapsed_us as i64 - micros(stream.preamble.timestamp) as i64);
+            for event in &stream.events {
+                correlator.process_event(event);
+                let time_us = micros(event.timestamp);
+                for policy_event in policy.process_event(event) {
+                    let voltage = policy_event
+                        .state
+                        .contract
+                        .and_then(|contract| contract.voltage)
+                        .map_or(0.0, |voltage| voltage.get::<volt>());
+                    if voltage != contract_voltage {
+                        contract_voltage = voltage;
+                        changes.push(Change {
+                            time_us: micros(policy_event.timestamp),
+                            signal: Signal::ContractVoltage,
+                            value: Value::Real(voltage),
+                        });
+                    }
+                }
+                match &event.data {
+                    PdEventData::Connect(()) => changes.push(Change {
+                        time_us,
+                        signal: Signal::Connected,
+                        value: Value::Bit(true),
+                    }),
+                    PdEventData::Disconnect(()) => changes.push(Change {
+                        time_us,
+                        signal: Signal::Connected,
+                        value: Value::Bit(false),
+                    }),
+                    PdEventData::PdMessage { sop, wire_data } if usize::from(*sop) < VCD_SOP_SCOPES.len() => {
+                        let name = PdWireMessage::from_bytes(wire_data).map_or_else(
+                            |_| "Malformed".to_string(),
+                            |message| message_name(message.message_type()),
+                        );
+                        message_counts[usize::from(*sop)] += 1;
+                        changes.push(Change {
+                            time_us,
+                            signal: Signal::Message(*sop),
+                            value: Value::Text(name),
+                        });
+                        changes.push(Change {
+                            time_us,
+                            signal: Signal::MessageCount(*sop),
+                            value: Value::Vector(message_counts[usize::from(*sop)]),
+                        });
+                    }
+                    PdEventData::PdMessage { .. } => {}
+                }
+            }
+        }
+
+        for entry in entries {
+            if let SessionRecord::PdTrace(trace) = &entry.record {
+                correlator.process_trace(trace);
+            }
+        }
+        let uptime_offset = correlator.clock_alignment().map(|alignment| alignment.offset);
+        for entry in entries {
+            let SessionRecord::PdTrace(trace) = &entry.record else {
+                continue;
+            };
+            for event in &trace.state_events {
+                let timestamp = uptime_offset.map_or(event.timestamp, |offset| event.timestamp - offset);
+                let time_us = micros(timestamp);
+                changes.push(Change {
+                    time_us,
+                    signal: Signal::TypeCState,
+                    value: Value::Vector(u32::from(u8::from(event.state))),
+                });
+                changes.push(Change {
+                    time_us,
+                    signal: Signal::TypeCStateName,
+                    value: Value::Text(state_name(event.state)),
+                });
+            }
+        }
+
+        let host_offset_us = host_offset_us.unwrap_or(0);
+        let device_us = |host_us: i64| (host_us - host_offset_us).max(0) as u64;
+        let mut last_vbus = None;
+        let mut last_ibus = None;
+        let mut analog = |changes: &mut Vec<Change>, time_us, vbus: ElectricPotential, ibus: ElectricCurrent| {
+            if let Some(step) = options.vbus_step {
+                let value = quantise(vbus.get::<volt>(), step.get::<volt>());
+                if last_vbus.replace(value) != Some(value) {
+                    changes.push(Change {
+                        time_us,
+                        signal: Signal::Vbus,
+                        value: Value::Real(value),
+                    });
+                }
+            }
+            if let Some(step) = options.ibus_step {
+                let value = quantise(ibus.get::<ampere>(), step.get::<ampere>());
+                if last_ibus.replace(value) != Some(value) {
+                    changes.push(Change {
+                        time_us,
+                        signal: Signal::Ibus,
+                        value: Value::Real(value),
+                    });
+                }
+            }
+        };
+        if options.vbus_step.is_some() || options.ibus_step.is_some() {
+            for entry in entries {
+                match &entry.record {
+                    SessionRecord::AdcQueue { rate, data } => {
+                        let Some((_, last)) = data.sequence_range() else {
+                            continue;
+                        };
+                        for sample in data.decode(*rate).samples {
+                            // The record was read right after its last sample.
+                            let age_us = i64::from(last.wrapping_sub(sample.sequence)) * 1_000;
+                            let time_us = device_us(entry.elapsed_us as i64 - age_us);
+                            analog(&mut changes, time_us, sample.vbus, sample.ibus);
+                        }
+                    }
+                    SessionRecord::Adc(adc) => {
+                        analog(&mut changes, device_us(entry.elapsed_us as i64), adc.vbus, adc.ibus);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Stable, so changes at the same time keep their capture order.
+        changes.sort_by_key(|change| change.time_us);
+        Self { options, changes }
+    }
+
+    /// Number of value changes after the initial values.
+    pub fn value_changes(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Device time of the last value change, in microseconds.
+    pub fn end_time_us(&self) -> u64 {
+        self.changes.last().map_or(0, |change| change.time_us)
+    }
+
+    /// Write the dump.
+    pub fn write(&self, mut writer: impl Write) -> Result<(), KMError> {
+        writer.write_all(self.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), KMError> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn signals(&self) -> Vec<Signal> {
+        let mut signals = vec![
+            Signal::Connected,
+            Signal::TypeCState,
+            Signal::TypeCStateName,
+            Signal::ContractVoltage,
+        ];
+        for sop in 0..VCD_SOP_SCOPES.len() as u8 {
+            signals.extend([Signal::Message(sop), Signal::MessageCount(sop)]);
+        }
+        if self.options.vbus_step.is_some() {
+            signals.push(Signal::Vbus);
+        }
+        if self.options.ibus_step.is_some() {
+            signals.push(Signal::Ibus);
+        }
+        signals
+    }
+}
+
+impl std::fmt::Display for VcdExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let signals = self.signals();
+        let id = |signal: Signal| {
+            let index = signals.iter().position(|&candidate| candidate == signal).unwrap();
+            identifier(index)
+        };
+
+        writeln!(f, "$version km003c-rs {} $end", env!("CARGO_PKG_VERSION"))?;
+        writeln!(f, "$timescale 1 us $end")?;
+        writeln!(f, "$scope module km003c $end")?;
+        let mut scope = None;
+        for &signal in &signals {
+            let sop_scope = match signal {
+                Signal::Message(sop) | Signal::MessageCount(sop) => Some(sop),
+                _ => None,
+            };
+            if scope != sop_scope {
+                if scope.is_some() {
+                    writeln!(f, "$upscope $end")?;
+                }
+                if let Some(sop) = sop_scope {
+                    writeln!(f, "$scope module {} $end", VCD_SOP_SCOPES[usize::from(sop)])?;
+                }
+                scope = sop_scope;
+            }
+            let (kind, width, name) = match signal {
+                Signal::Connected => ("wire", 1, "connected"),
+                Signal::TypeCState => ("reg", 8, "typec_state"),
+                Signal::TypeCStateName => ("string", 1, "typec_state_name"),
+                Signal::ContractVoltage => ("real", 64, "contract_voltage"),
+                Signal::Message(_) => ("string", 1, "message"),
+                Signal::MessageCount(_) => ("integer", 32, "messages"),
+                Signal::Vbus => ("real", 64, "vbus"),
+                Signal::Ibus => ("real", 64, "ibus"),
+            };
+            writeln!(f, "$var {kind} {width} {} {name} $end", id(signal))?;
+        }
+        if scope.is_some() {
+            writeln!(f, "$upscope $end")?;
+        }
+        writeln!(f, "$upscope $end")?;
+        writeln!(f, "$enddefinitions $end")?;
+
+        writeln!(f, "#0")?;
+        writeln!(f, "$dumpvars")?;
+        for &signal in &signals {
+            let initial = match signal {
+                Signal::Connected => "x".to_string(),
+                Signal::TypeCState => "bx ".to_string(),
+                Signal::ContractVoltage => "r0 ".to_string(),
+                Signal::MessageCount(_) => "b0 ".to_string(),
+                Signal::TypeCStateName | Signal::Message(_) | Signal::Vbus | Signal::Ibus => continue,
+            };
+            writeln!(f, "{initial}{}", id(signal))?;
+        }
+        writeln!(f, "$end")?;
+
+        let mut time = 0;
+        for change in &self.changes {
+            if change.time_us != time {
+                writeln!(f, "#{}", change.time_us)?;
+                time = change.time_us;
+            }
+            let id = id(change.signal);
+            match &change.value {
+                Value::Bit(bit) => writeln!(f, "{}{id}", u8::from(*bit))?,
+                Value::Vector(value) => writeln!(f, "b{value:b} {id}")?,
+                Value::Real(value) => writeln!(f, "r{value} {id}")?,
+                Value::Text(text) => writeln!(f, "s{text} {id}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Short printable identifier for the `index`th variable.
+fn identifier(mut index: usize) -> String {
+    const FIRST: u8 = b'!';
+    const COUNT: usize = (b'~' - FIRST + 1) as usize;
+    let mut id = String::new();
+    loop {
+        id.push(char::from(FIRST + (index % COUNT) as u8));
+        index /= COUNT;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+/// Whole microseconds, clamped at zero.
+fn micros(time: Time) -> u64 {
+    time.get::<microsecond>().round().max(0.0) as u64
+}
+
+fn quantise(value: f64, step: f64) -> f64 {
+    if step > 0.0 {
+        (value / step).round() * step
+    } else {
+        value
+    }
+}
+
+fn message_name(message_type: PdMessageType) -> String {
+    match message_type {
+        PdMessageType::Control(kind) => format!("{kind:?}"),
+        PdMessageType::Data(kind) => format!("{kind:?}"),
+        PdMessageType::Extended(kind) => format!("{kind:?}"),
+    }
+}
+
+fn state_name(state: PdTypeCState) -> String {
+    format!("{state:?}").replace(['(', ')'], "_")
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::*;
+use km003c_lib::auth::{
+    CALIBRATION_ADDRESS, DEVICE_INFO_ADDRESS, FIRMWARE_INFO_ADDRESS, HARDWARE_ID_ADDRESS, INFO_BLOCK_SIZE,
+    PREFERRED_CALIBRATION_ADDRESS,
+};
+use km003c_lib::backup::BACKUP_IMAGE_MAGIC;
+use km003c_lib::{AuthCredential, BackupImage, BackupRegion, BackupRegionKind, LogMetadata};
+
+// Captured LogMetadata entry for a 521-sample recording named "A01.d".
+const CAPTURED_METADATA: &str = concat!(
+    "4130312e640000000000000000000000",
+    "450a09021027000050140000",
+    "a1a2f3ffe04da8ff000000000000000000000000"
+);
+
+const CAPTURED_SAMPLES: [&str; 3] = [
+    "81494c0021f0e2ff56ebffffb998ffff",
+    "bcaa89006e25f2ff2dd5f8fff7fdd6ff",
+    "cf2a8900947dfeffa1a2f3ffe04da8ff",
+];
+
+fn info_block(fields: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut block = vec![0; INFO_BLOCK_SIZE];
+    for (offset, value) in fields {
+        block[*offset..*offset + value.len()].copy_from_slice(value);
+    }
+    block
+}
+
+fn calibration_block(record: &[u8]) -> Vec<u8> {
+    info_block(&[(0, record)])
+}
+
+/// Two recordings: the captured one, then a single sample 0x100 bytes further on.
+fn catalog() -> (Vec<LogMetadata>, Vec<u8>) {
+    let mut first = LogMetadata::from_bytes(&hex::decode(CAPTURED_METADATA).unwrap()).unwrap();
+    first.sample_count = 3;
+    let mut second = first.clone();
+    second.filename_raw[..5].copy_from_slice(b"A02.d");
+    second.sample_count = 1;
+    second.data_offset = 0x100;
+
+    let mut area = CAPTURED_SAMPLES
+        .into_iter()
+        .flat_map(|sample| hex::decode(sample).unwrap())
+        .collect::<Vec<_>>();
+    area.resize(0x100, 0xff);
+    area.extend(hex::decode(CAPTURED_SAMPLES[2]).unwrap());
+    (vec![first, second], area)
+}
+
+fn device_backup() -> BackupImage {
+    let mut image = BackupImage::new();
+    image.insert(BackupRegion::new(
+        BackupRegionKind::DeviceInfo,
+        DEVICE_INFO_ADDRESS,
+        info_block(&[(0x10, b"KM003C"), (0x1c, b"2.1"), (0x28, b"2022.11.7")]),
+    ));
+    image.insert(BackupRegion::new(
+        BackupRegionKind::FirmwareInfo,
+        FIRMWARE_INFO_ADDRESS,
+        info_block(&[(0, &[0x00, 0x40, 0x00, 0x00]), (0x1c, b"1.9.9"), (0x28, b"2025.9.22")]),
+    ));
+    image.insert(BackupRegion::new(
+        BackupRegionKind::Calibration,
+        CALIBRATION_ADDRESS,
+        calibration_block(b"007965 CDFDD0A84E6C1FD03B45DD4A28D4B7F6"),
+    ));
+    image.insert(BackupRegion::new(
+        BackupRegionKind::PreferredCalibration,
+        PREFERRED_CALIBRATION_ADDRESS,
+        vec![0xff; INFO_BLOCK_SIZE],
+    ));
+    image.insert(BackupRegion::new(
+        BackupRegionKind::HardwareId,
+        HARDWARE_ID_ADDRESS,
+        hex::decode("3037314b42500dff1f00ffff").unwrap(),
+    ));
+    let (metadata, area) = catalog();
+    image.set_offline_logs(&metadata, area).unwrap();
+    image
+}
+
+#[test]
+fn extracts_device_records_from_backup_image() {
+    let image = BackupImage::from_bytes(&device_backup().to_bytes().unwrap()).unwrap();
+    assert_eq!(image, device_backup());
+
+    let info = image.device_info();
+    assert_eq!(info.model, "KM003C");
+    assert_eq!(info.hw_version, "2.1");
+    assert_eq!(info.fw_version, "1.9.9");
+    assert_eq!(info.fw_date, "2025.9.22");
+    assert_eq!(info.serial_id, "007965");
+
+    let hardware_id = image.hardware_id().unwrap();
+    assert_eq!(hardware_id.serial_prefix().as_deref(), Some("071KBP"));
+    assert_eq!(hardware_id.device_id(), 0x1f);
+
+    // The preferred record is erased, so the firmware falls back to 0x03000C00.
+    assert_eq!(
+        image.calibration_credential(),
+        Some(AuthCredential::from_bytes(*b"007965 CDFDD"))
+    );
+    let mut preferred = image.clone();
+    preferred.insert(BackupRegion::new(
+        BackupRegionKind::PreferredCalibration,
+        PREFERRED_CALIBRATION_ADDRESS,
+        calibration_block(b"007965 0A84E6C1"),
+    ));
+    assert_eq!(
+        preferred.calibration_credential(),
+        Some(AuthCredential::from_bytes(*b"007965 0A84E"))
+    );
+
+    let logs = image.offline_logs().unwrap();
+    assert_eq!(logs.len(), 2);
+    assert_eq!(logs[0].metadata.filename().unwrap(), "A01.d");
+    assert_eq!(logs[0].samples[1].raw().charge_uah, -469_715);
+    assert_eq!(logs[1].metadata.filename().unwrap(), "A02.d");
+    assert_eq!(logs[1].samples.len(), 1);
+    assert_eq!(logs[1].to_bytes(), hex::decode(CAPTURED_SAMPLES[2]).unwrap());
+}
+
+#[test]
+fn backup_image_round_trips_through_file() {
+    let image = device_backup();
+    let path = std::env::temp_dir().join(format!("km003c-backup-{}.bin", std::process::id()));
+    image.save(&path).unwrap();
+    let loaded = BackupImage::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), image);
+
+    // Only the identification blocks: no recordings, nothing to extract.
+    let mut empty = BackupImage::new();
+    empty.set_offline_logs(&[], Vec::new()).unwrap();
+    let empty = BackupImage::from_bytes(&empty.to_bytes().unwrap()).unwrap();
+    assert!(empty.offline_logs().unwrap().is_empty());
+    assert_eq!(empty.hardware_id(), None);
+    assert_eq!(empty.calibration_credential(), None);
+}
+
+#[test]
+fn rejects_corrupted_backup_images() {
+    let bytes = device_backup().to_bytes().unwrap();
+    assert_eq!(&bytes[..8], BACKUP_IMAGE_MAGIC);
+
+    let mut corrupted = bytes.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    let error = BackupImage::from_bytes(&corrupted).unwrap_err();
+    assert!(error.to_string().contains("OfflineLogArea region checksum mismatch"));
+
+    let mut manifest = bytes.clone();
+    manifest[0x14] ^= 1;
+    assert!(matches!(
+        BackupImage::from_bytes(&manifest),
+        Err(KMError::ParseError { offset: 0x10, .. })
+    ));
+
+    assert!(BackupImage::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(BackupImage::from_bytes(&bytes[..12]).is_err());
+    assert!(BackupImage::from_bytes(b"KM003CXX\x01\x00\x00\x00\x00\x00\x00\x00").is_err());
+
+    let (metadata, area) = catalog();
+    assert!(
+        BackupImage::new()
+            .set_offline_logs(&metadata, area[..0x100].to_vec())
+            .is_err()
+    );
+}
@@ -0,0 +1,186 @@
+//! Remote meter access through the TCP bridge over loopback
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use km003c_lib::auth::{MEMORY_READ_KEY, aes_ecb_decrypt_blocks};
+use km003c_lib::device::INTERFACE_HID;
+use km003c_lib::error::KMError;
+use km003c_lib::{BridgeDevice, BridgeServer, DeviceConfig, KM003C};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Meter answering every request after `delay`, preceded by a response of an earlier exchange.
+struct FakeMeter {
+    tid: u8,
+    delay: Duration,
+    requests: Arc<Mutex<Vec<Vec<u8>>>>,
+    responses: VecDeque<Vec<u8>>,
+}
+
+impl BridgeDevice for FakeMeter {
+    fn next_tid(&mut self) -> u8 {
+        let id = self.tid;
+        self.tid = self.tid.wrapping_add(1);
+        id
+    }
+
+    async fn send_raw(&mut self, data: &[u8]) -> Result<(), KMError> {
+        tokio::time::sleep(self.delay).await;
+        self.requests.lock().unwrap().push(data.to_vec());
+        let id = data[1];
+        self.responses.push_back(vec![0x05, id.wrapping_sub(1), 0, 0]);
+        if data[0] & 0x7F == 0x44 {
+            // Source: usb_master_dataset.parquet, orig_adc_1000hz.6, frame 264.
+            let mut confirmation = hex::decode("c40201012004000040000000ffffffff1b8c1b24").unwrap();
+            confirmation[1] = id;
+            self.responses.push_back(confirmation);
+            self.responses.push_back((0..32).collect());
+            self.responses.push_back((32..64).collect());
+        } else {
+            self.responses.push_back(vec![0x05, id, 0, 0]);
+        }
+        Ok(())
+    }
+
+    async fn receive_raw(&mut self) -> Result<Vec<u8>, KMError> {
+        match self.responses.pop_front() {
+            Some(response) => Ok(response),
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// Serve a fake HID meter on a loopback port; returns its URL and request log.
+async fn serve(key: Option<&[u8]>) -> (String, Arc<Mutex<Vec<Vec<u8>>>>) {
+    serve_slow(key, Duration::ZERO).await
+}
+
+async fn serve_slow(key: Option<&[u8]>, delay: Duration) -> (String, Arc<Mutex<Vec<Vec<u8>>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let meter = FakeMeter {
+        tid: 40,
+        delay,
+        requests: requests.clone(),
+        responses: VecDeque::new(),
+    };
+    let mut server = BridgeServer::new(meter, INTERFACE_HID);
+    if let Some(key) = key {
+        server = server.with_key(key);
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("tcp://{}", listener.local_addr().unwrap());
+    tokio::spawn(server.serve(listener));
+    (url, requests)
+}
+
+#[tokio::test]
+async fn remaps_transaction_ids_of_concurrent_clients() {
+    let (url, requests) = serve(None).await;
+    let mut first = KM003C::new(DeviceConfig::remote(&url).unwrap()).await.unwrap();
+    let mut second = KM003C::new(DeviceConfig::remote(&url).unwrap()).await.unwrap();
+    assert!(first.is_basic_mode());
+    first.set_transaction_id(7);
+    second.set_transaction_id(7);
+
+    let (first_result, second_result) = tokio::join!(first.enable_pd_monitor(), second.enable_pd_monitor());
+    first_result.unwrap();
+    second_result.unwrap();
+    first.disable_pd_monitor().await.unwrap();
+
+    let requests = requests.lock().unwrap();
+    let ids = requests.iter().map(|request| request[1]).collect::<Vec<_>>();
+    assert_eq!(ids, [40, 41, 42]);
+    assert_eq!(requests.last().unwrap()[0], 0x11);
+}
+
+#[tokio::test]
+async fn waits_for_requests_queued_behind_other_clients() {
+    // Each request takes most of the server's exchange timeout, so the second
+    // client waits longer than that before its own request reaches the meter.
+    let (url, requests) = serve_slow(None, Duration::from_millis(1500)).await;
+    let mut first = KM003C::new(DeviceConfig::remote(&url).unwrap()).await.unwrap();
+    let mut second = KM003C::new(DeviceConfig::remote(&url).unwrap()).await.unwrap();
+
+    let started = Instant::now();
+    let (first_result, second_result) = tokio::join!(first.enable_pd_monitor(), second.enable_pd_monitor());
+    first_result.unwrap();
+    second_result.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(3000));
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn stays_in_sync_after_a_cancelled_read() {
+    let (url, requests) = serve_slow(None, Duration::from_millis(500)).await;
+    let mut device = KM003C::new(DeviceConfig::remote(&url).unwrap()).await.unwrap();
+
+    let cancelled = tokio::time::timeout(Duration::from_millis(100), device.enable_pd_monitor()).await;
+    assert!(cancelled.is_err());
+    // The late response of the abandoned request arrives before the next one.
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    device.enable_pd_monitor().await.unwrap();
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn times_out_after_the_configured_bridge_timeout() {
+    let (url, _) = serve_slow(None, Duration::from_millis(500)).await;
+    let config = DeviceConfig::remote(&url)
+        .unwrap()
+        .bridge_timeout(Duration::from_millis(100));
+    let mut device = KM003C::new(config).await.unwrap();
+
+    let error = device.enable_pd_monitor().await.err().unwrap();
+    assert!(matches!(error, KMError::Timeout(_)), "{error}");
+}
+
+#[tokio::test]
+async fn forwards_encrypted_memory_reads() {
+    let (url, _) = serve(None).await;
+    let mut device = KM003C::new(DeviceConfig::remote(&url).unwrap()).await.unwrap();
+
+    let data = device.read_memory_block(0x420, 64).await.unwrap();
+    let ciphertext = (0..64).collect::<Vec<u8>>();
+    assert_eq!(data, aes_ecb_decrypt_blocks(&ciphertext, MEMORY_READ_KEY).unwrap());
+    // The stale Accept queued before the confirmation never reaches the client.
+    device.enable_pd_monitor().await.unwrap();
+}
+
+#[tokio::test]
+async fn requires_the_pre_shared_key() {
+    let (url, requests) = serve(Some(b"rack-7")).await;
+    let config = DeviceConfig::remote(&url).unwrap();
+
+    for config in [config, config.bridge_key(b"rack-8")] {
+        let error = KM003C::new(config).await.err().unwrap();
+        assert!(error.to_string().contains("invalid key"), "{error}");
+    }
+    let mut device = KM003C::new(config.bridge_key(b"rack-7")).await.unwrap();
+    device.enable_pd_monitor().await.unwrap();
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn drops_clients_announcing_oversized_handshake_frames() {
+    let (url, _) = serve(Some(b"rack-7")).await;
+    let mut stream = TcpStream::connect(url.trim_start_matches("tcp://")).await.unwrap();
+    // Length, kind, magic, version and challenge.
+    let mut hello = [0; 4 + 1 + 4 + 1 + 16];
+    stream.read_exact(&mut hello).await.unwrap();
+    assert_eq!(&hello[5..9], b"KM3B");
+
+    // The server hangs up instead of waiting for a megabyte of Auth data.
+    stream.write_all(&(1u32 << 20).to_le_bytes()).await.unwrap();
+    let mut rest = Vec::new();
+    assert_eq!(stream.read_to_end(&mut rest).await.unwrap(), 0);
+}
+
+#[test]
+fn parses_bridge_urls() {
+    assert!(DeviceConfig::remote("tcp://127.0.0.1").unwrap().is_remote());
+    assert!(DeviceConfig::remote("tcp://[::1]:7100/").unwrap().is_remote());
+    assert!(DeviceConfig::remote("127.0.0.1:7003").is_err());
+    assert!(!DeviceConfig::vendor().bridge_key(b"unused").is_remote());
+}
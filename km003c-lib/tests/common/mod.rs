@@ -35,3 +35,6 @@ pub const REAL_ADC_RESPONSE: &[u8] = &[
 #[allow(dead_code)]
 pub const EXTENDED_ADC_DATA: &str =
     "410c82020100000be08d4d001e000000218e4d00eaffffff278e4d00480000001c0c9502737e000001007b7e0080a40c00000000";
+
+/// USB PD wire-message builders shared by the PD tracker tests
+pub mod pd;
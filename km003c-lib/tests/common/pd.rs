@@ -0,0 +1,87 @@
+//! USB PD wire-message builders and port fixtures
+
+// Not every PD test file uses every builder or fixture
+#![allow(dead_code)]
+
+use km003c_lib::{PdEvent, PdEventData};
+use uom::si::f64::Time;
+use uom::si::time::millisecond;
+
+pub const SOP: u8 = 0;
+pub const SOP_PRIME: u8 = 1;
+
+pub const SOURCE_CAPABILITIES: u16 = 0x01;
+pub const REQUEST: u16 = 0x02;
+pub const ACCEPT: u16 = 0x03;
+pub const PS_RDY: u16 = 0x06;
+pub const VENDOR_DEFINED: u16 = 0x0f;
+
+/// Sender of a test message: its power role (or Cable Plug flag) and data role.
+#[derive(Clone, Copy)]
+pub struct Port {
+    pub source: bool,
+    pub dfp: bool,
+}
+
+pub const SOURCE: Port = Port {
+    source: true,
+    dfp: true,
+};
+pub const SINK: Port = Port {
+    source: false,
+    dfp: false,
+};
+// Roles of the same ports after a power role swap.
+pub const NEW_SOURCE: Port = Port {
+    source: true,
+    dfp: false,
+};
+pub const NEW_SINK: Port = Port {
+    source: false,
+    dfp: true,
+};
+// Cable Plug flags on SOP'/SOP''.
+pub const PORT: Port = SINK;
+pub const CABLE: Port = Port {
+    source: true,
+    dfp: false,
+};
+
+/// Message header with Revision 3.x.
+pub fn message(message_type: u16, port: Port, message_id: u16, objects: &[u32]) -> Vec<u8> {
+    revision_message(2, message_type, port, message_id, objects)
+}
+
+pub fn revision_message(revision: u16, message_type: u16, port: Port, message_id: u16, objects: &[u32]) -> Vec<u8> {
+    let header = message_type
+        | (u16::from(port.dfp) << 5)
+        | (revision << 6)
+        | (u16::from(port.source) << 8)
+        | (message_id << 9)
+        | ((objects.len() as u16) << 12);
+    let mut wire_data = header.to_le_bytes().to_vec();
+    for object in objects {
+        wire_data.extend_from_slice(&object.to_le_bytes());
+    }
+    wire_data
+}
+
+/// Vendor_Defined message with message ID 0.
+pub fn vdm_event(timestamp_ms: f64, sop: u8, port: Port, objects: &[u32]) -> PdEvent {
+    event(timestamp_ms, sop, message(VENDOR_DEFINED, port, 0, objects))
+}
+
+pub fn event(timestamp_ms: f64, sop: u8, wire_data: Vec<u8>) -> PdEvent {
+    event_at(ms(timestamp_ms), sop, wire_data)
+}
+
+pub fn event_at(timestamp: Time, sop: u8, wire_data: Vec<u8>) -> PdEvent {
+    PdEvent {
+        timestamp,
+        data: PdEventData::PdMessage { sop, wire_data },
+    }
+}
+
+pub fn ms(value: f64) -> Time {
+    Time::new::<millisecond>(value)
+}
@@ -0,0 +1,262 @@
+//! MQTT messages and publishing against a minimal in-process broker
+#![cfg(feature = "mqtt")]
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use km003c_lib::mqtt::ContractPayload;
+use km003c_lib::pd_wire::PdControlMessageType;
+use km003c_lib::rumqttc::{self, ConnAck, ConnectReturnCode, Packet, PubAck, Publish};
+use km003c_lib::uom::si::electric_current::milliampere;
+use km003c_lib::uom::si::electric_potential::millivolt;
+use km003c_lib::uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use km003c_lib::uom::si::time::millisecond;
+use km003c_lib::{
+    AdcQueueRawData, DeviceInfo, DeviceState, GraphSampleRate, HardwareId, MqttConfig, MqttEncoder, MqttPublisher,
+    PdCaptureBuilder, PdMessageBody, PdMessageSender, PdSopType, PdStatus, RequestDataObject, SourcePdo,
+};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn device_state() -> DeviceState {
+    DeviceState {
+        info: DeviceInfo {
+            model: "KM003C".to_string(),
+            fw_version: "1.9.9".to_string(),
+            serial_id: "007965".to_string(),
+            ..DeviceInfo::default()
+        },
+        hardware_id: HardwareId::from_bytes(*b"071KBP\x0d\xff\x34\x12\xff\xff"),
+        auth_level: 1,
+        adcqueue_enabled: true,
+    }
+}
+
+fn json(payload: &[u8]) -> Value {
+    serde_json::from_slice(payload).unwrap()
+}
+
+fn ms(value: f64) -> Time {
+    Time::new::<millisecond>(value)
+}
+
+#[test]
+fn averages_measurements_over_the_interval() {
+    let mut encoder = MqttEncoder::new("lab/", "007965", Duration::from_millis(10));
+    let bytes = (0..=25u16)
+        .flat_map(|sequence| {
+            let mut sample = vec![0; 20];
+            sample[..2].copy_from_slice(&sequence.to_le_bytes());
+            sample[4..8].copy_from_slice(&(5_000_000 + i32::from(sequence) * 1_000).to_le_bytes());
+            sample
+        })
+        .collect::<Vec<_>>();
+    let data = AdcQueueRawData::from_bytes(&bytes)
+        .unwrap()
+        .decode(GraphSampleRate::Sps1000);
+    let messages = encoder.adc_queue(&data, GraphSampleRate::Sps1000);
+
+    // Windows of samples 0-10 and 11-21; 22-25 are still being averaged.
+    assert_eq!(messages.len(), 2);
+    assert!(messages.iter().all(|message| message.retain));
+    assert_eq!(messages[0].topic, "lab/007965/measurement");
+    let first = json(&messages[0].payload);
+    assert_eq!(first["samples"], 11);
+    assert_eq!(first["sample_rate_hz"], 1000);
+    assert!((first["vbus_v"].as_f64().unwrap() - 5.005).abs() < 1e-9);
+    assert!((json(&messages[1].payload)["elapsed_s"].as_f64().unwrap() - 0.021).abs() < 1e-9);
+}
+
+#[test]
+fn publishes_attach_and_contract_changes() {
+    let mut encoder = MqttEncoder::new("km003c", "a/b", Duration::from_secs(1));
+    assert_eq!(encoder.status(true).topic, "km003c/a_b/status");
+    assert_eq!(
+        MqttEncoder::new("km003c", " ", Duration::from_secs(1)).topic("device"),
+        "km003c/unknown/device"
+    );
+
+    let source = PdMessageSender::source();
+    let sink = PdMessageSender::sink();
+    let mut builder = PdCaptureBuilder::new(PdStatus {
+        timestamp: ms(0.0),
+        vbus: ElectricPotential::new::<millivolt>(5_000.0),
+        ibus: ElectricCurrent::new::<milliampere>(0.0),
+        cc1: ElectricPotential::new::<millivolt>(1_650.0),
+        cc2: ElectricPotential::new::<millivolt>(0.0),
+    });
+    builder.connect(ms(0.0));
+    builder
+        .message(
+            ms(10.0),
+            PdSopType::Sop,
+            source,
+            // 5 V 3 A and 9 V 3 A fixed supplies.
+            &PdMessageBody::SourceCapabilities(vec![
+                SourcePdo::from_raw(0x0001_912c),
+                SourcePdo::from_raw(0x0002_d12c),
+            ]),
+        )
+        .unwrap()
+        .message(
+            ms(12.0),
+            PdSopType::Sop,
+            sink,
+            &PdMessageBody::Request(RequestDataObject(0x2003_20c8)),
+        )
+        .unwrap()
+        .message(
+            ms(13.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Control(PdControlMessageType::Accept),
+        )
+        .unwrap()
+        .message(
+            ms(40.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Control(PdControlMessageType::PsRdy),
+        )
+        .unwrap();
+    builder.disconnect(ms(500.0));
+    let messages = encoder.pd_events(&builder.build());
+
+    let payloads = |suffix: &str| {
+        messages
+            .iter()
+            .filter(|message| message.topic == format!("km003c/a_b/{suffix}"))
+            .map(|message| json(&message.payload))
+            .collect::<Vec<_>>()
+    };
+    let attach = payloads("pd/attach");
+    assert_eq!(attach.len(), 2);
+    assert_eq!(attach[0]["attached"], true);
+    assert_eq!(attach[1]["attached"], false);
+    assert!((attach[1]["timestamp_s"].as_f64().unwrap() - 0.5).abs() < 1e-9);
+
+    let contract = messages
+        .iter()
+        .filter(|message| message.topic == "km003c/a_b/pd/contract")
+        .map(|message| serde_json::from_slice::<Option<ContractPayload>>(&message.payload).unwrap())
+        .collect::<Vec<_>>();
+    let accepted = contract[0].as_ref().unwrap();
+    assert_eq!(accepted.voltage_v, Some(9.0));
+    assert_eq!(accepted.current_a, Some(2.0));
+    assert_eq!(accepted.object_position, 2);
+    assert_eq!(contract.last().unwrap(), &None);
+    assert!(messages.iter().all(|message| message.retain));
+}
+
+/// Serve one client connection until `done` holds for the messages published
+/// on it, then drop the connection.
+async fn serve_client(listener: &TcpListener, done: impl Fn(&[Publish]) -> bool) -> (rumqttc::Connect, Vec<Publish>) {
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut buffer = BytesMut::new();
+    let mut connect = None;
+    let mut publishes = Vec::new();
+    while !done(&publishes) {
+        let packet = match Packet::read(&mut buffer, 1 << 20) {
+            Ok(packet) => packet,
+            Err(rumqttc::Error::InsufficientBytes(_)) => {
+                assert!(socket.read_buf(&mut buffer).await.unwrap() > 0, "client disconnected");
+                continue;
+            }
+            Err(error) => panic!("malformed packet: {error:?}"),
+        };
+        let reply = match packet {
+            Packet::Connect(packet) => {
+                connect = Some(packet);
+                Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false))
+            }
+            Packet::Publish(publish) => {
+                let pkid = publish.pkid;
+                publishes.push(publish);
+                Packet::PubAck(PubAck::new(pkid))
+            }
+            Packet::PingReq => Packet::PingResp,
+            _ => continue,
+        };
+        let mut out = BytesMut::new();
+        reply.write(&mut out, 1 << 20).unwrap();
+        socket.write_all(&out).await.unwrap();
+    }
+    (connect.expect("client sent CONNECT"), publishes)
+}
+
+fn has(publishes: &[Publish], topic: &str, payload: &[u8]) -> bool {
+    publishes
+        .iter()
+        .any(|publish| publish.topic == topic && publish.payload.as_ref() == payload)
+}
+
+#[tokio::test]
+async fn publishes_retained_state_once_on_the_first_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut config = MqttConfig::new("127.0.0.1");
+    config.port = listener.local_addr().unwrap().port();
+    let _publisher = MqttPublisher::connect(&config, &device_state());
+
+    let received = Cell::new(0);
+    let served = tokio::time::timeout(
+        Duration::from_secs(1),
+        serve_client(&listener, |publishes| {
+            received.set(publishes.len());
+            publishes.len() > 2
+        }),
+    )
+    .await;
+    assert!(served.is_err(), "device or status was published twice");
+    assert_eq!(received.get(), 2);
+}
+
+#[tokio::test]
+async fn republishes_retained_state_after_reconnecting() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut config = MqttConfig::new("127.0.0.1");
+    config.port = listener.local_addr().unwrap().port();
+    let mut publisher = MqttPublisher::connect(&config, &device_state());
+
+    let (connect, publishes) = tokio::time::timeout(
+        Duration::from_secs(10),
+        serve_client(&listener, |publishes| {
+            has(publishes, "km003c/007965/status", b"online")
+                && publishes.iter().any(|publish| publish.topic == "km003c/007965/device")
+        }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(connect.client_id, config.client_id);
+    let will = connect.last_will.unwrap();
+    assert_eq!(will.topic, "km003c/007965/status");
+    assert_eq!(will.message.as_ref(), b"offline");
+    assert!(will.retain);
+    let device = publishes
+        .iter()
+        .find(|publish| publish.topic == "km003c/007965/device")
+        .unwrap();
+    assert!(device.retain);
+    assert_eq!(json(&device.payload)["serial"], "007965");
+
+    // The broker went away; state published meanwhile reaches the next connection.
+    let attach = publisher.encoder().topic("pd/attach");
+    publisher.publish(km003c_lib::MqttMessage {
+        topic: attach.clone(),
+        payload: br#"{"attached":true}"#.to_vec(),
+        retain: true,
+    });
+    let (_, publishes) = tokio::time::timeout(
+        Duration::from_secs(10),
+        serve_client(&listener, |publishes| {
+            has(publishes, "km003c/007965/status", b"online")
+                && has(publishes, "km003c/007965/device", &device.payload)
+                && has(publishes, &attach, br#"{"attached":true}"#)
+        }),
+    )
+    .await
+    .unwrap();
+    assert!(publishes.iter().all(|publish| publish.retain));
+    assert_eq!(publisher.dropped(), 0);
+}
@@ -0,0 +1,211 @@
+mod common;
+
+use common::pd::{PORT, SOP, SOP_PRIME, event, message, vdm_event};
+use km003c_lib::pd_alt_mode::EnterUsbMode;
+use km003c_lib::pd_vdm::{
+    DISPLAYPORT_SVID, DisplayPortConfiguration, DisplayPortConnection, DisplayPortPinAssignment, DisplayPortRoles,
+    THUNDERBOLT_SVID, ThunderboltCableSpeed, UsbHighestSpeed, VdmCommand, VdmCommandType,
+};
+use km003c_lib::pd_wire::PdControlMessageType;
+use km003c_lib::{
+    AltModeTracker, ModeVdo, PdEvent, PdEventData, PdSopType, PdWireMessage, VdmContent, VendorDefinedMessage,
+};
+use uom::si::electric_current::ampere;
+use uom::si::f64::Time;
+use uom::si::time::millisecond;
+
+fn vdm(sop: u8, objects: &[u32]) -> VendorDefinedMessage {
+    let PdEventData::PdMessage { wire_data, .. } = vdm_event(0.0, sop, PORT, objects).data else {
+        unreachable!();
+    };
+    VendorDefinedMessage::from_wire(&PdWireMessage::from_bytes(&wire_data).unwrap()).unwrap()
+}
+
+// Structured VDM 2.0 headers.
+const DISCOVER_SVIDS_ACK: u32 = 0xff00_a042;
+const DP_DISCOVER_MODES_ACK: u32 = 0xff01_a043;
+const TBT_DISCOVER_MODES_ACK: u32 = 0x8087_a043;
+const DP_ENTER_MODE_REQUEST: u32 = 0xff01_a104;
+const DP_ENTER_MODE_ACK: u32 = 0xff01_a144;
+const DP_ENTER_MODE_NAK: u32 = 0xff01_a184;
+const DP_STATUS_UPDATE_REQUEST: u32 = 0xff01_a110;
+const DP_STATUS_UPDATE_ACK: u32 = 0xff01_a150;
+const DP_CONFIGURE_REQUEST: u32 = 0xff01_a111;
+const DP_CONFIGURE_ACK: u32 = 0xff01_a151;
+const DP_CONFIGURE_NAK: u32 = 0xff01_a191;
+const DP_ATTENTION: u32 = 0xff01_a106;
+
+// UFP_D-capable receptacle, DP 1.3 signalling, UFP_D pin assignments C, D and E.
+const DP_CAPABILITIES: u32 = 0x001c_0045;
+// UFP_D connected, enabled, HPD high.
+const DP_STATUS_HPD: u32 = 0x0000_008a;
+// Configure UFP_U as UFP_D, DP 1.3 signalling, pin assignment C.
+const DP_CONFIGURE_PIN_C: u32 = 0x0000_0406;
+
+#[test]
+fn decodes_discover_svids_until_the_terminating_zero() {
+    let message = vdm(SOP, &[DISCOVER_SVIDS_ACK, 0xff01_8087, 0x05ac_0000, 0x1234_5678]);
+
+    assert_eq!(
+        message.discover_svids(),
+        Some(vec![DISPLAYPORT_SVID, THUNDERBOLT_SVID, 0x05ac])
+    );
+    assert_eq!(
+        message.content(PdSopType::Sop),
+        VdmContent::DiscoverSvids(vec![DISPLAYPORT_SVID, THUNDERBOLT_SVID, 0x05ac])
+    );
+}
+
+#[test]
+fn types_mode_vdos_by_svid_and_sop() {
+    let dp = vdm(SOP, &[DP_DISCOVER_MODES_ACK, DP_CAPABILITIES]);
+    let modes = dp.discover_modes(PdSopType::Sop).unwrap();
+    let [ModeVdo::DisplayPort(capabilities)] = modes.as_slice() else {
+        panic!("expected one DisplayPort mode");
+    };
+    assert_eq!(capabilities.port_capability(), DisplayPortRoles::UfpD);
+    assert!(capabilities.receptacle());
+    assert_eq!(
+        capabilities.sink_pin_assignments(),
+        vec![
+            DisplayPortPinAssignment::C,
+            DisplayPortPinAssignment::D,
+            DisplayPortPinAssignment::E
+        ]
+    );
+
+    // Passive Thunderbolt 3 cable supporting 10 and 20 Gb/s.
+    let cable = vdm(SOP_PRIME, &[TBT_DISCOVER_MODES_ACK, 0x0003_0001]);
+    let VdmContent::DiscoverModes(modes) = cable.content(PdSopType::SopPrime) else {
+        panic!("expected Discover Modes content");
+    };
+    let [ModeVdo::ThunderboltCable(tbt_cable)] = modes.as_slice() else {
+        panic!("expected a Thunderbolt cable mode");
+    };
+    assert_eq!(tbt_cable.cable_speed(), ThunderboltCableSpeed::Gen3);
+    assert!(!tbt_cable.active());
+
+    let device = vdm(SOP, &[TBT_DISCOVER_MODES_ACK, 0x0000_0001]);
+    let modes = device.discover_modes(PdSopType::Sop).unwrap();
+    let [ModeVdo::Thunderbolt(tbt_device)] = modes.as_slice() else {
+        panic!("expected a Thunderbolt device mode");
+    };
+    assert!(tbt_device.is_thunderbolt_mode());
+    assert!(!tbt_device.legacy_adapter());
+}
+
+#[test]
+fn decodes_displayport_status_and_configure() {
+    let VdmContent::DisplayPortStatus(status) = vdm(SOP, &[DP_ATTENTION, 0x0000_018a]).content(PdSopType::Sop) else {
+        panic!("expected DisplayPort status");
+    };
+    assert_eq!(status.connected(), DisplayPortConnection::UfpD);
+    assert!(status.enabled());
+    assert!(status.hpd_state());
+    assert!(status.irq_hpd());
+
+    let VdmContent::DisplayPortConfigure(configure) =
+        vdm(SOP, &[DP_CONFIGURE_REQUEST, DP_CONFIGURE_PIN_C]).content(PdSopType::Sop)
+    else {
+        panic!("expected DisplayPort configure");
+    };
+    assert_eq!(configure.configuration(), DisplayPortConfiguration::UfpUAsUfpD);
+    assert_eq!(configure.pin_assignment(), Some(DisplayPortPinAssignment::C));
+
+    assert_eq!(vdm(SOP, &[DP_CONFIGURE_ACK]).content(PdSopType::Sop), VdmContent::Empty);
+}
+
+#[test]
+fn summarizes_displayport_alt_mode_entry() {
+    let mut tracker = AltModeTracker::new();
+    let events = [
+        vdm_event(1.0, SOP, PORT, &[DISCOVER_SVIDS_ACK, 0xff01_0000]),
+        vdm_event(2.0, SOP, PORT, &[DP_DISCOVER_MODES_ACK, DP_CAPABILITIES]),
+        vdm_event(3.0, SOP, PORT, &[DP_ENTER_MODE_REQUEST]),
+        vdm_event(4.0, SOP, PORT, &[DP_ENTER_MODE_ACK]),
+        vdm_event(5.0, SOP, PORT, &[DP_STATUS_UPDATE_REQUEST, 0x0000_0001]),
+        vdm_event(6.0, SOP, PORT, &[DP_STATUS_UPDATE_ACK, DP_STATUS_HPD]),
+        vdm_event(7.0, SOP, PORT, &[DP_CONFIGURE_REQUEST, DP_CONFIGURE_PIN_C]),
+        vdm_event(8.0, SOP, PORT, &[DP_CONFIGURE_ACK]),
+        vdm_event(9.0, SOP, PORT, &[DP_ATTENTION, DP_STATUS_HPD | 0x100]),
+    ];
+    for event in &events {
+        tracker.process_event(event);
+    }
+
+    let summary = tracker.summary();
+    assert_eq!(summary.partner_svids, vec![DISPLAYPORT_SVID]);
+    assert!(summary.is_active(DISPLAYPORT_SVID));
+    assert_eq!(
+        summary.active_modes[0].mode,
+        Some(ModeVdo::new(DISPLAYPORT_SVID, PdSopType::Sop, DP_CAPABILITIES))
+    );
+    assert_eq!(summary.active_modes[0].entered_at, Time::new::<millisecond>(4.0));
+    assert!(summary.failures.is_empty());
+
+    let displayport = summary.displayport.as_ref().unwrap();
+    assert!(displayport.hpd());
+    assert_eq!(displayport.pin_assignment(), Some(DisplayPortPinAssignment::C));
+    assert_eq!(displayport.configured_at, Some(Time::new::<millisecond>(8.0)));
+    assert_eq!(displayport.irq_hpd_count, 1);
+
+    tracker.process_event(&PdEvent {
+        timestamp: Time::new::<millisecond>(10.0),
+        data: PdEventData::Disconnect(()),
+    });
+    assert_eq!(tracker.summary(), &Default::default());
+}
+
+#[test]
+fn records_rejected_mode_entry_and_configuration() {
+    let mut tracker = AltModeTracker::new();
+    tracker.process_event(&vdm_event(1.0, SOP, PORT, &[DP_ENTER_MODE_REQUEST]));
+    tracker.process_event(&vdm_event(2.0, SOP, PORT, &[DP_ENTER_MODE_NAK]));
+    tracker.process_event(&vdm_event(3.0, SOP, PORT, &[DP_CONFIGURE_REQUEST, DP_CONFIGURE_PIN_C]));
+    tracker.process_event(&vdm_event(4.0, SOP, PORT, &[DP_CONFIGURE_NAK]));
+
+    let summary = tracker.summary();
+    assert!(!summary.is_active(DISPLAYPORT_SVID));
+    assert_eq!(summary.failures.len(), 2);
+    assert_eq!(summary.failures[0].command, VdmCommand::EnterMode);
+    assert_eq!(summary.failures[0].response, VdmCommandType::Nak);
+    assert_eq!(summary.failures[0].object_position, 1);
+    assert_eq!(summary.failures[1].command, VdmCommand::SvidSpecific(0x11));
+    assert!(summary.displayport.is_none());
+}
+
+#[test]
+fn tracks_enter_usb_and_its_response() {
+    // USB4, USB4 Gen3 passive 5 A cable, host present.
+    let enter_usb = 0x2066_2000;
+    let mut tracker = AltModeTracker::new();
+    tracker.process_event(&event(1.0, SOP, message(0x08, PORT, 0, &[enter_usb])));
+    tracker.process_event(&event(1.1, SOP, message(0x01, PORT, 0, &[])));
+    assert_eq!(tracker.summary().enter_usb.as_ref().unwrap().response, None);
+
+    tracker.process_event(&event(2.0, SOP, message(0x03, PORT, 0, &[])));
+    let state = tracker.summary().enter_usb.as_ref().unwrap();
+    assert_eq!(state.response, Some(PdControlMessageType::Accept));
+    assert!(state.accepted());
+    assert_eq!(state.request.usb_mode(), EnterUsbMode::Usb4);
+    assert_eq!(state.request.cable_speed(), UsbHighestSpeed::Usb4Gen3);
+    assert_eq!(state.request.cable_current().unwrap().get::<ampere>(), 5.0);
+    assert!(state.request.host_present());
+
+    // A later Accept belongs to another exchange.
+    tracker.process_event(&event(3.0, SOP, message(0x04, PORT, 0, &[])));
+    assert!(tracker.summary().enter_usb.as_ref().unwrap().accepted());
+}
+
+#[cfg(feature = "usbpd")]
+#[test]
+fn session_decoder_exposes_vdm_and_alt_mode_summary() {
+    use km003c_lib::PdSessionDecoder;
+
+    let mut decoder = PdSessionDecoder::new();
+    decoder.decode_event(&vdm_event(1.0, SOP, PORT, &[DP_ENTER_MODE_ACK]));
+    decoder.decode_event(&vdm_event(2.0, SOP, PORT, &[DP_STATUS_UPDATE_ACK, DP_STATUS_HPD]));
+
+    assert!(decoder.alt_modes().is_active(DISPLAYPORT_SVID));
+    assert!(decoder.alt_modes().displayport.as_ref().unwrap().hpd());
+}
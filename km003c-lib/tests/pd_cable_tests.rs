@@ -0,0 +1,152 @@
+mod common;
+
+use common::pd::{CABLE, PORT, SOP, SOP_PRIME, vdm_event};
+use km003c_lib::pd_vdm::{CableLatency, CablePlugType, CableTermination, IdHeaderVdo, UfpProductType, UsbHighestSpeed};
+use km003c_lib::{CableDiscoveryResult, CableIdentityTracker, CableKind, PdEvent, PdEventData, PdSopType};
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::f64::Time;
+use uom::si::thermodynamic_temperature::degree_celsius;
+use uom::si::time::millisecond;
+
+// Structured VDM 2.0 Discover Identity headers for the PD SID.
+const DISCOVER_IDENTITY_REQUEST: u32 = 0xff00_a001;
+const DISCOVER_IDENTITY_ACK: u32 = 0xff00_a041;
+const DISCOVER_IDENTITY_NAK: u32 = 0xff00_a081;
+
+#[test]
+fn identifies_passive_epr_cable_from_sop_prime_discover_identity() {
+    let mut tracker = CableIdentityTracker::new();
+
+    assert!(
+        tracker
+            .process_event(&vdm_event(10.0, SOP_PRIME, PORT, &[DISCOVER_IDENTITY_REQUEST]))
+            .is_none()
+    );
+    let discovery = tracker
+        .process_event(&vdm_event(
+            11.0,
+            SOP_PRIME,
+            CABLE,
+            &[
+                DISCOVER_IDENTITY_ACK,
+                // Passive cable plug, VID 0x1234.
+                0x1860_1234,
+                // XID.
+                0x0000_0abc,
+                // PID 0x5678, bcdDevice 0x0100.
+                0x5678_0100,
+                // HW 1, FW 2, Type-C, EPR, <10 ns, 50 V, 5 A, USB 3.2 Gen2.
+                0x120a_2642,
+            ],
+        ))
+        .expect("ACK completes the exchange");
+
+    assert_eq!(discovery.sop, PdSopType::SopPrime);
+    assert_eq!(discovery.request_timestamp, Some(Time::new::<millisecond>(10.0)));
+    let CableDiscoveryResult::Identified(cable) = discovery.result else {
+        panic!("expected an identified cable");
+    };
+    assert_eq!(cable.kind, CableKind::Passive);
+    assert_eq!(cable.vendor_id, 0x1234);
+    assert_eq!(cable.product_id, 0x5678);
+    assert_eq!(cable.bcd_device, 0x0100);
+    assert_eq!(cable.xid, 0xabc);
+    assert_eq!(cable.hardware_version, 1);
+    assert_eq!(cable.firmware_version, 2);
+    assert_eq!(cable.plug_type, CablePlugType::UsbTypeC);
+    assert!(cable.epr_mode_capable);
+    assert_eq!(cable.latency, CableLatency::Below10Ns);
+    assert_eq!(cable.termination, CableTermination::PassiveVconnNotRequired);
+    assert_eq!(cable.max_vbus_voltage.get::<volt>(), 50.0);
+    assert_eq!(cable.max_current.unwrap().get::<ampere>(), 5.0);
+    assert_eq!(cable.usb_highest_speed, UsbHighestSpeed::Usb32Gen2);
+    assert!(cable.active.is_none());
+    assert_eq!(tracker.cable(), Some(&cable));
+}
+
+#[test]
+fn decodes_active_cable_vdo2() {
+    let mut tracker = CableIdentityTracker::new();
+    let discovery = tracker
+        .process_event(&vdm_event(
+            1.0,
+            SOP_PRIME,
+            CABLE,
+            &[
+                DISCOVER_IDENTITY_ACK,
+                // Active cable plug, VID 0x2222.
+                0x2060_2222,
+                0,
+                0x0001_0000,
+                // VDO 1.3, Type-C, 20-30 ns, both ends active, 20 V, 3 A, USB4 Gen3.
+                0x0068_7823,
+                // 70 C operating, 85 C shutdown, retimer, USB4, two lanes.
+                0x4655_0208,
+            ],
+        ))
+        .unwrap();
+
+    let CableDiscoveryResult::Identified(cable) = discovery.result else {
+        panic!("expected an identified cable");
+    };
+    assert_eq!(discovery.request_timestamp, None);
+    assert_eq!(cable.kind, CableKind::Active);
+    assert_eq!(cable.vdo_version, 3);
+    assert_eq!(cable.latency, CableLatency::From20To30Ns);
+    assert_eq!(cable.termination, CableTermination::BothEndsActive);
+    assert_eq!(cable.max_vbus_voltage.get::<volt>(), 20.0);
+    assert_eq!(cable.max_current.unwrap().get::<ampere>(), 3.0);
+    assert_eq!(cable.usb_highest_speed, UsbHighestSpeed::Usb4Gen3);
+
+    let vdo2 = cable.active.unwrap().vdo2.unwrap();
+    assert_eq!(vdo2.max_operating_temperature().get::<degree_celsius>(), 70.0);
+    assert_eq!(vdo2.shutdown_temperature().get::<degree_celsius>(), 85.0);
+    assert!(vdo2.retimer());
+    assert!(vdo2.usb4_supported());
+    assert_eq!(vdo2.usb_lanes(), 2);
+}
+
+#[test]
+fn reports_nak_from_non_emarked_responder() {
+    let mut tracker = CableIdentityTracker::new();
+    tracker.process_event(&vdm_event(5.0, SOP_PRIME, PORT, &[DISCOVER_IDENTITY_REQUEST]));
+
+    let discovery = tracker
+        .process_event(&vdm_event(6.0, SOP_PRIME, CABLE, &[DISCOVER_IDENTITY_NAK]))
+        .unwrap();
+
+    assert_eq!(discovery.result, CableDiscoveryResult::Nak);
+    assert_eq!(discovery.request_timestamp, Some(Time::new::<millisecond>(5.0)));
+    assert!(tracker.cable().is_none());
+}
+
+#[test]
+fn ignores_port_partner_identity_and_resets_on_connection_change() {
+    let passive = [DISCOVER_IDENTITY_ACK, 0x1860_1234, 0, 0x5678_0100, 0x120a_2642];
+    let mut tracker = CableIdentityTracker::new();
+
+    assert!(tracker.process_event(&vdm_event(1.0, SOP, PORT, &passive)).is_none());
+    assert!(tracker.cable().is_none());
+
+    tracker.process_event(&vdm_event(2.0, SOP_PRIME, CABLE, &passive));
+    assert!(tracker.cable().is_some());
+
+    tracker.process_event(&PdEvent {
+        timestamp: Time::new::<millisecond>(3.0),
+        data: PdEventData::Disconnect(()),
+    });
+    assert!(tracker.cable().is_none());
+}
+
+#[test]
+fn decodes_ufp_product_types_of_port_partners() {
+    // USB device capable PSD (product type 011b) from VID 0x1234.
+    let psd = IdHeaderVdo(0x5800_1234);
+    assert!(psd.usb_device_capable());
+    assert_eq!(psd.ufp_product_type(), UfpProductType::PowerSinkingDevice);
+    assert_eq!(psd.vendor_id(), 0x1234);
+
+    assert_eq!(IdHeaderVdo(0x1000_0000).ufp_product_type(), UfpProductType::Peripheral);
+    assert_eq!(IdHeaderVdo(0x2800_0000).ufp_product_type(), UfpProductType::Unknown(5));
+}
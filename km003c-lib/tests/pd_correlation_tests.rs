@@ -0,0 +1,209 @@
+mod common;
+
+use common::*;
+use km003c_lib::pd_wire::PdControlMessageType;
+use km003c_lib::uom::si::time::second;
+use km003c_lib::{
+    PdCaptureBuilder, PdEvent, PdEventData, PdMessageBody, PdMessageSender, PdMessageType, PdProtocolTraceEventKind,
+    PdSopType, PdTrace, PdTraceCorrelator, PdTraceProtocolEvent, PdTraceStateEvent, PdTypeCState, PdWireMessage,
+    RequestDataObject, SourcePdo, TraceCorrelationIssue,
+};
+use uom::si::f64::Time;
+use uom::si::time::millisecond;
+
+// Source: usb_master_dataset.parquet, orig_with_pd.13, frame 714.
+const PD_NEGOTIATION: &str = "41a90205100000168bfa1200de130000750602009f80fa120000a1632c9101082cd102002cc103002cb10400454106003c21dcc08781fa12000041028b85fa1200008210dc7003238786fa1200002101878afa120000a305878afa1200004104";
+
+fn ms(value: f64) -> Time {
+    Time::new::<millisecond>(value)
+}
+
+fn seconds(value: f64) -> Time {
+    Time::new::<second>(value)
+}
+
+fn received(uptime: f64) -> PdTraceProtocolEvent {
+    protocol(PdProtocolTraceEventKind::ReceivedMessage, uptime)
+}
+
+fn protocol(kind: PdProtocolTraceEventKind, uptime: f64) -> PdTraceProtocolEvent {
+    PdTraceProtocolEvent {
+        kind,
+        timestamp: seconds(uptime),
+    }
+}
+
+fn state(state: PdTypeCState, uptime: f64) -> PdTraceStateEvent {
+    PdTraceStateEvent {
+        state,
+        timestamp: seconds(uptime),
+    }
+}
+
+fn is_good_crc(event: &PdEvent) -> bool {
+    let PdEventData::PdMessage { wire_data, .. } = &event.data else {
+        return false;
+    };
+    let message = PdWireMessage::from_bytes(wire_data).unwrap();
+    message.message_type() == PdMessageType::Control(PdControlMessageType::GoodCrc)
+}
+
+/// A negotiation whose PD clock runs 1000.6 s behind device uptime.
+fn negotiation() -> Vec<PdEvent> {
+    let packet = Packet::try_from(RawPacket::try_from(hex_to_bytes(PD_NEGOTIATION)).unwrap()).unwrap();
+    let pdos = vec![SourcePdo::from_raw(0x0001_912c)];
+    let source = PdMessageSender::source();
+    let sink = PdMessageSender::sink();
+
+    let mut builder = PdCaptureBuilder::new(packet.get_pd_events().unwrap().preamble);
+    builder
+        .connect(ms(100.0))
+        .message(
+            ms(300.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::SourceCapabilities(pdos),
+        )
+        .unwrap()
+        .message(
+            ms(500.0),
+            PdSopType::Sop,
+            sink,
+            &PdMessageBody::Request(RequestDataObject(0x1004_b12c)),
+        )
+        .unwrap()
+        .message(
+            ms(510.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Control(PdControlMessageType::Accept),
+        )
+        .unwrap()
+        .message(
+            ms(700.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Control(PdControlMessageType::PsRdy),
+        )
+        .unwrap()
+        .message(
+            ms(3000.0),
+            PdSopType::Sop,
+            sink,
+            &PdMessageBody::Control(PdControlMessageType::GetStatus),
+        )
+        .unwrap()
+        .disconnect(ms(5200.0));
+    builder.events().to_vec()
+}
+
+#[test]
+fn aligns_clocks_and_attributes_trace_events() {
+    let events = negotiation();
+    let mut correlator = PdTraceCorrelator::new();
+    for event in &events {
+        correlator.process_event(event);
+    }
+    // The firmware drains its queues in two polls.
+    correlator.process_trace(&PdTrace {
+        state_events: vec![
+            state(PdTypeCState::AttachWaitSink, 1000.0),
+            state(PdTypeCState::AttachedSink, 1000.0),
+        ],
+        protocol_events: vec![
+            received(1000.0),
+            protocol(PdProtocolTraceEventKind::Unknown(0x41), 1000.0),
+            received(1001.0),
+            received(1001.0),
+            received(1001.0),
+        ],
+    });
+    correlator.process_trace(&PdTrace {
+        state_events: vec![
+            state(PdTypeCState::DelayUnattached, 1005.0),
+            state(PdTypeCState::AttachedSink, 1007.0),
+        ],
+        protocol_events: vec![protocol(PdProtocolTraceEventKind::Unknown(0x50), 1003.0)],
+    });
+
+    let alignment = correlator.clock_alignment().unwrap();
+    assert!((alignment.min_offset.get::<second>() - 1000.3).abs() < 1e-9);
+    assert!((alignment.max_offset.get::<second>() - 1000.7).abs() < 1e-9);
+    assert_eq!(alignment.matches, 6);
+
+    let correlation = correlator.correlate();
+    let messages = (0..events.len())
+        .filter(|&index| matches!(events[index].data, PdEventData::PdMessage { .. }) && !is_good_crc(&events[index]))
+        .collect::<Vec<_>>();
+    let attributed = correlation
+        .protocol_events
+        .iter()
+        .map(|event| event.wire_event)
+        .collect::<Vec<_>>();
+    // The internal 0x41 state follows Source_Capabilities; 0x50 fires without traffic.
+    assert_eq!(
+        attributed,
+        vec![
+            Some(messages[0]),
+            Some(messages[0]),
+            Some(messages[1]),
+            Some(messages[2]),
+            Some(messages[3]),
+            None,
+        ]
+    );
+
+    let states = correlation
+        .state_events
+        .iter()
+        .map(|event| event.wire_event)
+        .collect::<Vec<_>>();
+    assert_eq!(states, vec![None, Some(0), Some(events.len() - 1), None]);
+
+    assert_eq!(
+        correlation.issues,
+        vec![
+            TraceCorrelationIssue::ProtocolEventWithoutTraffic(protocol(
+                PdProtocolTraceEventKind::Unknown(0x50),
+                1003.0
+            )),
+            TraceCorrelationIssue::MessageWithoutTrace {
+                wire_event: messages[4],
+                timestamp: ms(3000.0),
+            },
+            TraceCorrelationIssue::StateChangeWithoutConnection(state(PdTypeCState::AttachedSink, 1007.0)),
+        ]
+    );
+}
+
+#[test]
+fn traffic_outside_the_trace_is_not_flagged() {
+    let mut correlator = PdTraceCorrelator::new();
+    for event in negotiation() {
+        correlator.process_event(&event);
+    }
+
+    let correlation = correlator.correlate();
+    assert_eq!(correlation.alignment, None);
+    assert!(correlation.issues.is_empty());
+
+    // Without markers to align, the clocks are assumed to agree.
+    correlator.process_trace(&PdTrace {
+        state_events: Vec::new(),
+        protocol_events: vec![
+            protocol(PdProtocolTraceEventKind::Unknown(0x50), 4.0),
+            protocol(PdProtocolTraceEventKind::Disabled, 5.0),
+        ],
+    });
+    let correlation = correlator.correlate();
+    let disconnect = correlator.events().len() - 1;
+    assert_eq!(correlation.alignment, None);
+    assert_eq!(correlation.protocol_events[1].wire_event, Some(disconnect));
+    assert_eq!(
+        correlation.issues,
+        vec![TraceCorrelationIssue::ProtocolEventWithoutTraffic(protocol(
+            PdProtocolTraceEventKind::Unknown(0x50),
+            4.0
+        ))]
+    );
+}
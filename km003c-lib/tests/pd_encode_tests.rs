@@ -0,0 +1,299 @@
+mod common;
+
+use common::*;
+use km003c_lib::pd_epr::{EprModeDataObject, ExtendedControlType};
+use km003c_lib::pd_policy::{PdPowerRole, PdSpecRevision};
+use km003c_lib::pd_wire::{PdControlMessageType, PdExtendedMessageType};
+use km003c_lib::{
+    EprModeState, EprTracker, ExtendedChunkProgress, ExtendedMessageAssembler, ExtendedPayload, PdCaptureBuilder,
+    PdEvent, PdEventData, PdEventStream, PdMessageBody, PdMessageSender, PdSopType, PdWireMessage, ProtocolAnalyzer,
+    RequestDataObject, SourcePdo,
+};
+use uom::si::f64::Time;
+use uom::si::time::millisecond;
+
+// Source: usb_master_dataset.parquet, orig_with_pd.13, frame 714.
+const PD_NEGOTIATION: &str = "41a90205100000168bfa1200de130000750602009f80fa120000a1632c9101082cd102002cc103002cb10400454106003c21dcc08781fa12000041028b85fa1200008210dc7003238786fa1200002101878afa120000a305878afa1200004104";
+
+// Captured EPR Source Capabilities split into two chunks, MessageIDs 6 and 7.
+const EPR_CAPS_CHUNK_0: &str = "b1fd28802c91910a2cd112002cc113002cb11400f44116006432a4c90000";
+const EPR_CAPS_CHUNK_1: &str = "b1cf28880000f4c11800f4411b00f4011f00";
+
+fn ms(value: f64) -> Time {
+    Time::new::<millisecond>(value)
+}
+
+fn wire_data(event: &PdEvent) -> &[u8] {
+    match &event.data {
+        PdEventData::PdMessage { wire_data, .. } => wire_data,
+        _ => panic!("expected a PD message"),
+    }
+}
+
+fn message_id(event: &PdEvent) -> u8 {
+    PdWireMessage::from_bytes(wire_data(event)).unwrap().header.message_id()
+}
+
+fn negotiation_stream() -> PdEventStream {
+    let packet = Packet::try_from(RawPacket::try_from(hex_to_bytes(PD_NEGOTIATION)).unwrap()).unwrap();
+    packet.get_pd_events().unwrap().clone()
+}
+
+fn epr_capabilities() -> Vec<SourcePdo> {
+    let chunk_0 = hex::decode(EPR_CAPS_CHUNK_0).unwrap();
+    let chunk_1 = hex::decode(EPR_CAPS_CHUNK_1).unwrap();
+    let data = [&chunk_0[4..], &chunk_1[4..]].concat();
+    data.chunks(4)
+        .map(|raw| SourcePdo::from_raw(u32::from_le_bytes(raw.try_into().unwrap())))
+        .collect()
+}
+
+#[test]
+fn encodes_captured_data_messages() {
+    let stream = negotiation_stream();
+    let wire_data = |index: usize| wire_data(&stream.events[index]).to_vec();
+
+    let capabilities = PdWireMessage::from_bytes(&wire_data(0)).unwrap();
+    let pdos = capabilities.data_objects().map(SourcePdo::from_raw).collect();
+    let encoded = PdMessageBody::SourceCapabilities(pdos)
+        .encode(PdSopType::Sop, &PdMessageSender::source(), 1)
+        .unwrap();
+    assert_eq!(encoded, vec![wire_data(0)]);
+
+    let request = PdWireMessage::from_bytes(&wire_data(2)).unwrap();
+    let rdo = RequestDataObject(request.data_objects().next().unwrap());
+    let encoded = PdMessageBody::Request(rdo)
+        .encode(PdSopType::Sop, &PdMessageSender::sink(), request.header.message_id())
+        .unwrap();
+    assert_eq!(encoded, vec![wire_data(2)]);
+
+    // The sink acknowledges Source_Capabilities before the revision is negotiated.
+    let sink = PdMessageSender::sink().with_revision(PdSpecRevision::Revision2);
+    let encoded = PdMessageBody::Control(PdControlMessageType::GoodCrc)
+        .encode(PdSopType::Sop, &sink, 1)
+        .unwrap();
+    assert_eq!(encoded, vec![wire_data(1)]);
+}
+
+#[test]
+fn encodes_chunked_extended_messages() {
+    let encoded = PdMessageBody::epr_source_capabilities(&epr_capabilities())
+        .encode(PdSopType::Sop, &PdMessageSender::source(), 6)
+        .unwrap();
+    assert_eq!(
+        encoded,
+        vec![
+            hex::decode(EPR_CAPS_CHUNK_0).unwrap(),
+            hex::decode(EPR_CAPS_CHUNK_1).unwrap()
+        ]
+    );
+
+    // A short data block fits in one chunk, padded to a whole data object.
+    let keep_alive = PdMessageBody::extended_control(ExtendedControlType::EprKeepAlive, 0)
+        .encode(PdSopType::Sop, &PdMessageSender::sink(), 3)
+        .unwrap();
+    let message = PdWireMessage::from_bytes(&keep_alive[0]).unwrap();
+    assert_eq!(keep_alive.len(), 1);
+    assert_eq!(message.header.num_data_objects(), 1);
+    assert_eq!(message.extended_header.unwrap().data_size(), 2);
+
+    let too_long = PdMessageBody::Extended {
+        message_type: PdExtendedMessageType::VendorDefinedExtended,
+        data: vec![0; 261],
+    };
+    assert!(too_long.encode(PdSopType::Sop, &PdMessageSender::source(), 0).is_err());
+    assert!(
+        PdMessageBody::SourceCapabilities(Vec::new())
+            .encode(PdSopType::Sop, &PdMessageSender::source(), 0)
+            .is_err()
+    );
+}
+
+#[test]
+fn event_stream_round_trips_through_km003c_framing() {
+    let frame = RawPacket::try_from(hex_to_bytes(PD_NEGOTIATION)).unwrap();
+    let stream = negotiation_stream();
+    assert_eq!(stream.to_bytes().unwrap(), frame.logical_packets().unwrap()[0].payload);
+
+    let response = Packet::try_from(frame).unwrap();
+    let raw = response.clone().to_raw_packet(0xa9).unwrap();
+    assert_eq!(Packet::try_from(raw).unwrap(), response);
+}
+
+#[test]
+fn builds_captures_the_analyzers_accept() {
+    let preamble = negotiation_stream().preamble;
+    let source = PdMessageSender::source();
+    let sink = PdMessageSender::sink();
+    let pdos = epr_capabilities();
+    let enter = EprModeDataObject(0x0164_0000);
+
+    let mut builder = PdCaptureBuilder::new(preamble);
+    builder.connect(ms(0.0));
+    builder
+        .message(
+            ms(10.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::SourceCapabilities(pdos[..6].to_vec()),
+        )
+        .unwrap()
+        .message(
+            ms(12.0),
+            PdSopType::Sop,
+            sink,
+            &PdMessageBody::Request(RequestDataObject(0x1004_b12c)),
+        )
+        .unwrap()
+        .message(
+            ms(13.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Control(PdControlMessageType::Accept),
+        )
+        .unwrap()
+        .message(
+            ms(40.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Control(PdControlMessageType::PsRdy),
+        )
+        .unwrap()
+        .message(ms(100.0), PdSopType::Sop, sink, &PdMessageBody::EprMode(enter))
+        .unwrap()
+        .message(
+            ms(101.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::epr_source_capabilities(&pdos),
+        )
+        .unwrap();
+    let stream = builder.build();
+
+    // Round trip through the KM003C framing.
+    let reparsed = PdEventStream::from_bytes(Bytes::from(stream.to_bytes().unwrap())).unwrap();
+    assert_eq!(reparsed, stream);
+
+    let mut analyzer = ProtocolAnalyzer::new();
+    let mut assembler = ExtendedMessageAssembler::new();
+    let mut epr = EprTracker::new();
+    let mut completed = Vec::new();
+    for event in &reparsed.events {
+        analyzer.process_event(event);
+        epr.process_event(event);
+        if let Some(ExtendedChunkProgress::Complete(message)) = assembler.process_event(event) {
+            completed.push(message);
+        }
+    }
+    assert!(analyzer.issues().is_empty(), "{:?}", analyzer.issues());
+    // Six messages, one chunk request and two chunks, each acknowledged.
+    assert_eq!(analyzer.statistics().messages, 8);
+    assert_eq!(analyzer.statistics().good_crcs, 8);
+    assert_eq!(epr.state(), EprModeState::EnterRequested);
+
+    assert_eq!(completed.len(), 1);
+    assert_eq!(completed[0].message_type, PdExtendedMessageType::EprSourceCapabilities);
+    assert_eq!(completed[0].payload, ExtendedPayload::EprSourceCapabilities(pdos));
+}
+
+#[test]
+fn soft_reset_and_role_swap_keep_counters_consistent() {
+    let preamble = negotiation_stream().preamble;
+    let mut builder = PdCaptureBuilder::new(preamble);
+    let source = PdMessageSender::source();
+    let sink = PdMessageSender::sink();
+    let control = |message_type| PdMessageBody::Control(message_type);
+
+    builder
+        .message(
+            ms(0.0),
+            PdSopType::Sop,
+            source,
+            &control(PdControlMessageType::GetSinkCap),
+        )
+        .unwrap()
+        .message(ms(1.0), PdSopType::Sop, sink, &control(PdControlMessageType::SoftReset))
+        .unwrap()
+        .message(ms(2.0), PdSopType::Sop, source, &control(PdControlMessageType::Accept))
+        .unwrap()
+        .message(ms(3.0), PdSopType::Sop, sink, &control(PdControlMessageType::PrSwap))
+        .unwrap()
+        .message(ms(4.0), PdSopType::Sop, source, &control(PdControlMessageType::Accept))
+        .unwrap()
+        .message(ms(5.0), PdSopType::Sop, source, &control(PdControlMessageType::PsRdy))
+        .unwrap();
+    builder.swap_power_roles();
+    let new_source = PdMessageSender {
+        power_role: PdPowerRole::Source,
+        ..sink
+    };
+    builder
+        .message(
+            ms(6.0),
+            PdSopType::Sop,
+            new_source,
+            &control(PdControlMessageType::PsRdy),
+        )
+        .unwrap();
+
+    let ids = builder.events().iter().step_by(2).map(message_id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![0, 0, 0, 1, 1, 2, 2]);
+
+    let mut analyzer = ProtocolAnalyzer::new();
+    for event in builder.events() {
+        analyzer.process_event(event);
+    }
+    assert!(analyzer.issues().is_empty(), "{:?}", analyzer.issues());
+}
+
+#[cfg(feature = "usbpd")]
+#[test]
+fn pd_decode_reads_synthetic_captures() {
+    use km003c_lib::{DecodedPdEvent, PdSessionDecoder};
+
+    let pdos = epr_capabilities();
+    let mut builder = PdCaptureBuilder::new(negotiation_stream().preamble);
+    builder
+        .message(
+            ms(0.0),
+            PdSopType::Sop,
+            PdMessageSender::source(),
+            &PdMessageBody::SourceCapabilities(pdos[..6].to_vec()),
+        )
+        .unwrap()
+        .message(
+            ms(2.0),
+            PdSopType::Sop,
+            PdMessageSender::sink(),
+            &PdMessageBody::Request(RequestDataObject(0x1004_b12c)),
+        )
+        .unwrap()
+        .message(
+            ms(10.0),
+            PdSopType::Sop,
+            PdMessageSender::source(),
+            &PdMessageBody::epr_source_capabilities(&pdos),
+        )
+        .unwrap();
+
+    let mut decoder = PdSessionDecoder::new();
+    let decoded = builder
+        .events()
+        .iter()
+        .map(|event| decoder.decode_event(event))
+        .collect::<Vec<_>>();
+    assert!(!decoded.iter().any(|event| matches!(event, DecodedPdEvent::Error(_))));
+
+    let DecodedPdEvent::Message(request) = &decoded[2] else {
+        panic!("expected a Request message");
+    };
+    assert_eq!(request.request_capabilities.as_deref(), Some(&pdos[..6]));
+    // The last chunk completes the message; its GoodCRC follows.
+    let DecodedPdEvent::Message(capabilities) = &decoded[decoded.len() - 2] else {
+        panic!("expected the reassembled EPR_Source_Capabilities");
+    };
+    assert_eq!(
+        capabilities.extended.as_ref().unwrap().payload,
+        ExtendedPayload::EprSourceCapabilities(pdos)
+    );
+}
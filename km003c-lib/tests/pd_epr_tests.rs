@@ -0,0 +1,177 @@
+mod common;
+
+use common::pd::{
+    ACCEPT, CABLE, PS_RDY, Port, REQUEST, SINK, SOP, SOP_PRIME, SOURCE, SOURCE_CAPABILITIES, VENDOR_DEFINED, event,
+    message,
+};
+use km003c_lib::pd_epr::{EprEnterFailure, EprModeAction, EprModeDataObject};
+use km003c_lib::{EprEvent, EprModeState, EprTracker, EprViolation, PdStatus};
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use uom::si::time::millisecond;
+
+const EPR_REQUEST: u16 = 0x09;
+const EPR_MODE: u16 = 0x0a;
+const EXTENDED_CONTROL: u16 = 0x10;
+
+// 5 V 3 A fixed supply advertising EPR mode capability.
+const FIXED_5V_3A_EPR: u32 = 0x0081_912c;
+// EPR_Mode actions: Enter with a 140 W sink PDP, Enter Acknowledged, Enter Succeeded.
+const EPR_ENTER_140W: u32 = 0x018c_0000;
+const EPR_ENTER_ACKNOWLEDGED: u32 = 0x0200_0000;
+const EPR_ENTER_SUCCEEDED: u32 = 0x0300_0000;
+// Object position 8, 5 A, and a copy of the 28 V 5 A fixed PDO.
+const EPR_REQUEST_28V_5A: [u32; 2] = [0x8007_d1f4, 0x0008_c1f4];
+
+/// Unchunked Extended_Control message with message ID 0.
+fn extended_control(port: Port, control: u8) -> Vec<u8> {
+    let mut wire_data = message(
+        EXTENDED_CONTROL,
+        port,
+        0,
+        &[u32::from_le_bytes([0x02, 0x00, control, 0x00])],
+    );
+    wire_data[1] |= 0x80;
+    wire_data
+}
+
+fn status(timestamp_ms: f64, vbus_v: f64) -> PdStatus {
+    PdStatus {
+        timestamp: Time::new::<millisecond>(timestamp_ms),
+        vbus: ElectricPotential::new::<volt>(vbus_v),
+        ibus: ElectricCurrent::new::<ampere>(4.0),
+        cc1: ElectricPotential::new::<volt>(0.4),
+        cc2: ElectricPotential::new::<volt>(0.0),
+    }
+}
+
+fn violations(tracker: &EprTracker) -> Vec<&EprViolation> {
+    tracker
+        .events()
+        .iter()
+        .filter_map(|event| match event {
+            EprEvent::Violation { violation, .. } => Some(violation),
+            _ => None,
+        })
+        .collect()
+}
+
+fn enter_epr(tracker: &mut EprTracker) {
+    tracker.process_event(&event(
+        0.0,
+        SOP,
+        message(SOURCE_CAPABILITIES, SOURCE, 0, &[FIXED_5V_3A_EPR]),
+    ));
+    tracker.process_event(&event(100.0, SOP, message(EPR_MODE, SINK, 0, &[EPR_ENTER_140W])));
+    tracker.process_event(&event(
+        101.0,
+        SOP,
+        message(EPR_MODE, SOURCE, 0, &[EPR_ENTER_ACKNOWLEDGED]),
+    ));
+}
+
+#[test]
+fn decodes_epr_mode_data_object() {
+    let enter = EprModeDataObject(EPR_ENTER_140W);
+    assert_eq!(enter.action(), EprModeAction::Enter);
+    assert_eq!(enter.data(), 140);
+    assert_eq!(enter.failure_reason(), None);
+
+    let failed = EprModeDataObject(0x0401_0000);
+    assert_eq!(failed.failure_reason(), Some(EprEnterFailure::CableNotEprCapable));
+}
+
+#[test]
+fn follows_epr_entry_contract_and_keep_alive() {
+    let mut tracker = EprTracker::new();
+    enter_epr(&mut tracker);
+    assert_eq!(tracker.state(), EprModeState::EnterAcknowledged);
+
+    // The source checks the cable: passive, EPR capable, 50 V, 5 A.
+    let cable_plug_ack = message(
+        VENDOR_DEFINED,
+        CABLE,
+        0,
+        &[0xff00_a041, 0x1860_1234, 0, 0x5678_0100, 0x120a_2642],
+    );
+    tracker.process_event(&event(110.0, SOP_PRIME, cable_plug_ack));
+    tracker.process_event(&event(150.0, SOP, message(EPR_MODE, SOURCE, 0, &[EPR_ENTER_SUCCEEDED])));
+    assert_eq!(tracker.state(), EprModeState::Epr);
+
+    tracker.process_event(&event(300.0, SOP, message(EPR_REQUEST, SINK, 0, &EPR_REQUEST_28V_5A)));
+    let added = tracker.process_event(&event(301.0, SOP, message(ACCEPT, SOURCE, 0, &[])));
+    let [EprEvent::Contract(contract)] = added else {
+        panic!("expected a contract, got {added:?}");
+    };
+    assert_eq!(contract.voltage.get::<volt>(), 28.0);
+    assert_eq!(contract.current.get::<ampere>(), 5.0);
+    assert!(contract.is_epr_voltage());
+
+    tracker.process_event(&event(450.0, SOP, message(PS_RDY, SOURCE, 0, &[])));
+    tracker.process_status(&status(500.0, 27.9));
+    assert!(tracker.is_running_at_epr_voltage());
+
+    tracker.process_event(&event(750.0, SOP, extended_control(SINK, 3)));
+    let added = tracker.process_event(&event(752.0, SOP, extended_control(SOURCE, 4)));
+    assert!(matches!(
+        added,
+        [EprEvent::KeepAliveAck { latency: Some(latency), .. }] if (latency.get::<millisecond>() - 2.0).abs() < 1e-9
+    ));
+    tracker.process_event(&event(1000.0, SOP, extended_control(SINK, 3)));
+    assert!(matches!(
+        tracker.events().last(),
+        Some(EprEvent::KeepAlive { interval: Some(interval), .. }) if (interval.get::<millisecond>() - 250.0).abs() < 1e-9
+    ));
+    assert!(violations(&tracker).is_empty());
+
+    // The sink falls silent.
+    let added = tracker.process_status(&status(2100.0, 27.9));
+    assert!(matches!(
+        added,
+        [EprEvent::Violation {
+            violation: EprViolation::KeepAliveTimeout { .. },
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn flags_illegal_requests_and_unverified_cable() {
+    let mut tracker = EprTracker::new();
+    tracker.process_event(&event(0.0, SOP, message(EPR_REQUEST, SINK, 0, &EPR_REQUEST_28V_5A)));
+    assert_eq!(violations(&tracker), vec![&EprViolation::EprRequestOutsideEprMode]);
+
+    enter_epr(&mut tracker);
+    tracker.process_event(&event(150.0, SOP, message(EPR_MODE, SOURCE, 0, &[EPR_ENTER_SUCCEEDED])));
+    tracker.process_event(&event(200.0, SOP, message(REQUEST, SINK, 0, &[0x1004_b12c])));
+
+    assert_eq!(
+        violations(&tracker)[1..],
+        [
+            &EprViolation::UnverifiedCable { cable: None },
+            &EprViolation::SprRequestInEprMode
+        ]
+    );
+    assert!(!tracker.is_running_at_epr_voltage());
+}
+
+#[test]
+fn failed_entry_returns_to_spr() {
+    let mut tracker = EprTracker::new();
+    enter_epr(&mut tracker);
+    tracker.process_event(&event(150.0, SOP, message(EPR_MODE, SOURCE, 0, &[0x0401_0000])));
+
+    assert_eq!(tracker.state(), EprModeState::Spr);
+    assert!(matches!(
+        tracker.events().last(),
+        Some(EprEvent::Mode {
+            action: EprModeAction::EnterFailed,
+            data: 1,
+            ..
+        })
+    ));
+
+    // No keep-alive is expected outside EPR mode.
+    assert!(tracker.process_status(&status(5000.0, 20.0)).is_empty());
+}
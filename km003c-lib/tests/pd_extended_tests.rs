@@ -0,0 +1,288 @@
+use km003c_lib::pd_extended::{ManufacturerInfo, SourceCapabilitiesExtended};
+use km003c_lib::pd_wire::PdExtendedMessageType;
+use km003c_lib::{
+    ExtendedChunkProgress, ExtendedMessageAssembler, ExtendedPayload, PdEvent, PdEventData, PdSopType, PdWireMessage,
+    SourcePdo,
+};
+use uom::si::electric_potential::volt;
+use uom::si::f64::Time;
+use uom::si::power::watt;
+use uom::si::time::millisecond;
+
+const SOURCE_CAPABILITIES_EXTENDED: u16 = 0x01;
+const MANUFACTURER_INFO: u16 = 0x07;
+const COUNTRY_INFO: u16 = 0x0d;
+const PPS_STATUS: u16 = 0x0c;
+const SOFT_RESET: u16 = 0x0d;
+const EPR_SOURCE_CAPABILITIES: u16 = 0x11;
+
+// Captured EPR Source Capabilities split into two chunks.
+const EPR_CAPS_CHUNK_0: [u8; 30] = [
+    0xB1, 0xFD, 0x28, 0x80, 0x2C, 0x91, 0x91, 0x0A, 0x2C, 0xD1, 0x12, 0x00, 0x2C, 0xC1, 0x13, 0x00, 0x2C, 0xB1, 0x14,
+    0x00, 0xF4, 0x41, 0x16, 0x00, 0x64, 0x32, 0xA4, 0xC9, 0x00, 0x00,
+];
+const EPR_CAPS_CHUNK_1: [u8; 18] = [
+    0xB1, 0xCF, 0x28, 0x88, 0x00, 0x00, 0xF4, 0xC1, 0x18, 0x00, 0xF4, 0x41, 0x1B, 0x00, 0xF4, 0x01, 0x1F, 0x00,
+];
+
+/// Extended message with Revision 3.x, padded to whole data objects.
+fn extended(
+    message_type: u16,
+    from_source: bool,
+    message_id: u16,
+    chunked: Option<(u8, bool)>,
+    data_size: usize,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut extended_header = data_size as u16;
+    if let Some((chunk_number, request)) = chunked {
+        extended_header |= (u16::from(request) << 10) | (u16::from(chunk_number) << 11) | 0x8000;
+    }
+    let mut body = extended_header.to_le_bytes().to_vec();
+    body.extend_from_slice(data);
+    body.resize(body.len().div_ceil(4) * 4, 0);
+
+    let header = message_type
+        | (2 << 6)
+        | (u16::from(from_source) << 8)
+        | (message_id << 9)
+        | (((body.len() / 4) as u16) << 12)
+        | 0x8000;
+    let mut wire_data = header.to_le_bytes().to_vec();
+    wire_data.extend_from_slice(&body);
+    wire_data
+}
+
+fn process(assembler: &mut ExtendedMessageAssembler, sop: PdSopType, wire_data: &[u8]) -> ExtendedChunkProgress {
+    let message = PdWireMessage::from_bytes(wire_data).unwrap();
+    assembler.process_message(sop, &message).unwrap()
+}
+
+/// Country_Info chunks 0 to 2 with consecutive message IDs from `first_message_id`.
+fn country_info_chunks(first_message_id: u16) -> [Vec<u8>; 3] {
+    let mut data = vec![b'S', b'U', 0, 0];
+    data.extend(0..30);
+    let id = |chunk: u16| (first_message_id + chunk) % 8;
+    [
+        extended(COUNTRY_INFO, true, id(0), Some((0, false)), data.len(), &data[..26]),
+        extended(COUNTRY_INFO, true, id(1), Some((1, false)), data.len(), &data[26..]),
+        extended(COUNTRY_INFO, true, id(2), Some((2, false)), data.len(), &data[26..]),
+    ]
+}
+
+#[test]
+fn reassembles_interleaved_chunked_messages() {
+    let mut assembler = ExtendedMessageAssembler::new();
+    assert_eq!(
+        process(&mut assembler, PdSopType::Sop, &EPR_CAPS_CHUNK_0),
+        ExtendedChunkProgress::Pending {
+            received_chunk: 0,
+            next_chunk: 1
+        }
+    );
+    assert_eq!(
+        process(
+            &mut assembler,
+            PdSopType::Sop,
+            &extended(EPR_SOURCE_CAPABILITIES, false, 0, Some((1, true)), 0, &[])
+        ),
+        ExtendedChunkProgress::Request { chunk_number: 1 }
+    );
+
+    // A cable answers Get_Manufacturer_Info on SOP' between the two chunks.
+    let mut info = vec![0x34, 0x12, 0x78, 0x56];
+    info.extend_from_slice(b"ACME Cable\0");
+    let ExtendedChunkProgress::Complete(cable_info) = process(
+        &mut assembler,
+        PdSopType::SopPrime,
+        &extended(MANUFACTURER_INFO, true, 0, Some((0, false)), info.len(), &info),
+    ) else {
+        panic!("expected a complete Manufacturer_Info");
+    };
+    assert_eq!(cable_info.sop, PdSopType::SopPrime);
+    assert_eq!(
+        cable_info.payload,
+        ExtendedPayload::ManufacturerInfo(ManufacturerInfo {
+            vid: 0x1234,
+            pid: 0x5678,
+            manufacturer: "ACME Cable".to_string(),
+        })
+    );
+
+    let ExtendedChunkProgress::Complete(capabilities) = process(&mut assembler, PdSopType::Sop, &EPR_CAPS_CHUNK_1)
+    else {
+        panic!("expected complete EPR Source Capabilities");
+    };
+    assert_eq!(capabilities.message_type, PdExtendedMessageType::EprSourceCapabilities);
+    assert_eq!(capabilities.data.len(), 40);
+    let ExtendedPayload::EprSourceCapabilities(pdos) = capabilities.payload else {
+        panic!("expected EPR Source Capabilities payload");
+    };
+    assert_eq!(pdos.len(), 10);
+    let SourcePdo::FixedSupply(vsafe5v) = pdos[0] else {
+        panic!("expected a fixed vSafe5V PDO");
+    };
+    assert_eq!(vsafe5v.voltage().get::<volt>(), 5.0);
+}
+
+#[test]
+fn decodes_unchunked_source_capabilities_extended() {
+    let mut data = vec![
+        0x34, 0x12, 0x78, 0x56, 0x01, 0x00, 0x00, 0x00, 0x02, 0x03, 0x00, 0x03, 0x01, 0x00,
+    ];
+    data.extend_from_slice(&[0x00; 6]);
+    data.extend_from_slice(&[0x00, 0x03, 0x00, 100, 140]);
+
+    let mut assembler = ExtendedMessageAssembler::new();
+    let ExtendedChunkProgress::Complete(message) = process(
+        &mut assembler,
+        PdSopType::Sop,
+        &extended(SOURCE_CAPABILITIES_EXTENDED, true, 0, None, data.len(), &data),
+    ) else {
+        panic!("expected a complete message");
+    };
+    assert_eq!(message.data, data);
+    let ExtendedPayload::SourceCapabilitiesExtended(capabilities) = message.payload else {
+        panic!("expected Source_Capabilities_Extended");
+    };
+    assert_eq!(capabilities.vid, 0x1234);
+    assert_eq!(capabilities.pid, 0x5678);
+    assert_eq!(capabilities.firmware_version, 2);
+    assert_eq!(capabilities.holdup_time, 3);
+    assert_eq!(capabilities.spr_source_pdp.get::<watt>(), 100.0);
+    assert_eq!(capabilities.epr_source_pdp.map(|pdp| pdp.get::<watt>()), Some(140.0));
+
+    // Revision 3.0 data blocks end before the EPR Source PDP.
+    let legacy = SourceCapabilitiesExtended::from_bytes(&data[..24]).unwrap();
+    assert_eq!(legacy.epr_source_pdp, None);
+    assert_eq!(
+        ExtendedPayload::decode(PdExtendedMessageType::Status, &[0x2a]),
+        ExtendedPayload::Other(vec![0x2a])
+    );
+}
+
+#[test]
+fn discards_out_of_order_chunks() {
+    let [first, second, third] = country_info_chunks(0);
+    let mut assembler = ExtendedMessageAssembler::new();
+
+    process(&mut assembler, PdSopType::Sop, &first);
+    assert_eq!(
+        process(&mut assembler, PdSopType::Sop, &third),
+        ExtendedChunkProgress::Discarded {
+            chunk_number: 2,
+            expected_chunk: Some(1)
+        }
+    );
+    assert_eq!(
+        process(&mut assembler, PdSopType::Sop, &second),
+        ExtendedChunkProgress::Discarded {
+            chunk_number: 1,
+            expected_chunk: None
+        }
+    );
+
+    process(&mut assembler, PdSopType::Sop, &first);
+    let ExtendedChunkProgress::Complete(message) = process(&mut assembler, PdSopType::Sop, &second) else {
+        panic!("expected a complete Country_Info");
+    };
+    let ExtendedPayload::CountryInfo(info) = message.payload else {
+        panic!("expected Country_Info");
+    };
+    assert_eq!(info.country_code, "US");
+    assert_eq!(info.data, (0..30).collect::<Vec<u8>>());
+}
+
+#[test]
+fn ignores_retransmitted_chunks() {
+    let [first, second, _] = country_info_chunks(0);
+    let mut assembler = ExtendedMessageAssembler::new();
+
+    process(&mut assembler, PdSopType::Sop, &first);
+    assert_eq!(
+        process(&mut assembler, PdSopType::Sop, &first),
+        ExtendedChunkProgress::Duplicate { chunk_number: 0 }
+    );
+    let ExtendedChunkProgress::Complete(message) = process(&mut assembler, PdSopType::Sop, &second) else {
+        panic!("expected a complete Country_Info");
+    };
+    assert_eq!(message.data.len(), 34);
+
+    // Once complete, the transfer is closed and later chunks start over.
+    assert_eq!(
+        process(&mut assembler, PdSopType::Sop, &second),
+        ExtendedChunkProgress::Discarded {
+            chunk_number: 1,
+            expected_chunk: None
+        }
+    );
+}
+
+#[test]
+fn accepts_single_chunk_messages_reusing_a_message_id() {
+    // MessageID wraps every 8 messages, so back-to-back PPS_Status messages may share one.
+    let pps_status = extended(PPS_STATUS, true, 5, Some((0, false)), 4, &[0x2c, 0x01, 0x64, 0x00]);
+    let mut assembler = ExtendedMessageAssembler::new();
+
+    for _ in 0..2 {
+        let ExtendedChunkProgress::Complete(message) = process(&mut assembler, PdSopType::Sop, &pps_status) else {
+            panic!("expected a complete PPS_Status");
+        };
+        assert_eq!(message.data, [0x2c, 0x01, 0x64, 0x00]);
+    }
+}
+
+#[test]
+fn soft_reset_drops_transfers_on_its_channel() {
+    let [first, second, _] = country_info_chunks(0);
+    let soft_reset = (SOFT_RESET | (2 << 6) | (1 << 8)).to_le_bytes();
+    let mut assembler = ExtendedMessageAssembler::new();
+
+    process(&mut assembler, PdSopType::Sop, &first);
+    let message = PdWireMessage::from_bytes(&soft_reset).unwrap();
+    assert_eq!(assembler.process_message(PdSopType::Sop, &message), None);
+    assert_eq!(
+        process(&mut assembler, PdSopType::Sop, &second),
+        ExtendedChunkProgress::Discarded {
+            chunk_number: 1,
+            expected_chunk: None
+        }
+    );
+
+    // The restarted transfer reuses the MessageID of the interrupted one.
+    assert!(matches!(
+        process(&mut assembler, PdSopType::Sop, &first),
+        ExtendedChunkProgress::Pending { .. }
+    ));
+    assert!(matches!(
+        process(&mut assembler, PdSopType::Sop, &second),
+        ExtendedChunkProgress::Complete(_)
+    ));
+}
+
+#[test]
+fn connection_change_drops_partial_messages() {
+    let [first, second, _] = country_info_chunks(0);
+    let event = |timestamp_ms: f64, data| PdEvent {
+        timestamp: Time::new::<millisecond>(timestamp_ms),
+        data,
+    };
+    let message = |wire_data: &Vec<u8>| PdEventData::PdMessage {
+        sop: 0,
+        wire_data: wire_data.clone(),
+    };
+
+    let mut assembler = ExtendedMessageAssembler::new();
+    assert!(matches!(
+        assembler.process_event(&event(1.0, message(&first))),
+        Some(ExtendedChunkProgress::Pending { .. })
+    ));
+    assert_eq!(assembler.process_event(&event(2.0, PdEventData::Disconnect(()))), None);
+    assert!(matches!(
+        assembler.process_event(&event(3.0, message(&second))),
+        Some(ExtendedChunkProgress::Discarded {
+            expected_chunk: None,
+            ..
+        })
+    ));
+}
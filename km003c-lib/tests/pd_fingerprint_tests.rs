@@ -0,0 +1,257 @@
+mod common;
+
+use common::*;
+use km003c_lib::pd_extended::{ManufacturerInfo, SourceCapabilitiesExtended};
+use km003c_lib::{
+    ChargerFingerprint, ChargerIdentity, FingerprintDatabase, FingerprintTiming, PdEventData, PdWireMessage, SourcePdo,
+};
+use uom::si::f64::{Power, Time};
+use uom::si::power::watt;
+use uom::si::time::millisecond;
+
+// Data block of a captured EPR_Source_Capabilities message.
+const EPR_CAPABILITIES: &str = "2c91910a2cd112002cc113002cb11400f44116006432a4c900000000f4c11800f4411b00f4011f00";
+
+// Source: usb_master_dataset.parquet, orig_with_pd.13, frame 714.
+const PD_NEGOTIATION: &str = "41a90205100000168bfa1200de130000750602009f80fa120000a1632c9101082cd102002cc103002cb10400454106003c21dcc08781fa12000041028b85fa1200008210dc7003238786fa1200002101878afa120000a305878afa1200004104";
+
+fn ms(value: f64) -> Time {
+    Time::new::<millisecond>(value)
+}
+
+fn epr_capabilities() -> Vec<SourcePdo> {
+    hex::decode(EPR_CAPABILITIES)
+        .unwrap()
+        .chunks(4)
+        .map(|raw| SourcePdo::from_raw(u32::from_le_bytes(raw.try_into().unwrap())))
+        .collect()
+}
+
+fn negotiation_pdos() -> Vec<SourcePdo> {
+    let packet = Packet::try_from(RawPacket::try_from(hex_to_bytes(PD_NEGOTIATION)).unwrap()).unwrap();
+    let PdEventData::PdMessage { wire_data, .. } = &packet.get_pd_events().unwrap().events[0].data else {
+        panic!("expected Source_Capabilities");
+    };
+    PdWireMessage::from_bytes(wire_data)
+        .unwrap()
+        .data_objects()
+        .map(SourcePdo::from_raw)
+        .collect()
+}
+
+fn capabilities_extended() -> SourceCapabilitiesExtended {
+    SourceCapabilitiesExtended {
+        vid: 0x291a,
+        pid: 0x1735,
+        xid: 0,
+        firmware_version: 3,
+        hardware_version: 1,
+        voltage_regulation: 0,
+        holdup_time: 3,
+        compliance: 0x07,
+        touch_current: 0,
+        peak_current: [0; 3],
+        touch_temperature: 2,
+        source_inputs: 1,
+        batteries: 0,
+        spr_source_pdp: Power::new::<watt>(100.0),
+        epr_source_pdp: Some(Power::new::<watt>(140.0)),
+    }
+}
+
+fn known_charger() -> ChargerFingerprint {
+    let pdos = epr_capabilities();
+    ChargerFingerprint {
+        source_pdos: pdos[..6].to_vec(),
+        epr_source_pdos: pdos,
+        capabilities_extended: Some(capabilities_extended()),
+        manufacturer_info: Some(ManufacturerInfo {
+            vid: 0x291a,
+            pid: 0x1735,
+            manufacturer: "Anker".to_string(),
+        }),
+        identity: Some(ChargerIdentity {
+            vendor_id: 0x291a,
+            product_id: 0x1735,
+            bcd_device: 0x0100,
+            xid: 0,
+        }),
+        timing: FingerprintTiming {
+            capabilities_delay: Some(ms(250.0)),
+            capabilities_interval: Some(ms(150.0)),
+            transition_time: Some(ms(28.0)),
+        },
+    }
+}
+
+#[test]
+fn scores_captures_against_known_chargers() {
+    let mut database = FingerprintDatabase::new();
+    database.insert("Anker 735", known_charger());
+    database.insert(
+        "Captured 27W charger",
+        ChargerFingerprint {
+            source_pdos: negotiation_pdos(),
+            ..ChargerFingerprint::default()
+        },
+    );
+
+    // Another unit of the same model, on battery-backed mains and a little slower.
+    let mut capture = known_charger();
+    capture.source_pdos[0] = SourcePdo::from_raw(capture.source_pdos[0].raw() ^ (1 << 27));
+    capture.timing.transition_time = Some(ms(35.0));
+    assert_eq!(capture.signature(), known_charger().signature());
+
+    let matches = database.matches(&capture);
+    assert_eq!(matches[0].entry.name, "Anker 735");
+    assert!(
+        matches[0].score > 0.95 && matches[0].score < 1.0,
+        "{}",
+        matches[0].score
+    );
+    assert!(matches[1].score < 0.5, "{}", matches[1].score);
+
+    // Only the advertised PDOs are known for a sink that queried nothing else.
+    let pdos_only = ChargerFingerprint {
+        source_pdos: negotiation_pdos(),
+        ..ChargerFingerprint::default()
+    };
+    let best = database.best_match(&pdos_only, 0.9).unwrap();
+    assert_eq!(best.entry.name, "Captured 27W charger");
+    assert_eq!(best.score, 1.0);
+    assert_ne!(pdos_only.signature(), capture.signature());
+
+    assert!(database.best_match(&ChargerFingerprint::default(), 0.1).is_none());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn database_round_trips_through_json_file() {
+    let mut database = FingerprintDatabase::new();
+    database.insert("Anker 735", known_charger());
+    database.insert("Charger [rev 2]\n\"GaN\"", known_charger());
+    database.insert(
+        "Captured 27W charger",
+        ChargerFingerprint {
+            source_pdos: negotiation_pdos(),
+            ..ChargerFingerprint::default()
+        },
+    );
+
+    let json = database.to_json().unwrap();
+    assert_eq!(FingerprintDatabase::from_json(&json).unwrap(), database);
+    assert_eq!(database.entries()[1].name, "Charger [rev 2]\n\"GaN\"");
+
+    let path = std::env::temp_dir().join(format!("km003c-fingerprints-{}.json", std::process::id()));
+    database.save(&path).unwrap();
+    let loaded = FingerprintDatabase::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), database);
+
+    assert!(FingerprintDatabase::from_json("[Charger]\nsource_pdos = 0a91912c\n").is_err());
+    assert!(FingerprintDatabase::from_json(r#"{"entries": [{"name": "Charger"}]}"#).is_err());
+}
+
+#[cfg(feature = "usbpd")]
+#[test]
+fn collects_fingerprint_from_decoded_capture() {
+    use km003c_lib::pd_vdm::VdmHeader;
+    use km003c_lib::pd_wire::{PdControlMessageType, PdExtendedMessageType};
+    use km003c_lib::{
+        FingerprintCollector, PdCaptureBuilder, PdMessageBody, PdMessageSender, PdSessionDecoder, PdSopType,
+        RequestDataObject, VendorDefinedMessage,
+    };
+
+    let packet = Packet::try_from(RawPacket::try_from(hex_to_bytes(PD_NEGOTIATION)).unwrap()).unwrap();
+    let expected = known_charger();
+    let source = PdMessageSender::source();
+    let sink = PdMessageSender::sink();
+    let source_capabilities = PdMessageBody::SourceCapabilities(expected.source_pdos.clone());
+    let discover_identity_ack = VendorDefinedMessage {
+        header: VdmHeader(0xff00_a041),
+        objects: vec![0x4800_291a, 0, 0x1735_0100],
+    };
+
+    let mut builder = PdCaptureBuilder::new(packet.get_pd_events().unwrap().preamble);
+    builder
+        .connect(ms(0.0))
+        .message(ms(250.0), PdSopType::Sop, source, &source_capabilities)
+        .unwrap()
+        .message(ms(400.0), PdSopType::Sop, source, &source_capabilities)
+        .unwrap()
+        .message(
+            ms(402.0),
+            PdSopType::Sop,
+            sink,
+            &PdMessageBody::Request(RequestDataObject(0x1004_b12c)),
+        )
+        .unwrap()
+        .message(
+            ms(404.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Control(PdControlMessageType::Accept),
+        )
+        .unwrap()
+        .message(
+            ms(432.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Control(PdControlMessageType::PsRdy),
+        )
+        .unwrap()
+        .message(
+            ms(500.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Extended {
+                message_type: PdExtendedMessageType::SourceCapabilitiesExtended,
+                data: capabilities_extended().to_bytes(),
+            },
+        )
+        .unwrap()
+        .message(
+            ms(600.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::Extended {
+                message_type: PdExtendedMessageType::ManufacturerInfo,
+                data: expected.manufacturer_info.as_ref().unwrap().to_bytes(),
+            },
+        )
+        .unwrap()
+        .message(
+            ms(700.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::VendorDefined(discover_identity_ack),
+        )
+        .unwrap()
+        .message(
+            ms(800.0),
+            PdSopType::Sop,
+            source,
+            &PdMessageBody::epr_source_capabilities(&expected.epr_source_pdos),
+        )
+        .unwrap();
+
+    let mut decoder = PdSessionDecoder::new();
+    let mut collector = FingerprintCollector::new();
+    for event in builder.events() {
+        collector.process_event(&decoder.decode_event(event));
+    }
+    let fingerprint = collector.into_fingerprint();
+
+    assert_eq!(fingerprint.source_pdos, expected.source_pdos);
+    assert_eq!(fingerprint.epr_source_pdos, expected.epr_source_pdos);
+    assert_eq!(fingerprint.capabilities_extended, expected.capabilities_extended);
+    assert_eq!(fingerprint.manufacturer_info, expected.manufacturer_info);
+    assert_eq!(fingerprint.identity, expected.identity);
+    assert_eq!(fingerprint.signature(), expected.signature());
+
+    let timing = fingerprint.timing;
+    assert_eq!(timing.capabilities_delay, Some(ms(250.0)));
+    assert!((timing.capabilities_interval.unwrap().get::<millisecond>() - 150.0).abs() < 1e-9);
+    assert!((timing.transition_time.unwrap().get::<millisecond>() - 28.0).abs() < 1e-9);
+    assert!(fingerprint.similarity(&expected) > 0.999);
+}
@@ -0,0 +1,166 @@
+//! Rendering of decoded PD events shared by the CLI and GUI
+#![cfg(feature = "usbpd")]
+
+mod common;
+
+use common::*;
+use km003c_lib::pd_vdm::VdmHeader;
+use km003c_lib::pd_wire::PdControlMessageType;
+use km003c_lib::{
+    FormattedPdEvent, PdCaptureBuilder, PdEvent, PdEventCategory, PdField, PdMessageBody, PdMessageSender,
+    PdSessionDecoder, PdSopType, RequestDataObject, SourcePdo, VendorDefinedMessage, format_event,
+};
+use uom::si::f64::Time;
+use uom::si::time::millisecond;
+
+// Source: usb_master_dataset.parquet, orig_with_pd.13, frame 714.
+const PD_NEGOTIATION: &str = "41a90205100000168bfa1200de130000750602009f80fa120000a1632c9101082cd102002cc103002cb10400454106003c21dcc08781fa12000041028b85fa1200008210dc7003238786fa1200002101878afa120000a305878afa1200004104";
+
+// Data block of a captured EPR_Source_Capabilities message.
+const EPR_CAPABILITIES: &str = "2c91910a2cd112002cc113002cb11400f44116006432a4c900000000f4c11800f4411b00f4011f00";
+
+fn format_all(events: &[PdEvent]) -> Vec<FormattedPdEvent> {
+    let mut decoder = PdSessionDecoder::new();
+    events
+        .iter()
+        .map(|event| format_event(&decoder.decode_event(event)))
+        .collect()
+}
+
+fn field<'a>(fields: &'a [PdField], name: &str) -> &'a PdField {
+    fields.iter().find(|field| field.name == name).unwrap()
+}
+
+#[test]
+fn renders_captured_negotiation() {
+    let packet = Packet::try_from(RawPacket::try_from(hex_to_bytes(PD_NEGOTIATION)).unwrap()).unwrap();
+    let formatted = format_all(&packet.get_pd_events().unwrap().events);
+
+    let capabilities = &formatted[0];
+    assert_eq!(capabilities.category, PdEventCategory::SourceCapabilities);
+    assert_eq!(
+        capabilities.summary_line(),
+        "[1243.776s] SOP0: SourceCapabilities (ID=1, ROLE=Source/Dfp)"
+    );
+    assert_eq!(capabilities.details[1], "PDO[1]: Fixed 5V @ 3.0A (15W) [UP]");
+    assert_eq!(capabilities.details[6], "PDO[6]: PPS 3.3-11.0V @ 3.0A (33W)");
+    let pps = field(&capabilities.fields, "PDO 6");
+    assert_eq!(pps.value, "0xC0DC213C");
+    assert!(pps.children.contains(&PdField::new("Minimum Voltage", "3.30 V")));
+
+    // The sink acknowledges before the revision is negotiated.
+    let header = field(&formatted[1].fields, "Header");
+    assert_eq!(header.value, "0x0241");
+    assert!(header.children.contains(&PdField::new("Specification Revision", "2.0")));
+
+    let request = &formatted[2];
+    assert_eq!(request.category, PdEventCategory::Request);
+    assert_eq!(
+        request.text(),
+        "[1243.781s] SOP0: Request (ID=0, ROLE=Sink/Ufp)\n             \
+         RDO: PDO#2 (Fixed 9V @ 3.0A (27W)) @ 2.20A (Max 2.20A)"
+    );
+    let rdo = field(&request.fields, "RDO");
+    assert_eq!(rdo.value, "0x230370DC");
+    assert!(rdo.children.contains(&PdField::new("Operating Current", "2.20 A")));
+    assert!(
+        request
+            .verbose_text()
+            .contains("\n               USB Communications Capable: yes")
+    );
+}
+
+#[test]
+fn renders_chunks_extended_messages_and_vdms() {
+    let ms = Time::new::<millisecond>;
+    let pdos = hex::decode(EPR_CAPABILITIES)
+        .unwrap()
+        .chunks(4)
+        .map(|raw| SourcePdo::from_raw(u32::from_le_bytes(raw.try_into().unwrap())))
+        .collect::<Vec<_>>();
+    let discover_identity = VendorDefinedMessage {
+        header: VdmHeader(0xff00_8001),
+        objects: Vec::new(),
+    };
+
+    let preamble = Packet::try_from(RawPacket::try_from(hex_to_bytes(PD_NEGOTIATION)).unwrap())
+        .unwrap()
+        .get_pd_events()
+        .unwrap()
+        .preamble;
+    let mut builder = PdCaptureBuilder::new(preamble);
+    builder
+        .connect(ms(0.0))
+        .message(
+            ms(1.0),
+            PdSopType::Sop,
+            PdMessageSender::source(),
+            &PdMessageBody::epr_source_capabilities(&pdos),
+        )
+        .unwrap()
+        .message(
+            ms(2.0),
+            PdSopType::SopPrime,
+            PdMessageSender::source(),
+            &PdMessageBody::VendorDefined(discover_identity),
+        )
+        .unwrap()
+        .message(
+            ms(3.0),
+            PdSopType::Sop,
+            PdMessageSender::sink(),
+            &PdMessageBody::EprRequest {
+                request: RequestDataObject(0x9000_0000 | (150 << 10) | 150),
+                pdo: pdos[9],
+            },
+        )
+        .unwrap()
+        .message(
+            ms(4.0),
+            PdSopType::Sop,
+            PdMessageSender::source(),
+            &PdMessageBody::Control(PdControlMessageType::Accept),
+        )
+        .unwrap();
+    let formatted = format_all(builder.events());
+
+    assert_eq!(formatted[0].summary, "** CONNECT **");
+    assert_eq!(
+        formatted[1].summary,
+        "SOP0: EprSourceCapabilities chunk 0 received, waiting for chunk 1"
+    );
+    assert_eq!(
+        formatted[3].summary,
+        "SOP0: Chunk Request (chunk=1, type=EprSourceCapabilities)"
+    );
+
+    let capabilities = &formatted[5];
+    assert_eq!(capabilities.category, PdEventCategory::Extended);
+    assert_eq!(capabilities.details[0], "[EPR Source Capabilities]");
+    assert_eq!(capabilities.details[7], "PDO[7]: --- (separator) ---");
+    assert_eq!(capabilities.details[10], "PDO[10]: Fixed 48V @ 5.0A (240W)");
+    assert_eq!(field(&capabilities.fields, "Data Size").value, "40");
+    let extended_header = field(&capabilities.fields, "Extended Header");
+    assert!(extended_header.children.contains(&PdField::new("Chunk Number", 1)));
+
+    let vdm = &formatted[7];
+    assert_eq!(vdm.summary, "SOP1: VendorDefined (ID=0, FROM=Port)");
+    assert_eq!(
+        vdm.details,
+        vec!["VDM: SVID=0xFF00 DiscoverIdentity Request (pos=0)".to_string()]
+    );
+    assert!(
+        field(&vdm.fields, "Header")
+            .children
+            .contains(&PdField::new("Cable Plug", "Port"))
+    );
+
+    let epr_request = &formatted[9];
+    assert_eq!(
+        epr_request.details,
+        vec!["RDO: EPR PDO#9 (Fixed 48V @ 5.0A (240W)) @ 1.50A (Max 1.50A)".to_string()]
+    );
+    assert!(field(&epr_request.fields, "Requested PDO").value == "0x001F01F4");
+    assert_eq!(formatted[11].category, PdEventCategory::Control);
+    assert!(formatted[11].details.is_empty());
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use common::pd::{
+    ACCEPT, NEW_SINK, NEW_SOURCE, PS_RDY, REQUEST, SINK, SOURCE, SOURCE_CAPABILITIES, VENDOR_DEFINED, event, message,
+    ms,
+};
+use km003c_lib::pd_policy::{ContractEndReason, PdDataRole, PdPowerRole, PdRoleSwap, PdSpecRevision};
+use km003c_lib::pd_wire::PdControlMessageType;
+use km003c_lib::{PdEvent, PdEventData, PdSopType, PolicyChange, PolicyEvent, PolicyTracker};
+use uom::si::electric_potential::volt;
+
+const REJECT: u16 = 0x04;
+const DR_SWAP: u16 = 0x09;
+const PR_SWAP: u16 = 0x0a;
+const VCONN_SWAP: u16 = 0x0b;
+const SOFT_RESET: u16 = 0x0d;
+
+// 5 V 3 A and 9 V 3 A fixed supplies.
+const CAPABILITIES: [u32; 2] = [0x0001_912c, 0x0002_d12c];
+// 5 V 2 A from position 1 and 9 V 2 A from position 2.
+const REQUEST_5V: u32 = 0x1003_20c8;
+const REQUEST_9V: u32 = 0x2003_20c8;
+
+fn changes(tracker: &PolicyTracker) -> Vec<PolicyChange> {
+    tracker.events().iter().map(|event| event.change).collect()
+}
+
+/// Attach, then negotiate 9 V with message IDs 0-2 from the source and 0 from the sink.
+fn negotiate_9v(tracker: &mut PolicyTracker) {
+    tracker.process_event(&PdEvent {
+        timestamp: ms(0.0),
+        data: PdEventData::Connect(()),
+    });
+    tracker.process_event(&event(10.0, 0, message(SOURCE_CAPABILITIES, SOURCE, 0, &CAPABILITIES)));
+    tracker.process_event(&event(12.0, 0, message(REQUEST, SINK, 0, &[REQUEST_9V])));
+    tracker.process_event(&event(13.0, 0, message(ACCEPT, SOURCE, 1, &[])));
+    tracker.process_event(&event(80.0, 0, message(PS_RDY, SOURCE, 2, &[])));
+}
+
+#[test]
+fn reconstructs_contract_over_time() {
+    let mut tracker = PolicyTracker::new();
+    negotiate_9v(&mut tracker);
+
+    let changes = changes(&tracker);
+    assert!(matches!(
+        changes[..],
+        [
+            PolicyChange::Attached,
+            PolicyChange::RevisionNegotiated(PdSpecRevision::Revision3),
+            PolicyChange::ContractAccepted(_),
+            PolicyChange::PowerReady(_),
+        ]
+    ));
+
+    assert_eq!(tracker.contract_at(ms(12.5)), None);
+    let contract = tracker.contract_at(ms(50.0)).unwrap();
+    assert_eq!(contract.voltage.unwrap().get::<volt>(), 9.0);
+    assert_eq!(contract.ready_at, None);
+    assert_eq!(tracker.contract_at(ms(100.0)).unwrap().ready_at, Some(ms(80.0)));
+    assert!(tracker.state().attached);
+    assert_eq!(tracker.state().revision, Some(PdSpecRevision::Revision3));
+}
+
+#[test]
+fn explains_drop_to_5v_after_inferred_hard_reset() {
+    let mut tracker = PolicyTracker::new();
+    negotiate_9v(&mut tracker);
+
+    // The source restarts its MessageID counter without a Soft_Reset.
+    let added = tracker.process_event(&event(500.0, 0, message(SOURCE_CAPABILITIES, SOURCE, 0, &CAPABILITIES)));
+    assert!(matches!(
+        added,
+        [
+            PolicyEvent {
+                change: PolicyChange::ContractEnded {
+                    reason: ContractEndReason::HardReset,
+                    ..
+                },
+                ..
+            },
+            PolicyEvent {
+                change: PolicyChange::HardReset,
+                ..
+            }
+        ]
+    ));
+
+    tracker.process_event(&event(502.0, 0, message(REQUEST, SINK, 0, &[REQUEST_5V])));
+    tracker.process_event(&event(503.0, 0, message(ACCEPT, SOURCE, 1, &[])));
+    assert_eq!(tracker.contract_at(ms(501.0)), None);
+    assert_eq!(
+        tracker.contract_at(ms(600.0)).unwrap().voltage.unwrap().get::<volt>(),
+        5.0
+    );
+}
+
+#[test]
+fn soft_reset_ends_contract_without_hard_reset() {
+    let mut tracker = PolicyTracker::new();
+    negotiate_9v(&mut tracker);
+
+    tracker.process_event(&event(500.0, 0, message(SOFT_RESET, SINK, 0, &[])));
+    tracker.process_event(&event(501.0, 0, message(ACCEPT, SOURCE, 0, &[])));
+    tracker.process_event(&event(510.0, 0, message(SOURCE_CAPABILITIES, SOURCE, 1, &CAPABILITIES)));
+
+    let changes = changes(&tracker);
+    assert!(matches!(
+        changes[4..],
+        [
+            PolicyChange::ContractEnded {
+                reason: ContractEndReason::SoftReset,
+                ..
+            },
+            PolicyChange::SoftReset { sop: PdSopType::Sop },
+        ]
+    ));
+    assert_eq!(tracker.state().contract, None);
+}
+
+#[test]
+fn follows_power_data_and_vconn_swaps() {
+    let mut tracker = PolicyTracker::new();
+    negotiate_9v(&mut tracker);
+
+    tracker.process_event(&event(200.0, 0, message(PR_SWAP, SINK, 1, &[])));
+    tracker.process_event(&event(201.0, 0, message(ACCEPT, SOURCE, 3, &[])));
+    tracker.process_event(&event(230.0, 0, message(PS_RDY, SOURCE, 4, &[])));
+    assert!(!tracker.state().power_roles_swapped);
+    // The former sink, still UFP, now sends as source.
+    tracker.process_event(&event(260.0, 0, message(PS_RDY, NEW_SOURCE, 2, &[])));
+
+    let state = *tracker.state();
+    assert!(state.power_roles_swapped);
+    assert_eq!(state.contract, None);
+    assert_eq!(state.source_data_role, PdDataRole::Ufp);
+    assert_eq!(state.vconn_source, PdPowerRole::Sink);
+    assert!(matches!(
+        changes(&tracker)[4..],
+        [
+            PolicyChange::ContractEnded {
+                reason: ContractEndReason::PowerRoleSwap,
+                ..
+            },
+            PolicyChange::SwapCompleted(PdRoleSwap::PowerRole),
+        ]
+    ));
+
+    tracker.process_event(&event(300.0, 0, message(DR_SWAP, NEW_SINK, 5, &[])));
+    tracker.process_event(&event(301.0, 0, message(ACCEPT, NEW_SOURCE, 3, &[])));
+    assert_eq!(tracker.state().source_data_role, PdDataRole::Dfp);
+
+    tracker.process_event(&event(400.0, 0, message(VCONN_SWAP, NEW_SOURCE, 4, &[])));
+    let added = tracker.process_event(&event(401.0, 0, message(REJECT, NEW_SINK, 6, &[])));
+    assert!(matches!(
+        added,
+        [PolicyEvent {
+            change: PolicyChange::SwapRefused {
+                swap: PdRoleSwap::Vconn,
+                response: PdControlMessageType::Reject
+            },
+            ..
+        }]
+    ));
+
+    tracker.process_event(&event(500.0, 0, message(VCONN_SWAP, NEW_SINK, 7, &[])));
+    tracker.process_event(&event(501.0, 0, message(ACCEPT, NEW_SOURCE, 5, &[])));
+    tracker.process_event(&event(510.0, 0, message(PS_RDY, NEW_SOURCE, 6, &[])));
+    assert_eq!(tracker.state().vconn_source, PdPowerRole::Source);
+    // The new source's counter continues across the swap: no hard reset.
+    tracker.process_event(&event(
+        600.0,
+        0,
+        message(SOURCE_CAPABILITIES, NEW_SOURCE, 7, &CAPABILITIES),
+    ));
+    assert!(!changes(&tracker).contains(&PolicyChange::HardReset));
+}
+
+#[test]
+fn infers_cable_reset_from_message_id_restart() {
+    // Discover Identity sent by a port, with the Cable Plug flag clear.
+    let discover_identity = 0xff00_8001;
+    let mut tracker = PolicyTracker::new();
+    for (index, message_id) in [0, 1, 2, 0].into_iter().enumerate() {
+        tracker.process_event(&event(
+            index as f64,
+            1,
+            message(VENDOR_DEFINED, SINK, message_id, &[discover_identity]),
+        ));
+    }
+    assert_eq!(
+        changes(&tracker),
+        vec![PolicyChange::CableReset {
+            sop: PdSopType::SopPrime
+        }]
+    );
+
+    // Wrapping from 7 to 0 is not a reset.
+    let mut tracker = PolicyTracker::new();
+    for message_id in [5, 6, 7, 0] {
+        tracker.process_event(&event(
+            f64::from(message_id),
+            1,
+            message(VENDOR_DEFINED, SINK, message_id, &[discover_identity]),
+        ));
+    }
+    assert!(tracker.events().is_empty());
+}
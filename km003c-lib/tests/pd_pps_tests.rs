@@ -0,0 +1,202 @@
+mod common;
+
+use common::pd::{ACCEPT, PS_RDY, REQUEST, SINK, SOP, SOURCE, SOURCE_CAPABILITIES, event_at, message};
+use km003c_lib::pd_pps::{PpsStatus, PpsTemperatureFlag};
+use km003c_lib::pd_wire::PdControlMessageType;
+use km003c_lib::{
+    PdEvent, PdEventData, PdStatus, PpsTimelineEntry, PpsTracker, ProgrammableSupplyKind, RequestDataObject, SourcePdo,
+};
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use uom::si::power::watt;
+use uom::si::time::{millisecond, second};
+
+// 5 V 3 A fixed supply and a 3.3-11 V 5 A PPS APDO.
+const FIXED_5V_3A: u32 = 0x0001_912c;
+const PPS_3V3_11V_5A: u32 = 0xc0dc_2164;
+// EPR AVS 15-28 V, 140 W PDP.
+const EPR_AVS_15V_28V_140W: u32 = 0xd230_968c;
+
+// PPS requests for object position 2.
+const REQUEST_9V_2A: u32 = 0x2003_8428;
+const REQUEST_9V5_2A: u32 = 0x2003_b628;
+
+/// PD message on SOP at a timestamp in seconds.
+fn event(timestamp_s: f64, wire_data: Vec<u8>) -> PdEvent {
+    event_at(Time::new::<second>(timestamp_s), SOP, wire_data)
+}
+
+fn status(timestamp_s: f64, vbus_v: f64) -> PdStatus {
+    PdStatus {
+        timestamp: Time::new::<second>(timestamp_s),
+        vbus: ElectricPotential::new::<volt>(vbus_v),
+        ibus: ElectricCurrent::new::<ampere>(1.5),
+        cc1: ElectricPotential::new::<volt>(0.4),
+        cc2: ElectricPotential::new::<volt>(0.0),
+    }
+}
+
+const EPR_REQUEST: u16 = 0x09;
+
+#[test]
+fn decodes_source_pdos_and_request_objects() {
+    let SourcePdo::FixedSupply(fixed) = SourcePdo::from_raw(FIXED_5V_3A) else {
+        panic!("expected a fixed supply");
+    };
+    assert_eq!(fixed.voltage().get::<volt>(), 5.0);
+    assert_eq!(fixed.max_current().get::<ampere>(), 3.0);
+
+    let SourcePdo::SprPps(pps) = SourcePdo::from_raw(PPS_3V3_11V_5A) else {
+        panic!("expected a PPS APDO");
+    };
+    assert!((pps.min_voltage().get::<volt>() - 3.3).abs() < 1e-9);
+    assert_eq!(pps.max_voltage().get::<volt>(), 11.0);
+    assert_eq!(pps.max_current().get::<ampere>(), 5.0);
+
+    let SourcePdo::EprAvs(avs) = SourcePdo::from_raw(EPR_AVS_15V_28V_140W) else {
+        panic!("expected an EPR AVS APDO");
+    };
+    assert_eq!(avs.min_voltage().get::<volt>(), 15.0);
+    assert_eq!(avs.max_voltage().get::<volt>(), 28.0);
+    assert_eq!(avs.pdp().get::<watt>(), 140.0);
+
+    let rdo = RequestDataObject(REQUEST_9V_2A);
+    assert_eq!(rdo.object_position(), 2);
+    assert_eq!(rdo.pps_output_voltage().get::<volt>(), 9.0);
+    assert_eq!(rdo.programmable_operating_current().get::<ampere>(), 2.0);
+}
+
+#[test]
+fn builds_pps_timeline_with_responses_and_measurements() {
+    let mut tracker = PpsTracker::new();
+    tracker.process_event(&event(
+        0.0,
+        message(SOURCE_CAPABILITIES, SOURCE, 0, &[FIXED_5V_3A, PPS_3V3_11V_5A]),
+    ));
+
+    let added = tracker.process_event(&event(0.1, message(REQUEST, SINK, 0, &[REQUEST_9V_2A])));
+    let [PpsTimelineEntry::Request(first)] = added else {
+        panic!("expected one request entry, got {added:?}");
+    };
+    assert_eq!(first.kind, ProgrammableSupplyKind::SprPps);
+    assert_eq!(first.requested_voltage.get::<volt>(), 9.0);
+    assert_eq!(first.interval, None);
+
+    tracker.process_event(&event(0.11, message(ACCEPT, SOURCE, 0, &[])));
+    tracker.process_event(&event(0.2, message(PS_RDY, SOURCE, 0, &[])));
+    assert_eq!(tracker.contract(), Some(ProgrammableSupplyKind::SprPps));
+    assert_eq!(tracker.process_status(&status(0.3, 9.02)).len(), 1);
+
+    tracker.process_event(&event(5.0, message(REQUEST, SINK, 0, &[REQUEST_9V5_2A])));
+    tracker.process_event(&event(5.01, message(ACCEPT, SOURCE, 0, &[])));
+    let added = tracker.process_event(&event(13.0, message(REQUEST, SINK, 0, &[REQUEST_9V5_2A])));
+    let [PpsTimelineEntry::Request(keep_alive)] = added else {
+        panic!("expected one request entry, got {added:?}");
+    };
+    assert!(keep_alive.repeated);
+    assert_eq!(keep_alive.interval, Some(Time::new::<second>(8.0)));
+
+    // Request, Accept, PS_RDY, measurement, Request, Accept, keep-alive Request.
+    assert_eq!(tracker.timeline().len(), 7);
+    assert!(matches!(
+        tracker.timeline()[1],
+        PpsTimelineEntry::Response {
+            response: PdControlMessageType::Accept,
+            ..
+        }
+    ));
+    assert!(matches!(tracker.timeline()[2], PpsTimelineEntry::PowerReady { .. }));
+    assert!(matches!(tracker.timeline()[3], PpsTimelineEntry::Measurement { .. }));
+}
+
+#[test]
+fn flags_missed_pps_keep_alive_once() {
+    let mut tracker = PpsTracker::new();
+    tracker.process_event(&event(
+        0.0,
+        message(SOURCE_CAPABILITIES, SOURCE, 0, &[FIXED_5V_3A, PPS_3V3_11V_5A]),
+    ));
+    tracker.process_event(&event(1.0, message(REQUEST, SINK, 0, &[REQUEST_9V_2A])));
+    tracker.process_event(&event(1.01, message(ACCEPT, SOURCE, 0, &[])));
+
+    assert_eq!(tracker.process_status(&status(10.0, 9.0)).len(), 1);
+    let added = tracker.process_status(&status(11.5, 9.0));
+    assert!(matches!(
+        added[0],
+        PpsTimelineEntry::MissedKeepAlive { elapsed, .. } if elapsed == Time::new::<second>(10.5)
+    ));
+    assert!(
+        tracker
+            .process_status(&status(12.0, 9.0))
+            .iter()
+            .all(|entry| !matches!(entry, PpsTimelineEntry::MissedKeepAlive { .. }))
+    );
+}
+
+#[test]
+fn fixed_contract_ends_pps_tracking() {
+    let mut tracker = PpsTracker::new();
+    tracker.process_event(&event(
+        0.0,
+        message(SOURCE_CAPABILITIES, SOURCE, 0, &[FIXED_5V_3A, PPS_3V3_11V_5A]),
+    ));
+    tracker.process_event(&event(1.0, message(REQUEST, SINK, 0, &[REQUEST_9V_2A])));
+    tracker.process_event(&event(1.01, message(ACCEPT, SOURCE, 0, &[])));
+    // Fixed 5 V at 3 A from object position 1.
+    assert!(
+        tracker
+            .process_event(&event(2.0, message(REQUEST, SINK, 0, &[0x1004_b12c])))
+            .is_empty()
+    );
+    assert!(
+        tracker
+            .process_event(&event(2.01, message(ACCEPT, SOURCE, 0, &[])))
+            .is_empty()
+    );
+
+    assert_eq!(tracker.contract(), None);
+    assert!(tracker.process_status(&status(20.0, 5.0)).is_empty());
+}
+
+#[test]
+fn classifies_epr_avs_request_from_pdo_copy() {
+    let mut tracker = PpsTracker::new();
+    // Position 8, 20 V at 5 A.
+    let added = tracker.process_event(&event(
+        1.0,
+        message(EPR_REQUEST, SINK, 0, &[0x8006_4064, EPR_AVS_15V_28V_140W]),
+    ));
+    let [PpsTimelineEntry::Request(request)] = added else {
+        panic!("expected one request entry, got {added:?}");
+    };
+    assert_eq!(request.kind, ProgrammableSupplyKind::EprAvs);
+    assert_eq!(request.object_position, 8);
+    assert_eq!(request.requested_voltage.get::<volt>(), 20.0);
+    assert_eq!(request.requested_current.get::<ampere>(), 5.0);
+}
+
+#[test]
+fn records_pps_status_reports() {
+    // Extended header: unchunked, 4 bytes; 9.02 V, 2 A, normal temperature.
+    let mut wire_data = (0x0c_u16 | (2 << 6) | (2 << 12) | 0x8000).to_le_bytes().to_vec();
+    wire_data.extend_from_slice(&[0x04, 0x00, 0xc3, 0x01, 0x28, 0x02, 0x00, 0x00]);
+
+    let mut tracker = PpsTracker::new();
+    let added = tracker.process_event(&PdEvent {
+        timestamp: Time::new::<millisecond>(500.0),
+        data: PdEventData::PdMessage { sop: SOP, wire_data },
+    });
+    let [PpsTimelineEntry::Status { status, .. }] = added else {
+        panic!("expected one status entry, got {added:?}");
+    };
+    assert!((status.output_voltage.unwrap().get::<volt>() - 9.02).abs() < 1e-9);
+    assert_eq!(status.output_current.unwrap().get::<ampere>(), 2.0);
+    assert_eq!(status.temperature, PpsTemperatureFlag::Normal);
+    assert!(!status.current_limited);
+
+    let unsupported = PpsStatus::from_bytes(&[0xff, 0xff, 0xff, 0x08]).unwrap();
+    assert_eq!(unsupported.output_voltage, None);
+    assert_eq!(unsupported.output_current, None);
+    assert!(unsupported.current_limited);
+}
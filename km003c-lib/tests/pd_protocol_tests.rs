@@ -0,0 +1,230 @@
+mod common;
+
+use common::pd::{
+    ACCEPT, CABLE, NEW_SINK, NEW_SOURCE, PORT, PS_RDY, REQUEST, SINK, SOURCE, SOURCE_CAPABILITIES, VENDOR_DEFINED,
+    message, ms, revision_message,
+};
+use km003c_lib::pd_policy::PdSpecRevision;
+use km003c_lib::pd_protocol::PdHeaderField;
+use km003c_lib::pd_wire::{PdControlMessageType, PdDataMessageType};
+use km003c_lib::{PdEvent, PdEventData, PdMessageType, PdSopType, ProtocolAnalyzer, ProtocolIssue};
+
+const GOOD_CRC: u16 = 0x01;
+const PR_SWAP: u16 = 0x0a;
+
+// 5 V 3 A fixed supply, and a request for it.
+const CAPABILITIES: [u32; 1] = [0x0001_912c];
+const REQUEST_5V: u32 = 0x1004_b12c;
+
+/// Feed messages on one SOP* channel at one millisecond intervals from zero.
+fn process(analyzer: &mut ProtocolAnalyzer, sop: u8, messages: &[Vec<u8>]) -> Vec<ProtocolIssue> {
+    let start = analyzer.issues().len();
+    for (index, wire_data) in messages.iter().enumerate() {
+        analyzer.process_event(&PdEvent {
+            timestamp: ms(index as f64),
+            data: PdEventData::PdMessage {
+                sop,
+                wire_data: wire_data.clone(),
+            },
+        });
+    }
+    analyzer.issues()[start..].iter().map(|event| event.issue).collect()
+}
+
+/// Source_Capabilities, Request, Accept and PS_RDY, each acknowledged.
+fn negotiation() -> Vec<Vec<u8>> {
+    vec![
+        message(SOURCE_CAPABILITIES, SOURCE, 0, &CAPABILITIES),
+        message(GOOD_CRC, SINK, 0, &[]),
+        message(REQUEST, SINK, 0, &[REQUEST_5V]),
+        message(GOOD_CRC, SOURCE, 0, &[]),
+        message(ACCEPT, SOURCE, 1, &[]),
+        message(GOOD_CRC, SINK, 1, &[]),
+        message(PS_RDY, SOURCE, 2, &[]),
+        message(GOOD_CRC, SINK, 2, &[]),
+    ]
+}
+
+#[test]
+fn pairs_messages_with_good_crc() {
+    let mut analyzer = ProtocolAnalyzer::new();
+    assert!(process(&mut analyzer, 0, &negotiation()).is_empty());
+
+    let transmissions = analyzer.transmissions();
+    assert_eq!(transmissions.len(), 4);
+    assert!(transmissions.iter().all(|transmission| transmission.attempts == 1));
+    assert_eq!(
+        transmissions[1].message_type,
+        PdMessageType::Data(PdDataMessageType::Request)
+    );
+    assert_eq!(transmissions[1].timestamp, ms(2.0));
+    assert_eq!(transmissions[1].good_crc_at, Some(ms(3.0)));
+    assert_eq!(analyzer.statistics().messages, 4);
+    assert_eq!(analyzer.statistics().good_crcs, 4);
+}
+
+#[test]
+fn counts_retries_duplicates_and_missing_good_crc() {
+    let mut analyzer = ProtocolAnalyzer::new();
+    let request = message(REQUEST, SINK, 0, &[REQUEST_5V]);
+    let ps_rdy = message(PS_RDY, SOURCE, 2, &[]);
+    let issues = process(
+        &mut analyzer,
+        0,
+        &[
+            message(SOURCE_CAPABILITIES, SOURCE, 0, &CAPABILITIES),
+            message(GOOD_CRC, SINK, 0, &[]),
+            request.clone(),
+            request,
+            message(GOOD_CRC, SOURCE, 0, &[]),
+            // The Accept is lost: the sink answers nothing and the source
+            // never retries before it moves on.
+            message(ACCEPT, SOURCE, 1, &[]),
+            ps_rdy.clone(),
+            message(GOOD_CRC, SINK, 2, &[]),
+            // The source missed the GoodCRC and resends the PS_RDY.
+            ps_rdy,
+            message(GOOD_CRC, SINK, 2, &[]),
+        ],
+    );
+
+    assert_eq!(
+        issues,
+        vec![
+            ProtocolIssue::Retry {
+                message_id: 0,
+                attempt: 2
+            },
+            ProtocolIssue::MissingGoodCrc {
+                message_id: 1,
+                message_type: PdMessageType::Control(PdControlMessageType::Accept)
+            },
+            ProtocolIssue::Duplicate { message_id: 2 },
+        ]
+    );
+    let transmissions = analyzer.transmissions();
+    assert_eq!(transmissions.len(), 4);
+    assert_eq!(transmissions[1].attempts, 2);
+    assert!(transmissions[1].good_crc_at.is_some());
+    assert_eq!(transmissions[2].good_crc_at, None);
+    assert_eq!(transmissions[3].attempts, 2);
+    assert!(transmissions[3].good_crc_at.is_some());
+
+    let statistics = analyzer.statistics();
+    assert_eq!(statistics.retries, 1);
+    assert_eq!(statistics.duplicates, 1);
+    assert_eq!(statistics.missing_good_crcs, 1);
+}
+
+#[test]
+fn restarted_message_ids_are_not_retries() {
+    let mut analyzer = ProtocolAnalyzer::new();
+    let capabilities = message(SOURCE_CAPABILITIES, SOURCE, 0, &CAPABILITIES);
+    // A Hard Reset between the two offers is missing from the capture.
+    let issues = process(
+        &mut analyzer,
+        0,
+        &[
+            capabilities.clone(),
+            message(GOOD_CRC, SINK, 0, &[]),
+            capabilities,
+            message(GOOD_CRC, SINK, 0, &[]),
+        ],
+    );
+
+    assert!(issues.is_empty());
+    let transmissions = analyzer.transmissions();
+    assert_eq!(transmissions.len(), 2);
+    assert!(transmissions.iter().all(|transmission| transmission.attempts == 1));
+}
+
+#[test]
+fn reports_header_inconsistencies() {
+    let mut analyzer = ProtocolAnalyzer::new();
+    let issues = process(
+        &mut analyzer,
+        0,
+        &[
+            message(SOURCE_CAPABILITIES, SOURCE, 0, &CAPABILITIES),
+            message(GOOD_CRC, SINK, 0, &[]),
+            // The sink negotiates Revision 2.0.
+            revision_message(1, REQUEST, SINK, 0, &[REQUEST_5V]),
+            message(GOOD_CRC, SOURCE, 0, &[]),
+            revision_message(1, ACCEPT, SOURCE, 2, &[]),
+            message(GOOD_CRC, SINK, 1, &[]),
+            message(GOOD_CRC, SINK, 2, &[]),
+            message(PS_RDY, SOURCE, 3, &[]),
+            message(GOOD_CRC, SOURCE, 3, &[]),
+        ],
+    );
+
+    assert_eq!(
+        issues,
+        vec![
+            ProtocolIssue::UnexpectedMessageId { expected: 1, found: 2 },
+            ProtocolIssue::UnexpectedGoodCrc {
+                message_id: 1,
+                expected: Some(2)
+            },
+            ProtocolIssue::UnexpectedRevision {
+                expected: PdSpecRevision::Revision2,
+                found: PdSpecRevision::Revision3
+            },
+            ProtocolIssue::RoleConflict {
+                field: PdHeaderField::PowerRole
+            },
+            ProtocolIssue::RoleConflict {
+                field: PdHeaderField::DataRole
+            },
+        ]
+    );
+    let statistics = analyzer.statistics();
+    assert_eq!(statistics.unexpected_message_ids, 1);
+    assert_eq!(statistics.unexpected_good_crcs, 1);
+    assert_eq!(statistics.unexpected_revisions, 1);
+    assert_eq!(statistics.role_conflicts, 2);
+}
+
+#[test]
+fn tracks_counters_per_port_and_cable() {
+    // Discover Identity, sent by a port with the Cable Plug flag clear.
+    let discover_identity = 0xff00_8001;
+    let mut analyzer = ProtocolAnalyzer::new();
+    let mut messages = negotiation();
+    // Power role swap: the former sink becomes the source and continues its
+    // own MessageID counter.
+    messages.extend([
+        message(PR_SWAP, SINK, 1, &[]),
+        message(GOOD_CRC, SOURCE, 1, &[]),
+        message(ACCEPT, SOURCE, 3, &[]),
+        message(GOOD_CRC, SINK, 3, &[]),
+        message(PS_RDY, SOURCE, 4, &[]),
+        message(GOOD_CRC, SINK, 4, &[]),
+        message(PS_RDY, NEW_SOURCE, 2, &[]),
+        message(GOOD_CRC, NEW_SINK, 2, &[]),
+        message(SOURCE_CAPABILITIES, NEW_SOURCE, 3, &CAPABILITIES),
+        message(GOOD_CRC, NEW_SINK, 3, &[]),
+    ]);
+    assert!(process(&mut analyzer, 0, &messages).is_empty());
+
+    // The SOP' counter is independent of SOP.
+    let issues = process(
+        &mut analyzer,
+        1,
+        &[
+            message(VENDOR_DEFINED, PORT, 0, &[discover_identity]),
+            message(GOOD_CRC, CABLE, 0, &[]),
+            message(VENDOR_DEFINED, PORT, 1, &[discover_identity]),
+            // The port acknowledges its own message.
+            message(GOOD_CRC, PORT, 1, &[]),
+        ],
+    );
+    assert_eq!(
+        issues,
+        vec![ProtocolIssue::RoleConflict {
+            field: PdHeaderField::CablePlug
+        }]
+    );
+    assert_eq!(analyzer.issues()[0].sop, PdSopType::SopPrime);
+    assert_eq!(analyzer.transmissions().len(), 11);
+}
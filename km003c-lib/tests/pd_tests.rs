@@ -1,5 +1,6 @@
 use bytes::Bytes;
-use km003c_lib::{Packet, PayloadData, PdEventData, PdEventStream, RawPacket};
+use km003c_lib::pd_wire::{PdControlMessageType, PdDataMessageType};
+use km003c_lib::{Packet, PayloadData, PdEventData, PdEventStream, PdMessageType, PdWireMessage, RawPacket};
 use uom::si::electric_current::milliampere;
 use uom::si::electric_potential::volt;
 use uom::si::time::millisecond;
@@ -91,6 +92,35 @@ fn parses_recorded_pd_message_stream() {
     }
 }
 
+#[test]
+fn splits_recorded_wire_messages_into_header_and_data_objects() {
+    // Source: usb_master_dataset.parquet, orig_with_pd.13, frame 714.
+    let source_caps =
+        PdWireMessage::from_bytes(&hex::decode("a1632c9101082cd102002cc103002cb10400454106003c21dcc0").unwrap())
+            .unwrap();
+    assert_eq!(
+        source_caps.message_type(),
+        PdMessageType::Data(PdDataMessageType::SourceCapabilities)
+    );
+    assert_eq!(source_caps.header.spec_revision(), 2);
+    assert_eq!(source_caps.header.message_id(), 1);
+    assert_eq!(source_caps.data_objects().count(), 6);
+    assert_eq!(source_caps.data_objects().next(), Some(0x0801_912c));
+
+    let good_crc = PdWireMessage::from_bytes(&hex::decode("4102").unwrap()).unwrap();
+    assert_eq!(
+        good_crc.message_type(),
+        PdMessageType::Control(PdControlMessageType::GoodCrc)
+    );
+    assert_eq!(good_crc.header.message_id(), 1);
+}
+
+#[test]
+fn rejects_wire_messages_shorter_than_their_data_objects() {
+    let error = PdWireMessage::from_bytes(&hex::decode("a1632c910108").unwrap()).unwrap_err();
+    assert!(error.to_string().contains("announces 6 data objects"));
+}
+
 #[test]
 fn recognizes_legacy_recorded_connection_events() {
     // Source: usb_master_dataset.parquet, orig_with_pd.13, frames 666 and 1298.
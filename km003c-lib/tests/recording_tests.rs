@@ -0,0 +1,252 @@
+//! Recordings written in the schema shared by the CLI tools and the GUI
+#![cfg(feature = "recording")]
+
+mod common;
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use common::*;
+use km003c_lib::polars::prelude::{CsvReader, DataFrame, DataType, ParquetReader, SerReader};
+use km003c_lib::recording::{
+    RECORDING_COLUMNS, RECORDING_SCHEMA_VERSION, partial_path, write_recording, write_recording_to,
+};
+use km003c_lib::uom::si::electric_charge::microampere_hour;
+use km003c_lib::uom::si::energy::microwatt_hour;
+use km003c_lib::uom::si::f64::{ElectricCharge, Energy, Time};
+use km003c_lib::uom::si::time::{millisecond, second};
+use km003c_lib::{
+    DeviceInfo, LogMetadata, MeasurementSample, OfflineLog, RecordingFormat, RecordingMetadata, RecordingRow,
+    RecordingWriter,
+};
+
+fn device() -> DeviceInfo {
+    DeviceInfo {
+        model: "KM003C".to_string(),
+        fw_version: "1.9.9".to_string(),
+        serial_id: "007965".to_string(),
+        ..DeviceInfo::default()
+    }
+}
+
+fn measurement(elapsed_us: u64, missing: u64, interpolated_us: u64) -> MeasurementSample {
+    MeasurementSample {
+        elapsed_us,
+        sample_index: 100,
+        sequence: 42,
+        marker: 7,
+        sample_rate_hz: 50,
+        missing_samples: missing as u16,
+        gap_duration_us: interpolated_us,
+        interpolated: missing > 0,
+        cumulative_missing_samples: missing,
+        cumulative_interpolated_duration_us: interpolated_us,
+        discarded_sequence_samples: 0,
+        cumulative_discarded_sequence_samples: 0,
+        vbus_uv: 5_000_000,
+        ibus_ua: -1_000_000,
+        power_uw: -5_000_000,
+        charge_uah: -100.0,
+        energy_uwh: -500.0,
+        charge_throughput_uah: 100.0,
+        energy_throughput_uwh: 500.0,
+        cc1_uv: 1_000_000,
+        cc2_uv: 0,
+        dp_uv: 600_000,
+        dm_uv: 500_000,
+    }
+}
+
+/// The captured three-sample recording "A01.d".
+fn offline_log() -> OfflineLog {
+    let bytes = [
+        "81494c0021f0e2ff56ebffffb998ffff",
+        "bcaa89006e25f2ff2dd5f8fff7fdd6ff",
+        "cf2a8900947dfeffa1a2f3ffe04da8ff",
+    ]
+    .into_iter()
+    .flat_map(|sample| hex::decode(sample).unwrap())
+    .collect::<Vec<_>>();
+    let mut filename_raw = [0; 16];
+    filename_raw[..5].copy_from_slice(b"A01.d");
+    let metadata = LogMetadata {
+        filename_raw,
+        unknown_0x10: 0x0a45,
+        sample_count: 3,
+        interval: Time::new::<millisecond>(10_000.0),
+        flags: 0,
+        recorded_duration: Time::new::<second>(20.0),
+        final_charge: ElectricCharge::new::<microampere_hour>(-810_335.0),
+        final_energy: Energy::new::<microwatt_hour>(-5_747_232.0),
+        data_offset: 0,
+        reserved_tail: [0; 8],
+    };
+    OfflineLog::from_bytes(metadata, &bytes).unwrap()
+}
+
+fn test_path(name: &str, format: RecordingFormat) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "km003c-recording-{name}-{}.{}",
+        std::process::id(),
+        format.extension()
+    ))
+}
+
+fn read(path: &PathBuf, format: RecordingFormat) -> DataFrame {
+    let file = File::open(path).unwrap();
+    match format {
+        RecordingFormat::Parquet => ParquetReader::new(file).finish().unwrap(),
+        RecordingFormat::Csv => CsvReader::new(file).finish().unwrap(),
+    }
+}
+
+#[test]
+fn live_rows_are_relative_to_the_start() {
+    let origin = measurement(1_000_000, 2, 40_000);
+    let row = RecordingRow::from_measurement(&measurement(2_000_000, 3, 60_000), Some(&origin), 0);
+
+    assert_eq!(row.elapsed_us, 1_000_000);
+    assert_eq!(row.sample_index, 0);
+    assert_eq!(row.cumulative_missing_samples, Some(1));
+    assert_eq!(row.cumulative_interpolated_duration_us, Some(20_000));
+    assert_eq!(row.charge_uah, 0.0);
+    assert_eq!(row.cc1_uv, Some(1_000_000));
+
+    let unanchored = RecordingRow::from_measurement(&measurement(2_000_000, 3, 60_000), None, 5);
+    assert_eq!(unanchored.elapsed_us, 2_000_000);
+    assert_eq!(unanchored.charge_uah, -100.0);
+}
+
+#[test]
+fn offline_rows_keep_device_counters_and_leave_live_channels_empty() {
+    let rows = RecordingRow::from_offline_log(&offline_log());
+
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[1].elapsed_us, 10_000_000);
+    assert_eq!(rows[0].vbus_uv, 4_999_553);
+    assert_eq!(rows[0].ibus_ua, -1_904_607);
+    assert_eq!(rows[0].charge_throughput_uah, 5_290.0);
+    assert_eq!(rows[2].charge_uah, -810_335.0);
+    assert_eq!(rows[2].charge_throughput_uah, 810_335.0);
+    assert_eq!(rows[2].energy_throughput_uwh, 5_747_232.0);
+    assert_eq!(rows[0].sequence, None);
+    assert_eq!(rows[0].cc1_uv, None);
+}
+
+#[test]
+fn live_and_offline_recordings_share_one_schema() {
+    let live = [
+        RecordingRow::from_measurement(&measurement(0, 0, 0), None, 0),
+        RecordingRow::from_measurement(&measurement(20_000, 1, 20_000), None, 1),
+    ];
+    let offline = RecordingRow::from_offline_log(&offline_log());
+
+    for format in RecordingFormat::ALL {
+        let live_path = test_path("live", format);
+        write_recording(&live_path, format, &RecordingMetadata::live(&device()), &live).unwrap();
+        let offline_path = test_path("offline", format);
+        let metadata = RecordingMetadata::offline(&device(), &offline_log().metadata);
+        write_recording(&offline_path, format, &metadata, &offline).unwrap();
+
+        let live_frame = read(&live_path, format);
+        let offline_frame = read(&offline_path, format);
+        std::fs::remove_file(live_path).unwrap();
+        std::fs::remove_file(offline_path).unwrap();
+
+        assert_eq!(live_frame.shape(), (2, 23));
+        assert_eq!(offline_frame.shape(), (3, 23));
+        assert_eq!(live_frame.get_column_names_str(), RECORDING_COLUMNS);
+        assert_eq!(offline_frame.get_column_names_str(), RECORDING_COLUMNS);
+        // CSV readers infer signed integers; Parquet keeps the written types.
+        let elapsed = live_frame
+            .column("elapsed_us")
+            .unwrap()
+            .cast(&DataType::UInt64)
+            .unwrap();
+        assert_eq!(elapsed.u64().unwrap().get(1), Some(20_000));
+        assert_eq!(
+            live_frame.column("vbus_uv").unwrap().i64().unwrap().get(0),
+            Some(5_000_000)
+        );
+        assert_eq!(offline_frame.column("sequence").unwrap().null_count(), 3);
+        assert_eq!(offline_frame.column("cc1_uv").unwrap().null_count(), 3);
+        assert_eq!(
+            offline_frame.column("charge_uah").unwrap().f64().unwrap().get(2),
+            Some(-810_335.0)
+        );
+        if format == RecordingFormat::Parquet {
+            assert_eq!(live_frame.schema(), offline_frame.schema());
+        }
+    }
+}
+
+#[test]
+fn parquet_metadata_identifies_device_and_source() {
+    let live = RecordingMetadata::live(&device()).key_values();
+    assert!(live.contains(&(
+        "km003c.schema_version".to_string(),
+        RECORDING_SCHEMA_VERSION.to_string()
+    )));
+    assert!(live.contains(&("km003c.source".to_string(), "live".to_string())));
+    assert!(live.contains(&("km003c.accumulator_source".to_string(), "host_trapezoidal".to_string())));
+    assert!(live.contains(&("km003c.serial".to_string(), "007965".to_string())));
+
+    let offline = RecordingMetadata::offline(&device(), &offline_log().metadata).key_values();
+    assert!(offline.contains(&("km003c.source".to_string(), "offline".to_string())));
+    assert!(offline.contains(&("km003c.accumulator_source".to_string(), "device".to_string())));
+    assert!(offline.contains(&("km003c.offline.filename".to_string(), "A01.d".to_string())));
+    assert!(offline.contains(&("km003c.offline.interval_us".to_string(), "10000000".to_string())));
+    assert!(offline.contains(&("km003c.offline.flags".to_string(), "0x0000".to_string())));
+}
+
+#[test]
+fn unfinished_recordings_stay_partial() {
+    let path = test_path("unfinished", RecordingFormat::Parquet);
+    let rows = RecordingRow::from_offline_log(&offline_log());
+    let mut writer =
+        RecordingWriter::create(&path, RecordingFormat::Parquet, &RecordingMetadata::live(&device())).unwrap();
+    writer.write_rows(&rows).unwrap();
+    assert_eq!(writer.rows(), 3);
+    assert_eq!(writer.partial_path(), partial_path(&path));
+    drop(writer);
+
+    assert!(!path.exists());
+    assert!(partial_path(&path).exists());
+    std::fs::remove_file(partial_path(&path)).unwrap();
+
+    // Finishing replaces an earlier recording at the same path.
+    std::fs::write(&path, b"stale").unwrap();
+    let written = write_recording(&path, RecordingFormat::Csv, &RecordingMetadata::live(&device()), &rows).unwrap();
+    assert_eq!(written, path);
+    assert!(!partial_path(&path).exists());
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(csv.starts_with(&RECORDING_COLUMNS.join(",")));
+    assert_eq!(csv.lines().count(), 4);
+}
+
+#[test]
+fn recordings_write_to_in_memory_buffers() {
+    let path = test_path("buffer", RecordingFormat::Csv);
+    let rows = RecordingRow::from_offline_log(&offline_log());
+    let metadata = RecordingMetadata::offline(&device(), &offline_log().metadata);
+    write_recording(&path, RecordingFormat::Csv, &metadata, &rows).unwrap();
+    let mut csv = Vec::new();
+    write_recording_to(&mut csv, RecordingFormat::Csv, &metadata, &rows).unwrap();
+    assert_eq!(csv, std::fs::read(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+
+    let mut parquet = Vec::new();
+    write_recording_to(&mut parquet, RecordingFormat::Parquet, &metadata, &rows).unwrap();
+    let dataframe = ParquetReader::new(std::io::Cursor::new(parquet)).finish().unwrap();
+    assert_eq!(dataframe.height(), 3);
+}
+
+#[test]
+fn recording_errors_surface_as_km_errors() {
+    let path = std::env::temp_dir()
+        .join(format!("km003c-missing-{}", std::process::id()))
+        .join("recording.parquet");
+    let error = RecordingWriter::create(&path, RecordingFormat::Parquet, &RecordingMetadata::live(&device()));
+    assert!(matches!(error, Err(KMError::Io(_))));
+}
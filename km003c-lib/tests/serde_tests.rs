@@ -0,0 +1,137 @@
+//! Tests for the serde representations of captures and decoded PD sessions
+#![cfg(feature = "serde")]
+
+mod common;
+
+use common::*;
+use km003c_lib::auth::AuthCredential;
+
+// Source: usb_master_dataset.parquet, orig_with_pd.13, frame 714.
+const PD_NEGOTIATION: &str = "41a90205100000168bfa1200de130000750602009f80fa120000a1632c9101082cd102002cc103002cb10400454106003c21dcc08781fa12000041028b85fa1200008210dc7003238786fa1200002101878afa120000a305878afa1200004104";
+
+fn parse_packet(frame: &str) -> Packet {
+    Packet::try_from(RawPacket::try_from(hex_to_bytes(frame)).unwrap()).unwrap()
+}
+
+fn round_trip<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+    serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+}
+
+#[test]
+fn pd_event_stream_round_trips_through_json() {
+    let packet = parse_packet(PD_NEGOTIATION);
+    let stream = packet.get_pd_events().unwrap().clone();
+    assert_eq!(round_trip(&stream), stream);
+    assert_eq!(round_trip(&packet), packet);
+
+    // Raw wire bytes are kept verbatim next to the capture metadata.
+    let value = serde_json::to_value(&stream.events[0]).unwrap();
+    assert_eq!(value["data"]["PdMessage"]["sop"], 0);
+    assert_eq!(value["data"]["PdMessage"]["wire_data"][0], 0xa1);
+    assert_eq!(round_trip(&stream.preamble), stream.preamble);
+}
+
+#[test]
+fn packets_round_trip_through_json() {
+    let generic = Packet::Generic(RawPacket::Ctrl {
+        header: CtrlHeader::new()
+            .with_packet_type(0x0c)
+            .with_id(7)
+            .with_attribute(Attribute::Adc.into()),
+        payload: vec![0xaa, 0x55],
+    });
+    let auth = Packet::StreamingAuth {
+        credential: AuthCredential::from_bytes(*b"071KBP\r\xff\x11\n\xff\xff"),
+    };
+
+    let memory_read = Packet::MemoryRead {
+        address: 0x420,
+        size: 64,
+    };
+
+    for packet in [generic, auth, memory_read] {
+        assert_eq!(round_trip(&packet), packet);
+    }
+}
+
+#[cfg(feature = "usbpd")]
+#[test]
+fn decoded_pd_session_round_trips_through_json() {
+    use km003c_lib::{DecodedPdEvent, PdEvent, PdEventData, PdSessionDecoder};
+
+    let stream = parse_packet(PD_NEGOTIATION).get_pd_events().unwrap().clone();
+    let mut decoder = PdSessionDecoder::new();
+    let decoded = stream
+        .events
+        .iter()
+        .map(|event| decoder.decode_event(event))
+        .collect::<Vec<_>>();
+
+    let DecodedPdEvent::Message(request) = &decoded[2] else {
+        panic!("expected a Request message");
+    };
+    assert_eq!(request.request_capabilities.as_ref().map(Vec::len), Some(6));
+
+    // Loading decodes the wire bytes again, including the Request against
+    // its recorded source capabilities.
+    let json = serde_json::to_value(&decoded).unwrap();
+    let reloaded: Vec<DecodedPdEvent> = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&reloaded).unwrap(), json);
+    assert_eq!(
+        json[2]["Message"]["message_type"],
+        serde_json::json!({ "Data": "Request" })
+    );
+
+    // Decode failures record the kind of error and reproduce it on loading.
+    let failure = decoder.decode_event(&PdEvent {
+        timestamp: request.timestamp,
+        data: PdEventData::PdMessage {
+            sop: 0,
+            wire_data: vec![0x01],
+        },
+    });
+    let json = serde_json::to_value(&failure).unwrap();
+    assert_eq!(json["Error"]["error"], "Parse");
+    let reloaded: DecodedPdEvent = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&reloaded).unwrap(), json);
+
+    let mut tampered = json;
+    tampered["Error"]["error"] = "TruncatedChunk".into();
+    assert!(serde_json::from_value::<DecodedPdEvent>(tampered).is_err());
+}
+
+#[cfg(feature = "usbpd")]
+#[test]
+fn request_decode_failures_reload_against_their_capabilities() {
+    use km003c_lib::{DecodedPdEvent, PdEvent, PdEventData, PdSessionDecoder};
+
+    let stream = parse_packet(PD_NEGOTIATION).get_pd_events().unwrap().clone();
+    let mut decoder = PdSessionDecoder::new();
+    let decoded = stream
+        .events
+        .iter()
+        .map(|event| decoder.decode_event(event))
+        .collect::<Vec<_>>();
+    let DecodedPdEvent::Message(request) = &decoded[2] else {
+        panic!("expected a Request message");
+    };
+
+    // The Request header announces one data object, but only half of it arrived.
+    let failure = decoder.decode_event(&PdEvent {
+        timestamp: request.timestamp,
+        data: PdEventData::PdMessage {
+            sop: 0,
+            wire_data: request.wire_data[..4].to_vec(),
+        },
+    });
+    let DecodedPdEvent::Error(error) = &failure else {
+        panic!("expected a decode failure");
+    };
+    assert_eq!(error.request_capabilities, request.request_capabilities);
+
+    let json = serde_json::to_value(&failure).unwrap();
+    let capabilities = serde_json::to_value(&request.request_capabilities).unwrap();
+    assert_eq!(json["Error"]["request_capabilities"], capabilities);
+    let reloaded: DecodedPdEvent = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&reloaded).unwrap(), json);
+}
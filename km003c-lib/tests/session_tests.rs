@@ -0,0 +1,289 @@
+//! Append-only session files
+#![cfg(feature = "session")]
+
+mod common;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use common::*;
+use km003c_lib::session::SESSION_CHUNK_SIZE;
+use km003c_lib::uom::si::electric_current::milliampere;
+use km003c_lib::uom::si::electric_potential::millivolt;
+use km003c_lib::uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use km003c_lib::uom::si::time::millisecond;
+use km003c_lib::{
+    AdcQueueRawData, DeviceInfo, DeviceState, GraphSampleRate, HardwareId, PdEvent, PdEventData, PdEventStream,
+    PdStatus, PdTrace, SessionReader, SessionRecord, SessionRecordKind, SessionWriter, Settings,
+};
+
+fn test_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("km003c-session-{name}-{}.km003c", std::process::id()))
+}
+
+fn adc_queue(first_sequence: u16) -> SessionRecord {
+    let sample = |offset: u16| {
+        let mut bytes = vec![0; 20];
+        bytes[..2].copy_from_slice(&(first_sequence + offset).to_le_bytes());
+        bytes[4..8].copy_from_slice(&5_000_000i32.to_le_bytes());
+        bytes[8..12].copy_from_slice(&(-1_000_000i32).to_le_bytes());
+        bytes
+    };
+    SessionRecord::AdcQueue {
+        rate: GraphSampleRate::Sps1000,
+        data: AdcQueueRawData::from_bytes(&[sample(0), sample(1)].concat()).unwrap(),
+    }
+}
+
+fn pd_events() -> PdEventStream {
+    PdEventStream {
+        preamble: PdStatus {
+            timestamp: Time::new::<millisecond>(1_238_998.0),
+            vbus: ElectricPotential::new::<millivolt>(5_000.0),
+            ibus: ElectricCurrent::new::<milliampere>(-120.0),
+            cc1: ElectricPotential::new::<millivolt>(1_650.0),
+            cc2: ElectricPotential::new::<millivolt>(0.0),
+        },
+        events: vec![
+            PdEvent {
+                timestamp: Time::new::<millisecond>(1_239_000.0),
+                data: PdEventData::Connect(()),
+            },
+            PdEvent {
+                timestamp: Time::new::<millisecond>(1_239_010.0),
+                data: PdEventData::PdMessage {
+                    sop: 0,
+                    wire_data: hex::decode("4102").unwrap(),
+                },
+            },
+        ],
+    }
+}
+
+fn device_state() -> DeviceState {
+    DeviceState {
+        info: DeviceInfo {
+            model: "KM003C".to_string(),
+            hw_version: "2.1".to_string(),
+            fw_version: "1.9.9".to_string(),
+            serial_id: "007965".to_string(),
+            ..DeviceInfo::default()
+        },
+        hardware_id: HardwareId::from_bytes(*b"071KBP\x0d\xff\x34\x12\xff\xff"),
+        auth_level: 1,
+        adcqueue_enabled: true,
+    }
+}
+
+fn settings() -> Settings {
+    Settings::from_bytes(
+        &hex::decode(concat!(
+            "610150f800000000102741ff00000000",
+            "fffffffffffffffffffffffffaffffff",
+            "fafffffffafffffffafffffffaffffff",
+            "ed4a0f00ed4a0f00ed4a0f00ed4a0f00",
+            "ed4a0f00ed4a0f00ed4a0f00ed4a0f00",
+            "ed4a0f00ed4a0f005e000000268bb83a",
+            "43000000000000000000000000000000",
+            "504f5745522d5a000000000000000000",
+            "00000000000000000000000000000000",
+            "00000000000000000000000000000000",
+            "00000000000000000000000000000000",
+            "207d05d2"
+        ))
+        .unwrap(),
+    )
+    .unwrap()
+}
+
+fn adc() -> SessionRecord {
+    let packet = Packet::try_from(RawPacket::try_from(hex_to_bytes(EXTENDED_ADC_DATA)).unwrap()).unwrap();
+    SessionRecord::Adc(*packet.get_adc().unwrap())
+}
+
+#[test]
+fn stores_every_record_kind() {
+    let path = test_path("kinds");
+    let trace = PdTrace::from_bytes(&[10, 1, 100, 0, 0, 0, 4, 105, 0, 0, 0, 5, 0x82, 110, 0, 0, 0]).unwrap();
+    let records = [
+        SessionRecord::DeviceState(device_state()),
+        SessionRecord::Settings(settings()),
+        adc_queue(100),
+        SessionRecord::PdEvents(pd_events()),
+        SessionRecord::PdTrace(trace.clone()),
+        adc(),
+        SessionRecord::Marker("charger plugged in".to_string()),
+        SessionRecord::Unknown {
+            kind: 0xf0,
+            data: vec![1, 2, 3],
+        },
+    ];
+    let mut writer = SessionWriter::create(&path).unwrap();
+    for (index, record) in records.iter().enumerate() {
+        writer.write(index as u64 * 1_000, record).unwrap();
+    }
+    assert_eq!(writer.records(), 8);
+    writer.finish().unwrap();
+
+    let mut reader = SessionReader::open(&path).unwrap();
+    let entries = reader.entries().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(reader.chunks().len(), 1);
+    assert_eq!(reader.torn_bytes(), 0);
+    assert_eq!(entries.len(), 8);
+    assert_eq!(entries[7].elapsed_us, 7_000);
+    let kinds = entries.iter().map(|entry| entry.record.kind()).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            SessionRecordKind::DeviceState,
+            SessionRecordKind::Settings,
+            SessionRecordKind::AdcQueue,
+            SessionRecordKind::PdEvents,
+            SessionRecordKind::PdTrace,
+            SessionRecordKind::Adc,
+            SessionRecordKind::Marker,
+            SessionRecordKind::Unknown(0xf0),
+        ]
+    );
+    for (entry, record) in entries.iter().zip(&records) {
+        assert_eq!(entry.record.to_bytes().unwrap(), record.to_bytes().unwrap());
+    }
+
+    let SessionRecord::DeviceState(state) = &entries[0].record else {
+        panic!("expected device state");
+    };
+    assert_eq!(state.info.serial_id, "007965");
+    assert_eq!(state.hardware_id.device_id(), 0x1234);
+    assert!(state.adcqueue_enabled);
+    assert!(matches!(&entries[1].record, SessionRecord::Settings(stored) if *stored == settings()));
+    assert!(matches!(&entries[3].record, SessionRecord::PdEvents(stream) if *stream == pd_events()));
+    assert!(matches!(&entries[4].record, SessionRecord::PdTrace(stored) if *stored == trace));
+    let SessionRecord::AdcQueue { rate, data } = &entries[2].record else {
+        panic!("expected AdcQueue samples");
+    };
+    assert_eq!(*rate, GraphSampleRate::Sps1000);
+    assert_eq!(data.sequence_range(), Some((100, 101)));
+}
+
+#[test]
+fn reads_time_ranges_from_the_overlapping_chunks_only() {
+    let path = test_path("ranges");
+    let mut writer = SessionWriter::create(&path).unwrap();
+    for second in 0..5u64 {
+        for sample in 0..10 {
+            writer
+                .write(second * 1_000_000 + sample * 1_000, &adc_queue(sample as u16))
+                .unwrap();
+        }
+        writer.flush().unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut reader = SessionReader::open(&path).unwrap();
+    assert_eq!(reader.chunks().len(), 5);
+    assert_eq!(reader.record_count(), 50);
+    assert_eq!(reader.chunks()[3].first_elapsed_us, 3_000_000);
+    assert_eq!(reader.chunks()[3].last_elapsed_us, 3_009_000);
+
+    let second = reader.entries_between(2_000_000..3_000_000).unwrap();
+    assert_eq!(second.len(), 10);
+    assert!(second.iter().all(|entry| entry.elapsed_us / 1_000_000 == 2));
+    assert_eq!(reader.entries_between(4_005_000..).unwrap().len(), 5);
+    assert_eq!(reader.read_chunk(0).unwrap().len(), 10);
+    assert!(reader.read_chunk(5).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn large_sessions_are_split_into_chunks() {
+    let path = test_path("large");
+    let mut writer = SessionWriter::create(&path).unwrap();
+    let record = adc_queue(0);
+    let mut records = 0;
+    while writer.chunks() == 0 {
+        writer.write(records as u64, &record).unwrap();
+        records += 1;
+    }
+    assert_eq!(
+        records,
+        SESSION_CHUNK_SIZE.div_ceil(13 + record.to_bytes().unwrap().len())
+    );
+    for _ in 0..10 {
+        writer.write(records as u64, &record).unwrap();
+        records += 1;
+    }
+    drop(writer);
+
+    let mut reader = SessionReader::open(&path).unwrap();
+    assert_eq!(reader.chunks().len(), 2);
+    assert_eq!(reader.entries().unwrap().len(), records);
+    assert!(std::fs::metadata(&path).unwrap().len() < (records * 20) as u64);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn abrupt_termination_loses_at_most_the_torn_chunk() {
+    let path = test_path("torn");
+    let mut writer = SessionWriter::create(&path).unwrap();
+    writer.write(0, &SessionRecord::Marker("first".to_string())).unwrap();
+    writer.flush().unwrap();
+    writer
+        .write(1_000, &SessionRecord::Marker("second".to_string()))
+        .unwrap();
+    writer.finish().unwrap();
+    let complete = std::fs::read(&path).unwrap();
+
+    // A crash while writing the second chunk leaves only part of it behind.
+    std::fs::write(&path, &complete[..complete.len() - 3]).unwrap();
+    let mut reader = SessionReader::open(&path).unwrap();
+    assert_eq!(reader.chunks().len(), 1);
+    assert!(reader.torn_bytes() > 0);
+    assert_eq!(reader.entries().unwrap().len(), 1);
+
+    // A fully sized chunk whose data never reached the disk fails its checksum.
+    let mut corrupted = complete.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0xff;
+    std::fs::write(&path, &corrupted).unwrap();
+    let reader = SessionReader::open(&path).unwrap();
+    assert_eq!(reader.chunks().len(), 1);
+
+    // Appending cuts off the torn chunk and continues after the last good one.
+    let mut writer = SessionWriter::append(&path).unwrap();
+    assert_eq!(writer.records(), 1);
+    writer
+        .write(2_000, &SessionRecord::Marker("third".to_string()))
+        .unwrap();
+    writer.finish().unwrap();
+    let mut reader = SessionReader::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let markers = reader
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|entry| match entry.record {
+            SessionRecord::Marker(label) => label,
+            other => panic!("unexpected record {other:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(markers, ["first", "third"]);
+    assert_eq!(reader.torn_bytes(), 0);
+}
+
+#[test]
+fn rejects_files_that_are_not_sessions() {
+    let path = test_path("foreign");
+    std::fs::write(&path, b"KM003CBK\x01\x00\x00\x00").unwrap();
+    assert!(matches!(SessionReader::open(&path), Err(KMError::ParseError { .. })));
+
+    let mut file = OpenOptions::new().write(true).truncate(true).open(&path).unwrap();
+    file.write_all(b"KM003CSS\x02\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00")
+        .unwrap();
+    drop(file);
+    let error = SessionReader::open(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert!(error.to_string().contains("Unsupported session file version 2"));
+}
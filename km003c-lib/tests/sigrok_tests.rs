@@ -0,0 +1,183 @@
+//! Sigrok session archives exported from session files
+#![cfg(feature = "sigrok")]
+
+use std::io::{Cursor, Read};
+
+use km003c_lib::sigrok::{SIGROK_ANALOG_CHANNELS, SIGROK_LOGIC_CHANNELS};
+use km003c_lib::uom::si::electric_current::milliampere;
+use km003c_lib::uom::si::electric_potential::millivolt;
+use km003c_lib::uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use km003c_lib::uom::si::time::{millisecond, second};
+use km003c_lib::{
+    AdcQueueRawData, GraphSampleRate, PdEvent, PdEventData, PdEventStream, PdStatus, PdTrace, PdTraceStateEvent,
+    PdTypeCState, SessionEntry, SessionRecord, SigrokExport,
+};
+
+const CONNECTED: u8 = 1 << 0;
+const ATTACHED: u8 = 1 << 1;
+const PD_SOP_PRIME: u8 = 1 << 3;
+const GAP: u8 = 1 << 5;
+
+fn adc_queue(elapsed_us: u64, sequences: impl IntoIterator<Item = u16>) -> SessionEntry {
+    let bytes = sequences
+        .into_iter()
+        .flat_map(|sequence| {
+            let mut sample = vec![0; 20];
+            sample[..2].copy_from_slice(&sequence.to_le_bytes());
+            sample[4..8].copy_from_slice(&5_000_000i32.to_le_bytes());
+            sample[8..12].copy_from_slice(&(-2_000_000i32).to_le_bytes());
+            sample[12..14].copy_from_slice(&1_650u16.to_le_bytes());
+            sample
+        })
+        .collect::<Vec<_>>();
+    SessionEntry {
+        elapsed_us,
+        record: SessionRecord::AdcQueue {
+            rate: GraphSampleRate::Sps1000,
+            data: AdcQueueRawData::from_bytes(&bytes).unwrap(),
+        },
+    }
+}
+
+fn pd_events(elapsed_us: u64, preamble_ms: f64, events: Vec<(f64, PdEventData)>) -> SessionEntry {
+    SessionEntry {
+        elapsed_us,
+        record: SessionRecord::PdEvents(PdEventStream {
+            preamble: PdStatus {
+                timestamp: Time::new::<millisecond>(preamble_ms),
+                vbus: ElectricPotential::new::<millivolt>(5_000.0),
+                ibus: ElectricCurrent::new::<milliampere>(0.0),
+                cc1: ElectricPotential::new::<millivolt>(1_650.0),
+                cc2: ElectricPotential::new::<millivolt>(0.0),
+            },
+            events: events
+                .into_iter()
+                .map(|(timestamp_ms, data)| PdEvent {
+                    timestamp: Time::new::<millisecond>(timestamp_ms),
+                    data,
+                })
+                .collect(),
+        }),
+    }
+}
+
+fn trace(elapsed_us: u64, state: PdTypeCState) -> SessionEntry {
+    SessionEntry {
+        elapsed_us,
+        record: SessionRecord::PdTrace(PdTrace {
+            state_events: vec![PdTraceStateEvent {
+                state,
+                timestamp: Time::new::<second>(500.0),
+            }],
+            protocol_events: Vec::new(),
+        }),
+    }
+}
+
+#[test]
+fn places_samples_on_the_rate_grid_and_marks_gaps() {
+    // Samples 10-12 were lost between the two records.
+    let entries = [adc_queue(10_000, 0..10), adc_queue(25_000, 13..20)];
+    let export = SigrokExport::from_session(&entries).unwrap();
+
+    assert_eq!(export.sample_rate(), GraphSampleRate::Sps1000);
+    assert_eq!(export.len(), 20);
+    assert_eq!(export.gap_samples(), 3);
+    let vbus = export.analog(0).collect::<Vec<_>>();
+    assert_eq!(vbus[0], 5.0);
+    assert!(vbus[10..13].iter().all(|value| value.is_nan()));
+    assert_eq!(vbus[13], 5.0);
+    assert_eq!(export.analog(1).next(), Some(-2.0));
+    assert_eq!(export.analog(2).next(), Some(-10.0));
+    assert_eq!(export.analog(3).next(), Some(1.65));
+    assert_eq!(export.logic()[9], 0);
+    assert_eq!(export.logic()[10], GAP);
+    assert_eq!(export.logic()[13], 0);
+}
+
+#[test]
+fn marks_connection_and_pd_messages_on_the_sample_grid() {
+    // The first record ends 9 ms after the grid starts at 1 ms host time.
+    let entries = [
+        pd_events(
+            5_000,
+            100_000.0,
+            vec![
+                (100_000.0, PdEventData::Connect(())),
+                (
+                    100_003.0,
+                    PdEventData::PdMessage {
+                        sop: 1,
+                        wire_data: vec![0x41, 0x02],
+                    },
+                ),
+                (100_010.0, PdEventData::Disconnect(())),
+            ],
+        ),
+        adc_queue(10_000, 0..10),
+        adc_queue(20_000, 10..20),
+    ];
+    let export = SigrokExport::from_session(&entries).unwrap();
+    let logic = export.logic();
+
+    assert_eq!(logic[3] & CONNECTED, 0);
+    assert!(logic[4..14].iter().all(|sample| sample & CONNECTED != 0));
+    assert_eq!(logic[14] & CONNECTED, 0);
+    assert_eq!(logic[7] & PD_SOP_PRIME, PD_SOP_PRIME);
+    assert_eq!(logic.iter().filter(|sample| *sample & PD_SOP_PRIME != 0).count(), 1);
+}
+
+#[test]
+fn places_trace_states_at_their_read_time_without_pd_traffic() {
+    let entries = [
+        trace(3_000, PdTypeCState::AttachedSink),
+        adc_queue(10_000, 0..10),
+        trace(15_000, PdTypeCState::UnattachedSource),
+        adc_queue(20_000, 10..20),
+    ];
+    let logic = SigrokExport::from_session(&entries).unwrap().logic().to_vec();
+
+    assert_eq!(logic[1] & ATTACHED, 0);
+    assert!(logic[2..14].iter().all(|sample| sample & ATTACHED != 0));
+    assert_eq!(logic[14] & ATTACHED, 0);
+}
+
+#[test]
+fn rejects_sessions_without_a_single_sample_rate() {
+    assert!(SigrokExport::from_session(&[trace(0, PdTypeCState::AttachedSink)]).is_err());
+
+    let mut changed = adc_queue(20_000, 10..20);
+    if let SessionRecord::AdcQueue { rate, .. } = &mut changed.record {
+        *rate = GraphSampleRate::Sps50;
+    }
+    let error = SigrokExport::from_session(&[adc_queue(10_000, 0..10), changed]).unwrap_err();
+    assert!(error.to_string().contains("from 1000 SPS to 50 SPS"));
+}
+
+#[test]
+fn writes_a_sigrok_session_archive() {
+    let export = SigrokExport::from_session(&[adc_queue(10_000, 0..10), adc_queue(25_000, 13..20)]).unwrap();
+    let mut archive = Cursor::new(Vec::new());
+    export.write(&mut archive).unwrap();
+
+    let mut zip = zip::ZipArchive::new(archive).unwrap();
+    let mut read = |name: &str| {
+        let mut bytes = Vec::new();
+        zip.by_name(name).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    };
+    assert_eq!(read("version"), b"2");
+    let metadata = String::from_utf8(read("metadata")).unwrap();
+    assert!(metadata.contains("samplerate=1 kHz\n"));
+    assert!(metadata.contains(&format!("total probes={}\n", SIGROK_LOGIC_CHANNELS.len())));
+    assert!(metadata.contains("probe6=Gap\n"));
+    assert!(metadata.contains("analog7=VBUS\n"));
+    assert!(metadata.contains("analog13=D-\n"));
+    assert_eq!(read("logic-1-1"), export.logic());
+
+    let ibus = read("analog-1-8-1");
+    assert_eq!(ibus.len(), 20 * 4);
+    assert_eq!(f32::from_le_bytes(ibus[..4].try_into().unwrap()), -2.0);
+    assert!(f32::from_le_bytes(ibus[40..44].try_into().unwrap()).is_nan());
+    assert_eq!(zip.len(), 3 + SIGROK_ANALOG_CHANNELS.len());
+}
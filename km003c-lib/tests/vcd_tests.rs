@@ -0,0 +1,200 @@
+//! VCD waveforms exported from session files
+#![cfg(feature = "session")]
+
+use km003c_lib::uom::si::electric_current::milliampere;
+use km003c_lib::uom::si::electric_potential::millivolt;
+use km003c_lib::uom::si::f64::{ElectricCurrent, ElectricPotential, Time};
+use km003c_lib::uom::si::time::{millisecond, second};
+use km003c_lib::{
+    AdcQueueRawData, GraphSampleRate, PdEvent, PdEventData, PdEventStream, PdStatus, PdTrace, PdTraceStateEvent,
+    PdTypeCState, SessionEntry, SessionRecord, VcdExport, VcdOptions,
+};
+
+fn adc_queue(elapsed_us: u64, samples: impl IntoIterator<Item = (u16, i32)>) -> SessionEntry {
+    let bytes = samples
+        .into_iter()
+        .flat_map(|(sequence, vbus_uv)| {
+            let mut sample = vec![0; 20];
+            sample[..2].copy_from_slice(&sequence.to_le_bytes());
+            sample[4..8].copy_from_slice(&vbus_uv.to_le_bytes());
+            sample
+        })
+        .collect::<Vec<_>>();
+    SessionEntry {
+        elapsed_us,
+        record: SessionRecord::AdcQueue {
+            rate: GraphSampleRate::Sps1000,
+            data: AdcQueueRawData::from_bytes(&bytes).unwrap(),
+        },
+    }
+}
+
+fn pd_events(elapsed_us: u64, preamble_ms: f64, events: Vec<(f64, PdEventData)>) -> SessionEntry {
+    SessionEntry {
+        elapsed_us,
+        record: SessionRecord::PdEvents(PdEventStream {
+            preamble: PdStatus {
+                timestamp: Time::new::<millisecond>(preamble_ms),
+                vbus: ElectricPotential::new::<millivolt>(5_000.0),
+                ibus: ElectricCurrent::new::<milliampere>(0.0),
+                cc1: ElectricPotential::new::<millivolt>(1_650.0),
+                cc2: ElectricPotential::new::<millivolt>(0.0),
+            },
+            events: events
+                .into_iter()
+                .map(|(timestamp_ms, data)| PdEvent {
+                    timestamp: Time::new::<millisecond>(timestamp_ms),
+                    data,
+                })
+                .collect(),
+        }),
+    }
+}
+
+fn message(sop: u8, wire_data: &[u8]) -> PdEventData {
+    PdEventData::PdMessage {
+        sop,
+        wire_data: wire_data.to_vec(),
+    }
+}
+
+fn dump(export: &VcdExport) -> String {
+    let mut bytes = Vec::new();
+    export.write(&mut bytes).unwrap();
+    String::from_utf8(bytes).unwrap()
+}
+
+/// Identifier of the variable named `name` inside `scope`.
+fn identifier(vcd: &str, scope: &str, name: &str) -> String {
+    let mut current = Vec::new();
+    for line in vcd.lines() {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            ["$scope", _, scope_name, "$end"] => current.push(*scope_name),
+            ["$upscope", "$end"] => {
+                current.pop();
+            }
+            ["$var", _, _, id, var_name, "$end"] if *var_name == name && current.last() == Some(&scope) => {
+                return id.to_string();
+            }
+            _ => {}
+        }
+    }
+    panic!("no variable {scope}.{name}");
+}
+
+/// `(time, value)` changes of one variable after the initial values.
+fn changes(vcd: &str, id: &str) -> Vec<(u64, String)> {
+    let body = vcd.split_once("$dumpvars").unwrap().1.split_once("$end").unwrap().1;
+    let mut time = 0;
+    let mut changes = Vec::new();
+    for line in body.lines() {
+        if let Some(timestamp) = line.strip_prefix('#') {
+            time = timestamp.parse().unwrap();
+        } else if let Some((value, line_id)) = line.rsplit_once(' ') {
+            if line_id == id {
+                changes.push((time, value.to_string()));
+            }
+        } else if line.len() > id.len() && line.ends_with(id) {
+            changes.push((time, line[..line.len() - id.len()].to_string()));
+        }
+    }
+    changes
+}
+
+#[test]
+fn dumps_connection_and_messages_per_sop_in_device_time() {
+    let entries = [pd_events(
+        5_000,
+        100_000.0,
+        vec![
+            (100_000.0, PdEventData::Connect(())),
+            (100_003.5, message(0, &[0x41, 0x02])),
+            (100_004.0, message(1, &[0x43, 0x02])),
+            (100_005.0, message(0, &[0x46, 0x02])),
+            (100_010.0, PdEventData::Disconnect(())),
+        ],
+    )];
+    let export = VcdExport::from_session(&entries, VcdOptions::default());
+    let vcd = dump(&export);
+
+    assert!(vcd.contains("$timescale 1 us $end"));
+    assert_eq!(export.end_time_us(), 100_010_000);
+    let connected = identifier(&vcd, "km003c", "connected");
+    assert_eq!(
+        changes(&vcd, &connected),
+        [(100_000_000, "1".to_string()), (100_010_000, "0".to_string())]
+    );
+    let sop = changes(&vcd, &identifier(&vcd, "sop", "message"));
+    assert_eq!(
+        sop,
+        [
+            (100_003_500, "sGoodCrc".to_string()),
+            (100_005_000, "sPsRdy".to_string())
+        ]
+    );
+    let sop_prime = changes(&vcd, &identifier(&vcd, "sop_prime", "message"));
+    assert_eq!(sop_prime, [(100_004_000, "sAccept".to_string())]);
+    let counts = changes(&vcd, &identifier(&vcd, "sop", "messages"));
+    assert_eq!(counts.last().unwrap().1, "b10");
+    assert!(changes(&vcd, &identifier(&vcd, "sop_double_prime", "messages")).is_empty());
+}
+
+#[test]
+fn dumps_type_c_states_on_the_trace_clock_without_pd_traffic() {
+    let trace = |state, uptime_s| SessionEntry {
+        elapsed_us: 0,
+        record: SessionRecord::PdTrace(PdTrace {
+            state_events: vec![PdTraceStateEvent {
+                state,
+                timestamp: Time::new::<second>(uptime_s),
+            }],
+            protocol_events: Vec::new(),
+        }),
+    };
+    let export = VcdExport::from_session(
+        &[
+            trace(PdTypeCState::AttachWaitSink, 2.0),
+            trace(PdTypeCState::AttachedSink, 2.5),
+        ],
+        VcdOptions::default(),
+    );
+    let vcd = dump(&export);
+    let state = identifier(&vcd, "km003c", "typec_state");
+    let name = identifier(&vcd, "km003c", "typec_state_name");
+    assert_eq!(
+        changes(&vcd, &state),
+        [
+            (2_000_000, format!("b{:b}", u8::from(PdTypeCState::AttachWaitSink))),
+            (2_500_000, format!("b{:b}", u8::from(PdTypeCState::AttachedSink))),
+        ]
+    );
+    assert_eq!(changes(&vcd, &name)[1], (2_500_000, "sAttachedSink".to_string()));
+}
+
+#[test]
+fn quantises_vbus_and_emits_it_only_on_change() {
+    let samples = [5_000_000, 5_004_000, 5_012_000, 5_013_000, 9_000_000].into_iter();
+    let entries = [
+        pd_events(1_000, 50.0, vec![(50.0, PdEventData::Connect(()))]),
+        adc_queue(10_000, (0..).zip(samples)),
+    ];
+    let options = VcdOptions {
+        vbus_step: Some(ElectricPotential::new::<millivolt>(10.0)),
+        ibus_step: None,
+    };
+    let vcd = dump(&VcdExport::from_session(&entries, options));
+
+    // Read at 10 ms host time, 9 ms after a preamble captured at 50 ms device time.
+    let vbus = changes(&vcd, &identifier(&vcd, "km003c", "vbus"))
+        .into_iter()
+        .map(|(time, value)| (time, value[1..].parse::<f64>().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(vbus.len(), 3);
+    assert_eq!(vbus[0].0, 55_000);
+    assert!((vbus[0].1 - 5.0).abs() < 1e-9);
+    assert_eq!(vbus[1].0, 57_000);
+    assert!((vbus[1].1 - 5.01).abs() < 1e-9);
+    assert_eq!(vbus[2].0, 59_000);
+    assert!(!vcd.contains(" ibus "));
+}
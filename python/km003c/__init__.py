@@ -1,7 +1,9 @@
 """
 KM003C Python bindings
 
-This package provides Python bindings for parsing KM003C USB power meter data.
+This package provides Python bindings for parsing KM003C USB power meter data
+and for controlling a live meter. ``km003c.aio`` offers the same device API for
+asyncio code.
 """
 
 from .km003c_lib import (
@@ -16,6 +18,20 @@ from .km003c_lib import (
     PdEvent,
     PdEventStream,
     LogicalPacket,
+    PdTrace,
+    PdTraceStateEvent,
+    PdTraceProtocolEvent,
+    LogMetadata,
+    OfflineLog,
+    OfflineLogSample,
+    # Device control
+    AttachedDevice,
+    KM003C,
+    GraphStream,
+    PdEventMonitor,
+    PdSessionDecoder,
+    PdEventCategory,
+    list_devices,
     # Parsing functions
     parse_raw_adc_data,
     parse_packet,
@@ -61,6 +77,20 @@ __all__ = [
     "PdEvent",
     "PdEventStream",
     "LogicalPacket",
+    "PdTrace",
+    "PdTraceStateEvent",
+    "PdTraceProtocolEvent",
+    "LogMetadata",
+    "OfflineLog",
+    "OfflineLogSample",
+    # Device control
+    "AttachedDevice",
+    "KM003C",
+    "GraphStream",
+    "PdEventMonitor",
+    "PdSessionDecoder",
+    "PdEventCategory",
+    "list_devices",
     # Parsing functions
     "parse_raw_adc_data",
     "parse_packet",
@@ -0,0 +1,173 @@
+"""
+asyncio interface to a live KM003C
+
+Every call runs the blocking :class:`km003c.KM003C` method in the default
+executor. The native code releases the GIL while it waits for the meter, so
+the event loop keeps running.
+
+Example::
+
+    async with await AsyncKM003C.connect() as meter:
+        print(await meter.request_adc_data())
+        async with meter.stream(RATE_1000_SPS, duration=5.0) as samples:
+            async for sample in samples:
+                print(sample.vbus_v, sample.ibus_a)
+"""
+
+import asyncio
+import functools
+
+from .km003c_lib import KM003C, RATE_50_SPS
+
+__all__ = ["AsyncKM003C", "AsyncCapture"]
+
+
+async def _run(function, *args, **kwargs):
+    loop = asyncio.get_running_loop()
+    return await loop.run_in_executor(None, functools.partial(function, *args, **kwargs))
+
+
+class AsyncCapture:
+    """Async iterator over a :class:`km003c.GraphStream` or :class:`km003c.PdEventMonitor`.
+
+    The capture starts on entering an ``async with`` block, or on the first
+    iteration, and stops when the block exits, like ``with meter.stream(...)``
+    in the blocking API.
+    """
+
+    def __init__(self, start, *args):
+        self._start = functools.partial(start, *args)
+        self._iterator = None
+
+    async def __aenter__(self):
+        await self._open()
+        return self
+
+    async def __aexit__(self, exc_type, exc_value, traceback):
+        await self.close()
+
+    def __aiter__(self):
+        return self
+
+    async def __anext__(self):
+        iterator = await self._open()
+        item = await _run(next, iterator, None)
+        if item is None:
+            raise StopAsyncIteration
+        return item
+
+    async def close(self):
+        """Stop the capture; a capture that never started is left alone."""
+        if self._iterator is not None:
+            await _run(self._iterator.close)
+
+    async def _open(self):
+        if self._iterator is None:
+            self._iterator = await _run(self._start)
+        return self._iterator
+
+
+class AsyncKM003C:
+    """Awaitable wrapper around a connected :class:`km003c.KM003C`.
+
+    Create it with :meth:`connect`, or with :meth:`wrap` for a meter that is
+    already open. ``is_full_mode``, ``adcqueue_enabled`` and ``device_info``
+    are read once, off the event loop, when the wrapper is created.
+    """
+
+    def __init__(self, device, *, is_full_mode, adcqueue_enabled, device_info):
+        self.device = device
+        self._is_full_mode = is_full_mode
+        self._adcqueue_enabled = adcqueue_enabled
+        self._device_info = device_info
+
+    @classmethod
+    async def connect(cls, interface="vendor", *, device_index=0, reset=True, remote=None, bridge_key=None):
+        """Open a meter; takes the same arguments as :class:`km003c.KM003C`."""
+        device = await _run(
+            KM003C,
+            interface,
+            device_index=device_index,
+            reset=reset,
+            remote=remote,
+            bridge_key=bridge_key,
+        )
+        return await cls.wrap(device)
+
+    @classmethod
+    async def wrap(cls, device):
+        """Wrap an open :class:`km003c.KM003C`."""
+
+        def state():
+            return {
+                "is_full_mode": device.is_full_mode,
+                "adcqueue_enabled": device.adcqueue_enabled,
+                "device_info": device.device_info,
+            }
+
+        return cls(device, **await _run(state))
+
+    @property
+    def is_full_mode(self):
+        return self._is_full_mode
+
+    @property
+    def adcqueue_enabled(self):
+        return self._adcqueue_enabled
+
+    @property
+    def device_info(self):
+        return self._device_info
+
+    async def request_adc_data(self):
+        return await _run(self.device.request_adc_data)
+
+    async def request_pd_data(self):
+        return await _run(self.device.request_pd_data)
+
+    async def request_settings(self):
+        return await _run(self.device.request_settings)
+
+    async def request_pd_trace(self):
+        return await _run(self.device.request_pd_trace)
+
+    async def request_log_metadata(self):
+        return await _run(self.device.request_log_metadata)
+
+    async def download_offline_log(self, metadata):
+        return await _run(self.device.download_offline_log, metadata)
+
+    async def start_graph_mode(self, rate_index):
+        await _run(self.device.start_graph_mode, rate_index)
+
+    async def stop_graph_mode(self):
+        await _run(self.device.stop_graph_mode)
+
+    async def read_graph_samples(self):
+        return await _run(self.device.read_graph_samples)
+
+    async def enable_pd_monitor(self):
+        await _run(self.device.enable_pd_monitor)
+
+    async def disable_pd_monitor(self):
+        await _run(self.device.disable_pd_monitor)
+
+    async def read_pd_events(self):
+        return await _run(self.device.read_pd_events)
+
+    def stream(self, rate_index=RATE_50_SPS, duration=None):
+        """AdcQueueSamples from graph mode; graph mode stops when the ``async with`` block exits."""
+        return AsyncCapture(self.device.stream, rate_index, duration)
+
+    def pd_events(self, duration=None):
+        """PdEvents from the PD monitor; monitoring stops when the ``async with`` block exits."""
+        return AsyncCapture(self.device.pd_events, duration)
+
+    async def close(self):
+        await _run(self.device.close)
+
+    async def __aenter__(self):
+        return self
+
+    async def __aexit__(self, exc_type, exc_value, traceback):
+        await self.close()
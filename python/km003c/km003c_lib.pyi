@@ -1,6 +1,7 @@
-"""Types for the native KM003C protocol parser extension."""
+"""Types for the native KM003C protocol parser and device control extension."""
 
-from typing import Any, Dict, List, Optional, Tuple
+from types import TracebackType
+from typing import Any, ClassVar, Dict, Iterator, List, Optional, Tuple, Type
 
 VID: int
 PID: int
@@ -128,6 +129,64 @@ class LogicalPacket:
     size: int
     payload: List[int]
 
+class PdTraceStateEvent:
+    @property
+    def state_code(self) -> int: ...
+    @property
+    def state_name(self) -> str: ...
+    @property
+    def timestamp_seconds(self) -> float: ...
+
+class PdTraceProtocolEvent:
+    @property
+    def code(self) -> int: ...
+    @property
+    def event_name(self) -> str: ...
+    @property
+    def timestamp_seconds(self) -> float: ...
+
+class PdTrace:
+    @property
+    def state_events(self) -> List[PdTraceStateEvent]: ...
+    @property
+    def protocol_events(self) -> List[PdTraceProtocolEvent]: ...
+
+class LogMetadata:
+    @property
+    def filename(self) -> str: ...
+    @property
+    def sample_count(self) -> int: ...
+    @property
+    def interval_s(self) -> float: ...
+    @property
+    def recorded_duration_s(self) -> float: ...
+    @property
+    def final_charge_ah(self) -> float: ...
+    @property
+    def final_energy_wh(self) -> float: ...
+    def __repr__(self) -> str: ...
+
+class OfflineLogSample:
+    @property
+    def voltage_v(self) -> float: ...
+    @property
+    def current_a(self) -> float: ...
+    @property
+    def power_w(self) -> float: ...
+    @property
+    def charge_ah(self) -> float: ...
+    @property
+    def energy_wh(self) -> float: ...
+    def __repr__(self) -> str: ...
+
+class OfflineLog:
+    @property
+    def metadata(self) -> LogMetadata: ...
+    @property
+    def samples(self) -> List[OfflineLogSample]: ...
+    def __len__(self) -> int: ...
+    def __repr__(self) -> str: ...
+
 # PyO3 converts the Rust enums to one-key dictionaries whose key is the
 # active variant, for example {"Accept": {"id": 3}}.
 Packet = Dict[str, Any]
@@ -139,3 +198,91 @@ def parse_raw_packet(data: bytes) -> RawPacket: ...
 def parse_raw_adc_data(data: bytes) -> AdcData: ...
 def get_sample_rates() -> List[SampleRate]: ...
 def create_packet(packet_type: int, transaction_id: int, data: int) -> bytes: ...
+
+class AttachedDevice:
+    index: int
+    bus_id: str
+    address: int
+
+def list_devices() -> List[AttachedDevice]: ...
+
+class GraphStream(Iterator[AdcQueueSample]):
+    @property
+    def rate_index(self) -> int: ...
+    def __iter__(self) -> "GraphStream": ...
+    def __next__(self) -> AdcQueueSample: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> "GraphStream": ...
+    def __exit__(
+        self,
+        exc_type: Optional[Type[BaseException]],
+        exc_value: Optional[BaseException],
+        traceback: Optional[TracebackType],
+    ) -> None: ...
+
+class PdEventMonitor(Iterator[PdEvent]):
+    def __iter__(self) -> "PdEventMonitor": ...
+    def __next__(self) -> PdEvent: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> "PdEventMonitor": ...
+    def __exit__(
+        self,
+        exc_type: Optional[Type[BaseException]],
+        exc_value: Optional[BaseException],
+        traceback: Optional[TracebackType],
+    ) -> None: ...
+
+class KM003C:
+    def __init__(
+        self,
+        interface: str = "vendor",
+        *,
+        device_index: int = 0,
+        reset: bool = True,
+        remote: Optional[str] = None,
+        bridge_key: Optional[bytes] = None,
+    ) -> None: ...
+    @property
+    def is_full_mode(self) -> bool: ...
+    @property
+    def adcqueue_enabled(self) -> bool: ...
+    @property
+    def device_info(self) -> Optional[Dict[str, Any]]: ...
+    def request_adc_data(self) -> AdcData: ...
+    def request_pd_data(self) -> Packet: ...
+    def request_settings(self) -> Dict[str, Any]: ...
+    def request_pd_trace(self) -> PdTrace: ...
+    def request_log_metadata(self) -> List[LogMetadata]: ...
+    def download_offline_log(self, metadata: LogMetadata) -> OfflineLog: ...
+    def start_graph_mode(self, rate_index: int) -> None: ...
+    def stop_graph_mode(self) -> None: ...
+    def read_graph_samples(self) -> List[AdcQueueSample]: ...
+    def enable_pd_monitor(self) -> None: ...
+    def disable_pd_monitor(self) -> None: ...
+    def read_pd_events(self) -> List[PdEvent]: ...
+    def stream(self, rate_index: int = ..., duration: Optional[float] = None) -> GraphStream: ...
+    def pd_events(self, duration: Optional[float] = None) -> PdEventMonitor: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> "KM003C": ...
+    def __exit__(
+        self,
+        exc_type: Optional[Type[BaseException]],
+        exc_value: Optional[BaseException],
+        traceback: Optional[TracebackType],
+    ) -> None: ...
+
+class PdEventCategory:
+    Connect: ClassVar["PdEventCategory"]
+    Disconnect: ClassVar["PdEventCategory"]
+    SourceCapabilities: ClassVar["PdEventCategory"]
+    Request: ClassVar["PdEventCategory"]
+    Control: ClassVar["PdEventCategory"]
+    Extended: ClassVar["PdEventCategory"]
+    Error: ClassVar["PdEventCategory"]
+    def __eq__(self, other: object) -> bool: ...
+    def __int__(self) -> int: ...
+
+class PdSessionDecoder:
+    def __init__(self) -> None: ...
+    def decode(self, event: PdEvent) -> Dict[str, Any]: ...
+    def reset(self) -> None: ...
@@ -1,9 +1,14 @@
 #!/usr/bin/env python3
 
+import asyncio
+import socket
+import struct
+import threading
 from importlib.metadata import version
 
 import km003c
 import pytest
+from km003c.aio import AsyncKM003C
 
 
 def test_constants():
@@ -127,6 +132,122 @@ def test_raw_adc_parsing():
         raise
 
 
+def unused_tcp_url():
+    with socket.socket() as listener:
+        listener.bind(("127.0.0.1", 0))
+        port = listener.getsockname()[1]
+    return f"tcp://127.0.0.1:{port}"
+
+
+def test_device_connection_errors():
+    assert isinstance(km003c.list_devices(), list)
+
+    with pytest.raises(ValueError, match="Unknown interface"):
+        km003c.KM003C("cdc")
+    with pytest.raises(ValueError):
+        km003c.KM003C(remote="http://127.0.0.1")
+    with pytest.raises(ConnectionRefusedError):
+        km003c.KM003C(remote=unused_tcp_url())
+
+
+def test_async_connect_runs_off_the_event_loop():
+    async def connect():
+        async with await AsyncKM003C.connect(remote=unused_tcp_url()):
+            pass
+
+    with pytest.raises(ConnectionRefusedError):
+        asyncio.run(connect())
+
+
+class FakeBridge(threading.Thread):
+    """bridge-server for a HID meter whose PD buffer always holds one connect event."""
+
+    # Recorded PD attribute response holding a single connect event
+    PD_RESPONSE = bytes.fromhex("419dc20010008004def81200000000007406020045d4f8120011")
+
+    def __init__(self):
+        super().__init__(daemon=True)
+        self.listener = socket.create_server(("127.0.0.1", 0))
+        self.url = "tcp://127.0.0.1:%d" % self.listener.getsockname()[1]
+        self.requests = []
+
+    def send(self, connection, kind, payload):
+        connection.sendall(struct.pack("<IB", len(payload) + 1, kind) + payload)
+
+    def receive(self, connection):
+        header = connection.recv(5, socket.MSG_WAITALL)
+        if len(header) < 5:
+            return None
+        length, kind = struct.unpack("<IB", header)
+        return connection.recv(length - 1, socket.MSG_WAITALL)
+
+    def run(self):
+        connection, _ = self.listener.accept()
+        with connection:
+            self.send(connection, 1, b"KM3B\x01" + bytes(16))  # Hello
+            self.receive(connection)  # Auth
+            self.send(connection, 3, bytes([3]))  # Ready on the HID interface
+            while (request := self.receive(connection)) is not None:
+                self.requests.append(request)
+                if request[0] & 0x7F == km003c.CMD_GET_DATA:
+                    response = bytearray(self.PD_RESPONSE)
+                    response[1] = request[1]
+                else:
+                    response = bytes([km003c.CMD_ACCEPT, request[1], 0, 0])
+                self.send(connection, 4, bytes(response))  # Transfer
+
+
+def test_pd_monitor_over_bridge():
+    bridge = FakeBridge()
+    bridge.start()
+
+    with km003c.KM003C(remote=bridge.url) as meter:
+        assert not meter.is_full_mode
+        assert meter.device_info is None
+        with meter.pd_events(duration=5.0) as events:
+            event = next(events)
+        assert repr(event).startswith("PdEvent(timestamp=1243348ms, type=connect")
+
+    commands = [request[0] & 0x7F for request in bridge.requests]
+    # Enable the PD monitor, poll once, disable it again
+    assert len(commands) == 3
+    assert commands[1] == km003c.CMD_GET_DATA
+    with pytest.raises(RuntimeError, match="closed"):
+        meter.request_adc_data()
+
+
+def test_async_pd_monitor_stops_when_the_block_exits():
+    bridge = FakeBridge()
+    bridge.start()
+
+    async def monitor():
+        async with await AsyncKM003C.connect(remote=bridge.url) as meter:
+            assert not meter.is_full_mode
+            assert meter.device_info is None
+            async with meter.pd_events(duration=5.0) as events:
+                async for event in events:
+                    return event
+
+    event = asyncio.run(monitor())
+    assert repr(event).startswith("PdEvent(timestamp=1243348ms, type=connect")
+    commands = [request[0] & 0x7F for request in bridge.requests]
+    assert len(commands) == 3
+    assert commands[1] == km003c.CMD_GET_DATA
+
+
+def test_pd_session_decoder_formats_events():
+    # Recorded PD attribute response holding a single connect event
+    packet = km003c.parse_packet(bytes.fromhex("419dc20010008004def81200000000007406020045d4f8120011"))
+    stream = packet["DataResponse"]["payloads"][0]
+
+    decoder = km003c.PdSessionDecoder()
+    decoded = decoder.decode(stream.events[0])
+    assert decoded["category"] == km003c.PdEventCategory.Connect
+    assert decoded["summary"] == "** CONNECT **"
+    assert abs(decoded["timestamp_s"] - 1243.348) < 1e-6
+    assert decoded["text"].endswith("** CONNECT **")
+
+
 def main():
     print("Testing KM003C Python bindings...\n")
 
@@ -135,6 +256,11 @@ def main():
     test_packet_api_shapes()
     test_adcqueue_helpers_use_rate_index()
     test_raw_adc_parsing()
+    test_device_connection_errors()
+    test_async_connect_runs_off_the_event_loop()
+    test_pd_monitor_over_bridge()
+    test_async_pd_monitor_stops_when_the_block_exits()
+    test_pd_session_decoder_formats_events()
 
     print("\n🎉 All tests passed!")
 